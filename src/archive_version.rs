@@ -0,0 +1,111 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::toc_entry::TocEntry;
+use crate::toc_error::TocError;
+use crate::toc_header::TocHeader;
+use crate::toc_string::TocString;
+
+// access method used by all tables before it became configurable in PostgreSQL 12
+const DEFAULT_TABLE_AM: &str = "heap";
+
+/// Archive format version of `pg_dump` TOC.
+///
+/// Can be parsed from a string like `1.13`. Versions `1.12` and `1.13` (`pg_dump` 9.6 to 11)
+/// have the same layout as `1.14` except that TOC entries do not contain table access
+/// method, they can be read and written to restore a dump into an older PostgreSQL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ArchiveVersion {
+    /// Version `1.12`, separate large object entries
+    V1_12,
+    /// Version `1.13`, `search_path` is set for every entry
+    V1_13,
+    /// Version `1.14`, table access method is added to TOC entries
+    V1_14,
+}
+
+impl ArchiveVersion {
+    pub(crate) fn from_minor(minor: u8) -> Option<Self> {
+        match minor {
+            12 => Some(Self::V1_12),
+            13 => Some(Self::V1_13),
+            14 => Some(Self::V1_14),
+            _ => None
+        }
+    }
+
+    pub(crate) fn minor(&self) -> u8 {
+        match self {
+            Self::V1_12 => 12,
+            Self::V1_13 => 13,
+            Self::V1_14 => 14
+        }
+    }
+
+    pub(crate) fn has_tableam(&self) -> bool {
+        *self >= Self::V1_14
+    }
+
+    // header without version bytes (created in memory) is written as 1.14
+    pub(crate) fn of_header(header: &TocHeader) -> Self {
+        header.version.get(1).and_then(|minor| Self::from_minor(*minor)).unwrap_or(Self::V1_14)
+    }
+
+    /// Changes the version in TOC header and adjusts TOC entries to the layout of this version.
+    ///
+    /// Table access method `heap` is dropped when converting to the version that
+    /// does not support it, any other access method fails the conversion.
+    pub(crate) fn apply(&self, header: &mut TocHeader, entries: &mut [TocEntry]) -> Result<(), TocError> {
+        if !self.has_tableam() {
+            for te in entries.iter() {
+                if let Some(tableam) = te.tableam.to_string_opt()? {
+                    if !tableam.is_empty() && DEFAULT_TABLE_AM != tableam {
+                        return Err(TocError::UnsupportedFormat(format!(
+                            "Archive version {} cannot represent table access method, dump_id: {}, tag: {}, access method: {}",
+                            self, te.dump_id, te.tag, tableam)));
+                    }
+                }
+            }
+            for te in entries.iter_mut() {
+                te.tableam = TocString::none();
+            }
+        }
+        header.version = vec!(1, self.minor(), 0);
+        Ok(())
+    }
+}
+
+impl FromStr for ArchiveVersion {
+    type Err = TocError;
+
+    fn from_str(st: &str) -> Result<Self, Self::Err> {
+        let minor = st.strip_prefix("1.").and_then(|minor| minor.parse::<u8>().ok());
+        match minor.and_then(Self::from_minor) {
+            Some(version) => Ok(version),
+            None => Err(TocError::new(&format!(
+                "Unsupported archive version specified: {}, supported versions: 1.12, 1.13, 1.14", st)))
+        }
+    }
+}
+
+impl fmt::Display for ArchiveVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "1.{}", self.minor())
+    }
+}
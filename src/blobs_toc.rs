@@ -0,0 +1,51 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::toc_error::TocError;
+
+
+/// Reads large objects index file `blobs.toc` from `pg_dump` directory.
+///
+/// Every line of this file contains large object OID and the name of its data file
+/// (like `16385 blob_16385.dat`), data file names do not include compression suffix.
+///
+/// Returns mapping from large object OIDs to data file names.
+///
+/// # Arguments
+///
+/// * `blobs_toc_path` - Path to `blobs.toc` file
+pub fn read_blobs_toc<P: AsRef<Path>>(blobs_toc_path: P) -> Result<BTreeMap<u32, String>, TocError> {
+    let contents = fs::read_to_string(blobs_toc_path.as_ref())?;
+    let mut res = BTreeMap::new();
+    for (idx, line) in contents.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let error = || TocError::new(&format!(
+            "Invalid blobs TOC entry, line: {}, contents: {}", idx + 1, line));
+        let (oid, filename) = line.split_once(' ').ok_or_else(error)?;
+        let oid = oid.parse::<u32>().map_err(|_| error())?;
+        if filename.is_empty() {
+            return Err(error());
+        }
+        res.insert(oid, filename.to_string());
+    }
+    Ok(res)
+}
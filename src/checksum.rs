@@ -0,0 +1,128 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::io::Read;
+use std::io::Write;
+
+#[cfg(feature = "manifest")]
+use sha2::Digest;
+#[cfg(feature = "manifest")]
+use sha2::Sha256;
+
+use crate::toc_error::TocError;
+
+
+#[cfg(feature = "manifest")]
+type Hasher = Sha256;
+
+// hashing is not available without 'manifest' feature,
+// hasher cannot be created in this case
+#[cfg(not(feature = "manifest"))]
+enum Hasher {}
+
+#[cfg(feature = "manifest")]
+fn new_hasher() -> Result<Hasher, TocError> {
+    Ok(Sha256::new())
+}
+
+#[cfg(not(feature = "manifest"))]
+fn new_hasher() -> Result<Hasher, TocError> {
+    Err(TocError::new(&"Catalog checksums require 'manifest' feature to be enabled"))
+}
+
+#[cfg(feature = "manifest")]
+fn update(hasher: &mut Hasher, data: &[u8]) {
+    hasher.update(data);
+}
+
+#[cfg(not(feature = "manifest"))]
+fn update(hasher: &mut Hasher, _data: &[u8]) {
+    match *hasher {}
+}
+
+#[cfg(feature = "manifest")]
+fn hex_digest(hasher: Hasher) -> String {
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(not(feature = "manifest"))]
+fn hex_digest(hasher: Hasher) -> String {
+    match hasher {}
+}
+
+fn new_hasher_opt(enabled: bool) -> Result<Option<Hasher>, TocError> {
+    if enabled {
+        Ok(Some(new_hasher()?))
+    } else {
+        Ok(None)
+    }
+}
+
+// computes SHA-256 of the data read through it, when enabled
+pub(crate) struct ChecksumReader<R: Read> {
+    inner: R,
+    hasher: Option<Hasher>
+}
+
+impl<R: Read> ChecksumReader<R> {
+    pub(crate) fn new(inner: R, enabled: bool) -> Result<Self, TocError> {
+        Ok(Self { inner, hasher: new_hasher_opt(enabled)? })
+    }
+
+    pub(crate) fn hex_digest(self) -> Option<String> {
+        self.hasher.map(hex_digest)
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            update(hasher, &buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+// computes SHA-256 of the data written through it, when enabled
+pub(crate) struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: Option<Hasher>
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub(crate) fn new(inner: W, enabled: bool) -> Result<Self, TocError> {
+        Ok(Self { inner, hasher: new_hasher_opt(enabled)? })
+    }
+
+    pub(crate) fn into_parts(self) -> (W, Option<String>) {
+        (self.inner, self.hasher.map(hex_digest))
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            update(hasher, &buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,89 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::toc_error::TocError;
+
+
+/// Compression of `pg_dump` data files.
+///
+/// Can be parsed from a string like `none`, `gzip` or `gzip:6`, gzip level
+/// defaults to `6` when not specified.
+///
+/// Only gzip is supported, `lz4` and `zstd` data files were introduced in dump
+/// format version `1.15` (`pg_dump` 16) that has a different TOC header layout,
+/// while this crate reads and writes TOC files of versions up to `1.14` where header
+/// can only specify gzip compression level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionSpec {
+    /// Data files are not compressed
+    None,
+    /// Data files are compressed with gzip using the specified level from `1` to `9`
+    Gzip(u32),
+}
+
+impl CompressionSpec {
+    // compression of data files described by the compression field in TOC header,
+    // -1 is Z_DEFAULT_COMPRESSION that is level 6 in zlib
+    pub(crate) fn from_header_value(value: i32) -> Self {
+        match value {
+            -1 => Self::Gzip(6),
+            level if level > 0 => Self::Gzip(level as u32),
+            _ => Self::None
+        }
+    }
+
+    // value of the compression field in TOC header
+    pub(crate) fn header_value(&self) -> i32 {
+        match self {
+            Self::None => 0,
+            Self::Gzip(level) => *level as i32
+        }
+    }
+}
+
+impl FromStr for CompressionSpec {
+    type Err = TocError;
+
+    fn from_str(st: &str) -> Result<Self, Self::Err> {
+        let error = || TocError::new(&format!("Invalid compression specified: {}", st));
+        let (algorithm, level) = match st.split_once(':') {
+            Some((algorithm, level)) => (algorithm, Some(level.parse::<u32>().map_err(|_| error())?)),
+            None => (st, None)
+        };
+        if "zstd" == algorithm || "lz4" == algorithm {
+            return Err(TocError::new(&format!(
+                "Compression '{}' is not supported, TOC format version 1.14 only supports gzip", algorithm)));
+        }
+        match (algorithm, level) {
+            ("none", None) => Ok(Self::None),
+            ("gzip", None) => Ok(Self::Gzip(6)),
+            ("gzip", Some(level)) if (1..=9).contains(&level) => Ok(Self::Gzip(level)),
+            _ => Err(error())
+        }
+    }
+}
+
+impl fmt::Display for CompressionSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Gzip(level) => write!(f, "gzip:{}", level)
+        }
+    }
+}
@@ -0,0 +1,181 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::toc_error::TocError;
+
+const NULL_MARKER: &str = "\\N";
+
+fn take_digits(bytes: &[u8], start: usize, max_len: usize, radix: u32) -> (u8, usize) {
+    let mut val = 0u32;
+    let mut len = 0usize;
+    while len < max_len && start + len < bytes.len() {
+        match (bytes[start + len] as char).to_digit(radix) {
+            Some(digit) => val = val * radix + digit,
+            None => break
+        }
+        len += 1;
+    }
+    // octal values above 0377 are truncated the same way as in postgres
+    ((val & 0xff) as u8, len)
+}
+
+// decodes a single field in COPY text format, '\N' is decoded as NULL,
+// bytes that are not valid UTF-8 (data in non-UTF8 server encodings)
+// are replaced with U+FFFD
+pub(crate) fn decode_copy_field(field: &str) -> Result<Option<String>, TocError> {
+    if NULL_MARKER == field {
+        return Ok(None);
+    }
+    let bytes = field.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0usize;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        i += 1;
+        if b'\\' != byte {
+            decoded.push(byte);
+            continue;
+        }
+        if i == bytes.len() {
+            return Err(TocError::new(&format!("Unterminated escape sequence in COPY field: {}", field)));
+        }
+        let escaped = bytes[i];
+        i += 1;
+        match escaped {
+            b'b' => decoded.push(0x08),
+            b'f' => decoded.push(0x0c),
+            b'n' => decoded.push(b'\n'),
+            b'r' => decoded.push(b'\r'),
+            b't' => decoded.push(b'\t'),
+            b'v' => decoded.push(0x0b),
+            b'0'..=b'7' => {
+                let (val, len) = take_digits(bytes, i - 1, 3, 8);
+                decoded.push(val);
+                i += len - 1;
+            },
+            b'x' if i < bytes.len() && bytes[i].is_ascii_hexdigit() => {
+                let (val, len) = take_digits(bytes, i, 2, 16);
+                decoded.push(val);
+                i += len;
+            },
+            other => decoded.push(other)
+        }
+    }
+    Ok(Some(String::from_utf8_lossy(&decoded).into_owned()))
+}
+
+// splits a line of COPY text format into fields, delimiters escaped
+// with a backslash are a part of the field value
+pub(crate) fn split_copy_line(line: &str, delimiter: char) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut start = 0usize;
+    let mut escaped = false;
+    for (idx, ch) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if '\\' == ch {
+            escaped = true;
+        } else if delimiter == ch {
+            res.push(&line[start..idx]);
+            start = idx + ch.len_utf8();
+        }
+    }
+    res.push(&line[start..]);
+    res
+}
+
+// splits a line that is not valid UTF-8 the same way as 'split_copy_line',
+// delimiter must be ASCII so it cannot be a part of a multibyte sequence
+pub(crate) fn split_copy_line_raw(line: &[u8], delimiter: u8) -> Vec<&[u8]> {
+    let mut res = Vec::new();
+    let mut start = 0usize;
+    let mut escaped = false;
+    for (idx, byte) in line.iter().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if b'\\' == *byte {
+            escaped = true;
+        } else if delimiter == *byte {
+            res.push(&line[start..idx]);
+            start = idx + 1;
+        }
+    }
+    res.push(&line[start..]);
+    res
+}
+
+// encodes a single field in COPY text format the same way as postgres does
+pub(crate) fn encode_copy_field(field: Option<&str>, delimiter: char) -> String {
+    let st = match field {
+        Some(st) => st,
+        None => return NULL_MARKER.to_string()
+    };
+    let mut encoded = String::with_capacity(st.len());
+    for ch in st.chars() {
+        match ch {
+            '\\' => encoded.push_str("\\\\"),
+            '\x08' => encoded.push_str("\\b"),
+            '\x0c' => encoded.push_str("\\f"),
+            '\n' => encoded.push_str("\\n"),
+            '\r' => encoded.push_str("\\r"),
+            '\t' => encoded.push_str("\\t"),
+            '\x0b' => encoded.push_str("\\v"),
+            _ if delimiter == ch => {
+                encoded.push('\\');
+                encoded.push(ch);
+            },
+            _ => encoded.push(ch)
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        assert_eq!(decode_copy_field("\\N").unwrap(), None);
+        assert_eq!(decode_copy_field("").unwrap(), Some("".to_string()));
+        assert_eq!(decode_copy_field("\\\\N").unwrap(), Some("\\N".to_string()));
+        assert_eq!(decode_copy_field("a\\r\\nb\\tc").unwrap(), Some("a\r\nb\tc".to_string()));
+        assert_eq!(decode_copy_field("\\101\\x42\\103d\\.").unwrap(), Some("ABCd.".to_string()));
+        assert_eq!(decode_copy_field("\\xg").unwrap(), Some("xg".to_string()));
+        assert_eq!(decode_copy_field("\\320\\227").unwrap(), Some("\u{0417}".to_string()));
+        assert!(decode_copy_field("a\\").is_err());
+        assert_eq!(decode_copy_field("\\377a\\xe9").unwrap(), Some("\u{FFFD}a\u{FFFD}".to_string()));
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(encode_copy_field(None, '\t'), "\\N");
+        assert_eq!(encode_copy_field(Some("\\N"), '\t'), "\\\\N");
+        assert_eq!(encode_copy_field(Some("a\r\nb\tc\u{0417}"), '\t'), "a\\r\\nb\\tc\u{0417}");
+        assert_eq!(encode_copy_field(Some("a,b|c"), '|'), "a,b\\|c");
+        for st in ["", "foo", "a\\b\x08\x0c\x0b", "a|b"] {
+            assert_eq!(decode_copy_field(&encode_copy_field(Some(st), '|')).unwrap(), Some(st.to_string()));
+        }
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(split_copy_line("a\tb\t\\N", '\t'), ["a", "b", "\\N"]);
+        assert_eq!(split_copy_line("a\\|b|c\\\\|d", '|'), ["a\\|b", "c\\\\", "d"]);
+        assert_eq!(split_copy_line("", '|'), [""]);
+        assert_eq!(split_copy_line("a|", '|'), ["a", ""]);
+    }
+}
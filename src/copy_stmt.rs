@@ -0,0 +1,126 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use sqlparser::dialect::GenericDialect;
+use sqlparser::keywords::Keyword;
+use sqlparser::tokenizer::Token;
+use sqlparser::tokenizer::Tokenizer;
+
+use crate::toc_error::TocError;
+
+// default delimiter of COPY text format
+pub(crate) const DEFAULT_DELIMITER: char = '\t';
+
+fn tokenize_copy_stmt(copy_stmt: &str) -> Result<Vec<Token>, TocError> {
+    let dialect = GenericDialect {};
+    match Tokenizer::new(&dialect, copy_stmt).tokenize() {
+        Ok(tokens) => Ok(tokens),
+        Err(e) => Err(TocError::new(&format!(
+            "COPY statement tokenizer error: {}, sql: {}", e, copy_stmt)))
+    }
+}
+
+pub(crate) fn copy_stmt_columns(copy_stmt: &str) -> Result<Vec<String>, TocError> {
+    let tokens = tokenize_copy_stmt(copy_stmt)?;
+    let mut columns = Vec::new();
+    let start = match tokens.iter().position(|tok| Token::LParen == *tok) {
+        Some(idx) => idx + 1,
+        None => return Ok(columns)
+    };
+    for tok in tokens.iter().skip(start) {
+        match tok {
+            Token::Word(word) => columns.push(word.value.clone()),
+            Token::Comma | Token::Whitespace(_) => {},
+            Token::RParen => return Ok(columns),
+            _ => return Err(TocError::new(&format!(
+                "Unexpected token in COPY statement columns list: {}, sql: {}", tok, copy_stmt)))
+        }
+    }
+    Err(TocError::new(&format!("Unterminated COPY statement columns list, sql: {}", copy_stmt)))
+}
+
+fn is_keyword(tok: &Token, keyword: Keyword) -> bool {
+    matches!(tok, Token::Word(word) if word.quote_style.is_none() && word.keyword == keyword)
+}
+
+// pg_dump writes 'COPY ... FROM stdin;' without options, DELIMITER option can be
+// specified as 'WITH (DELIMITER ',')', 'WITH DELIMITER ','' or 'DELIMITER AS ','',
+// only the options after FROM are considered, a column can be named 'delimiter'
+pub(crate) fn copy_stmt_delimiter(copy_stmt: &str) -> Result<char, TocError> {
+    let tokens = tokenize_copy_stmt(copy_stmt)?;
+    let significant: Vec<&Token> = tokens.iter()
+        .filter(|tok| !matches!(tok, Token::Whitespace(_)))
+        .collect();
+    let from_idx = match significant.iter().position(|tok| is_keyword(tok, Keyword::FROM)) {
+        Some(idx) => idx,
+        None => return Ok(DEFAULT_DELIMITER)
+    };
+    let mut iter = significant.iter().skip(from_idx + 1);
+    while let Some(tok) = iter.next() {
+        if !is_keyword(tok, Keyword::DELIMITER) {
+            continue;
+        }
+        let mut value = iter.next();
+        if value.map(|tok| is_keyword(tok, Keyword::AS)).unwrap_or(false) {
+            value = iter.next();
+        }
+        let st = match value {
+            Some(Token::SingleQuotedString(st)) | Some(Token::EscapedStringLiteral(st)) => st,
+            _ => return Err(TocError::new(&format!(
+                "Invalid DELIMITER option in COPY statement, sql: {}", copy_stmt)))
+        };
+        let mut chars = st.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(ch), None) if ch.is_ascii() => Ok(ch),
+            _ => Err(TocError::new(&format!(
+                "COPY delimiter must be a single one-byte character, delimiter: '{}', sql: {}", st, copy_stmt)))
+        };
+    }
+    Ok(DEFAULT_DELIMITER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns() {
+        assert_eq!(copy_stmt_columns(
+            "COPY sys.babelfish_sysdatabases (status, status2, owner, default_collation, name, crdate, properties) FROM stdin;\n").unwrap(),
+            vec!("status", "status2", "owner", "default_collation", "name", "crdate", "properties"));
+        assert_eq!(copy_stmt_columns("COPY tab1 (id, \"Val 1\") FROM stdin;").unwrap(), vec!("id", "Val 1"));
+        assert!(copy_stmt_columns("COPY tab1 FROM stdin;").unwrap().is_empty());
+        assert!(copy_stmt_columns("").unwrap().is_empty());
+        assert!(copy_stmt_columns("COPY tab1 (id, 42) FROM stdin;").is_err());
+        assert!(copy_stmt_columns("COPY tab1 (id, val").is_err());
+    }
+
+    #[test]
+    fn delimiter() {
+        assert_eq!(copy_stmt_delimiter("COPY sys.babelfish_sysdatabases (status, name) FROM stdin;\n").unwrap(), '\t');
+        assert_eq!(copy_stmt_delimiter("").unwrap(), '\t');
+        assert_eq!(copy_stmt_delimiter("COPY tab1 (id, delimiter) FROM stdin;").unwrap(), '\t');
+        assert_eq!(copy_stmt_delimiter("COPY tab1 (id, val) FROM stdin WITH (DELIMITER ',');").unwrap(), ',');
+        assert_eq!(copy_stmt_delimiter("COPY tab1 FROM stdin WITH (FORMAT text, delimiter '|');").unwrap(), '|');
+        assert_eq!(copy_stmt_delimiter("COPY tab1 FROM stdin WITH DELIMITER ';';").unwrap(), ';');
+        assert_eq!(copy_stmt_delimiter("COPY tab1 FROM stdin DELIMITER AS ',';").unwrap(), ',');
+        assert_eq!(copy_stmt_delimiter("COPY tab1 FROM stdin WITH (DELIMITER \"x\");").unwrap_err().to_string(),
+            "Invalid DELIMITER option in COPY statement, sql: COPY tab1 FROM stdin WITH (DELIMITER \"x\");");
+        assert!(copy_stmt_delimiter("COPY tab1 FROM stdin WITH (DELIMITER ',,');").is_err());
+        assert!(copy_stmt_delimiter("COPY tab1 FROM stdin WITH (DELIMITER '');").is_err());
+        assert!(copy_stmt_delimiter("COPY tab1 FROM stdin WITH (DELIMITER);").is_err());
+    }
+}
@@ -0,0 +1,120 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::error::Error;
+use std::fmt;
+
+use crate::keywords::KEYWORDS;
+
+/// Maximum length of PostgreSQL identifier in bytes (`NAMEDATALEN - 1`), longer names
+/// are silently truncated by the server.
+pub const MAX_IDENTIFIER_LEN: usize = 63;
+
+/// Reason why the DB name cannot be used, returned by `check_dbname`.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum DbNameError {
+    /// DB name is empty
+    Empty,
+    /// DB name starts or ends with whitespace
+    Whitespace,
+    /// First character is not allowed at the start of the name
+    InvalidFirstChar(char),
+    /// Character is not allowed in the name
+    InvalidChar {
+        /// The invalid character
+        ch: char,
+        /// Position of the character in the name, starting from 1
+        position: usize
+    },
+    /// DB name is a reserved SQL keyword
+    ReservedKeyword,
+}
+
+impl fmt::Display for DbNameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "DB name is empty"),
+            Self::Whitespace => write!(f, "DB name has leading or trailing whitespace"),
+            Self::InvalidFirstChar(ch) => write!(f,
+                "DB name cannot start with this character, found: '{}'", ch),
+            Self::InvalidChar { ch, position } => write!(f,
+                "DB name contains invalid character, found: '{}' at position: {}", ch, position),
+            Self::ReservedKeyword => write!(f, "DB name is a reserved keyword"),
+        }
+    }
+}
+
+impl Error for DbNameError {}
+
+/// Checks that the specified DB name can be used as a new Babelfish logical DB name.
+///
+/// The same rules are applied by `rewrite_toc` and other functions that take
+/// the new DB name: name must be a non-empty lower case SQL identifier that does not
+/// require quoting and is not a reserved keyword.
+///
+/// # Arguments
+///
+/// * `dbname` - DB name to check
+pub fn check_dbname(dbname: &str) -> Result<(), DbNameError> {
+    if dbname.is_empty() {
+        return Err(DbNameError::Empty);
+    }
+    if dbname.trim() != dbname {
+        return Err(DbNameError::Whitespace);
+    }
+    for (idx, ch) in dbname.chars().enumerate() {
+        if 0 == idx && !(ch.is_ascii_lowercase() || ch == '_') {
+            return Err(DbNameError::InvalidFirstChar(ch));
+        }
+        if !(ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '_') {
+            return Err(DbNameError::InvalidChar { ch, position: idx + 1 });
+        }
+    }
+    if KEYWORDS.contains(&dbname) {
+        return Err(DbNameError::ReservedKeyword);
+    }
+    Ok(())
+}
+
+/// Checks the DB name using T-SQL regular identifier rules and returns the name
+/// in the form it is stored by Babelfish.
+///
+/// Name must start with a letter or underscore and can contain letters, digits
+/// and `_`, `@`, `#` or `$` characters, reserved keywords are allowed. Babelfish
+/// stores logical DB names in lower case, so the returned name is lower-cased,
+/// schema and role names derived from it are quoted in SQL where necessary.
+///
+/// # Arguments
+///
+/// * `dbname` - DB name to check
+pub fn check_dbname_relaxed(dbname: &str) -> Result<String, DbNameError> {
+    if dbname.is_empty() {
+        return Err(DbNameError::Empty);
+    }
+    if dbname.trim() != dbname {
+        return Err(DbNameError::Whitespace);
+    }
+    for (idx, ch) in dbname.chars().enumerate() {
+        if 0 == idx && !(ch.is_alphabetic() || ch == '_') {
+            return Err(DbNameError::InvalidFirstChar(ch));
+        }
+        if !(ch.is_alphanumeric() || ch == '_' || ch == '@' || ch == '#' || ch == '$') {
+            return Err(DbNameError::InvalidChar { ch, position: idx + 1 });
+        }
+    }
+    Ok(dbname.to_lowercase())
+}
@@ -0,0 +1,162 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+
+use crate::toc_entry::TocEntry;
+use crate::toc_error::TocError;
+
+/// Dependencies between TOC entries, keyed by dump ID.
+///
+/// Created with `Toc::dependency_graph`.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct DependencyGraph {
+    labels: BTreeMap<i32, String>,
+    deps: BTreeMap<i32, Vec<i32>>,
+    unknown_deps: Vec<(i32, String)>,
+}
+
+/// Problems found in the TOC dependency graph.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct DependencyReport {
+    /// Pairs of entry dump ID and the dependency that is not present in TOC
+    pub unknown_deps: Vec<(i32, String)>,
+    /// Dependency cycles, every cycle is a list of dump IDs where each entry depends on the next one
+    /// and the last entry depends on the first one
+    pub cycles: Vec<Vec<i32>>,
+}
+
+impl DependencyGraph {
+    pub(crate) fn from_entries(entries: &[TocEntry]) -> Self {
+        let mut res = Self::default();
+        for te in entries {
+            let label = format!("{}: {}", te.description.to_string_lossy(), te.tag.to_string_lossy());
+            res.labels.insert(te.dump_id, label);
+        }
+        for te in entries {
+            let mut deps = Vec::with_capacity(te.deps.len());
+            for dep in te.deps.iter() {
+                let dep_st = dep.to_string_lossy();
+                match dep_st.parse::<i32>() {
+                    Ok(id) if res.labels.contains_key(&id) => deps.push(id),
+                    _ => res.unknown_deps.push((te.dump_id, dep_st))
+                }
+            }
+            res.deps.insert(te.dump_id, deps);
+        }
+        res
+    }
+
+    /// Dump IDs of all entries in ascending order.
+    pub fn dump_ids(&self) -> Vec<i32> {
+        self.labels.keys().copied().collect()
+    }
+
+    /// Dump IDs of the entries the specified entry depends on, dependencies
+    /// that are not present in TOC are not included.
+    pub fn dependencies(&self, dump_id: i32) -> Option<&[i32]> {
+        self.deps.get(&dump_id).map(|deps| deps.as_slice())
+    }
+
+    /// Checks the graph for dependencies on unknown dump IDs and for dependency cycles.
+    pub fn validate(&self) -> DependencyReport {
+        DependencyReport {
+            unknown_deps: self.unknown_deps.clone(),
+            cycles: self.find_cycles(),
+        }
+    }
+
+    /// Writes the graph in Graphviz DOT format, nodes are labeled with
+    /// `description: tag`, edges point from an entry to its dependencies.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Destination writer
+    pub fn to_dot<W: Write>(&self, writer: &mut W) -> Result<(), TocError> {
+        writeln!(writer, "digraph toc {{")?;
+        for (dump_id, label) in self.labels.iter() {
+            let escaped = label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            writeln!(writer, "  {} [label=\"{}\\n{}\"];", dump_id, dump_id, escaped)?;
+        }
+        for (dump_id, deps) in self.deps.iter() {
+            for dep in deps {
+                writeln!(writer, "  {} -> {};", dump_id, dep)?;
+            }
+        }
+        writeln!(writer, "}}")?;
+        Ok(())
+    }
+
+    // iterative DFS, recursion depth would be unbounded on long dependency chains
+    fn find_cycles(&self) -> Vec<Vec<i32>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum State { New, InProgress, Done }
+        let mut states: BTreeMap<i32, State> = self.labels.keys().map(|id| (*id, State::New)).collect();
+        let mut cycles = Vec::new();
+        for start in self.labels.keys() {
+            if states[start] != State::New {
+                continue;
+            }
+            // path of (dump_id, index of the next dependency to visit)
+            let mut path: Vec<(i32, usize)> = vec![(*start, 0)];
+            states.insert(*start, State::InProgress);
+            while let Some((dump_id, next_idx)) = path.last_mut() {
+                let deps = &self.deps[dump_id];
+                if *next_idx >= deps.len() {
+                    states.insert(*dump_id, State::Done);
+                    path.pop();
+                    continue;
+                }
+                let dep = deps[*next_idx];
+                *next_idx += 1;
+                match states[&dep] {
+                    State::New => {
+                        states.insert(dep, State::InProgress);
+                        path.push((dep, 0));
+                    },
+                    State::InProgress => {
+                        let pos = path.iter().position(|(id, _)| *id == dep).unwrap_or(0);
+                        cycles.push(path[pos..].iter().map(|(id, _)| *id).collect());
+                    },
+                    State::Done => {}
+                }
+            }
+        }
+        cycles
+    }
+}
+
+impl DependencyReport {
+    /// Returns `true` if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.unknown_deps.is_empty() && self.cycles.is_empty()
+    }
+}
+
+impl fmt::Display for DependencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (dump_id, dep) in self.unknown_deps.iter() {
+            writeln!(f, "Unknown dependency: entry {} depends on {}", dump_id, dep)?;
+        }
+        for cycle in self.cycles.iter() {
+            let ids: Vec<String> = cycle.iter().map(|id| id.to_string()).collect();
+            writeln!(f, "Dependency cycle: {} -> {}", ids.join(" -> "), ids[0])?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt;
+
+/// Class of a file in dump directory, see `list_dump_files`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFileKind {
+    /// `toc.dat` or a data file referenced by TOC entries (including large objects files)
+    Referenced,
+    /// Backup of the original file created by the in-place rewrite, like `1234.dat.orig.gz`
+    Backup,
+    /// Temporary file left by an interrupted run, like `toc.dat.1234-5f3a9c0e.tmp`
+    Temp,
+    /// Any other file, for example, a data file of other dump or a `.new` file
+    Unknown,
+}
+
+impl fmt::Display for DumpFileKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Referenced => "referenced",
+            Self::Backup => "backup",
+            Self::Temp => "temp",
+            Self::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Result of the dump directory check.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct DumpDirReport {
+    /// Data files referenced by TOC entries that do not exist in dump directory
+    pub missing_files: Vec<String>,
+    /// Table data files (like `1234.dat.gz`) in dump directory that are not referenced by TOC entries
+    pub orphan_files: Vec<String>,
+    /// Data files that are referenced by more than one TOC entry
+    pub duplicate_files: Vec<String>,
+    /// Data files that are not gzip files while compression is enabled in TOC header
+    pub compression_mismatches: Vec<String>,
+}
+
+impl DumpDirReport {
+    /// Returns `true` if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.missing_files.is_empty() &&
+            self.orphan_files.is_empty() &&
+            self.duplicate_files.is_empty() &&
+            self.compression_mismatches.is_empty()
+    }
+}
+
+impl fmt::Display for DumpDirReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (label, files) in [
+            ("Missing file", &self.missing_files),
+            ("Orphan file", &self.orphan_files),
+            ("Duplicate file", &self.duplicate_files),
+            ("Compression mismatch", &self.compression_mismatches),
+        ] {
+            for file in files {
+                writeln!(f, "{}: {}", label, file)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fs::File;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Seek;
+use std::path::Path;
+
+use crate::resolve_toc_path;
+use crate::toc_error::TocError;
+use crate::toc_filter::TocEntryView;
+use crate::toc_reader::TocReader;
+
+/// TOC reader with random access to entries.
+///
+/// All entries are read once on creation to record their byte offsets,
+/// after that any entry can be read again with a single seek.
+pub struct IndexedTocReader<R: Read + Seek> {
+    reader: TocReader<R>,
+}
+
+impl IndexedTocReader<BufReader<File>> {
+    /// Opens `pg_dump` TOC file and builds an index of its entries.
+    ///
+    /// # Arguments
+    ///
+    /// * `toc_path` - Path to TOC file or to the dump directory containing it
+    pub fn open<P: AsRef<Path>>(toc_path: P) -> Result<Self, TocError> {
+        let file = File::open(resolve_toc_path(toc_path)?)?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> IndexedTocReader<R> {
+    /// Builds an index of TOC entries reading the TOC from the start of the specified reader.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Seekable source of TOC data
+    pub fn new(reader: R) -> Result<Self, TocError> {
+        let mut reader = TocReader::new(reader);
+        reader.build_index()?;
+        Ok(Self { reader })
+    }
+
+    /// Number of entries in TOC.
+    pub fn len(&self) -> usize {
+        self.reader.entry_offsets().len()
+    }
+
+    /// Returns `true` if TOC has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.reader.entry_offsets().is_empty()
+    }
+
+    /// Byte offset of the entry with the specified index from the start of TOC.
+    pub fn offset(&self, index: usize) -> Option<u64> {
+        self.reader.entry_offsets().get(index).copied()
+    }
+
+    /// Reads the entry with the specified index (position in TOC, not `dump_id`).
+    pub fn read_entry_at(&mut self, index: usize) -> Result<TocEntryView, TocError> {
+        let te = self.reader.read_entry_at(index)?;
+        TocEntryView::from_entry(&te)
+    }
+}
@@ -0,0 +1,149 @@
+/*
+ * Copyright 2023, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::convert::TryFrom;
+
+use chrono::naive::NaiveDateTime;
+use serde_json::Value;
+
+use crate::toc_error::TocError;
+
+pub(crate) const MAGIC_LEN: usize = 5;
+pub(crate) const VERSION_LEN: usize = 3;
+pub(crate) const FLAGS_LEN: usize = 3;
+
+const HEADER_STRING_FIELDS: [&str; 3] = ["postgres_dbname", "version_server", "version_pgdump"];
+const ENTRY_INT_FIELDS: [&str; 3] = ["dump_id", "had_dumper", "section"];
+const ENTRY_STRING_FIELDS: [&str; 12] = ["table_oid", "catalog_oid", "tag", "description", "create_stmt",
+    "drop_stmt", "copy_stmt", "namespace", "tablespace", "tableam", "owner", "table_with_oids"];
+
+fn invalid(field: &str, message: &str) -> TocError {
+    TocError::InvalidJson { field: field.to_string(), message: message.to_string() }
+}
+
+fn object_field<'a>(obj: &'a Value, path: &str, name: &str) -> Result<&'a Value, TocError> {
+    obj.get(name).ok_or_else(|| invalid(&format!("{}.{}", path, name), "field is missing"))
+}
+
+fn check_int(obj: &Value, path: &str, name: &str) -> Result<(), TocError> {
+    let val = object_field(obj, path, name)?;
+    match val.as_i64() {
+        Some(num) if i32::try_from(num).is_ok() => Ok(()),
+        _ => Err(invalid(&format!("{}.{}", path, name), &format!("expected 32-bit integer, found: {}", val)))
+    }
+}
+
+// null and string values are both valid, but the field must be present
+// to not silently write null instead of a misspelled field
+fn check_string_opt(obj: &Value, path: &str, name: &str) -> Result<(), TocError> {
+    let val = object_field(obj, path, name)?;
+    if val.is_string() || val.is_null() {
+        Ok(())
+    } else {
+        Err(invalid(&format!("{}.{}", path, name), &format!("expected string or null, found: {}", val)))
+    }
+}
+
+// used both for validation and for decoding of the already validated JSON,
+// any length is accepted when the length is not specified
+pub(crate) fn bytes_from_hex<S: AsRef<str>>(hex_list: &[S], field: &str, len: Option<usize>) -> Result<Vec<u8>, TocError> {
+    match len {
+        Some(len) if hex_list.len() != len => return Err(invalid(field, &format!(
+            "expected length: {}, actual: {}", len, hex_list.len()))),
+        _ => {}
+    }
+    hex_list.iter().enumerate().map(|(idx, hex)| {
+        let hex = hex.as_ref();
+        match hex.len() {
+            1 | 2 if hex.chars().all(|ch| ch.is_ascii_hexdigit()) => u8::from_str_radix(hex, 16).ok(),
+            _ => None
+        }.ok_or_else(|| invalid(field, &format!("expected hex byte at index: {}, found: '{}'", idx, hex)))
+    }).collect()
+}
+
+fn check_hex_array(val: &Value, field: &str, len: Option<usize>) -> Result<(), TocError> {
+    let hex_list = match val.as_array() {
+        Some(arr) => arr.iter().map(|val| val.as_str()).collect::<Option<Vec<&str>>>(),
+        None => None
+    }.ok_or_else(|| invalid(field, "expected array of hex strings"))?;
+    bytes_from_hex(&hex_list, field, len)?;
+    Ok(())
+}
+
+fn check_header(header: &Value) -> Result<(), TocError> {
+    let path = "header";
+    if !header.is_object() {
+        return Err(invalid(path, "expected object"));
+    }
+    for (name, len) in [("magic", MAGIC_LEN), ("version", VERSION_LEN), ("flags", FLAGS_LEN)] {
+        check_hex_array(object_field(header, path, name)?, &format!("{}.{}", path, name), Some(len))?;
+    }
+    check_int(header, path, "compression")?;
+    let timestamp = object_field(header, path, "timestamp")?;
+    match timestamp.as_str() {
+        Some(st) => if let Err(e) = NaiveDateTime::parse_from_str(st, "%Y-%m-%d %H:%M:%S") {
+            return Err(invalid("header.timestamp", &format!(
+                "expected 'YYYY-MM-DD HH:MM:SS', found: '{}', error: {}", st, e)));
+        },
+        None => return Err(invalid("header.timestamp", &format!("expected string, found: {}", timestamp)))
+    };
+    let is_dst = object_field(header, path, "is_dst")?;
+    if !is_dst.is_boolean() && !is_dst.is_null() {
+        return Err(invalid("header.is_dst", "expected boolean or null"));
+    }
+    for name in HEADER_STRING_FIELDS {
+        check_string_opt(header, path, name)?;
+    }
+    check_int(header, path, "toc_count")
+}
+
+fn check_entry(entry: &Value, path: &str) -> Result<(), TocError> {
+    if !entry.is_object() {
+        return Err(invalid(path, "expected object"));
+    }
+    for name in ENTRY_INT_FIELDS {
+        check_int(entry, path, name)?;
+    }
+    for name in ENTRY_STRING_FIELDS {
+        check_string_opt(entry, path, name)?;
+    }
+    if !object_field(entry, path, "deps")?.is_array() {
+        return Err(invalid(&format!("{}.deps", path), "expected array"));
+    }
+    check_string_opt(entry, path, "filename")
+}
+
+// checked before deserialization to report the problem field by name,
+// serde errors only specify a line and a column
+pub(crate) fn check_toc_json(json: &Value) -> Result<(), TocError> {
+    if !json.is_object() {
+        return Err(invalid("", "expected object"));
+    }
+    let header = json.get("header").ok_or_else(|| invalid("header", "field is missing"))?;
+    check_header(header)?;
+    let entries = match json.get("entries") {
+        Some(Value::Array(entries)) => entries,
+        Some(_) => return Err(invalid("entries", "expected array")),
+        None => return Err(invalid("entries", "field is missing"))
+    };
+    for (idx, entry) in entries.iter().enumerate() {
+        check_entry(entry, &format!("entries[{}]", idx))?;
+    }
+    if let Some(trailer) = json.get("trailer") {
+        check_hex_array(trailer, "trailer", None)?;
+    }
+    Ok(())
+}
@@ -14,7 +14,7 @@
  * limitations under the License.
  */
 
-pub(crate) const KEYWORDS: &'static [&'static str] = &[
+pub(crate) const KEYWORDS: &[&str] = &[
     "abort",
     "absolute",
     "access",
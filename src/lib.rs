@@ -14,57 +14,156 @@
  * limitations under the License.
  */
 
+mod archive_version;
+mod blobs_toc;
+mod checksum;
+mod compression_spec;
+mod copy_data;
+mod copy_stmt;
+mod dbname;
+mod dependency_graph;
+mod dump_dir_report;
+mod indexed_toc;
+mod json_check;
 mod keywords;
 mod rewrite_catalog;
+mod rewrite_options;
 mod rewrite_sql;
+mod roundtrip;
+mod stale_reference;
+mod toc;
 mod toc_datetime;
 mod toc_entry;
 mod toc_error;
+mod toc_filter;
 mod toc_header;
 mod toc_string;
 mod toc_reader;
+mod temp_file;
 mod toc_writer;
 mod utils;
 
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
 use std::io::BufReader;
 use std::io::BufWriter;
+use std::io::Read;
 use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
 
+use chrono::Local;
+use log::Level;
 use serde::Deserialize;
 use serde::Serialize;
-use serde_json;
 
-use keywords::KEYWORDS;
+use copy_data::decode_copy_field;
+use copy_data::encode_copy_field;
+use copy_data::split_copy_line;
+use copy_stmt::copy_stmt_columns;
+use copy_stmt::copy_stmt_delimiter;
+use copy_stmt::DEFAULT_DELIMITER;
+use json_check::bytes_from_hex;
+use json_check::check_toc_json;
+use rewrite_catalog::CatalogFile;
+use rewrite_catalog::CatalogRewrite;
+use rewrite_catalog::count_data_lines;
+use rewrite_catalog::detect_file_compression;
+use rewrite_catalog::read_catalog_contents;
+use rewrite_catalog::recompress_data_file;
 use rewrite_catalog::rewrite_catalog;
-use rewrite_catalog::rewrite_catalog_all_at_once;
+use rewrite_catalog::rewrite_catalog_statements;
+use rewrite_sql::rewrite_schema_in_sql_internal;
+use stale_reference::contains_name_prefix;
+use temp_file::copy_file_metadata;
+use temp_file::BackupRestore;
+use temp_file::is_service_file;
+use temp_file::is_temp_filename;
+use temp_file::sync_dir;
+use temp_file::sync_file;
+use temp_file::DumpLock;
+use temp_file::TempPath;
+use toc::sort_entries_by_dump_id;
+use toc::dependency_order_error;
+use toc::dependency_order_violations;
+use toc_datetime::TocDateTime;
 use toc_entry::TocEntry;
 use toc_entry::TocEntryJson;
-use toc_error::TocError;
 use toc_header::TocHeader;
 use toc_header::TocHeaderJson;
 use toc_reader::TocReader;
 use toc_string::TocString;
 use toc_writer::TocWriter;
 
+pub use archive_version::ArchiveVersion;
+pub use blobs_toc::read_blobs_toc;
+pub use compression_spec::CompressionSpec;
+pub use dbname::check_dbname;
+pub use dbname::check_dbname_relaxed;
+pub use dbname::DbNameError;
+pub use dbname::MAX_IDENTIFIER_LEN;
+pub use dependency_graph::DependencyGraph;
+pub use dependency_graph::DependencyReport;
+pub use dump_dir_report::DumpDirReport;
+pub use dump_dir_report::DumpFileKind;
+pub use indexed_toc::IndexedTocReader;
+pub use rewrite_options::CancellationToken;
+pub use rewrite_options::ProgressEvent;
+pub use rewrite_options::RewriteOptions;
+pub use temp_file::LOCK_FILENAME;
+pub use rewrite_sql::rewrite_dbname_in_sql;
+pub use rewrite_sql::rewrite_role_in_sql;
 pub use rewrite_sql::rewrite_schema_in_sql;
 pub use rewrite_sql::rewrite_schema_in_sql_single_quoted;
 pub use rewrite_sql::rewrite_schema_in_sql_unqualified;
 pub use rewrite_sql::rewrite_schema_in_sql_qualified_single_quoted;
+pub use rewrite_sql::rewrite_schema_in_sql_with_dialect;
+pub use rewrite_sql::rewrite_tablespace_in_sql;
+pub use rewrite_sql::SqlDialect;
+pub use roundtrip::verify_roundtrip;
+pub use roundtrip::verify_roundtrip_bytes;
+pub use roundtrip::RoundtripMismatch;
+pub use stale_reference::StaleReference;
+pub use toc_filter::exclude_table_data;
+pub use toc_filter::exclude_tags;
+pub use toc_filter::include_sections;
+pub use toc_filter::FilterOptions;
+pub use toc_filter::FilterSummary;
+pub use toc_filter::TocEntryView;
+pub use toc_filter::TocSection;
+pub use toc::renumber_dump_ids;
+pub use toc::Toc;
+pub use toc::TocBuilder;
+pub use toc::TocEntryBuilder;
+pub use toc_error::TocError;
+pub use toc_reader::DEFAULT_MAX_STRING_LEN;
 
 
 #[derive(Default, Debug, Clone)]
 struct TocCtx {
     header: TocHeader,
     orig_dbname: String,
-    orig_dbname_with_underscore: String,
     dest_dbname: String,
     schemas: HashMap<String, String>,
     owners: HashMap<String, String>,
-    catalog_files: HashMap<String, String>
+    tablespaces: HashMap<String, String>,
+    catalog_files: HashMap<String, String>,
+    catalog_columns: HashMap<String, Vec<String>>,
+    catalog_delimiters: HashMap<String, char>,
+    recompression_level: Option<u32>,
+    dest_dir_path: Option<PathBuf>,
+    new_files: bool,
+    catalog_checksums: bool,
+    preserve_mtime: bool,
+    durable: bool,
+    max_sql_len: Option<usize>,
+    sql_dialect: SqlDialect,
+    catalog_sql_dialect: SqlDialect,
+    keep_oversized_sql: bool,
+    oversized_sql_warnings: RefCell<Vec<String>>
 }
 
 impl TocCtx {
@@ -72,188 +171,462 @@ impl TocCtx {
         Self {
             header,
             orig_dbname: orig_dbname.to_string(),
-            orig_dbname_with_underscore: format!("{}_", orig_dbname),
             dest_dbname: dest_dbname.to_string(),
             ..Default::default()
         }
     }
 
-    fn catalog_filename(&self, bbf_catalog: &str) -> Result<String, TocError> {
+    // only babelfish_sysdatabases is required, other catalogs can be
+    // absent in dumps of old Babelfish versions
+    fn optional_catalog_filename(&self, bbf_catalog: &str) -> Option<&str> {
+        self.catalog_files.get(bbf_catalog).map(|fname| fname.as_str())
+    }
+
+    fn catalog_file<'a>(&'a self, dir_path: &'a Path, bbf_catalog: &'a str) -> Result<CatalogFile<'a>, TocError> {
         match self.catalog_files.get(bbf_catalog) {
-            Some(fname) => Ok(fname.clone()),
-            None => return Err(TocError::new(&format!(
-                "Catalog table not found: {}", bbf_catalog)))
+            Some(filename) => Ok(CatalogFile {
+                dir_path,
+                catalog: bbf_catalog,
+                filename,
+                compression: detect_file_compression(dir_path, filename, self.header.compression),
+                force_level: self.recompression_level,
+                dest_dir_path: self.dest_dir_path.as_deref(),
+                new_file: self.new_files,
+                checksums: self.catalog_checksums,
+                preserve_mtime: self.preserve_mtime,
+                durable: self.durable,
+                delimiter: self.catalog_delimiters.get(bbf_catalog).copied().unwrap_or(DEFAULT_DELIMITER),
+                restore: None
+            }),
+            None => Err(TocError::CatalogMissing(bbf_catalog.to_string()))
+        }
+    }
+
+    // file name on disk, compression extension may not match the header
+    fn catalog_data_file_name(&self, dir_path: &Path, bbf_catalog: &str) -> Result<String, TocError> {
+        let file = self.catalog_file(dir_path, bbf_catalog)?;
+        if file.compression > 0 {
+            Ok(format!("{}.gz", file.filename))
+        } else {
+            Ok(file.filename.to_string())
+        }
+    }
+
+    fn rewrite_schemas(&self, sql: &str, qualified_only: bool, single_quoted_only: bool, dialect: SqlDialect) -> Result<String, TocError> {
+        match rewrite_schema_in_sql_internal(&self.schemas, sql, qualified_only, single_quoted_only, self.max_sql_len, dialect) {
+            Err(TocError::SqlTooLong { length, limit }) if self.keep_oversized_sql => {
+                let start: String = sql.chars().take(32).collect();
+                self.oversized_sql_warnings.borrow_mut().push(format!(
+                    "SQL statement is too long, left unmodified, length: {}, limit: {}, start: {}",
+                    length, limit, start));
+                Ok(sql.to_string())
+            },
+            res => res
         }
     }
+
+    fn catalog_column_idx(&self, bbf_catalog: &str, column: &str, default_idx: usize) -> Result<usize, TocError> {
+        match self.catalog_columns.get(bbf_catalog) {
+            Some(columns) if !columns.is_empty() => {
+                columns.iter().position(|col| col == column).ok_or(TocError::new(&format!(
+                    "Column: {} not found in catalog table: {}, columns: {}", column, bbf_catalog, columns.join(", "))))
+            },
+            _ => Ok(default_idx)
+        }
+    }
+}
+
+/// Summary of the TOC rewrite.
+#[derive(Default, Debug, Clone)]
+pub struct RewriteSummary {
+    /// Names of the Babelfish catalogs that were rewritten
+    pub catalogs_rewritten: Vec<String>,
+    /// Warnings about the skipped steps
+    pub warnings: Vec<String>,
+    /// Names of the files in dump directory that are not referenced by TOC entries,
+    /// only collected when writing into the output directory
+    pub unreferenced_files: Vec<String>,
+    /// Checksums of the rewritten Babelfish catalog files, only collected
+    /// when `catalog_checksums` option is enabled
+    pub catalog_checksums: Vec<CatalogChecksum>,
+    /// Dump IDs of the TOC entries that were removed (like ACL entries when ACLs are dropped or owners are stripped)
+    pub removed_entries: Vec<i32>,
+    /// Set when dump already uses the specified DB name and the in-place rewrite wrote nothing
+    pub no_change_needed: bool
+}
+
+/// SHA-256 checksums (lower-case hex) of the decompressed contents of a Babelfish catalog file.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct CatalogChecksum {
+    /// Name of the Babelfish catalog table
+    pub catalog: String,
+    /// Name of the data file in dump directory
+    pub filename: String,
+    /// Checksum of the file contents before the rewrite
+    pub sha256_before: String,
+    /// Checksum of the file contents after the rewrite
+    pub sha256_after: String
+}
+
+/// Schema and role renames that are performed when logical DB is renamed.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct RenameMaps {
+    /// Original logical DB name detected from schema names
+    pub orig_dbname: String,
+    /// Mapping from original schema names to new schema names
+    pub schemas: HashMap<String, String>,
+    /// Mapping from original role names to new role names
+    pub owners: HashMap<String, String>
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) struct TocJson {
     pub(crate) header: TocHeaderJson,
-    pub(crate) entries: Vec<TocEntryJson>
+    pub(crate) entries: Vec<TocEntryJson>,
+    // hex bytes after the last entry, omitted when there are none
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) trailer: Vec<String>
+}
+
+#[derive(Serialize, Debug)]
+struct TocDebugJson {
+    header: TocHeaderJson,
+    entries: Vec<TocEntryDebugJson>
+}
+
+#[derive(Serialize, Debug)]
+struct TocEntryDebugJson {
+    offset: u64,
+    length: u64,
+    #[serde(flatten)]
+    entry: TocEntryJson
+}
+
+fn record_column(rec: &mut [String], idx: usize) -> Result<&mut String, TocError> {
+    let len = rec.len();
+    rec.get_mut(idx).ok_or(TocError::new(&format!(
+        "Column index out of range, expected index: {}, columns count: {}", idx, len)))
 }
 
-fn replace_record_rolname(ctx: &TocCtx, rec: &mut Vec<String>, idx: usize) -> Result<(), TocError> {
-    let rolname = &rec[idx];
+fn replace_record_rolname(ctx: &TocCtx, rec: &mut [String], idx: usize) -> Result<(), TocError> {
+    let rolname = record_column(rec, idx)?;
     if let Some(replaced) = ctx.owners.get(rolname) {
-        rec[idx] = replaced.clone();
+        *rolname = replaced.clone();
     };
     Ok(())
 }
 
-fn replace_record_schema(ctx: &TocCtx, rec: &mut Vec<String>, idx: usize) -> Result<(), TocError> {
-    let schema = &rec[idx];
+fn replace_record_schema(ctx: &TocCtx, rec: &mut [String], idx: usize) -> Result<(), TocError> {
+    let schema = record_column(rec, idx)?;
     if let Some(replaced) = ctx.schemas.get(schema) {
-        rec[idx] = replaced.clone();
+        *schema = replaced.clone();
     };
     Ok(())
 }
 
-fn replace_record_schema_in_signature(ctx: &TocCtx, rec: &mut Vec<String>, idx: usize) -> Result<(), TocError> {
-    let sig = &rec[idx];
-    let replaced = rewrite_schema_in_sql(&ctx.schemas, sig)?;
-    rec[idx] = replaced;
+fn replace_record_schema_in_signature(ctx: &TocCtx, rec: &mut [String], idx: usize) -> Result<(), TocError> {
+    let sig = record_column(rec, idx)?;
+    let replaced = ctx.rewrite_schemas(sig, true, false, ctx.catalog_sql_dialect)?;
+    *sig = replaced;
     Ok(())
 }
 
-fn replace_record_dbname(ctx: &TocCtx, rec: &mut Vec<String>, idx: usize) -> Result<(), TocError> {
-    let dbname = &rec[idx];
+fn replace_record_dbname(ctx: &TocCtx, rec: &mut [String], idx: usize) -> Result<(), TocError> {
+    let dbname = record_column(rec, idx)?;
     if ctx.orig_dbname == *dbname {
-        rec[idx] = ctx.dest_dbname.clone()
+        *dbname = ctx.dest_dbname.clone()
     }
     Ok(())
 }
 
-fn rewrite_bbf_authid_user_ext(ctx: &TocCtx, dir_path: &Path) -> Result<(), TocError> {
-    let filename = ctx.catalog_filename("babelfish_authid_user_ext")?;
-    rewrite_catalog(dir_path, &filename, ctx.header.compression, |mut rec| {
-        replace_record_rolname(ctx, &mut rec, 0)?;
-        replace_record_dbname(ctx, &mut rec, 11)?;
+fn rewrite_bbf_authid_user_ext(ctx: &TocCtx, dir_path: &Path, restore: &BackupRestore, progress: &dyn Fn(u64, u64)) -> Result<CatalogRewrite, TocError> {
+    let file = CatalogFile { restore: Some(restore), ..ctx.catalog_file(dir_path, "babelfish_authid_user_ext")? };
+    let rolname_idx = ctx.catalog_column_idx("babelfish_authid_user_ext", "rolname", 0)?;
+    let dbname_idx = ctx.catalog_column_idx("babelfish_authid_user_ext", "database_name", 11)?;
+    let res = rewrite_catalog(&file, progress, |mut rec| {
+        replace_record_rolname(ctx, &mut rec, rolname_idx)?;
+        replace_record_dbname(ctx, &mut rec, dbname_idx)?;
         Ok(rec)
     })?;
-    Ok(())
+    Ok(res)
 }
 
-fn rewrite_bbf_extended_properties(ctx: &TocCtx, dir_path: &Path) -> Result<(), TocError> {
-    let filename = ctx.catalog_filename("babelfish_extended_properties")?;
-    rewrite_catalog_all_at_once(dir_path, &filename, ctx.header.compression, |sql| {
-        let replaced = rewrite_schema_in_sql_single_quoted(&ctx.schemas, &sql)?;
+fn rewrite_bbf_extended_properties(ctx: &TocCtx, dir_path: &Path, restore: &BackupRestore, progress: &dyn Fn(u64, u64)) -> Result<CatalogRewrite, TocError> {
+    let file = CatalogFile { restore: Some(restore), ..ctx.catalog_file(dir_path, "babelfish_extended_properties")? };
+    let res = rewrite_catalog_statements(&file, progress, |sql| {
+        let replaced = ctx.rewrite_schemas(&sql, false, true, ctx.catalog_sql_dialect)?;
         Ok(replaced)
     })?;
-    Ok(())
+    Ok(res)
 }
 
-fn rewrite_bbf_function_ext(ctx: &TocCtx, dir_path: &Path) -> Result<(), TocError> {
-    let filename = ctx.catalog_filename("babelfish_function_ext")?;
-    rewrite_catalog(dir_path, &filename, ctx.header.compression, |mut rec| {
-        replace_record_schema(ctx, &mut rec, 0)?;
-        replace_record_schema_in_signature(ctx, &mut rec, 3)?;
+fn rewrite_bbf_function_ext(ctx: &TocCtx, dir_path: &Path, restore: &BackupRestore, progress: &dyn Fn(u64, u64)) -> Result<CatalogRewrite, TocError> {
+    let file = CatalogFile { restore: Some(restore), ..ctx.catalog_file(dir_path, "babelfish_function_ext")? };
+    let nspname_idx = ctx.catalog_column_idx("babelfish_function_ext", "nspname", 0)?;
+    let signature_idx = ctx.catalog_column_idx("babelfish_function_ext", "funcsignature", 3)?;
+    let res = rewrite_catalog(&file, progress, |mut rec| {
+        replace_record_schema(ctx, &mut rec, nspname_idx)?;
+        replace_record_schema_in_signature(ctx, &mut rec, signature_idx)?;
         Ok(rec)
     })?;
-    Ok(())
+    Ok(res)
 }
 
-fn rewrite_bbf_namespace_ext(ctx: &TocCtx, dir_path: &Path) -> Result<(), TocError> {
-    let filename = ctx.catalog_filename("babelfish_namespace_ext")?;
-    rewrite_catalog(dir_path, &filename, ctx.header.compression, |mut rec| {
-        replace_record_schema(ctx, &mut rec, 0)?;
+fn rewrite_bbf_namespace_ext(ctx: &TocCtx, dir_path: &Path, restore: &BackupRestore, progress: &dyn Fn(u64, u64)) -> Result<CatalogRewrite, TocError> {
+    let file = CatalogFile { restore: Some(restore), ..ctx.catalog_file(dir_path, "babelfish_namespace_ext")? };
+    let nspname_idx = ctx.catalog_column_idx("babelfish_namespace_ext", "nspname", 0)?;
+    let res = rewrite_catalog(&file, progress, |mut rec| {
+        replace_record_schema(ctx, &mut rec, nspname_idx)?;
         Ok(rec)
     })?;
-    Ok(())
+    Ok(res)
 }
 
-fn rewrite_bbf_sysdatabases(ctx: &TocCtx, dir_path: &Path) -> Result<(), TocError> {
-    let filename = ctx.catalog_filename("babelfish_sysdatabases")?;
-    rewrite_catalog(dir_path, &filename, ctx.header.compression, |mut rec| {
-        replace_record_dbname(ctx, &mut rec, 4)?;
+// Dumped columns: status, status2, owner, default_collation, name, crdate, properties.
+// Owner is a login name, it is only replaced when it matches one of the renamed roles,
+// default schema is not stored in this catalog (it is implied by the 'dbo' schema).
+fn rewrite_bbf_sysdatabases(ctx: &TocCtx, dir_path: &Path, restore: &BackupRestore, progress: &dyn Fn(u64, u64)) -> Result<CatalogRewrite, TocError> {
+    let file = CatalogFile { restore: Some(restore), ..ctx.catalog_file(dir_path, "babelfish_sysdatabases")? };
+    let owner_idx = ctx.catalog_column_idx("babelfish_sysdatabases", "owner", 2)?;
+    let name_idx = ctx.catalog_column_idx("babelfish_sysdatabases", "name", 4)?;
+    let res = rewrite_catalog(&file, progress, |mut rec| {
+        replace_record_rolname(ctx, &mut rec, owner_idx)?;
+        replace_record_dbname(ctx, &mut rec, name_idx)?;
         Ok(rec)
     })?;
+    Ok(res)
+}
+
+fn log_catalog<L: Fn(Level, &str)>(ctx: &TocCtx, log: &L, dir_path: &Path, bbf_catalog: &str, res: &CatalogRewrite) -> Result<(), TocError> {
+    let filename = ctx.catalog_data_file_name(dir_path, bbf_catalog)?;
+    if ctx.catalog_file(dir_path, bbf_catalog)?.compression > 0 {
+        log(Level::Info, &format!("catalog {} ({}): {} rows rewritten, compression level: {}",
+            bbf_catalog, filename, res.rows_count, res.compression));
+    } else {
+        log(Level::Info, &format!("catalog {} ({}): {} rows rewritten", bbf_catalog, filename, res.rows_count));
+    }
     Ok(())
 }
 
-fn rewrite_babelfish_catalogs(ctx: &TocCtx, dir_path: &Path) -> Result<(), TocError> {
-    rewrite_bbf_authid_user_ext(ctx, dir_path)?;
-    rewrite_bbf_extended_properties(ctx, dir_path)?;
-    rewrite_bbf_function_ext(ctx, dir_path)?;
-    rewrite_bbf_namespace_ext(ctx, dir_path)?;
-    rewrite_bbf_sysdatabases(ctx, dir_path)?;
+// values of the specified column in all COPY data rows of the catalog
+fn read_catalog_column(ctx: &TocCtx, dir_path: &Path, bbf_catalog: &str, column: &str, default_idx: usize) -> Result<Vec<String>, TocError> {
+    let file = ctx.catalog_file(dir_path, bbf_catalog)?;
+    let idx = ctx.catalog_column_idx(bbf_catalog, column, default_idx)?;
+    let contents = read_catalog_contents(dir_path, file.filename, file.compression)?;
+    let mut res = Vec::new();
+    for line in contents.lines() {
+        if "\\." == line {
+            break;
+        }
+        if let Some(Some(value)) = split_copy_line(line, file.delimiter).get(idx).map(|field| decode_copy_field(field)).transpose()? {
+            res.push(value);
+        }
+    }
+    Ok(res)
+}
+
+// catalogs rows of other logical DBs that already use the new names
+fn find_name_collisions(ctx: &TocCtx, dir_path: &Path) -> Result<Vec<String>, TocError> {
+    let mut res = Vec::new();
+    if ctx.optional_catalog_filename("babelfish_sysdatabases").is_some() {
+        for name in read_catalog_column(ctx, dir_path, "babelfish_sysdatabases", "name", 4)? {
+            if name == ctx.dest_dbname && name != ctx.orig_dbname {
+                res.push(format!("babelfish_sysdatabases: {}", name));
+            }
+        }
+    }
+    let checks = [
+        ("babelfish_namespace_ext", "nspname", &ctx.schemas),
+        ("babelfish_authid_user_ext", "rolname", &ctx.owners),
+    ];
+    for (bbf_catalog, column, renames) in checks {
+        if ctx.optional_catalog_filename(bbf_catalog).is_none() {
+            continue;
+        }
+        for name in read_catalog_column(ctx, dir_path, bbf_catalog, column, 0)? {
+            if !renames.contains_key(&name) && renames.values().any(|new_name| *new_name == name) {
+                res.push(format!("{}: {}", bbf_catalog, name));
+            }
+        }
+    }
+    Ok(res)
+}
+
+type CatalogRewriteFn = fn(&TocCtx, &Path, &BackupRestore, &dyn Fn(u64, u64)) -> Result<CatalogRewrite, TocError>;
+
+fn rewrite_babelfish_catalogs(ctx: &TocCtx, dir_path: &Path, options: &RewriteOptions,
+                              summary: &mut RewriteSummary, restore: &BackupRestore) -> Result<(), TocError> {
+    let log = |level: Level, msg: &str| options.log(level, msg);
+    let catalogs: [(&str, CatalogRewriteFn); 5] = [
+        ("babelfish_authid_user_ext", rewrite_bbf_authid_user_ext),
+        ("babelfish_extended_properties", rewrite_bbf_extended_properties),
+        ("babelfish_function_ext", rewrite_bbf_function_ext),
+        ("babelfish_namespace_ext", rewrite_bbf_namespace_ext),
+        ("babelfish_sysdatabases", rewrite_bbf_sysdatabases),
+    ];
+    // fail before any of the catalog files is modified
+    if ctx.optional_catalog_filename("babelfish_sysdatabases").is_none() {
+        return Err(TocError::CatalogMissing("babelfish_sysdatabases".to_string()));
+    }
+    for (bbf_catalog, rewrite_fun) in catalogs {
+        options.check_cancelled()?;
+        if ctx.optional_catalog_filename(bbf_catalog).is_none() {
+            let msg = format!("catalog {} not found in TOC, skipped", bbf_catalog);
+            log(Level::Warn, &msg);
+            summary.warnings.push(msg);
+            continue;
+        }
+        let progress = |processed, total| options.progress(ProgressEvent::CatalogBytes {
+            catalog: bbf_catalog.to_string(),
+            processed,
+            total
+        });
+        let file_compression = ctx.catalog_file(dir_path, bbf_catalog)?.compression;
+        if file_compression > 0 && ctx.header.compression <= 0 {
+            let msg = format!("catalog {}: TOC header specifies no compression, but only compressed file {} exists, reading it as gzip",
+                bbf_catalog, ctx.catalog_data_file_name(dir_path, bbf_catalog)?);
+            log(Level::Warn, &msg);
+            summary.warnings.push(msg);
+        } else if file_compression <= 0 && ctx.header.compression > 0 {
+            let msg = format!("catalog {}: TOC header specifies compression, but only uncompressed file {} exists, reading it as plain text",
+                bbf_catalog, ctx.catalog_data_file_name(dir_path, bbf_catalog)?);
+            log(Level::Warn, &msg);
+            summary.warnings.push(msg);
+        }
+        let res = rewrite_fun(ctx, dir_path, restore, &progress)?;
+        log_catalog(ctx, &log, dir_path, bbf_catalog, &res)?;
+        summary.catalogs_rewritten.push(bbf_catalog.to_string());
+        if let Some((sha256_before, sha256_after)) = res.checksums {
+            summary.catalog_checksums.push(CatalogChecksum {
+                catalog: bbf_catalog.to_string(),
+                filename: ctx.catalog_data_file_name(dir_path, bbf_catalog)?,
+                sha256_before,
+                sha256_after
+            });
+        }
+    }
     Ok(())
 }
 
-fn replace_schema_tstr(schemas: &HashMap<String, String>, sql: &TocString) -> Result<TocString, TocError> {
+fn replace_schema_tstr(ctx: &TocCtx, sql: &TocString) -> Result<TocString, TocError> {
     if sql.opt.is_none() {
         return Ok(TocString::none())
     };
     let sql_st = sql.to_string()?;
-    let sql_rewritten = rewrite_schema_in_sql(schemas, &sql_st)?;
+    let sql_rewritten = ctx.rewrite_schemas(&sql_st, true, false, ctx.sql_dialect)?;
     Ok(TocString::from_string(sql_rewritten))
 }
 
-fn replace_schema_tstr_unqualified(schemas: &HashMap<String, String>, sql: &TocString) -> Result<TocString, TocError> {
+fn replace_schema_tstr_unqualified(ctx: &TocCtx, sql: &TocString) -> Result<TocString, TocError> {
     if sql.opt.is_none() {
         return Ok(TocString::none())
     };
     let sql_st = sql.to_string()?;
-    let sql_rewritten = rewrite_schema_in_sql_unqualified(schemas, &sql_st)?;
+    let sql_rewritten = ctx.rewrite_schemas(&sql_st, false, false, ctx.sql_dialect)?;
     Ok(TocString::from_string(sql_rewritten))
 }
 
-fn replace_schema_tstr_qualified_single_quoted(schemas: &HashMap<String, String>, sql: &TocString) -> Result<TocString, TocError> {
+fn replace_schema_tstr_qualified_single_quoted(ctx: &TocCtx, sql: &TocString) -> Result<TocString, TocError> {
     if sql.opt.is_none() {
         return Ok(TocString::none())
     };
     let sql_st = sql.to_string()?;
-    let sql_rewritten = rewrite_schema_in_sql_qualified_single_quoted(schemas, &sql_st)?;
+    let sql_rewritten = ctx.rewrite_schemas(&sql_st, true, true, ctx.sql_dialect)?;
     Ok(TocString::from_string(sql_rewritten))
 }
 
 fn replace_create_stmt(ctx: &TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
-    te.create_stmt = replace_schema_tstr(&ctx.schemas, &te.create_stmt)?;
+    te.create_stmt = replace_schema_tstr(ctx, &te.create_stmt)?;
     Ok(())
 }
 
 fn replace_create_stmt_unqualified(ctx: &TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
-    te.create_stmt = replace_schema_tstr_unqualified(&ctx.schemas, &te.create_stmt)?;
+    te.create_stmt = replace_schema_tstr_unqualified(ctx, &te.create_stmt)?;
     Ok(())
 }
 
 fn replace_create_stmt_qualified_single_quoted(ctx: &TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
-    te.create_stmt = replace_schema_tstr_qualified_single_quoted(&ctx.schemas, &te.create_stmt)?;
+    te.create_stmt = replace_schema_tstr_qualified_single_quoted(ctx, &te.create_stmt)?;
+    Ok(())
+}
+
+fn replace_create_stmt_roles(ctx: &TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
+    if te.create_stmt.opt.is_none() {
+        return Ok(())
+    };
+    let sql = te.create_stmt.to_string()?;
+    te.create_stmt = TocString::from_string(rewrite_role_in_sql(&ctx.owners, &sql)?);
     Ok(())
 }
 
 fn replace_drop_stmt(ctx: &TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
-    te.drop_stmt = replace_schema_tstr(&ctx.schemas, &te.drop_stmt)?;
+    te.drop_stmt = replace_schema_tstr(ctx, &te.drop_stmt)?;
     Ok(())
 }
 
 fn replace_drop_stmt_unqualified(ctx: &TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
-    te.drop_stmt = replace_schema_tstr_unqualified(&ctx.schemas, &te.drop_stmt)?;
+    te.drop_stmt = replace_schema_tstr_unqualified(ctx, &te.drop_stmt)?;
     Ok(())
 }
 
+// Babelfish can emit 'database.schema.table' names
 fn replace_copy_stmt(ctx: &TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
-    te.copy_stmt = replace_schema_tstr(&ctx.schemas, &te.copy_stmt)?;
+    te.copy_stmt = replace_schema_tstr(ctx, &te.copy_stmt)?;
+    if te.copy_stmt.opt.is_some() && !ctx.orig_dbname.is_empty() {
+        let sql = te.copy_stmt.to_string()?;
+        te.copy_stmt = TocString::from_string(rewrite_dbname_in_sql(&ctx.orig_dbname, &ctx.dest_dbname, &sql)?);
+    }
     Ok(())
 }
 
 fn replace_tag(ctx: &TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
-    te.tag = replace_schema_tstr(&ctx.schemas, &te.tag)?;
+    te.tag = replace_schema_tstr(ctx, &te.tag)?;
+    Ok(())
+}
+
+// tag of the SCHEMA entry is a plain name, pg_dump does not quote it
+// even if the name requires quoting in SQL
+fn replace_tag_schema_name(ctx: &TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
+    if let Some(replaced) = ctx.schemas.get(&te.tag.to_string()?) {
+        te.tag = TocString::from_str(replaced);
+    };
     Ok(())
 }
 
 fn replace_tag_unqualified(ctx: &TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
-    te.tag = replace_schema_tstr_unqualified(&ctx.schemas, &te.tag)?;
+    te.tag = replace_schema_tstr_unqualified(ctx, &te.tag)?;
     Ok(())
 }
 
 fn replace_owner(ctx: &TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
-    if let Some(replaced) = ctx.owners.get(&te.owner.to_string()?) {
+    // null owner is read as an empty string, entries without owner are left as is
+    // even if the owners map passed to 'rewrite_owners' contains an empty role name
+    let owner = te.owner.to_string()?;
+    if owner.is_empty() {
+        return Ok(());
+    }
+    if let Some(replaced) = ctx.owners.get(&owner) {
         te.owner = TocString::from_str(replaced);
     };
     Ok(())
 }
 
+fn replace_tablespace(ctx: &TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
+    if ctx.tablespaces.is_empty() {
+        return Ok(());
+    }
+    if let Some(replaced) = ctx.tablespaces.get(&te.tablespace.to_string()?) {
+        te.tablespace = TocString::from_str(replaced);
+    };
+    for stmt in [&mut te.create_stmt, &mut te.drop_stmt] {
+        if stmt.opt.is_some() {
+            let sql = TocString::to_string(stmt)?;
+            *stmt = TocString::from_string(rewrite_tablespace_in_sql(&ctx.tablespaces, &sql)?);
+        }
+    }
+    Ok(())
+}
+
 fn replace_namespace(ctx: &TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
     if let Some(replaced) = ctx.schemas.get(&te.namespace.to_string()?) {
         te.namespace = TocString::from_str(replaced);
@@ -261,49 +634,156 @@ fn replace_namespace(ctx: &TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
     Ok(())
 }
 
-fn collect_schema_and_owner(ctx: &mut TocCtx, te: &TocEntry) -> Result<(), TocError> {
-    let schema_orig = te.tag.to_string()?;
-    if !schema_orig.starts_with(&ctx.orig_dbname_with_underscore) {
-        return Err(TocError::new(&format!("Unexpected schema name: {}", schema_orig)));
+// '<dbname>_<suffix>' with the suffix transformed as specified in options
+fn dest_name(dest_dbname: &str, suffix: &str, suffix_transform: Option<&dyn Fn(&str) -> String>) -> String {
+    match suffix_transform {
+        Some(transform) => format!("{}_{}", dest_dbname, transform(suffix)),
+        None => format!("{}_{}", dest_dbname, suffix)
     }
-    let schema_suffix = schema_orig.chars().skip(ctx.orig_dbname_with_underscore.len()).collect::<String>();
-    let schema_dest = format!("{}_{}", ctx.dest_dbname, schema_suffix);
-    ctx.schemas.insert(schema_orig.clone(), schema_dest.clone());
+}
+
+fn collect_schema_and_owner(maps: &mut RenameMaps, dest_dbname: &str, te: &TocEntry,
+                            suffix_transform: Option<&dyn Fn(&str) -> String>) -> Result<(), TocError> {
+    let orig_dbname_with_underscore = format!("{}_", maps.orig_dbname);
+    let schema_orig = te.tag.to_string()?;
+    let schema_suffix = match schema_orig.strip_prefix(&orig_dbname_with_underscore) {
+        Some(suffix) => suffix,
+        None => return Err(TocError::new(&format!("Unexpected schema name: {}", schema_orig)))
+    };
+    let schema_dest = dest_name(dest_dbname, schema_suffix, suffix_transform);
+    maps.schemas.insert(schema_orig.clone(), schema_dest.clone());
 
     let owner_orig = te.owner.to_string()?;
-    if owner_orig.starts_with(&ctx.orig_dbname_with_underscore) {
-        let owner_suffix = owner_orig.chars().skip(ctx.orig_dbname_with_underscore.len()).collect::<String>();
-        let owner_dest = format!("{}_{}", ctx.dest_dbname, owner_suffix);
-        ctx.owners.insert(owner_orig.clone(), owner_dest.clone());
+    if let Some(owner_suffix) = owner_orig.strip_prefix(&orig_dbname_with_underscore) {
+        let owner_dest = dest_name(dest_dbname, owner_suffix, suffix_transform);
+        maps.owners.insert(owner_orig.clone(), owner_dest.clone());
+    }
+    Ok(())
+}
+
+fn collect_rename_maps(entries: &Vec<TocEntry>, dbname: &str,
+                       suffix_transform: Option<&dyn Fn(&str) -> String>) -> Result<RenameMaps, TocError> {
+    let mut maps = RenameMaps {
+        orig_dbname: find_out_orig_dbname(entries)?,
+        ..Default::default()
+    };
+    // dbo and db_owner roles may not own any schema if custom schemas are not used
+    for role in ["dbo", "db_owner"] {
+        maps.owners.insert(format!("{}_{}", maps.orig_dbname, role), dest_name(dbname, role, suffix_transform));
+    }
+    for te in entries {
+        if "SCHEMA" == te.description.to_string()? {
+            collect_schema_and_owner(&mut maps, dbname, te, suffix_transform)?;
+        }
+    }
+    Ok(maps)
+}
+
+// explicit renames replace the derived ones for the same original names,
+// long names are left to find_too_long_names that honours allow_long_names
+fn apply_rename_overrides(maps: &mut RenameMaps, options: &RewriteOptions) -> Result<(), TocError> {
+    let overrides = [(options.schema_map, &mut maps.schemas), (options.owner_map, &mut maps.owners)];
+    for (map_opt, names) in overrides {
+        if let Some(map) = map_opt {
+            for (orig, dest) in map.iter() {
+                match validate_identifier(orig, dest) {
+                    Err(TocError::NameTooLong { .. }) | Ok(()) => {},
+                    Err(err) => return Err(err)
+                }
+                names.insert(orig.clone(), dest.clone());
+            }
+        }
     }
     Ok(())
 }
 
+// sorted by original name, names shared by schemas and roles are reported once
+fn find_too_long_names(maps: &RenameMaps) -> Vec<TocError> {
+    let mut names: Vec<(&String, &String)> = maps.schemas.iter()
+        .chain(maps.owners.iter())
+        .filter(|(_, name)| name.len() > MAX_IDENTIFIER_LEN)
+        .collect();
+    names.sort();
+    names.dedup();
+    names.into_iter().map(|(orig_name, name)| TocError::NameTooLong {
+        orig_name: orig_name.clone(),
+        name: name.clone(),
+        length: name.len()
+    }).collect()
+}
+
+fn checked_rename_maps(entries: &Vec<TocEntry>, dbname: &str, options: &RewriteOptions) -> Result<RenameMaps, TocError> {
+    let mut maps = collect_rename_maps(entries, dbname, options.suffix_transform)?;
+    apply_rename_overrides(&mut maps, options)?;
+    if !options.allow_long_names {
+        if let Some(err) = find_too_long_names(&maps).into_iter().next() {
+            return Err(err);
+        }
+    }
+    Ok(maps)
+}
+
+// rewrite options that affect TOC entries and catalogs contents
+fn rewrite_ctx(header: TocHeader, maps: RenameMaps, dbname: &str, options: &RewriteOptions) -> TocCtx {
+    let mut ctx = TocCtx::new(header, &maps.orig_dbname, dbname);
+    ctx.schemas = maps.schemas;
+    ctx.owners = maps.owners;
+    ctx.recompression_level = options.compression_level;
+    ctx.catalog_checksums = options.catalog_checksums;
+    ctx.preserve_mtime = options.preserve_mtime;
+    ctx.durable = options.durable;
+    ctx.max_sql_len = options.max_sql_len;
+    if let Some(tablespaces) = options.tablespaces {
+        ctx.tablespaces = tablespaces.clone();
+    }
+    ctx.sql_dialect = options.sql_dialect;
+    ctx.catalog_sql_dialect = options.catalog_sql_dialect;
+    ctx.keep_oversized_sql = options.keep_oversized_sql;
+    ctx
+}
+
+// catalogs that contain logical DB, schema or role names
+const BABELFISH_CATALOGS: [&str; 5] = [
+    "babelfish_authid_user_ext",
+    "babelfish_extended_properties",
+    "babelfish_function_ext",
+    "babelfish_namespace_ext",
+    "babelfish_sysdatabases",
+];
+
 fn collect_babelfish_catalog_filename(ctx: &mut TocCtx, te: &TocEntry) -> Result<(), TocError> {
-    let catalogs = vec!(
-        "babelfish_authid_user_ext",
-        "babelfish_extended_properties",
-        "babelfish_function_ext",
-        "babelfish_namespace_ext",
-        "babelfish_sysdatabases",
-    );
     let tag = te.tag.to_string()?;
-    if catalogs.contains(&tag.as_str()) {
+    if BABELFISH_CATALOGS.contains(&tag.as_str()) {
+        let copy_stmt = te.copy_stmt.to_string()?;
+        ctx.catalog_columns.insert(tag.clone(), copy_stmt_columns(&copy_stmt)?);
+        ctx.catalog_delimiters.insert(tag.clone(), copy_stmt_delimiter(&copy_stmt)?);
         ctx.catalog_files.insert(tag, te.filename.to_string()?);
     }
     Ok(())
 }
 
-fn modify_toc_entry(ctx: &mut TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
+fn replace_in_toc_entry(ctx: &mut TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
+    let dump_id = te.dump_id;
+    let tag = te.tag.to_string_lossy();
+    let description = te.description.to_string_lossy();
+    replace_in_toc_entry_fields(ctx, te).map_err(|e| TocError::Entry {
+        dump_id,
+        description,
+        tag,
+        source: Box::new(e)
+    })
+}
+
+fn replace_in_toc_entry_fields(ctx: &mut TocCtx, te: &mut TocEntry) -> Result<(), TocError> {
     let tag = te.tag.to_string()?;
     let description = te.description.to_string()?;
     if "SCHEMA" == description {
-        collect_schema_and_owner(ctx, te)?;
-        replace_tag_unqualified(ctx, te)?;
+        replace_tag_schema_name(ctx, te)?;
         replace_create_stmt_unqualified(ctx, te)?;
         replace_drop_stmt_unqualified(ctx, te)?;
         replace_owner(ctx, te)?;
-    } else if "ACL" == description && tag.starts_with("SCHEMA ") {
+    } else if ("ACL" == description || "COMMENT" == description) && tag.starts_with("SCHEMA ") {
+        // schema name is not qualified in 'COMMENT ON SCHEMA', comment text is left as is
         replace_tag_unqualified(ctx, te)?;
         replace_create_stmt_unqualified(ctx, te)?;
         replace_owner(ctx, te)?;
@@ -322,90 +802,350 @@ fn modify_toc_entry(ctx: &mut TocCtx, te: &mut TocEntry) -> Result<(), TocError>
         replace_namespace(ctx, te)?;
         replace_owner(ctx, te)?;
     }
+    replace_tablespace(ctx, te)?;
 
     Ok(())
 }
 
-fn check_dbname(dbname: &str) -> Result<(), TocError> {
-    let error = Err(TocError::new(&format!("Invalid db name specified: [{}]", dbname)));
-    if dbname.is_empty() {
-        return error;
+// ACL entries that are owned by the original DB roles or grant privileges to them,
+// physical DB ACL also contains grants for other logical DBs and is kept
+fn is_acl_with_orig_roles(ctx: &TocCtx, te: &TocEntry) -> Result<bool, TocError> {
+    if "ACL" != te.description.to_string()? || te.tag.to_string()?.starts_with("DATABASE ") {
+        return Ok(false);
+    }
+    if ctx.owners.contains_key(&te.owner.to_string()?) {
+        return Ok(true);
+    }
+    let create_stmt = te.create_stmt.to_string()?;
+    Ok(rewrite_role_in_sql(&ctx.owners, &create_stmt)? != create_stmt)
+}
+
+fn is_acl_entry(te: &TocEntry) -> Result<bool, TocError> {
+    let description = te.description.to_string()?;
+    Ok("ACL" == description || "DEFAULT ACL" == description)
+}
+
+fn is_alter_owner_stmt(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with("ALTER ") && line.contains(" OWNER TO ") && line.ends_with(';')
+}
+
+fn strip_owner(te: &mut TocEntry) -> Result<(), TocError> {
+    te.owner = TocString::from_str("");
+    if te.create_stmt.opt.is_some() {
+        let create_stmt = te.create_stmt.to_string()?;
+        if create_stmt.lines().any(is_alter_owner_stmt) {
+            let stripped: String = create_stmt.split_inclusive('\n')
+                .filter(|line| !is_alter_owner_stmt(line))
+                .collect();
+            te.create_stmt = TocString::from_string(stripped);
+        }
     }
-    if dbname.trim() != dbname {
-        return error;
+    Ok(())
+}
+
+// removes entries with the specified dump IDs along with dependencies
+// on them and updates entry count in header
+fn remove_toc_entries(header: &mut TocHeader, entries: &mut Vec<TocEntry>, dump_ids: &[i32]) {
+    entries.retain(|te| !dump_ids.contains(&te.dump_id));
+    let dump_ids: Vec<String> = dump_ids.iter().map(|id| id.to_string()).collect();
+    for te in entries.iter_mut() {
+        te.deps.retain(|dep| !dump_ids.contains(&dep.to_string_lossy()));
     }
-    let first_char = dbname.chars().nth(0).ok_or(TocError::from_str("First char read error"))?;
-    if !((first_char >= 'a' && first_char <= 'z') || first_char == '_') {
-        return error;
+    header.toc_count = entries.len() as i32;
+}
+
+fn log_entry_changes<L: Fn(Level, &str)>(log: &L, te_orig: &TocEntry, te: &TocEntry) {
+    let mut changes = Vec::new();
+    for (name, orig, rewritten) in [
+        ("tag", &te_orig.tag, &te.tag),
+        ("namespace", &te_orig.namespace, &te.namespace),
+        ("owner", &te_orig.owner, &te.owner),
+    ] {
+        if orig != rewritten {
+            changes.push(format!("{} {} -> {}", name, orig, rewritten));
+        }
     }
-    for ch in dbname.chars() {
-        if !((ch >= 'a' && ch <= 'z') || (ch >= '0' && ch <= '9') || (ch == '_')) {
-            return error;
+    for (name, orig, rewritten) in [
+        ("create_stmt", &te_orig.create_stmt, &te.create_stmt),
+        ("drop_stmt", &te_orig.drop_stmt, &te.drop_stmt),
+        ("copy_stmt", &te_orig.copy_stmt, &te.copy_stmt),
+    ] {
+        if orig != rewritten {
+            changes.push(format!("{} rewritten", name));
         }
     }
-    if KEYWORDS.contains(&dbname) {
-        return error;
+    if !changes.is_empty() {
+        log(Level::Debug, &format!("entry {} {} {}", te.dump_id, te.description, changes.join(", ")));
+    }
+}
+
+fn validate_dbname(dbname: &str) -> Result<(), TocError> {
+    check_dbname(dbname).map_err(|_| TocError::InvalidDbName(dbname.to_string()))
+}
+
+// new schema and role names are quoted in SQL when necessary, so only the
+// restrictions of the server itself apply to them
+fn validate_identifier(orig_name: &str, name: &str) -> Result<(), TocError> {
+    if name.is_empty() || name.contains('\0') {
+        return Err(TocError::new(&format!("Invalid identifier specified: [{}]", name)));
+    }
+    if name.len() > MAX_IDENTIFIER_LEN {
+        return Err(TocError::NameTooLong { orig_name: orig_name.to_string(), name: name.to_string(), length: name.len() });
     }
     Ok(())
 }
 
+fn validate_dbname_with_options(dbname: &str, options: &RewriteOptions) -> Result<String, TocError> {
+    if options.allow_mixed_case {
+        check_dbname_relaxed(dbname).map_err(|_| TocError::InvalidDbName(dbname.to_string()))
+    } else {
+        validate_dbname(dbname)?;
+        Ok(dbname.to_string())
+    }
+}
+
+// schema-only dumps (pg_dump -s) have no data entries at all
+fn has_table_data(entries: &[TocEntry]) -> Result<bool, TocError> {
+    for te in entries {
+        if "TABLE DATA" == te.description.to_string()? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// catalogs data must be restored after babelfish_sysdatabases, catalog entries are moved
+// right after it keeping their relative order, other entries keep their relative order too
 fn reorder_babelfish_catalogs(entries: &mut Vec<TocEntry>) -> Result<(), TocError> {
-    let mut sysdatabases_idx = 0usize;
-    let mut extended_properties_idx = 0usize;
-    let mut function_ext_idx = 0usize;
-    let mut namespace_ext_idx = 0usize;
-    let mut view_def_idx = 0usize;
-    for idx in 0..entries.len() {
-        let te = &entries[idx];
+    let catalogs = [
+        "babelfish_extended_properties",
+        "babelfish_function_ext",
+        "babelfish_namespace_ext",
+        "babelfish_view_def",
+    ];
+    let mut sysdatabases_idx = None;
+    let mut catalog_indices = Vec::new();
+    for (idx, te) in entries.iter().enumerate() {
         if te.description.to_string()? == "TABLE DATA" {
             let tag = te.tag.to_string()?;
             if tag == "babelfish_sysdatabases" {
-                sysdatabases_idx = idx;
-            } else if tag == "babelfish_extended_properties" {
-                extended_properties_idx = idx;
-            } else if tag == "babelfish_function_ext" {
-                function_ext_idx = idx;
-            } else if tag == "babelfish_namespace_ext" {
-                namespace_ext_idx = idx;
-            } else if tag == "babelfish_view_def" {
-                view_def_idx = idx;
+                sysdatabases_idx = Some(idx);
+            } else if catalogs.contains(&tag.as_str()) {
+                catalog_indices.push(idx);
+            }
+        }
+    }
+    let sysdatabases_idx = match sysdatabases_idx {
+        Some(idx) => idx,
+        None => return Err(TocError::NotBabelfishDump(
+            "Invalid TOC, 'babelfish_sysdatabases' table data must be present".to_string()))
+    };
+
+    let mut reordered = Vec::with_capacity(entries.len());
+    for (idx, te) in entries.iter().enumerate() {
+        if catalog_indices.contains(&idx) {
+            continue;
+        }
+        reordered.push(te.clone());
+        if idx == sysdatabases_idx {
+            for catalog_idx in catalog_indices.iter() {
+                reordered.push(entries[*catalog_idx].clone());
             }
         }
     }
+    // entries order in source TOC is not checked, only the new violations are reported
+    let violations_before = dependency_order_violations(entries);
+    for violation in dependency_order_violations(&reordered) {
+        if !violations_before.contains(&violation) {
+            return Err(dependency_order_error(&violation));
+        }
+    }
+    *entries = reordered;
+    Ok(())
+}
+
+/// Resolves the path to `pg_dump` TOC file.
+///
+/// When the specified path is a directory (or a symlink to a directory),
+/// the path to `toc.dat` file inside it is returned, otherwise the path is
+/// returned unchanged.
+///
+/// # Arguments
+///
+/// * `path` - Path to `pg_dump` TOC file or to the dump directory containing it
+pub fn resolve_toc_path<P: AsRef<Path>>(path: P) -> Result<PathBuf, TocError> {
+    let path = path.as_ref();
+    if !path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+    let toc_path = path.join("toc.dat");
+    if !toc_path.is_file() {
+        return Err(TocError::new(&format!(
+            "TOC file 'toc.dat' not found in dump directory: {}", path.to_string_lossy())));
+    }
+    Ok(toc_path)
+}
+
+// symlinks are not resolved, backups and rewritten files are written next
+// to the specified TOC path, 'toc.dat' in current directory has empty parent
+fn dump_dir_path(toc_path: &Path) -> PathBuf {
+    match toc_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from(".")
+    }
+}
+
+// bytes after the last entry are read into trailer and must be passed back to 'write_toc'
+fn read_toc(toc_path: &Path) -> Result<Toc, TocError> {
+    read_toc_with_max_string_len(toc_path, DEFAULT_MAX_STRING_LEN)
+}
+
+fn read_toc_with_max_string_len(toc_path: &Path, max_string_len: usize) -> Result<Toc, TocError> {
+    let toc_file = File::open(toc_path)?;
+    let mut reader = TocReader::new(BufReader::new(toc_file))
+        .with_max_string_len(max_string_len);
+    let header = reader.read_header()?;
+    let mut entries = Vec::with_capacity(header.toc_count as usize);
+    for _ in 0..header.toc_count {
+        let te = reader.read_entry()?;
+        entries.push(te);
+    }
+    let trailer = reader.read_trailer()?;
+    Ok(Toc { header, entries, trailer })
+}
+
+fn write_toc(toc_path: &Path, header: &TocHeader, entries: &[TocEntry], trailer: &[u8]) -> Result<(), TocError> {
+    let dest_file = File::create(toc_path)?;
+    let mut writer = TocWriter::new(BufWriter::new(dest_file));
+    writer.write_toc_with_trailer(header, entries, trailer)?;
+    Ok(())
+}
 
-    if 0 == sysdatabases_idx {
-        return Err(TocError::from_str("Invalid TOC, 'babelfish_sysdatabases' table data must be present"));
+// only 'preserve_mtime' and 'durable' options are used
+// original TOC is restored with the other files registered in the specified restore
+fn write_toc_with_backup(toc_path: &Path, dir_path: &Path, header: &TocHeader, entries: &[TocEntry], trailer: &[u8],
+                         options: &RewriteOptions, restore: &BackupRestore) -> Result<(), TocError> {
+    let toc_dest_path = TempPath::new(toc_path)?;
+    write_toc(toc_dest_path.path(), header, entries, trailer)?;
+    if options.durable {
+        toc_dest_path.sync()?;
+    }
+    copy_file_metadata(toc_path, toc_dest_path.path(), options.preserve_mtime)?;
+    let toc_orig_path = dir_path.join("toc.dat.orig");
+    fs::rename(toc_path, &toc_orig_path)?;
+    restore.push(toc_orig_path, toc_path.to_path_buf());
+    toc_dest_path.persist(toc_path)?;
+    if options.durable {
+        sync_dir(dir_path)?;
     }
+    Ok(())
+}
 
-    let mut indices = vec!(
-        &mut extended_properties_idx,
-        &mut function_ext_idx,
-        &mut namespace_ext_idx,
-        &mut view_def_idx
-    );
+fn data_file_name(header: &TocHeader, filename: &str) -> String {
+    if header.compression > 0 {
+        format!("{}.gz", filename)
+    } else {
+        filename.to_string()
+    }
+}
 
-    // bubble sort variation
-    loop {
-        let mut swapped = false;
-        for i in 0..indices.len()  {
-            let idx = &mut indices[i];
-            if **idx > 0 && **idx < sysdatabases_idx {
-                entries.swap(**idx, sysdatabases_idx);
-                let tmp = **idx;
-                **idx = sysdatabases_idx;
-                sysdatabases_idx = tmp;
-                swapped = true;
+// data files of all entries and large objects files listed in blobs TOC
+fn referenced_data_files(header: &TocHeader, entries: &[TocEntry], dir_path: &Path) -> Result<Vec<String>, TocError> {
+    let mut res = Vec::new();
+    for te in entries {
+        let filename = te.filename.to_string()?;
+        if filename.is_empty() {
+            continue;
+        }
+        if "blobs.toc" == filename && dir_path.join(&filename).exists() {
+            for blob_filename in read_blobs_toc(dir_path.join(&filename))?.values() {
+                res.push(data_file_name(header, blob_filename));
             }
+            res.push(filename);
+        } else {
+            res.push(data_file_name(header, &filename));
         }
-        if !swapped {
-            break;
+    }
+    Ok(res)
+}
+
+fn is_gzip_file(path: &Path) -> Result<bool, TocError> {
+    let mut magic = Vec::with_capacity(2);
+    File::open(path)?.take(2).read_to_end(&mut magic)?;
+    Ok(magic == [0x1f, 0x8b])
+}
+
+fn prepare_output_dir(dest_dir_path: &Path) -> Result<(), TocError> {
+    if dest_dir_path.exists() {
+        if dest_dir_path.read_dir()?.next().is_some() {
+            return Err(TocError::new(&format!(
+                "Output directory is not empty: {}", dest_dir_path.to_string_lossy())));
+        }
+    } else {
+        fs::create_dir_all(dest_dir_path)?;
+    }
+    Ok(())
+}
+
+fn copy_data_file(src_path: &Path, dest_path: &Path, hard_link: bool) -> Result<(), TocError> {
+    if hard_link && fs::hard_link(src_path, dest_path).is_ok() {
+        return Ok(());
+    }
+    fs::copy(src_path, dest_path)?;
+    Ok(())
+}
+
+fn copy_data_files(ctx: &TocCtx, entries: &[TocEntry], dir_path: &Path, dest_dir_path: &Path,
+                   options: &RewriteOptions, summary: &mut RewriteSummary) -> Result<(), TocError> {
+    // both variants are listed, catalog file extension may not match the header
+    let rewritten: Vec<String> = match options.toc_only {
+        true => Vec::new(),
+        false => ctx.catalog_files.values()
+            .flat_map(|filename| [filename.clone(), format!("{}.gz", filename)])
+            .collect()
+    };
+    let mut to_copy: Vec<String> = referenced_data_files(&ctx.header, entries, dir_path)?.into_iter()
+        .filter(|filename| !rewritten.contains(filename))
+        .collect();
+
+    let mut dir_files = Vec::new();
+    for en in dir_path.read_dir()? {
+        dir_files.push(en?.file_name().to_string_lossy().to_string());
+    }
+    dir_files.sort();
+    for filename in dir_files {
+        if "toc.dat" == filename || is_service_file(&filename) || rewritten.contains(&filename) || to_copy.contains(&filename) {
+            continue;
         }
+        let msg = format!("file {} is not referenced in TOC", filename);
+        options.log(Level::Warn, &msg);
+        summary.warnings.push(msg);
+        if options.copy_unreferenced_files {
+            to_copy.push(filename.clone());
+        }
+        summary.unreferenced_files.push(filename);
     }
 
+    let total = to_copy.len();
+    for (idx, filename) in to_copy.iter().enumerate() {
+        options.check_cancelled()?;
+        let src_path = dir_path.join(filename);
+        if !src_path.is_file() {
+            return Err(TocError::new(&format!(
+                "Data file not found: {}", src_path.to_string_lossy())));
+        }
+        let dest_path = dest_dir_path.join(filename);
+        copy_data_file(&src_path, &dest_path, options.hard_link_data_files)?;
+        if options.durable {
+            sync_file(&dest_path)?;
+        }
+        options.progress(ProgressEvent::CopyingFiles { processed: idx + 1, total });
+    }
     Ok(())
 }
 
-fn longest_common_prefix(strs: &Vec<String>) -> String {
+fn longest_common_prefix(strs: &[String]) -> String {
     if strs.is_empty() {
         return String::new();
     }
@@ -437,126 +1177,1521 @@ fn find_out_orig_dbname(entries: &Vec<TocEntry>) -> Result<String, TocError> {
         }
     }
 
-    let dbname_with_underscore = longest_common_prefix(&schemas);
-    if dbname_with_underscore.len() < 2 || !dbname_with_underscore.ends_with("_") {
-        return Err(TocError::from_str(&format!("Cannot determine original DB name, TOC schemas: {}", schemas.join(", "))));
+    // every Babelfish DB has the 'dbo' schema, it is not necessarily the first one in TOC
+    // and custom schema names can end with '_dbo' too, so the shortest DB name is taken
+    let dbo_dbname = schemas.iter()
+        .filter_map(|schema| schema.strip_suffix("_dbo"))
+        .filter(|dbname| !dbname.is_empty())
+        .min_by_key(|dbname| dbname.len());
+    if let Some(dbname) = dbo_dbname {
+        return Ok(dbname.to_string());
     }
 
-    let dbname = dbname_with_underscore.chars().take(dbname_with_underscore.len() - 1).collect();
-    Ok(dbname)
+    let dbname_with_underscore = longest_common_prefix(&schemas);
+    match dbname_with_underscore.strip_suffix('_') {
+        Some(dbname) if !dbname.is_empty() => Ok(dbname.to_string()),
+        _ => Err(TocError::NotBabelfishDump(format!(
+            "Cannot determine original DB name, TOC schemas: {}", schemas.join(", "))))
+    }
 }
 
 /// Reads `pg_dump` TOC as a JSON string.
 ///
 /// TOC file `toc.dat` is created by `pg_dump` when it is run with directory format (`-Z d` flag).
 ///
+/// Bytes that follow the last entry (if any) are stored as hex bytes in the `trailer` array.
+///
+/// Entries are converted to JSON on multiple threads when `parallel` feature is enabled.
+///
 /// # Arguments
 ///
-/// * `toc_path` - Path to `pg_dump` TOC file
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
 pub fn read_toc_to_json<P: AsRef<Path>>(toc_path: P) -> Result<String, TocError> {
-    let toc_file = File::open(toc_path)?;
-    let mut reader = TocReader::new(BufReader::new(toc_file));
-    let header = reader.read_header()?;
-    let mut entries = Vec::with_capacity(header.toc_count as usize);
-    for _ in 0..header.toc_count {
-        let te = reader.read_entry()?;
-        entries.push(te.to_json()?);
-    }
-    let tj = TocJson { header: header.to_json()?, entries };
-    let res = serde_json::to_string_pretty(&tj)?;
-    Ok(res)
+    read_toc_to_json_internal(BufReader::new(File::open(resolve_toc_path(toc_path)?)?), false)
 }
 
-/// Writes `pg_dump` TOC from a JSON string.
+/// Reads `pg_dump` TOC as a JSON string checking that entries count in TOC header is correct.
 ///
-/// JSON string can be generated with `read_toc_json`.
+/// Same as `read_toc_to_json`, but fails if TOC file contains any data after
+/// the number of entries specified in TOC header.
 ///
 /// # Arguments
 ///
-/// * `toc_path` - Path to destination TOC file
-/// * `toc_json` - JSON string
-pub fn write_toc_from_json<P: AsRef<Path>>(toc_path: P, toc_json: &str) -> Result<(), TocError> {
-    if toc_path.as_ref().exists() {
-        return Err(TocError::new(&format!("TOC file already exists on path: {}", toc_path.as_ref().to_string_lossy())));
-    }
-    let tj: TocJson = serde_json::from_str(toc_json)?;
-    let toc_file = File::create(toc_path)?;
-    let mut writer = TocWriter::new(BufWriter::new(toc_file));
-    let header = TocHeader::from_json(&tj.header)?;
-    writer.write_header(&header)?;
-    for ej in tj.entries {
-        let te = TocEntry::from_json(&ej)?;
-        writer.write_toc_entry(&te)?;
-    }
-    Ok(())
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+pub fn read_toc_to_json_strict<P: AsRef<Path>>(toc_path: P) -> Result<String, TocError> {
+    read_toc_to_json_internal(BufReader::new(File::open(resolve_toc_path(toc_path)?)?), true)
 }
 
-/// Prints `pg_dump` TOC contents to the specified writer.
+/// Reads `pg_dump` TOC as a JSON string from the specified reader.
 ///
-/// TOC file `toc.dat` is created by `pg_dump` when it is run with directory format (`-Z d` flag).
+/// Same as `read_toc_to_json`, but TOC contents are read from a stream (for example, from stdin).
 ///
 /// # Arguments
 ///
-/// * `toc_path` - Path to `pg_dump` TOC file
-/// * `writer` - Destination writer.
-pub fn print_toc<P: AsRef<Path>, W: Write>(toc_path: P, writer: &mut W) -> Result<(), TocError> {
-    let toc_file = File::open(toc_path)?;
-    let mut reader = TocReader::new(BufReader::new(toc_file));
-    let header = reader.read_header()?;
-    write!(writer, "{}", header)?;
-    for i in 0..header.toc_count {
-        let te = reader.read_entry()?;
-        writeln!(writer, "Entry: {}", i + 1)?;
-        writeln!(writer, "{}", te)?;
-    }
-    Ok(())
+/// * `reader` - Source of TOC data
+pub fn read_toc_to_json_from_reader<R: Read>(reader: R) -> Result<String, TocError> {
+    read_toc_to_json_internal(BufReader::new(reader), false)
 }
 
-/// Rewrites `pg_dump` TOC and catalogs contents with the specified DB name.
-///
-/// TOC file `toc.dat` is created by `pg_dump` when it is run with directory format (`-Z d` flag).
-///
-/// # Arguments
-///
-/// * `toc_path` - Path to `pg_dump` TOC file
-/// * `dbname` - New name for logical database.
-pub fn rewrite_toc<P: AsRef<Path>>(toc_path: P, dbname: &str) -> Result<(), TocError> {
-    check_dbname(dbname)?;
-    let toc_src_path = toc_path.as_ref();
-    let dir_path = match toc_src_path.canonicalize()?.parent() {
-        Some(parent) => parent.to_path_buf(),
-        None => return Err(TocError::from_str("Error accessing dump directory"))
+fn read_toc_to_json_internal<R: Read>(source: R, strict: bool) -> Result<String, TocError> {
+    let mut reader = TocReader::new(source);
+    let header = reader.read_header()?;
+    let entries = read_entries_to_json(&mut reader, header.toc_count)?;
+    let trailer = if strict {
+        let remaining = reader.count_remaining_bytes()?;
+        if remaining > 0 {
+            return Err(TocError::new(&format!(
+                "TOC entries count mismatch, header toc_count: {}, bytes left after the last entry: {}",
+                header.toc_count, remaining)));
+        }
+        Vec::new()
+    } else {
+        reader.read_trailer()?
     };
-    let toc_dest_path = dir_path.join("toc_rewritten.dat");
-    let toc_src = File::open(&toc_src_path)?;
-    let mut reader = TocReader::new(BufReader::new(toc_src));
-    let dest_file = File::create(&toc_dest_path)?;
-    let mut writer = TocWriter::new(BufWriter::new(dest_file));
+    let tj = TocJson {
+        header: header.to_json()?,
+        entries,
+        trailer: trailer.iter().map(|byte| format!("{:02x}", byte)).collect()
+    };
+    let res = serde_json::to_string_pretty(&tj)?;
+    Ok(res)
+}
+
+// entries are converted after the whole TOC is read, order is preserved
+#[cfg(feature = "parallel")]
+fn read_entries_to_json<R: Read>(reader: &mut TocReader<R>, toc_count: i32) -> Result<Vec<TocEntryJson>, TocError> {
+    use rayon::prelude::*;
+    let mut entries = Vec::with_capacity(toc_count.max(0) as usize);
+    for _ in 0..toc_count {
+        entries.push(reader.read_entry()?);
+    }
+    entries.par_iter().map(|te| te.to_json()).collect()
+}
+
+// every entry is converted as soon as it is read
+#[cfg(not(feature = "parallel"))]
+fn read_entries_to_json<R: Read>(reader: &mut TocReader<R>, toc_count: i32) -> Result<Vec<TocEntryJson>, TocError> {
+    let mut entries = Vec::with_capacity(toc_count.max(0) as usize);
+    for _ in 0..toc_count {
+        entries.push(reader.read_entry()?.to_json()?);
+    }
+    Ok(entries)
+}
 
+/// Reads `pg_dump` TOC file into a JSON string with byte offsets of entries.
+///
+/// Debug variant of `read_toc_to_json`, every entry additionally has `offset`
+/// and `length` fields with the position of its bytes in TOC file. Such
+/// JSON cannot be passed to `write_toc_from_json`.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to TOC file or to the dump directory containing it
+pub fn read_toc_to_json_with_offsets<P: AsRef<Path>>(toc_path: P) -> Result<String, TocError> {
+    let toc_file = File::open(resolve_toc_path(toc_path)?)?;
+    let mut reader = TocReader::new(BufReader::new(toc_file));
+    let (header, toc_entries) = reader.build_index()?;
+    let offsets = reader.entry_offsets();
+    let mut entries = Vec::with_capacity(toc_entries.len());
+    for (idx, te) in toc_entries.iter().enumerate() {
+        let end = offsets.get(idx + 1).copied().unwrap_or(reader.end_offset());
+        entries.push(TocEntryDebugJson {
+            offset: offsets[idx],
+            length: end - offsets[idx],
+            entry: te.to_json()?
+        });
+    }
+    let tj = TocDebugJson { header: header.to_json()?, entries };
+    let res = serde_json::to_string_pretty(&tj)?;
+    Ok(res)
+}
+
+/// Writes `pg_dump` TOC from a JSON string.
+///
+/// JSON string can be generated with `read_toc_json`.
+///
+/// `toc_count` field from JSON header is ignored, entries count written
+/// into TOC header is always taken from the `entries` array.
+///
+/// `null` and empty string values are written differently (as string lengths `-1` and `0`),
+/// `deps` arrays cannot contain `null` values.
+///
+/// JSON structure is checked before anything is written, all fields must be present
+/// (string fields can be `null`), `magic`, `version` and `flags` arrays must contain
+/// 5, 3 and 3 hex bytes, `magic` must be `PGDMP`, optional `trailer` array is written after
+/// the last entry. `InvalidJson` error specifies the problem field.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to destination TOC file
+/// * `toc_json` - JSON string
+pub fn write_toc_from_json<P: AsRef<Path>>(toc_path: P, toc_json: &str) -> Result<(), TocError> {
+    write_toc_from_json_internal(toc_path.as_ref(), toc_json, false)
+}
+
+/// Writes `pg_dump` TOC from a JSON string with any magic bytes in the header.
+///
+/// Same as `write_toc_from_json`, but `magic` field of the header is not required to be `PGDMP`,
+/// such TOC cannot be read by `pg_restore` and by this library, this can be used to create
+/// test files.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to destination TOC file
+/// * `toc_json` - JSON string
+pub fn write_toc_from_json_allow_nonstandard_header<P: AsRef<Path>>(toc_path: P, toc_json: &str) -> Result<(), TocError> {
+    write_toc_from_json_internal(toc_path.as_ref(), toc_json, true)
+}
+
+fn write_toc_from_json_internal(toc_path: &Path, toc_json: &str, allow_nonstandard_header: bool) -> Result<(), TocError> {
+    if toc_path.exists() {
+        return Err(TocError::new(&format!("TOC file already exists on path: {}", toc_path.to_string_lossy())));
+    }
+    let json: serde_json::Value = serde_json::from_str(toc_json)?;
+    check_toc_json(&json)?;
+    let tj: TocJson = serde_json::from_value(json)?;
+    let header = TocHeader::from_json(&tj.header)?;
+    if !allow_nonstandard_header && b"PGDMP" != header.magic.as_slice() {
+        let found: Vec<String> = header.magic.iter().map(|byte| format!("{:02x}", byte)).collect();
+        return Err(TocError::InvalidJson {
+            field: "header.magic".to_string(),
+            message: format!("expected 'PGDMP' (50 47 44 4d 50), found: {}", found.join(" "))
+        });
+    }
+    let mut entries = Vec::with_capacity(tj.entries.len());
+    for ej in tj.entries.iter() {
+        entries.push(TocEntry::from_json(ej)?);
+    }
+    let trailer = bytes_from_hex(&tj.trailer, "trailer", None)?;
+    write_toc(toc_path, &header, &entries, &trailer)
+}
+
+/// Prints `pg_dump` TOC contents to the specified writer.
+///
+/// TOC file `toc.dat` is created by `pg_dump` when it is run with directory format (`-Z d` flag).
+///
+/// Number of large objects listed in `blobs.toc` is printed after the header
+/// when the dump contains them.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `writer` - Destination writer.
+pub fn print_toc<P: AsRef<Path>, W: Write>(toc_path: P, writer: &mut W) -> Result<(), TocError> {
+    let toc_src_path = &resolve_toc_path(toc_path)?;
+    let dir_path = dump_dir_path(toc_src_path);
+    let Toc { header, entries, .. } = read_toc(toc_src_path)?;
+    print_toc_internal(&header, &entries, Some(&dir_path), writer)
+}
+
+/// Writes `CREATE` statements of all TOC entries as an SQL script.
+///
+/// Entries are written in restore order: pre-data, data and post-data sections
+/// with `ACL` entries last, TOC order (that `pg_dump` sorts by dependencies) is kept
+/// within every section. Each statement is preceded by a comment with the name, type,
+/// schema and owner of the object like in `pg_restore -f` output. Entries with `null`
+/// or empty `create_stmt` (like `TABLE DATA`) are skipped.
+///
+/// The script is intended for reviewing the DDL, nothing is written into the dump directory.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `writer` - Destination writer.
+pub fn dump_create_script<P: AsRef<Path>, W: Write>(toc_path: P, writer: &mut W) -> Result<(), TocError> {
+    let Toc { entries, .. } = read_toc(&resolve_toc_path(toc_path)?)?;
+    write_create_script(&entries, writer)
+}
+
+/// Writes `CREATE` statements of all TOC entries as an SQL script after changing the DB name.
+///
+/// Same as `dump_create_script`, but the statements are rewritten the same way as
+/// `rewrite_toc` would rewrite them for the specified DB name.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `dbname` - New Babelfish logical DB name
+/// * `writer` - Destination writer.
+pub fn dump_create_script_with_dbname<P: AsRef<Path>, W: Write>(toc_path: P, dbname: &str, writer: &mut W) -> Result<(), TocError> {
+    dump_create_script_with_options(toc_path, dbname, &RewriteOptions::default(), writer)
+}
+
+/// Writes `CREATE` statements of all TOC entries as an SQL script after changing the DB name
+/// with the specified options.
+///
+/// Same as `dump_create_script_with_dbname`, but the statements are rewritten the same way as
+/// `rewrite_toc_with_options` would rewrite them, including `schema_map`, `owner_map`,
+/// `suffix_transform` and `tablespaces` options. Options that affect only the written files
+/// are ignored.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `dbname` - New Babelfish logical DB name
+/// * `options` - Rewrite options.
+/// * `writer` - Destination writer.
+pub fn dump_create_script_with_options<P: AsRef<Path>, W: Write>(toc_path: P, dbname: &str, options: &RewriteOptions, writer: &mut W) -> Result<(), TocError> {
+    let dbname = &validate_dbname_with_options(dbname, options)?;
+    let Toc { header, mut entries, .. } = read_toc(&resolve_toc_path(toc_path)?)?;
+    let maps = checked_rename_maps(&entries, dbname, options)?;
+    let mut ctx = rewrite_ctx(header, maps, dbname, options);
+    for te in entries.iter_mut() {
+        replace_in_toc_entry(&mut ctx, te)?;
+    }
+    write_create_script(&entries, writer)
+}
+
+// entries outside of sections are either session settings (first in TOC) or ACLs
+fn create_script_order(te: &TocEntry) -> Result<u8, TocError> {
+    let order = match TocSection::from_i32(te.section) {
+        Some(TocSection::PreData) => 1,
+        Some(TocSection::Data) => 2,
+        Some(TocSection::PostData) => 3,
+        _ if is_acl_entry(te)? => 4,
+        _ => 0
+    };
+    Ok(order)
+}
+
+fn write_create_script<W: Write>(entries: &[TocEntry], writer: &mut W) -> Result<(), TocError> {
+    let mut ordered = Vec::with_capacity(entries.len());
+    for te in entries.iter() {
+        let create_stmt = te.create_stmt.to_string()?;
+        if !create_stmt.is_empty() {
+            ordered.push((create_script_order(te)?, te, create_stmt));
+        }
+    }
+    // sort is stable, dependency order within the section is kept
+    ordered.sort_by_key(|(order, _, _)| *order);
+    let or_dash = |ts: &TocString| -> Result<String, TocError> {
+        Ok(ts.to_string_opt()?.filter(|st| !st.is_empty()).unwrap_or_else(|| "-".to_string()))
+    };
+    for (_, te, create_stmt) in ordered {
+        writeln!(writer, "--")?;
+        writeln!(writer, "-- Name: {}; Type: {}; Schema: {}; Owner: {}",
+            te.tag, te.description, or_dash(&te.namespace)?, or_dash(&te.owner)?)?;
+        writeln!(writer, "--")?;
+        writeln!(writer)?;
+        writeln!(writer, "{}", create_stmt.trim_end())?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Prints `pg_dump` TOC contents read from the specified reader.
+///
+/// Same as `print_toc`, but TOC contents are read from a stream (for example, from stdin).
+/// Number of large objects is not printed because `blobs.toc` file cannot be located.
+///
+/// # Arguments
+///
+/// * `reader` - Source of TOC data
+/// * `writer` - Destination writer.
+pub fn print_toc_from_reader<R: Read, W: Write>(reader: R, writer: &mut W) -> Result<(), TocError> {
+    let mut reader = TocReader::new(BufReader::new(reader));
     let header = reader.read_header()?;
-    let mut entries = Vec::with_capacity(header.toc_count as usize);
+    let mut entries = Vec::with_capacity(header.toc_count.max(0) as usize);
     for _ in 0..header.toc_count {
-        let te  = reader.read_entry()?;
-        entries.push(te);
+        entries.push(reader.read_entry()?);
     }
+    print_toc_internal(&header, &entries, None, writer)
+}
 
-    reorder_babelfish_catalogs(&mut entries)?;
+fn print_toc_internal<W: Write>(header: &TocHeader, entries: &[TocEntry], dir_path: Option<&Path>, writer: &mut W) -> Result<(), TocError> {
+    write!(writer, "{}", header)?;
+    for te in entries.iter() {
+        if let Some(dir_path) = dir_path {
+            if "BLOBS" == te.description.to_string()? {
+                let blobs_toc_path = dir_path.join(te.filename.to_string()?);
+                if blobs_toc_path.is_file() {
+                    writeln!(writer, "Large objects: {}", read_blobs_toc(&blobs_toc_path)?.len())?;
+                }
+            }
+        }
+    }
+    for (i, te) in entries.iter().enumerate() {
+        writeln!(writer, "Entry: {}", i + 1)?;
+        writeln!(writer, "{}", te)?;
+    }
+    Ok(())
+}
 
-    writer.write_header(&header)?;
-    let orig_dbname = find_out_orig_dbname(&entries)?;
-    let mut ctx = TocCtx::new(header, &orig_dbname, &dbname);
-    // _dbo owner may not be present if custom schemas are not used
-    ctx.owners.insert(format!("{}_dbo", &orig_dbname), format!("{}_dbo", &dbname));
-    for mut te in entries {
-        modify_toc_entry(&mut ctx, &mut te)?;
-        writer.write_toc_entry(&te)?;
+/// Rewrites `pg_dump` TOC and catalogs contents with the specified DB name.
+///
+/// TOC file `toc.dat` is created by `pg_dump` when it is run with directory format (`-Z d` flag).
+///
+/// Babelfish catalog files are re-compressed using the compression level detected from
+/// the gzip header of the source file (when it indicates the fastest or the best compression),
+/// or the compression level specified in TOC header otherwise. Gzip header fields (modification
+/// time, OS, file name) are preserved. Decompressed contents of these files are preserved (except
+/// the renamed values), but compressed bytes can differ from the ones written by `pg_dump`.
+/// Leading UTF-8 BOM in catalog files is ignored when matching the values and is written
+/// back to the rewritten file.
+///
+/// If TOC header compression does not match the catalog file on disk (only `<file>.gz` exists
+/// while header specifies no compression, or only `<file>` exists while header specifies
+/// compression), the file is read and written as it is found on disk and a warning is added.
+///
+/// If the dump already uses the specified DB name (for example, it was already rewritten),
+/// nothing is written and the existing backups of the original files are kept.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `dbname` - New name for logical database.
+pub fn rewrite_toc<P: AsRef<Path>>(toc_path: P, dbname: &str) -> Result<(), TocError> {
+    rewrite_toc_with_options(toc_path, dbname, &RewriteOptions::default())?;
+    Ok(())
+}
+
+/// Rewrites `pg_dump` TOC and catalogs contents with the specified DB name reporting the changes.
+///
+/// Same as `rewrite_toc`, but also calls the specified log function with a single-line
+/// message for every modified TOC entry (for example:
+/// `entry 1234 SCHEMA tag test1_dbo -> foobar_dbo, owner test1_dbo -> foobar_dbo`)
+/// and for every rewritten Babelfish catalog file.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `dbname` - New name for logical database.
+/// * `log` - Function that receives log messages.
+pub fn rewrite_toc_with_log<P: AsRef<Path>, L: Fn(&str)>(toc_path: P, dbname: &str, log: L) -> Result<(), TocError> {
+    let options = RewriteOptions {
+        log: Some(&log),
+        ..Default::default()
+    };
+    rewrite_toc_with_options(toc_path, dbname, &options)?;
+    Ok(())
+}
+
+/// Rewrites `pg_dump` TOC and catalogs contents with the specified DB name skipping
+/// the missing Babelfish catalogs.
+///
+/// Same as `rewrite_toc_with_log`, missing Babelfish catalogs are now always skipped
+/// with a warning.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `dbname` - New name for logical database.
+/// * `log` - Function that receives log messages.
+#[deprecated(since = "1.0.7", note = "use `rewrite_toc_with_options`, missing catalogs are skipped by default")]
+pub fn rewrite_toc_skip_missing_catalogs<P: AsRef<Path>, L: Fn(&str)>(toc_path: P, dbname: &str, log: L) -> Result<RewriteSummary, TocError> {
+    let options = RewriteOptions {
+        log: Some(&log),
+        ..Default::default()
+    };
+    rewrite_toc_with_options(toc_path, dbname, &options)
+}
+
+/// Rewrites `pg_dump` TOC and catalogs contents with the specified DB name re-compressing
+/// Babelfish catalog files with the specified compression level.
+///
+/// Same as `rewrite_toc_with_options` with `compression_level` and `log` options.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `dbname` - New name for logical database.
+/// * `compression_level` - Compression level from `0` to `9`.
+/// * `log` - Function that receives log messages.
+#[deprecated(since = "1.0.7", note = "use `rewrite_toc_with_options` with `compression_level` option")]
+pub fn rewrite_toc_with_compression_level<P: AsRef<Path>, L: Fn(&str)>(toc_path: P, dbname: &str, compression_level: u32, log: L) -> Result<(), TocError> {
+    let options = RewriteOptions {
+        compression_level: Some(compression_level),
+        log: Some(&log),
+        ..Default::default()
+    };
+    rewrite_toc_with_options(toc_path, dbname, &options)?;
+    Ok(())
+}
+
+/// Rewrites `pg_dump` TOC and catalogs contents with the specified DB name and options.
+///
+/// Same as `rewrite_toc`, but the rewrite behaviour can be adjusted with the specified options.
+///
+/// Schema-only dumps (without any `TABLE DATA` entries) are supported, only TOC entries
+/// are rewritten for them and a warning is added to the returned summary.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `dbname` - New name for logical database.
+/// * `options` - Rewrite options.
+pub fn rewrite_toc_with_options<P: AsRef<Path>>(toc_path: P, dbname: &str, options: &RewriteOptions) -> Result<RewriteSummary, TocError> {
+    let dbname = &validate_dbname_with_options(dbname, options)?;
+    if let Some(level) = options.compression_level {
+        if level > 9 {
+            return Err(TocError::new(&format!("Invalid compression level specified: {}", level)));
+        }
+    }
+    if options.catalog_checksums && !cfg!(feature = "manifest") {
+        return Err(TocError::new(&"Catalog checksums require 'manifest' feature to be enabled"));
+    }
+    if options.write_new_files && options.output_dir.is_some() {
+        return Err(TocError::new(&"Output directory cannot be used together with writing '.new' files"));
+    }
+    let log = |level: Level, msg: &str| options.log(level, msg);
+    let toc_src_path = &if options.follow_symlinks {
+        resolve_toc_path(toc_path)?.canonicalize()?
+    } else {
+        resolve_toc_path(toc_path)?
+    };
+    let dir_path = dump_dir_path(toc_src_path);
+    // source directory is only read when writing into the output directory,
+    // that is locked instead once it is prepared
+    let mut _lock = match options.output_dir {
+        Some(_) => None,
+        None => Some(DumpLock::acquire(&dir_path)?)
+    };
+    if options.verify_roundtrip {
+        verify_roundtrip(toc_src_path)?;
     }
+    let max_string_len = options.max_string_len.unwrap_or(DEFAULT_MAX_STRING_LEN);
+    let Toc { header, mut entries, trailer } = read_toc_with_max_string_len(toc_src_path, max_string_len)?;
 
-    rewrite_babelfish_catalogs(&ctx, dir_path.as_path())?;
+    if options.sort_by_dump_id {
+        sort_entries_by_dump_id(&mut entries)?;
+    }
+    let schema_only = !has_table_data(&entries)?;
+    if !schema_only {
+        reorder_babelfish_catalogs(&mut entries)?;
+    }
 
-    let toc_orig_path = dir_path.join("toc.dat.orig");
-    fs::rename(&toc_src_path, &toc_orig_path)?;
-    fs::rename(&toc_dest_path, &toc_src_path)?;
+    let mut maps = collect_rename_maps(&entries, dbname, options.suffix_transform)?;
+    apply_rename_overrides(&mut maps, options)?;
+    let has_overrides = options.schema_map.is_some() || options.owner_map.is_some() ||
+        options.suffix_transform.is_some();
+    let mut summary = RewriteSummary::default();
+    // output directory and new files are still written as requested
+    let in_place = options.output_dir.is_none() && !options.write_new_files;
+    if in_place && &maps.orig_dbname == dbname && !has_overrides && !options.force {
+        let msg = format!("dump already uses DB name '{}', no change needed", dbname);
+        log(Level::Warn, &msg);
+        summary.warnings.push(msg);
+        summary.no_change_needed = true;
+        return Ok(summary);
+    }
+    // running the in-place rewrite again would replace the backups of the original files
+    if in_place {
+        if let Some(backup_path) = find_backup_file(&dir_path)? {
+            return Err(TocError::new(&format!(
+                "Backup file already exists: {}, revert the previous rewrite with 'undo_rewrite', \
+                delete backups with 'clean_backups' or write the rewritten dump into an output directory \
+                or into '.new' files", backup_path.to_string_lossy())));
+        }
+    }
+    for err in find_too_long_names(&maps) {
+        if !options.allow_long_names {
+            return Err(err);
+        }
+        let msg = err.to_string();
+        log(Level::Warn, &msg);
+        summary.warnings.push(msg);
+    }
+    log(Level::Info, &format!("rewriting DB name {} -> {}", maps.orig_dbname, dbname));
+    let mut ctx = rewrite_ctx(header, maps, dbname, options);
+    if let Some(postgres_dbname) = options.postgres_dbname {
+        log(Level::Debug, &format!("header postgres_dbname {} -> {}", ctx.header.postgres_dbname, postgres_dbname));
+        ctx.header.postgres_dbname = TocString::from_str(postgres_dbname);
+    }
+    if options.touch_timestamp {
+        let now = Local::now().naive_local();
+        log(Level::Debug, &format!("header timestamp {} -> {}", ctx.header.timestamp, now.format("%Y-%m-%d %H:%M:%S")));
+        // DST is left unknown, pg_restore lets mktime determine it
+        ctx.header.timestamp = TocDateTime::from_naive_date_time(&now, None);
+    }
+    if let Some(version) = options.target_version {
+        log(Level::Debug, &format!("header version {} -> {}", ArchiveVersion::of_header(&ctx.header), version));
+        version.apply(&mut ctx.header, &mut entries)?;
+    }
+    if let Some(output_dir) = options.output_dir {
+        prepare_output_dir(output_dir)?;
+        _lock = Some(DumpLock::acquire(output_dir)?);
+        ctx.dest_dir_path = Some(output_dir.to_path_buf());
+    }
+    ctx.new_files = options.write_new_files;
+    // catalogs replaced in place are restored if the rewrite fails or is cancelled
+    let restore = BackupRestore::default();
+    let total = entries.len();
+    for (idx, te) in entries.iter_mut().enumerate() {
+        options.check_cancelled()?;
+        if (options.drop_acls && is_acl_entry(te)?) ||
+            (options.strip_owners && is_acl_with_orig_roles(&ctx, te)?) {
+            log(Level::Debug, &format!("entry {} {} {} removed", te.dump_id, te.description, te.tag));
+            summary.removed_entries.push(te.dump_id);
+        } else {
+            let te_orig = te.clone();
+            replace_in_toc_entry(&mut ctx, te)?;
+            if options.strip_owners {
+                strip_owner(te)?;
+            }
+            log_entry_changes(&log, &te_orig, te);
+        }
+        options.progress(ProgressEvent::TocEntries { processed: idx + 1, total });
+    }
+    if !summary.removed_entries.is_empty() {
+        remove_toc_entries(&mut ctx.header, &mut entries, &summary.removed_entries);
+    }
+
+    if options.toc_only {
+        log(Level::Info, "only TOC is rewritten, Babelfish catalogs are left as is");
+    } else if schema_only {
+        let msg = "dump contains no table data, Babelfish catalogs were not rewritten".to_string();
+        log(Level::Warn, &msg);
+        summary.warnings.push(msg);
+    } else {
+        let conflicts = find_name_collisions(&ctx, dir_path.as_path())?;
+        if !conflicts.is_empty() && !options.allow_name_collisions {
+            return Err(TocError::NameCollision(conflicts));
+        }
+        for conflict in conflicts {
+            let msg = format!("new name already exists in the dump, {}", conflict);
+            log(Level::Warn, &msg);
+            summary.warnings.push(msg);
+        }
+        rewrite_babelfish_catalogs(&ctx, dir_path.as_path(), options, &mut summary, &restore)?;
+    }
+    for msg in ctx.oversized_sql_warnings.take() {
+        log(Level::Warn, &msg);
+        summary.warnings.push(msg);
+    }
+
+    options.check_cancelled()?;
+    match options.output_dir {
+        Some(output_dir) => {
+            copy_data_files(&ctx, &entries, dir_path.as_path(), output_dir, options, &mut summary)?;
+            let toc_dest_path = output_dir.join("toc.dat");
+            write_toc(&toc_dest_path, &ctx.header, &entries, &trailer)?;
+            if options.durable {
+                sync_file(&toc_dest_path)?;
+            }
+            copy_file_metadata(toc_src_path, &toc_dest_path, options.preserve_mtime)?;
+            if options.durable {
+                sync_dir(output_dir)?;
+            }
+        },
+        None if options.write_new_files => {
+            // replaces the read-only file left by the previous run
+            let toc_new_path = dir_path.join("toc.dat.new");
+            let toc_dest_path = TempPath::new(&toc_new_path)?;
+            write_toc(toc_dest_path.path(), &ctx.header, &entries, &trailer)?;
+            if options.durable {
+                toc_dest_path.sync()?;
+            }
+            copy_file_metadata(toc_src_path, toc_dest_path.path(), options.preserve_mtime)?;
+            toc_dest_path.persist(&toc_new_path)?;
+            if options.durable {
+                sync_dir(&dir_path)?;
+            }
+        },
+        None => {
+            options.progress(ProgressEvent::RenamingFiles);
+            write_toc_with_backup(toc_src_path, dir_path.as_path(), &ctx.header, &entries, &trailer, options, &restore)?;
+        }
+    }
+    restore.complete();
+
+    Ok(summary)
+}
+
+/// Collects schema and role renames that `rewrite_toc` performs for the specified DB name.
+///
+/// Nothing is written, dump directory is left as is. Fails with `TocError::NameTooLong`
+/// if any of the new names is longer than `MAX_IDENTIFIER_LEN` bytes.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `dbname` - New name for logical database.
+pub fn derive_rename_maps<P: AsRef<Path>>(toc_path: P, dbname: &str) -> Result<RenameMaps, TocError> {
+    derive_rename_maps_with_options(toc_path, dbname, &RewriteOptions::default())
+}
 
+/// Collects schema and role renames that `rewrite_toc_with_options` performs for the specified
+/// DB name and options.
+///
+/// Same as `derive_rename_maps`, but `schema_map` and `owner_map` overrides from the options
+/// are merged into the derived renames, and the DB name is checked according to
+/// `allow_mixed_case` option. Long names do not fail the call when `allow_long_names` is set.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `dbname` - New name for logical database.
+/// * `options` - Rewrite options.
+pub fn derive_rename_maps_with_options<P: AsRef<Path>>(toc_path: P, dbname: &str, options: &RewriteOptions) -> Result<RenameMaps, TocError> {
+    let dbname = &validate_dbname_with_options(dbname, options)?;
+    let Toc { entries, .. } = read_toc(&resolve_toc_path(toc_path)?)?;
+    checked_rename_maps(&entries, dbname, options)
+}
+
+/// Writes a copy of `pg_dump` dump with TOC and catalogs contents rewritten with the specified DB name.
+///
+/// Same as `rewrite_toc`, but rewritten TOC and catalogs are written into the specified
+/// directory, all other data files referenced by TOC are copied there. Files in the source
+/// dump directory are never modified. Files that are not referenced by TOC are reported
+/// in the summary and are not copied.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `dest_dir` - Output directory, it is created if it does not exist and must be empty if it does.
+/// * `dbname` - New name for logical database.
+pub fn rewrite_toc_to_dir<P: AsRef<Path>, D: AsRef<Path>>(toc_path: P, dest_dir: D, dbname: &str) -> Result<RewriteSummary, TocError> {
+    let options = RewriteOptions {
+        output_dir: Some(dest_dir.as_ref()),
+        ..Default::default()
+    };
+    rewrite_toc_with_options(toc_path, dbname, &options)
+}
+
+/// Checks that `pg_dump` dump directory is consistent with its TOC.
+///
+/// Every data file referenced by TOC entries (including large objects files listed
+/// in `blobs.toc`) must exist in dump directory, every table data file (like `1234.dat`
+/// or `1234.dat.gz`) in dump directory must be referenced by exactly one entry and, when
+/// compression is enabled in TOC header, data files must be gzip files. Backups, temporary
+/// and `.new` files (see `list_dump_files`) are not reported. Problems found are returned
+/// in the report.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+pub fn check_dump_dir<P: AsRef<Path>>(toc_path: P) -> Result<DumpDirReport, TocError> {
+    let toc_src_path = &resolve_toc_path(toc_path)?;
+    let dir_path = dump_dir_path(toc_src_path);
+    let Toc { header, entries, .. } = read_toc(toc_src_path)?;
+    let mut report = DumpDirReport::default();
+
+    let mut referenced = referenced_data_files(&header, &entries, &dir_path)?;
+    referenced.sort();
+    for (idx, filename) in referenced.iter().enumerate() {
+        if idx > 0 && referenced[idx - 1] == *filename {
+            if !report.duplicate_files.contains(filename) {
+                report.duplicate_files.push(filename.clone());
+            }
+            continue;
+        }
+        let path = dir_path.join(filename);
+        if !path.is_file() {
+            report.missing_files.push(filename.clone());
+        } else if header.compression > 0 && "blobs.toc" != filename && !is_gzip_file(&path)? {
+            report.compression_mismatches.push(filename.clone());
+        }
+    }
+
+    let toc_filename = toc_src_path.file_name().map(|name| name.to_string_lossy().to_string());
+    let mut dir_files = Vec::new();
+    for en in dir_path.read_dir()? {
+        dir_files.push(en?.file_name().to_string_lossy().to_string());
+    }
+    dir_files.sort();
+    for filename in dir_files {
+        let kind = dump_file_kind(&filename, toc_filename.as_deref(), &referenced);
+        if Some(DumpFileKind::Unknown) == kind && is_table_data_filename(&filename) {
+            report.orphan_files.push(filename);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Checks that the rewritten dump does not reference the original logical DB name.
+///
+/// Tags, namespaces, owners and SQL statements of all TOC entries and data lines of Babelfish
+/// catalogs are searched for names that start with `<orig_dbname>_` (like `test1_dbo`). Original
+/// DB name must be specified, because it cannot be derived from the rewritten dump and backup
+/// files are not written when the dump is rewritten into an output directory. When the new
+/// DB name itself starts with `<orig_dbname>_` (like `test1_copy`), the new names are not
+/// reported. ACL of the
+/// physical DB is skipped, it contains grants for other logical DBs and is kept as is by
+/// the rewrite. Note that the text of comments and string literals, that is preserved
+/// by the rewrite, is also reported.
+///
+/// Fails if any of the catalog files cannot be read or decoded.
+///
+/// Returns the list of references found, empty list means the check passed.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to the rewritten `pg_dump` TOC file or to the dump directory containing it
+/// * `orig_dbname` - Logical DB name the dump used before the rewrite
+pub fn verify_rewritten<P: AsRef<Path>>(toc_path: P, orig_dbname: &str) -> Result<Vec<StaleReference>, TocError> {
+    let toc_src_path = &resolve_toc_path(toc_path)?;
+    let dir_path = dump_dir_path(toc_src_path);
+    let Toc { header, entries, .. } = read_toc(toc_src_path)?;
+    let prefix = format!("{}_", orig_dbname);
+    // new names start with the original prefix when the new DB name does
+    let exclude = match find_out_orig_dbname(&entries) {
+        Ok(dbname) if dbname.starts_with(&prefix) => Some(dbname),
+        _ => None
+    };
+    let mut res = Vec::new();
+    for te in entries.iter() {
+        let description = te.description.to_string_lossy();
+        let tag = te.tag.to_string_lossy();
+        if "ACL" == description && tag.starts_with("DATABASE ") {
+            continue;
+        }
+        let mut report = |location: String, value: String| res.push(StaleReference {
+            dump_id: te.dump_id,
+            description: description.clone(),
+            tag: tag.clone(),
+            location,
+            value
+        });
+        for (field, value) in [
+            ("tag", &te.tag),
+            ("namespace", &te.namespace),
+            ("owner", &te.owner),
+            ("create_stmt", &te.create_stmt),
+            ("drop_stmt", &te.drop_stmt),
+            ("copy_stmt", &te.copy_stmt),
+        ] {
+            let value = value.to_string_lossy();
+            if contains_name_prefix(&value, &prefix, exclude.as_deref()) {
+                report(field.to_string(), value);
+            }
+        }
+        if "TABLE DATA" == description && BABELFISH_CATALOGS.contains(&tag.as_str()) {
+            let filename = te.filename.to_string()?;
+            let compression = detect_file_compression(&dir_path, &filename, header.compression);
+            let contents = read_catalog_contents(&dir_path, &filename, compression).map_err(|e| TocError::DataFile {
+                table: Some(tag.clone()),
+                filename: filename.clone(),
+                line: None,
+                source: Box::new(e)
+            })?;
+            for (idx, line) in contents.lines().enumerate() {
+                if contains_name_prefix(line, &prefix, exclude.as_deref()) {
+                    report(format!("line {}", idx + 1), line.to_string());
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Lists files in `pg_dump` directory classifying them by their relation to TOC.
+///
+/// Files referenced by TOC entries (including large objects files listed in `blobs.toc`)
+/// and `toc.dat` itself are `Referenced`, files with `.orig` suffix (like `toc.dat.orig`
+/// or `1234.dat.orig.gz`) are `Backup`, temporary files left by interrupted runs are `Temp`,
+/// all other files are `Unknown`. Subdirectories and the lock file are not listed.
+///
+/// Returns paths sorted by file name.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+pub fn list_dump_files<P: AsRef<Path>>(toc_path: P) -> Result<Vec<(PathBuf, DumpFileKind)>, TocError> {
+    let toc_src_path = &resolve_toc_path(toc_path)?;
+    let dir_path = dump_dir_path(toc_src_path);
+    let Toc { header, entries, .. } = read_toc(toc_src_path)?;
+    let referenced = referenced_data_files(&header, &entries, &dir_path)?;
+    let toc_filename = toc_src_path.file_name().map(|name| name.to_string_lossy().to_string());
+    let mut res = Vec::new();
+    for en in dir_path.read_dir()? {
+        let en = en?;
+        if !en.file_type()?.is_file() {
+            continue;
+        }
+        let filename = en.file_name().to_string_lossy().to_string();
+        if let Some(kind) = dump_file_kind(&filename, toc_filename.as_deref(), &referenced) {
+            res.push((dir_path.join(&filename), kind));
+        }
+    }
+    res.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(res)
+}
+
+// lock file is not a part of the dump and is not classified
+fn dump_file_kind(filename: &str, toc_filename: Option<&str>, referenced: &[String]) -> Option<DumpFileKind> {
+    let kind = if LOCK_FILENAME == filename {
+        return None;
+    } else if Some(filename) == toc_filename || referenced.iter().any(|name| name == filename) {
+        DumpFileKind::Referenced
+    } else if is_temp_filename(filename) {
+        DumpFileKind::Temp
+    } else if backup_target_name(filename).is_some() {
+        DumpFileKind::Backup
+    } else {
+        DumpFileKind::Unknown
+    };
+    Some(kind)
+}
+
+// table data file written by pg_dump, like 1234.dat or 1234.dat.gz
+fn is_table_data_filename(filename: &str) -> bool {
+    let name = filename.strip_suffix(".gz").unwrap_or(filename);
+    match name.strip_suffix(".dat") {
+        Some(dump_id) => !dump_id.is_empty() && dump_id.chars().all(|ch| ch.is_ascii_digit()),
+        None => false
+    }
+}
+
+/// Lists files in `pg_dump` directory that are not referenced by TOC.
+///
+/// Backups, temporary files and unknown files are returned, see `list_dump_files`
+/// for details.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+pub fn list_orphans<P: AsRef<Path>>(toc_path: P) -> Result<Vec<PathBuf>, TocError> {
+    Ok(list_dump_files(toc_path)?.into_iter()
+        .filter(|(_, kind)| DumpFileKind::Referenced != *kind)
+        .map(|(path, _)| path)
+        .collect())
+}
+
+/// Deletes backup and temporary files from `pg_dump` directory.
+///
+/// Files classified by `list_dump_files` as `Backup` or `Temp` are deleted, referenced
+/// and unknown files are never touched. Note that the rewrite cannot be reverted
+/// with `undo_rewrite` after its backups are deleted.
+///
+/// Returns paths of the deleted files.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+pub fn clean_backups<P: AsRef<Path>>(toc_path: P) -> Result<Vec<PathBuf>, TocError> {
+    let toc_src_path = &resolve_toc_path(toc_path)?;
+    // stale temporary files are removed when the lock is acquired
+    let lock = DumpLock::acquire(&dump_dir_path(toc_src_path))?;
+    let mut deleted = lock.removed_temp_files().to_vec();
+    for (path, kind) in list_dump_files(toc_src_path)? {
+        if DumpFileKind::Backup == kind || DumpFileKind::Temp == kind {
+            fs::remove_file(&path)?;
+            deleted.push(path);
+        }
+    }
+    deleted.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    Ok(deleted)
+}
+
+/// Rewrites owners in `pg_dump` TOC and in Babelfish catalogs using the specified mapping.
+///
+/// Only entry owners, role names in ACL entries and role names in `babelfish_authid_user_ext`
+/// catalog are rewritten, logical DB name and schema names are left as is.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `owners` - Mapping from original role names to new role names.
+pub fn rewrite_owners<P: AsRef<Path>>(toc_path: P, owners: &HashMap<String, String>) -> Result<(), TocError> {
+    let toc_src_path = &resolve_toc_path(toc_path)?;
+    let dir_path = dump_dir_path(toc_src_path);
+    let _lock = DumpLock::acquire(&dir_path)?;
+    let Toc { header, mut entries, trailer } = read_toc(toc_src_path)?;
+
+    // empty original and destination DB names make DB name replacement a no-op
+    let mut ctx = TocCtx::new(header, "", "");
+    ctx.owners = owners.clone();
+    for te in entries.iter_mut() {
+        let description = te.description.to_string()?;
+        if "TABLE DATA" == description {
+            collect_babelfish_catalog_filename(&mut ctx, te)?;
+        } else if "ACL" == description || "DEFAULT ACL" == description {
+            replace_create_stmt_roles(&ctx, te)?;
+        }
+        replace_owner(&ctx, te)?;
+    }
+
+    // catalog is restored if TOC cannot be written
+    let restore = BackupRestore::default();
+    if ctx.catalog_files.contains_key("babelfish_authid_user_ext") {
+        rewrite_bbf_authid_user_ext(&ctx, dir_path.as_path(), &restore, &|_, _| {})?;
+    }
+    write_toc_with_backup(toc_src_path, dir_path.as_path(), &ctx.header, &entries, &trailer, &RewriteOptions::default(), &restore)?;
+    restore.complete();
+
+    Ok(())
+}
+
+/// Renames a single schema in `pg_dump` TOC and in Babelfish catalogs.
+///
+/// Schema entry, its ACL, namespaces of contained objects, qualified references in SQL
+/// statements and schema names in `babelfish_namespace_ext`, `babelfish_function_ext` and
+/// `babelfish_extended_properties` catalogs are rewritten. Logical DB name, owners
+/// and other schemas are left as is.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `from` - Name of the existing schema
+/// * `to` - New name for this schema.
+pub fn rewrite_single_schema<P: AsRef<Path>>(toc_path: P, from: &str, to: &str) -> Result<(), TocError> {
+    validate_identifier(from, to)?;
+    let toc_src_path = &resolve_toc_path(toc_path)?;
+    let dir_path = dump_dir_path(toc_src_path);
+    let _lock = DumpLock::acquire(&dir_path)?;
+    let Toc { header, mut entries, trailer } = read_toc(toc_src_path)?;
+
+    let mut schema_found = false;
+    for te in entries.iter() {
+        if "SCHEMA" == te.description.to_string()? {
+            let tag = te.tag.to_string()?;
+            if to == tag {
+                return Err(TocError::new(&format!("Schema already exists: {}", to)));
+            }
+            schema_found |= from == tag;
+        }
+    }
+    if !schema_found {
+        return Err(TocError::new(&format!("Schema not found: {}", from)));
+    }
+
+    // empty original and destination DB names make DB name replacement a no-op
+    let mut ctx = TocCtx::new(header, "", "");
+    ctx.schemas.insert(from.to_string(), to.to_string());
+    for te in entries.iter_mut() {
+        replace_in_toc_entry(&mut ctx, te)?;
+    }
+
+    let dir_path = dir_path.as_path();
+    // catalogs replaced so far are restored if any of the files cannot be written
+    let restore = BackupRestore::default();
+    if ctx.catalog_files.contains_key("babelfish_extended_properties") {
+        rewrite_bbf_extended_properties(&ctx, dir_path, &restore, &|_, _| {})?;
+    }
+    if ctx.catalog_files.contains_key("babelfish_function_ext") {
+        rewrite_bbf_function_ext(&ctx, dir_path, &restore, &|_, _| {})?;
+    }
+    if ctx.catalog_files.contains_key("babelfish_namespace_ext") {
+        rewrite_bbf_namespace_ext(&ctx, dir_path, &restore, &|_, _| {})?;
+    }
+    write_toc_with_backup(toc_src_path, dir_path, &ctx.header, &entries, &trailer, &RewriteOptions::default(), &restore)?;
+    restore.complete();
+
+    Ok(())
+}
+
+/// Counts entries in `pg_dump` TOC by their description (object type, like `TABLE` or `ACL`).
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+pub fn toc_histogram<P: AsRef<Path>>(toc_path: P) -> Result<BTreeMap<String, usize>, TocError> {
+    let Toc { entries, .. } = read_toc(&resolve_toc_path(toc_path)?)?;
+    let mut res = BTreeMap::new();
+    for te in entries.iter() {
+        *res.entry(te.description.to_string()?).or_insert(0) += 1;
+    }
+    Ok(res)
+}
+
+/// Finds the name of the data file for the specified table in `pg_dump` TOC.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `namespace` - Schema name of the table
+/// * `tag` - Table name
+pub fn table_data_filename<P: AsRef<Path>>(toc_path: P, namespace: &str, tag: &str) -> Result<String, TocError> {
+    let Toc { entries, .. } = read_toc(&resolve_toc_path(toc_path)?)?;
+    for te in entries.iter() {
+        if "TABLE DATA" == te.description.to_string()? &&
+            namespace == te.namespace.to_string()? &&
+            tag == te.tag.to_string()? {
+            return te.filename.to_string();
+        }
+    }
+    Err(TocError::new(&format!("Table data not found, namespace: {}, tag: {}", namespace, tag)))
+}
+
+/// Rewrites the contents of the specified table data file in `pg_dump` directory.
+///
+/// Data file is processed line by line, fields of every data row are decoded from COPY
+/// text format and passed to the specified function, `\N` fields are passed as `None`,
+/// bytes that are not valid UTF-8 are replaced with `U+FFFD`.
+/// Fields returned from the function are encoded back, fields that were not changed keep
+/// their original encoding and bytes. The rewrite fails if the function returns a different number
+/// of fields. Original file is kept with `.orig` suffix the same way as for
+/// Babelfish catalogs.
+///
+/// # Arguments
+///
+/// * `dump_dir` - Path to `pg_dump` directory
+/// * `filename` - Data file name as specified in TOC (like `1234.dat`), `.gz` suffix is added when compression is enabled
+/// * `compression` - Compression of data files from TOC header (see `Toc::compression`), `CompressionSpec::None` for uncompressed dumps
+/// * `fun` - Function that receives fields of every data row and returns the rewritten fields
+pub fn rewrite_table_data<P, F>(dump_dir: P, filename: &str, compression: CompressionSpec, mut fun: F) -> Result<usize, TocError>
+where
+    P: AsRef<Path>,
+    F: FnMut(Vec<Option<String>>) -> Result<Vec<Option<String>>, TocError>
+{
+    let _lock = DumpLock::acquire(dump_dir.as_ref())?;
+    let restore = BackupRestore::default();
+    let file = CatalogFile {
+        dir_path: dump_dir.as_ref(),
+        catalog: "",
+        filename,
+        compression: compression.header_value(),
+        force_level: None,
+        dest_dir_path: None,
+        new_file: false,
+        checksums: false,
+        preserve_mtime: false,
+        durable: false,
+        delimiter: DEFAULT_DELIMITER,
+        restore: Some(&restore)
+    };
+    let res = rewrite_catalog(&file, &|_, _| {}, |rec| {
+        let decoded = rec.iter().map(|field| decode_copy_field(field)).collect::<Result<Vec<Option<String>>, TocError>>()?;
+        let rewritten = fun(decoded.clone())?;
+        let res = rewritten.iter().enumerate().map(|(idx, field)| {
+            match (decoded.get(idx), rec.get(idx)) {
+                (Some(field_orig), Some(raw)) if field_orig == field => raw.clone(),
+                _ => encode_copy_field(field.as_deref(), DEFAULT_DELIMITER)
+            }
+        }).collect();
+        Ok(res)
+    })?;
+    restore.complete();
+    Ok(res.rows_count)
+}
+
+/// Renumbers dump IDs of all entries in `pg_dump` TOC file sequentially.
+///
+/// See `renumber_dump_ids` for details. Dependencies on dump IDs that are not present
+/// in TOC are removed before renumbering, otherwise they could point to the new IDs
+/// of unrelated entries. Original TOC file is kept with `.orig` suffix.
+///
+/// Returns the list of messages about removed dependencies.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+pub fn renumber_toc<P: AsRef<Path>>(toc_path: P) -> Result<Vec<String>, TocError> {
+    let toc_src_path = &resolve_toc_path(toc_path)?;
+    let dir_path = dump_dir_path(toc_src_path);
+    let _lock = DumpLock::acquire(&dir_path)?;
+    let mut toc = Toc::read_from_path(toc_src_path)?;
+    let messages = toc.remove_missing_deps().into_iter()
+        .map(|(dump_id, dep)| format!("entry {}: dependency {} not found in TOC, removed", dump_id, dep))
+        .collect();
+    renumber_dump_ids(&mut toc)?;
+    let restore = BackupRestore::default();
+    write_toc_with_backup(toc_src_path, &dir_path, &toc.header, &toc.entries, &toc.trailer, &RewriteOptions::default(), &restore)?;
+    restore.complete();
+    Ok(messages)
+}
+
+/// Lists Babelfish catalogs present in `pg_dump` directory.
+///
+/// Returns a mapping from catalog name (like `babelfish_function_ext`) to the data
+/// file name as specified in TOC (like `1234.dat`).
+///
+/// # Arguments
+///
+/// * `dump_dir` - Path to `pg_dump` directory
+pub fn list_catalogs<P: AsRef<Path>>(dump_dir: P) -> Result<BTreeMap<String, String>, TocError> {
+    let Toc { header, entries, .. } = read_toc(&dump_dir.as_ref().join("toc.dat"))?;
+    let mut ctx = TocCtx::new(header, "", "");
+    for te in entries.iter() {
+        if "TABLE DATA" == te.description.to_string()? {
+            collect_babelfish_catalog_filename(&mut ctx, te)?;
+        }
+    }
+    Ok(ctx.catalog_files.into_iter().collect())
+}
+
+/// Reads decompressed contents of the specified Babelfish catalog from `pg_dump` directory.
+///
+/// Catalog data file is located using TOC, its contents are returned as is
+/// without any rewriting, dump directory is not modified.
+///
+/// # Arguments
+///
+/// * `dump_dir` - Path to `pg_dump` directory
+/// * `catalog_name` - Catalog table name, like `babelfish_function_ext`
+/// * `compression` - Compression of data files from TOC header (see `Toc::compression`), `CompressionSpec::None` for uncompressed dumps
+pub fn read_catalog<P: AsRef<Path>>(dump_dir: P, catalog_name: &str, compression: CompressionSpec) -> Result<String, TocError> {
+    let catalogs = list_catalogs(dump_dir.as_ref())?;
+    let filename = match catalogs.get(catalog_name) {
+        Some(filename) => filename,
+        None => return Err(TocError::CatalogMissing(catalog_name.to_string()))
+    };
+    read_catalog_contents(dump_dir.as_ref(), filename, compression.header_value())
+}
+
+/// Counts data lines of the specified Babelfish catalog in `pg_dump` directory.
+///
+/// Catalog file is read as a stream and is not loaded into memory. For COPY data the
+/// result is the number of rows, end-of-data marker (`\.`) and empty lines are not counted.
+/// Can be used to estimate the progress of the catalog rewrite.
+///
+/// # Arguments
+///
+/// * `dump_dir` - Path to `pg_dump` directory
+/// * `catalog_name` - Catalog table name, like `babelfish_function_ext`
+/// * `compression` - Compression of data files from TOC header (see `Toc::compression`), `CompressionSpec::None` for uncompressed dumps
+pub fn catalog_line_count<P: AsRef<Path>>(dump_dir: P, catalog_name: &str, compression: CompressionSpec) -> Result<usize, TocError> {
+    let catalogs = list_catalogs(dump_dir.as_ref())?;
+    let filename = match catalogs.get(catalog_name) {
+        Some(filename) => filename,
+        None => return Err(TocError::CatalogMissing(catalog_name.to_string()))
+    };
+    count_data_lines(dump_dir.as_ref(), filename, compression.header_value())
+}
+
+/// Writes a reduced copy of `pg_dump` TOC that contains only the entries matching the specified predicate.
+///
+/// Same as `filter_toc_with_options` with default options.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `dest_toc_path` - Path to the filtered TOC file
+/// * `predicate` - Function that returns `true` for entries that need to be kept
+pub fn filter_toc<P, D, F>(toc_path: P, dest_toc_path: D, predicate: F) -> Result<FilterSummary, TocError>
+where
+    P: AsRef<Path>,
+    D: AsRef<Path>,
+    F: Fn(&TocEntryView) -> bool
+{
+    filter_toc_with_options(toc_path, dest_toc_path, predicate, &FilterOptions::default())
+}
+
+/// Writes a reduced copy of `pg_dump` TOC that contains only the entries matching the specified predicate.
+///
+/// Entry count in TOC header is updated and dependencies that point to the removed entries
+/// are dropped. When the destination TOC is in the source dump directory, data files of
+/// the removed entries are left as is (or deleted if requested in options), if the
+/// destination path is the same as the source one, the original TOC is kept with `.orig` suffix.
+/// When the destination TOC is in another directory, this directory is created if it
+/// does not exist and must be empty if it does, only data files referenced by the
+/// remaining entries are copied there.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `dest_toc_path` - Path to the filtered TOC file or to the existing directory to write `toc.dat` into
+/// * `predicate` - Function that returns `true` for entries that need to be kept
+/// * `options` - Filter options.
+pub fn filter_toc_with_options<P, D, F>(toc_path: P, dest_toc_path: D, predicate: F, options: &FilterOptions) -> Result<FilterSummary, TocError>
+where
+    P: AsRef<Path>,
+    D: AsRef<Path>,
+    F: Fn(&TocEntryView) -> bool
+{
+    let toc_src_path = &resolve_toc_path(toc_path)?;
+    // destination TOC file may not exist yet, so only existing directories are resolved
+    let toc_dest_path = &match dest_toc_path.as_ref() {
+        dest if dest.is_dir() => dest.join("toc.dat"),
+        dest => dest.to_path_buf()
+    };
+    let dir_path = dump_dir_path(toc_src_path);
+    let Toc { mut header, entries, trailer } = read_toc(toc_src_path)?;
+
+    let mut summary = FilterSummary::default();
+    let mut removed = Vec::new();
+    for te in entries.iter() {
+        if !predicate(&TocEntryView::from_entry(te)?) {
+            summary.removed_entries.push(te.dump_id);
+            removed.push(te.clone());
+        }
+    }
+    let mut kept = entries;
+    remove_toc_entries(&mut header, &mut kept, &summary.removed_entries);
+
+    let kept_files = referenced_data_files(&header, &kept, &dir_path)?;
+    for filename in referenced_data_files(&header, &removed, &dir_path)? {
+        if !kept_files.contains(&filename) && !summary.unreferenced_files.contains(&filename) {
+            summary.unreferenced_files.push(filename);
+        }
+    }
+
+    let dest_dir_path = dump_dir_path(toc_dest_path);
+    let in_place = dest_dir_path.exists() && dest_dir_path.canonicalize()? == dir_path.canonicalize()?;
+    if in_place {
+        let _lock = DumpLock::acquire(&dir_path)?;
+        if toc_dest_path.exists() && toc_dest_path.canonicalize()? == toc_src_path.canonicalize()? {
+            let restore = BackupRestore::default();
+            write_toc_with_backup(toc_src_path, &dir_path, &header, &kept, &trailer, &RewriteOptions::default(), &restore)?;
+            restore.complete();
+        } else {
+            write_toc(toc_dest_path, &header, &kept, &trailer)?;
+        }
+        if options.delete_unreferenced_files {
+            for filename in summary.unreferenced_files.iter() {
+                let path = dir_path.join(filename);
+                if path.exists() {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+    } else {
+        prepare_output_dir(&dest_dir_path)?;
+        for filename in kept_files.iter() {
+            let src_path = dir_path.join(filename);
+            if !src_path.is_file() {
+                return Err(TocError::new(&format!(
+                    "Data file not found: {}", src_path.to_string_lossy())));
+            }
+            copy_data_file(&src_path, &dest_dir_path.join(filename), options.hard_link_data_files)?;
+        }
+        write_toc(toc_dest_path, &header, &kept, &trailer)?;
+    }
+
+    Ok(summary)
+}
+
+/// Re-compresses all data files in `pg_dump` directory using the specified compression.
+///
+/// Data files of all TOC entries (including large objects listed in `blobs.toc`) are decompressed
+/// and compressed again (or left uncompressed), original files are kept with `.orig` suffix
+/// (like `1234.dat.orig.gz`). Compression field in TOC header is updated, the original TOC is kept
+/// as `toc.dat.orig`. Data file names in TOC entries do not include compression suffix, so only
+/// the names of the files in dump directory are changed (`1234.dat.gz` to `1234.dat` when
+/// decompressing).
+///
+/// Fails without changing anything if dump directory already contains backup files, files
+/// replaced so far are restored from backups if any of the files cannot be re-compressed.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+/// * `spec` - Compression to use for data files.
+pub fn set_compression<P: AsRef<Path>>(toc_path: P, spec: CompressionSpec) -> Result<(), TocError> {
+    let toc_src_path = &resolve_toc_path(toc_path)?;
+    let dir_path = dump_dir_path(toc_src_path);
+    let _lock = DumpLock::acquire(&dir_path)?;
+    if let Some(backup_path) = find_backup_file(&dir_path)? {
+        return Err(TocError::new(&format!(
+            "Backup file already exists: {}, revert the previous run with 'undo_rewrite' \
+            or delete backups with 'clean_backups'", backup_path.to_string_lossy())));
+    }
+    let Toc { mut header, entries, trailer } = read_toc(toc_src_path)?;
+    let src_header = header.clone();
+    let src_compressed = src_header.compression > 0;
+    header.compression = spec.header_value();
+    let dest_level = match spec {
+        CompressionSpec::None => None,
+        CompressionSpec::Gzip(level) => Some(level)
+    };
+
+    // files replaced so far are restored if any of the files cannot be re-compressed
+    let restore = BackupRestore::default();
+    for src_filename in referenced_data_files(&src_header, &entries, &dir_path)? {
+        // blobs TOC is never compressed
+        if "blobs.toc" == src_filename {
+            continue;
+        }
+        let filename = if src_compressed {
+            src_filename.strip_suffix(".gz").unwrap_or(&src_filename)
+        } else {
+            src_filename.as_str()
+        };
+        let src_path = dir_path.join(&src_filename);
+        if !src_path.is_file() {
+            return Err(TocError::new(&format!(
+                "Data file not found: {}", src_path.to_string_lossy())));
+        }
+        let dest_filename = data_file_name(&header, filename);
+        let dest_path = dir_path.join(&dest_filename);
+        let tmp_path = TempPath::new(&dest_path)?;
+        recompress_data_file(&src_path, tmp_path.path(), src_compressed, dest_level)?;
+        copy_file_metadata(&src_path, tmp_path.path(), false)?;
+        let orig_path = dir_path.join(data_file_name(&src_header, &format!("{}.orig", filename)));
+        fs::rename(&src_path, &orig_path)?;
+        restore.push(orig_path, src_path);
+        tmp_path.persist(&dest_path)?;
+        if src_filename != dest_filename {
+            restore.push_created(dest_path);
+        }
+    }
+
+    write_toc_with_backup(toc_src_path, &dir_path, &header, &entries, &trailer, &RewriteOptions::default(), &restore)?;
+    restore.complete();
     Ok(())
-}
\ No newline at end of file
+}
+
+// name of the file that was backed up, like 1234.dat.gz for 1234.dat.orig.gz
+fn backup_target_name(filename: &str) -> Option<String> {
+    if let Some(name) = filename.strip_suffix(".orig") {
+        Some(name.to_string())
+    } else {
+        filename.strip_suffix(".orig.gz").map(|name| format!("{}.gz", name))
+    }
+}
+
+// operations that keep backups refuse to replace the ones left by the previous run
+fn find_backup_file(dir_path: &Path) -> Result<Option<PathBuf>, TocError> {
+    for en in dir_path.read_dir()? {
+        let en = en?;
+        let filename = en.file_name().to_string_lossy().to_string();
+        if backup_target_name(&filename).is_some() && en.path().is_file() {
+            return Ok(Some(dir_path.join(filename)));
+        }
+    }
+    Ok(None)
+}
+
+/// Reverts the in-place rewrite of `pg_dump` directory using backup files.
+///
+/// Backup files (`toc.dat.orig` and data files with `.orig` suffix like `1234.dat.orig.gz`) are
+/// renamed back over the rewritten files. Nothing is changed if `toc.dat.orig` is not present or
+/// if the rewritten file does not exist for any of the backup files.
+///
+/// Only the single most recent in-place rewrite can be reverted, backups are kept for one
+/// rewrite only and the next in-place rewrite is refused while they are present.
+///
+/// Returns names of the restored files.
+///
+/// # Arguments
+///
+/// * `dump_dir` - Path to `pg_dump` directory
+pub fn undo_rewrite<P: AsRef<Path>>(dump_dir: P) -> Result<Vec<String>, TocError> {
+    let dir_path = dump_dir.as_ref();
+    let _lock = DumpLock::acquire(dir_path)?;
+    let mut backups = Vec::new();
+    for en in dir_path.read_dir()? {
+        let filename = en?.file_name().to_string_lossy().to_string();
+        if let Some(target) = backup_target_name(&filename) {
+            backups.push((filename, target));
+        }
+    }
+    backups.sort();
+    if !backups.iter().any(|(_, target)| "toc.dat" == target) {
+        return Err(TocError::new(&format!(
+            "TOC backup file not found: {}", dir_path.join("toc.dat.orig").to_string_lossy())));
+    }
+    for (backup, target) in backups.iter() {
+        if !dir_path.join(target).is_file() {
+            return Err(TocError::new(&format!(
+                "Rewritten file not found for backup: {}, expected file: {}", backup, target)));
+        }
+    }
+    let mut restored = Vec::new();
+    for (backup, target) in backups {
+        fs::rename(dir_path.join(&backup), dir_path.join(&target))?;
+        restored.push(target);
+    }
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(dump_id: i32, tag: &str, deps: &[i32]) -> TocEntry {
+        TocEntry {
+            dump_id,
+            tag: TocString::from_str(tag),
+            description: TocString::from_str("TABLE DATA"),
+            deps: deps.iter().map(|dep| TocString::from_string(dep.to_string())).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn tags(entries: &[TocEntry]) -> Vec<String> {
+        entries.iter().map(|te| te.tag.to_string_lossy()).collect()
+    }
+
+    #[test]
+    fn reorder_catalogs_before_sysdatabases() {
+        let mut entries = vec![
+            entry(1, "tab1", &[]),
+            entry(2, "babelfish_namespace_ext", &[]),
+            entry(3, "babelfish_function_ext", &[]),
+            entry(4, "tab2", &[]),
+            entry(5, "babelfish_sysdatabases", &[]),
+            entry(6, "tab3", &[]),
+        ];
+        reorder_babelfish_catalogs(&mut entries).unwrap();
+        assert_eq!(tags(&entries), ["tab1", "tab2", "babelfish_sysdatabases",
+            "babelfish_namespace_ext", "babelfish_function_ext", "tab3"]);
+    }
+
+    #[test]
+    fn reorder_catalogs_after_sysdatabases() {
+        let mut entries = vec![
+            entry(1, "babelfish_sysdatabases", &[]),
+            entry(2, "tab1", &[]),
+            entry(3, "babelfish_view_def", &[]),
+            entry(4, "tab2", &[]),
+            entry(5, "babelfish_extended_properties", &[]),
+        ];
+        reorder_babelfish_catalogs(&mut entries).unwrap();
+        assert_eq!(tags(&entries), ["babelfish_sysdatabases", "babelfish_view_def",
+            "babelfish_extended_properties", "tab1", "tab2"]);
+    }
+
+    #[test]
+    fn reorder_catalogs_interleaved() {
+        let mut entries = vec![
+            entry(1, "babelfish_extended_properties", &[]),
+            entry(2, "tab1", &[]),
+            entry(3, "babelfish_sysdatabases", &[]),
+            entry(4, "tab2", &[2]),
+            entry(5, "babelfish_function_ext", &[]),
+            entry(6, "tab3", &[4]),
+        ];
+        reorder_babelfish_catalogs(&mut entries).unwrap();
+        assert_eq!(tags(&entries), ["tab1", "babelfish_sysdatabases", "babelfish_extended_properties",
+            "babelfish_function_ext", "tab2", "tab3"]);
+    }
+
+    #[test]
+    fn orig_dbname_from_dbo_schema() {
+        let schema = |tag: &str| TocEntry {
+            tag: TocString::from_str(tag),
+            description: TocString::from_str("SCHEMA"),
+            ..Default::default()
+        };
+        // custom schemas precede 'dbo' and do not share the common prefix with it
+        let entries = vec![schema("test1_dbadmin"), schema("test1_sales_dbo"), schema("test1_dbo")];
+        assert_eq!(find_out_orig_dbname(&entries).unwrap(), "test1");
+
+        let entries = vec![schema("test1_dbo")];
+        assert_eq!(find_out_orig_dbname(&entries).unwrap(), "test1");
+
+        // without 'dbo' schema DB name is the common prefix of all schemas
+        let entries = vec![schema("test1_schema1"), schema("test1_guest")];
+        assert_eq!(find_out_orig_dbname(&entries).unwrap(), "test1");
+
+        let entries = vec![schema("public")];
+        assert!(matches!(find_out_orig_dbname(&entries), Err(TocError::NotBabelfishDump(_))));
+    }
+
+    #[test]
+    fn replace_owner_null_and_empty() {
+        let mut ctx = TocCtx::new(TocHeader::default(), "test1", "foobar");
+        ctx.owners.insert("test1_dbo".to_string(), "foobar_dbo".to_string());
+        ctx.owners.insert("".to_string(), "foobar_db_owner".to_string());
+
+        let mut te = TocEntry { owner: TocString::none(), ..Default::default() };
+        replace_owner(&ctx, &mut te).unwrap();
+        assert_eq!(te.owner.to_string_opt().unwrap(), None);
+
+        let mut te = TocEntry { owner: TocString::empty(), ..Default::default() };
+        replace_owner(&ctx, &mut te).unwrap();
+        assert_eq!(te.owner.to_string_opt().unwrap(), Some("".to_string()));
+
+        let mut te = TocEntry { owner: TocString::from_str("test1_dbo"), ..Default::default() };
+        replace_owner(&ctx, &mut te).unwrap();
+        assert_eq!(te.owner.to_string().unwrap(), "foobar_dbo");
+    }
+
+    #[test]
+    fn reorder_catalogs_errors() {
+        let mut entries = vec![entry(1, "tab1", &[]), entry(2, "babelfish_function_ext", &[])];
+        assert!(reorder_babelfish_catalogs(&mut entries).is_err());
+
+        // moving the catalog after sysdatabases would put it after its dependent
+        let mut entries = vec![
+            entry(1, "babelfish_namespace_ext", &[]),
+            entry(2, "tab1", &[1]),
+            entry(3, "babelfish_sysdatabases", &[]),
+        ];
+        let err = reorder_babelfish_catalogs(&mut entries).unwrap_err();
+        assert!(err.to_string().contains("dump_id: 2 comes before its dependency: 1"));
+        assert_eq!(tags(&entries), ["babelfish_namespace_ext", "tab1", "babelfish_sysdatabases"]);
+    }
+}
@@ -13,53 +13,1059 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
-
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
 use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::io::IsTerminal;
+use std::path::PathBuf;
 use std::process;
 
 use clap::Arg;
 use clap::ArgAction;
+use clap::ArgMatches;
 use clap::Command;
+use clap::ValueHint;
+use clap::value_parser;
+use clap_complete::Shell;
+use log::Level;
+use log::LevelFilter;
 
-fn main() {
-    let args = Command::new("pg_dump TOC rewriter")
-        .author("WiltonDB Software")
-        .version("1.0.6")
-        .about("Changes Babelfish logical DB name in pg_dump files")
-        .arg(Arg::new("dbname")
+use pgdump_toc_rewrite::DumpFileKind;
+use pgdump_toc_rewrite::ProgressEvent;
+use pgdump_toc_rewrite::RenameMaps;
+use pgdump_toc_rewrite::TocError;
+
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+// same code is used by clap for argument errors
+const EXIT_USAGE: i32 = 2;
+const EXIT_INVALID_INPUT: i32 = 3;
+const EXIT_IO: i32 = 4;
+const EXIT_NOT_BABELFISH: i32 = 5;
+const EXIT_UNSUPPORTED_DUMP: i32 = 6;
+// 128 + SIGINT, as reported by shells for the processes killed with Ctrl+C
+const EXIT_CANCELLED: i32 = 130;
+
+const BIN_NAME: &str = "pgdump_toc_rewrite";
+// TOC path that means reading TOC contents from stdin
+const STDIN_PATH: &str = "-";
+const TOC_STDIN_HELP: &str = "TOC file or dump directory, '-' to read TOC from stdin";
+
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0  success
+  1  check did not pass or other failure, details are printed to stderr
+  2  invalid command-line arguments
+  3  invalid input value, like DB name, new names that are too long or already exist, or TOC JSON
+  4  I/O error, like missing file or no space left on device
+  5  dump is not a Babelfish dump or required Babelfish catalog is missing
+  6  dump is corrupt or uses TOC format features that are not supported
+130  rewrite was interrupted with Ctrl+C, catalog files replaced so far were restored";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ErrorFormat {
+    Text,
+    Json,
+    // '--json-errors' output of the previous versions
+    LegacyJson,
+}
+
+// percentage line is redrawn in place, log messages are printed above it
+fn print_progress(event: ProgressEvent) {
+    let percent = |processed: u64, total: u64| processed * 100 / total.max(1);
+    let line = match event {
+        ProgressEvent::TocEntries { processed, total } =>
+            format!("TOC entries: {}%", percent(processed as u64, total as u64)),
+        ProgressEvent::CatalogBytes { catalog, processed, total } =>
+            format!("catalog {}: {}%", catalog, percent(processed, total)),
+        ProgressEvent::CopyingFiles { processed, total } =>
+            format!("copying files: {}%", percent(processed as u64, total as u64)),
+        ProgressEvent::RenamingFiles => "renaming files".to_string()
+    };
+    eprint!("\r\x1b[K{}", line);
+}
+
+// library and utility messages go to stderr through the log facade, successful runs
+// print nothing by default, '-v' adds rewritten catalogs, '-vv' every modified entry
+fn init_logger(verbosity: u8, quiet: bool) {
+    let level = match (quiet, verbosity) {
+        (true, _) => LevelFilter::Off,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Info,
+        (false, _) => LevelFilter::Debug
+    };
+    // the progress line (if any) is cleared before printing the message
+    let clear_line = io::stderr().is_terminal();
+    env_logger::Builder::new()
+        .target(env_logger::Target::Stderr)
+        .filter_module("pgdump_toc_rewrite", level)
+        .format(move |buf, record| {
+            let prefix = if clear_line { "\r\x1b[K" } else { "" };
+            match record.level() {
+                Level::Error => writeln!(buf, "{}Error: {}", prefix, record.args()),
+                Level::Warn => writeln!(buf, "{}Warning: {}", prefix, record.args()),
+                _ => writeln!(buf, "{}{}", prefix, record.args())
+            }
+        })
+        .init();
+}
+
+// failures that are not caused by TocError use kinds that are not TocError variant names
+fn print_failure(error_format: ErrorFormat, message: &str, kind: &str) {
+    match error_format {
+        ErrorFormat::Text => eprintln!("{}", message),
+        ErrorFormat::Json => eprintln!("{}", serde_json::json!({
+            "error_kind": kind, "message": message, "entry": null, "path": null })),
+        ErrorFormat::LegacyJson => eprintln!("{}", serde_json::json!({ "error": message, "kind": kind }))
+    }
+}
+
+// entry and data file wrappers are unwrapped to report the kind of the cause
+fn error_cause(e: &TocError) -> &TocError {
+    match e {
+        TocError::Entry { source, .. } => error_cause(source),
+        TocError::DataFile { source, .. } => error_cause(source),
+        _ => e
+    }
+}
+
+fn error_entry(e: &TocError) -> serde_json::Value {
+    match e {
+        TocError::Entry { dump_id, description, tag, .. } => serde_json::json!({
+            "dump_id": dump_id, "description": description, "tag": tag }),
+        TocError::DataFile { source, .. } => error_entry(source),
+        _ => serde_json::Value::Null
+    }
+}
+
+fn error_path(e: &TocError) -> serde_json::Value {
+    match e {
+        TocError::Entry { source, .. } => error_path(source),
+        TocError::DataFile { filename, .. } => serde_json::json!(filename),
+        _ => serde_json::Value::Null
+    }
+}
+
+fn exit_code(e: &TocError) -> i32 {
+    match error_cause(e) {
+        TocError::InvalidDbName(_) | TocError::NameTooLong { .. } | TocError::NameCollision(_) |
+        TocError::InvalidJson { .. } | TocError::Json(_) => EXIT_INVALID_INPUT,
+        // truncated TOC file
+        TocError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => EXIT_UNSUPPORTED_DUMP,
+        TocError::Io(_) => EXIT_IO,
+        TocError::NotBabelfishDump(_) | TocError::CatalogMissing(_) => EXIT_NOT_BABELFISH,
+        TocError::MagicMismatch | TocError::UnsupportedVersion | TocError::UnsupportedFormat(_) |
+        TocError::Corrupt { .. } | TocError::Utf8(_) | TocError::DateTime(_) | TocError::SqlRewrite(_) |
+        TocError::Tokenizer { .. } | TocError::SqlTooLong { .. } => EXIT_UNSUPPORTED_DUMP,
+        TocError::Cancelled { .. } => EXIT_CANCELLED,
+        _ => EXIT_FAILURE
+    }
+}
+
+fn print_error(error_format: ErrorFormat, prefix: &str, e: &TocError) -> i32 {
+    match error_format {
+        ErrorFormat::Text => eprintln!("{}: {}", prefix, e),
+        ErrorFormat::Json => eprintln!("{}", serde_json::json!({
+            "error_kind": error_cause(e).kind(),
+            "message": e.to_string(),
+            "entry": error_entry(e),
+            "path": error_path(e)
+        })),
+        ErrorFormat::LegacyJson => print_failure(error_format, &e.to_string(), e.kind())
+    }
+    exit_code(e)
+}
+
+// modes that modify the dump need the dump directory to locate data files
+fn reject_stdin(toc_file: &str, mode: &str, error_format: ErrorFormat) -> Option<i32> {
+    if toc_file != STDIN_PATH {
+        return None;
+    }
+    let msg = format!("Error: '{}' cannot read TOC from stdin, path to the dump directory or to the 'toc.dat' file inside it is required", mode);
+    print_failure(error_format, &msg, "usage");
+    Some(EXIT_USAGE)
+}
+
+// output closed by the reader (like 'head') is not an error in pipelines
+fn is_broken_pipe(e: &TocError) -> bool {
+    matches!(e, TocError::Io(e) if e.kind() == io::ErrorKind::BrokenPipe)
+}
+
+fn read_stdin() -> Result<Vec<u8>, TocError> {
+    let mut buf = Vec::new();
+    io::stdin().lock().read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+// map file has two whitespace-separated columns: original name and new name,
+// empty lines and lines starting with '#' are ignored
+fn read_rename_map(path: &str, error_format: ErrorFormat) -> Result<HashMap<String, String>, i32> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return Err(print_error(error_format, &format!("Map file error, path: {}", path), &e.into()))
+    };
+    let mut map = HashMap::new();
+    let mut line_numbers = HashMap::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let cols: Vec<&str> = line.split_whitespace().collect();
+        let problem = if cols.len() != 2 {
+            Some(format!("expected original and new names, found {} column(s)", cols.len()))
+        } else if let Some(first) = line_numbers.get(cols[0]) {
+            Some(format!("duplicate original name '{}', first specified at line: {}", cols[0], first))
+        } else if pgdump_toc_rewrite::check_dbname(cols[1]).is_err() {
+            Some(format!("new name '{}' must be a lower case SQL identifier that is not a reserved keyword", cols[1]))
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            let msg = format!("Map file error, path: {}, line: {}, message: {}", path, idx + 1, problem);
+            print_failure(error_format, &msg, "invalid_map");
+            return Err(EXIT_INVALID_INPUT);
+        }
+        line_numbers.insert(cols[0].to_string(), idx + 1);
+        map.insert(cols[0].to_string(), cols[1].to_string());
+    }
+    Ok(map)
+}
+
+// each section uses the format of the map files
+fn print_rename_maps(maps: &RenameMaps) {
+    for (title, map) in [("schemas", &maps.schemas), ("owners", &maps.owners)] {
+        let mut pairs: Vec<(&String, &String)> = map.iter().collect();
+        pairs.sort();
+        println!("# {}", title);
+        for (orig, dest) in pairs {
+            println!("{} {}", orig, dest);
+        }
+    }
+}
+
+fn toc_arg() -> Arg {
+    Arg::new("toc.dat")
+        .value_name("TOC")
+        .value_hint(ValueHint::AnyPath)
+        .required(true)
+        .help("TOC file or dump directory")
+}
+
+fn rewrite_args() -> Vec<Arg> {
+    vec!(
+        Arg::new("dbname")
             .short('d')
             .long("dbname")
-            .help("DB name to use instead of original DB name")
-        )
-        .arg(Arg::new("print")
+            .help("DB name to use instead of original DB name"),
+        Arg::new("allow-mixed-case")
+            .long("allow-mixed-case")
+            .action(ArgAction::SetTrue)
+            .requires("dbname")
+            .help("Accept any T-SQL regular identifier as 'dbname', the name is lower-cased as in Babelfish"),
+        Arg::new("allow-long-names")
+            .long("allow-long-names")
+            .action(ArgAction::SetTrue)
+            .requires("dbname")
+            .help("Warn instead of failing when new schema or role names exceed 63 bytes"),
+        Arg::new("write-new")
+            .long("write-new")
+            .action(ArgAction::SetTrue)
+            .requires("dbname")
+            .conflicts_with("output-dir")
+            .help("Write 'toc.dat.new' and '.new' catalog files next to the originals, original files are not modified"),
+        Arg::new("force")
+            .long("force")
+            .action(ArgAction::SetTrue)
+            .requires("dbname")
+            .help("Rewrite the dump even if it already uses 'dbname'"),
+        Arg::new("preserve-mtime")
+            .long("preserve-mtime")
+            .action(ArgAction::SetTrue)
+            .requires("dbname")
+            .help("Keep modification time of the original files on the rewritten TOC and catalogs"),
+        Arg::new("follow-symlinks")
+            .long("follow-symlinks")
+            .action(ArgAction::SetTrue)
+            .requires("dbname")
+            .help("Rewrite the dump in the directory of the symlink target when TOC file is a symlink"),
+        Arg::new("no-fsync")
+            .long("no-fsync")
+            .action(ArgAction::SetTrue)
+            .requires("dbname")
+            .help("Do not flush rewritten files to disk before replacing the originals, faster but not crash-safe"),
+        Arg::new("allow-collisions")
+            .long("allow-collisions")
+            .action(ArgAction::SetTrue)
+            .requires("dbname")
+            .help("Warn instead of failing when new DB, schema or role names already exist in Babelfish catalogs"),
+        Arg::new("target-version")
+            .long("target-version")
+            .value_name("VERSION")
+            .value_parser(["1.12", "1.13", "1.14"])
+            .requires("dbname")
+            .help("Archive format version to write the rewritten TOC with, to restore into older PostgreSQL"),
+        Arg::new("header-dbname")
+            .long("header-dbname")
+            .value_name("NAME")
+            .requires("dbname")
+            .help("Physical PostgreSQL DB name to store in the TOC header, kept as is by default"),
+        Arg::new("toc-only")
+            .long("toc-only")
+            .action(ArgAction::SetTrue)
+            .requires("dbname")
+            .help("Rewrite only the TOC file, leave Babelfish catalog files as is"),
+        Arg::new("touch-timestamp")
+            .long("touch-timestamp")
+            .action(ArgAction::SetTrue)
+            .requires("dbname")
+            .help("Set the timestamp in the TOC header to the current time"),
+        Arg::new("compression-level")
+            .long("compression-level")
+            .value_parser(value_parser!(u32).range(0..=9))
+            .requires("dbname")
+            .help("Compression level to use for rewritten catalogs instead of the detected one"),
+        Arg::new("strip-owners")
+            .long("strip-owners")
+            .action(ArgAction::SetTrue)
+            .requires("dbname")
+            .help("Remove owners and ACL entries of the original DB roles instead of renaming them"),
+        Arg::new("no-acl")
+            .long("no-acl")
+            .action(ArgAction::SetTrue)
+            .requires("dbname")
+            .help("Remove all ACL entries (grant/revoke privileges) from TOC"),
+        Arg::new("schema-map")
+            .long("schema-map")
+            .value_name("FILE")
+            .value_hint(ValueHint::FilePath)
+            .requires("dbname")
+            .help("File with 'original new' schema name pairs, one per line, that replace or extend the derived schema renames"),
+        Arg::new("owner-map")
+            .long("owner-map")
+            .value_name("FILE")
+            .value_hint(ValueHint::FilePath)
+            .requires("dbname")
+            .help("File with 'original new' role name pairs, one per line, that replace or extend the derived role renames"),
+        Arg::new("print-maps")
+            .long("print-maps")
+            .action(ArgAction::SetTrue)
+            .requires("dbname")
+            .help("Print schema and role renames that would be applied and exit without modifying the dump"),
+        Arg::new("check-roundtrip")
+            .long("check-roundtrip")
+            .action(ArgAction::SetTrue)
+            .help("Check that TOC can be written back without changes before the rewrite"),
+    )
+}
+
+fn output_dir_arg() -> Arg {
+    Arg::new("output-dir")
+        .long("output-dir")
+        .value_name("PATH")
+        .value_hint(ValueHint::DirPath)
+        .help("Write rewritten or filtered dump into the specified directory leaving the source dump unchanged")
+}
+
+fn hard_link_arg() -> Arg {
+    Arg::new("hard-link")
+        .long("hard-link")
+        .action(ArgAction::SetTrue)
+        .requires("output-dir")
+        .help("Create hard links to data files in output directory instead of copying them when possible")
+}
+
+fn exclude_table_data_arg() -> Arg {
+    Arg::new("exclude-table-data")
+        .long("exclude-table-data")
+        .value_name("TAG")
+        .action(ArgAction::Append)
+        .help("Remove data of the specified table (like 'tab1' or 'test1_dbo.tab1') from TOC, can be repeated")
+}
+
+fn subcommands() -> Vec<Command> {
+    vec!(
+        Command::new("print")
+            .about("Print TOC details")
+            .arg(toc_arg().help(TOC_STDIN_HELP)),
+        Command::new("rewrite")
+            .about("Change Babelfish logical DB name in TOC and catalog files")
+            .args(rewrite_args().into_iter().map(|arg| if arg.get_id() == "dbname" { arg.required(true) } else { arg }))
+            .arg(output_dir_arg())
+            .arg(hard_link_arg())
+            .arg(toc_arg()),
+        Command::new("to-json")
+            .about("Write TOC contents as JSON")
+            .arg(Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .value_hint(ValueHint::FilePath)
+                .help("Write JSON into the specified file instead of stdout"))
+            .arg(toc_arg().help(TOC_STDIN_HELP)),
+        Command::new("from-json")
+            .about("Write TOC file from JSON created with 'to-json'")
+            .arg(Arg::new("json")
+                .value_name("JSON")
+                .value_hint(ValueHint::FilePath)
+                .required(true)
+                .help("JSON file, '-' to read JSON from stdin"))
+            .arg(Arg::new("allow-nonstandard-header")
+                .long("allow-nonstandard-header")
+                .action(ArgAction::SetTrue)
+                .help("Do not require 'PGDMP' magic in the header, such TOC cannot be restored"))
+            .arg(toc_arg().help("TOC file to create, must not exist")),
+        Command::new("create-script")
+            .about("Write CREATE statements of all TOC entries as SQL script for review")
+            .arg(Arg::new("dbname")
+                .short('d')
+                .long("dbname")
+                .help("Rewrite statements for the specified DB name as 'rewrite' would do"))
+            .arg(toc_arg()),
+        Command::new("verify")
+            .about("Check that all data files referenced in TOC are present and that TOC can be written back without changes")
+            .arg(toc_arg().help(TOC_STDIN_HELP)),
+        Command::new("verify-rewritten")
+            .about("Check that the rewritten dump does not reference the original DB name")
+            .arg(Arg::new("orig-dbname")
+                .long("orig-dbname")
+                .value_name("DBNAME")
+                .required(true)
+                .help("DB name the dump used before the rewrite"))
+            .arg(toc_arg()),
+        Command::new("orphans")
+            .about("List files in dump directory that are not referenced by TOC")
+            .arg(Arg::new("clean-backups")
+                .long("clean-backups")
+                .action(ArgAction::SetTrue)
+                .help("Delete '.orig' backups and temporary files after confirmation, other files are not deleted"))
+            .arg(Arg::new("yes")
+                .short('y')
+                .long("yes")
+                .action(ArgAction::SetTrue)
+                .requires("clean-backups")
+                .help("Do not ask for confirmation"))
+            .arg(toc_arg()),
+        Command::new("restore-backup")
+            .about("Revert the previous in-place rewrite using '.orig' backup files")
+            .arg(toc_arg()),
+        Command::new("filter")
+            .about("Remove table data entries from TOC")
+            .arg(exclude_table_data_arg().required(true))
+            .arg(output_dir_arg())
+            .arg(hard_link_arg())
+            .arg(toc_arg()),
+        Command::new("set-compression")
+            .about("Re-compress all data files")
+            .arg(Arg::new("set-compression")
+                .value_name("SPEC")
+                .required(true)
+                .help("'none', 'gzip' or 'gzip:LEVEL'"))
+            .arg(toc_arg()),
+        Command::new("renumber")
+            .about("Assign sequential dump IDs to TOC entries updating their dependencies")
+            .arg(toc_arg()),
+        Command::new("deps-dot")
+            .about("Write the graph of dependencies between TOC entries in Graphviz DOT format and check it for cycles")
+            .arg(Arg::new("deps-dot")
+                .value_name("FILE")
+                .value_hint(ValueHint::FilePath)
+                .required(true)
+                .help("DOT file to write"))
+            .arg(toc_arg()),
+        Command::new("completions")
+            .about("Write shell completion script to stdout")
+            .arg(Arg::new("shell")
+                .value_name("SHELL")
+                .value_parser(["bash", "zsh", "fish", "powershell"])
+                .required(true)
+                .help("Shell to generate the completion script for")),
+    ).into_iter().map(|cmd| cmd.after_help(EXIT_CODES_HELP)).collect()
+}
+
+// flags used before the subcommands were introduced, kept for compatibility
+fn legacy_args() -> Vec<Arg> {
+    let mut args = rewrite_args();
+    args.extend([
+        Arg::new("print")
             .short('p')
             .long("print")
             .action(ArgAction::SetTrue)
             .conflicts_with("dbname")
-            .help("Only print TOC details without rewriting")
-        )
-        .arg(Arg::new("toc.dat")
-            .required(true)
-            .help("TOC file")
-        )
-        .get_matches();
+            .help("Only print TOC details without rewriting"),
+        Arg::new("check")
+            .long("check")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["dbname", "print"])
+            .help("Only check that all data files referenced in TOC are present in dump directory"),
+        exclude_table_data_arg()
+            .conflicts_with_all(["dbname", "print", "check"]),
+        Arg::new("set-compression")
+            .long("set-compression")
+            .value_name("SPEC")
+            .conflicts_with_all(["dbname", "print", "check", "exclude-table-data", "output-dir"])
+            .help("Re-compress all data files, SPEC is 'none', 'gzip' or 'gzip:LEVEL'"),
+        Arg::new("undo")
+            .long("undo")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["dbname", "print", "check", "exclude-table-data", "set-compression", "output-dir"])
+            .help("Revert the previous in-place rewrite using '.orig' backup files"),
+        Arg::new("renumber")
+            .long("renumber")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["dbname", "print", "check", "exclude-table-data", "set-compression", "undo", "output-dir"])
+            .help("Assign sequential dump IDs to TOC entries updating their dependencies"),
+        Arg::new("deps-dot")
+            .long("deps-dot")
+            .value_name("FILE")
+            .conflicts_with_all(["dbname", "print", "check", "exclude-table-data", "set-compression", "undo", "renumber", "output-dir"])
+            .help("Write the graph of dependencies between TOC entries in Graphviz DOT format and check it for cycles"),
+        output_dir_arg()
+            .conflicts_with_all(["print", "check"]),
+        hard_link_arg(),
+        toc_arg()
+            .required(false),
+    ]);
+    args.into_iter().map(|arg| {
+        let arg = arg.hide(true);
+        match arg.get_id().as_str() {
+            "check-roundtrip" => arg.conflicts_with_all(["print", "check", "exclude-table-data", "set-compression", "undo", "renumber", "deps-dot"]),
+            _ => arg
+        }
+    }).collect()
+}
+
+fn toc_file(args: &ArgMatches) -> String {
+    args.get_one::<String>("toc.dat").map(|s| s.to_string()).expect("toc.dat not specified")
+}
+
+fn run_print(toc_file: &str, error_format: ErrorFormat) -> i32 {
+    let res = if toc_file == STDIN_PATH {
+        pgdump_toc_rewrite::print_toc_from_reader(io::stdin().lock(), &mut io::stdout())
+    } else {
+        pgdump_toc_rewrite::print_toc(toc_file, &mut io::stdout())
+    };
+    match res {
+        Ok(_) => EXIT_SUCCESS,
+        Err(e) if is_broken_pipe(&e) => EXIT_SUCCESS,
+        Err(e) => print_error(error_format, "TOC print error", &e)
+    }
+}
+
+fn run_rewrite(args: &ArgMatches, error_format: ErrorFormat) -> i32 {
+    let toc_file = toc_file(args);
+    if let Some(code) = reject_stdin(&toc_file, "rewrite", error_format) {
+        return code;
+    }
+    let name = args.get_one::<String>("dbname").map(|s| s.to_string()).expect("dbname not specified");
+    let output_dir = args.get_one::<String>("output-dir").map(PathBuf::from);
+    let target_version = match args.get_one::<String>("target-version").map(|st| st.parse()).transpose() {
+        Ok(version) => version,
+        Err(e) => return print_error(error_format, "TOC rewrite error", &e)
+    };
+    let read_map = |id: &str| args.get_one::<String>(id).map(|path| read_rename_map(path, error_format)).transpose();
+    let schema_map = match read_map("schema-map") {
+        Ok(map) => map,
+        Err(code) => return code
+    };
+    let owner_map = match read_map("owner-map") {
+        Ok(map) => map,
+        Err(code) => return code
+    };
+    let show_progress = log::log_enabled!(Level::Info) && io::stderr().is_terminal();
+    // rewrite stops at the next step and restores the replaced files
+    let cancellation = pgdump_toc_rewrite::CancellationToken::new();
+    let handler_token = cancellation.clone();
+    if let Err(e) = ctrlc::set_handler(move || handler_token.cancel()) {
+        log::warn!("Ctrl+C handler cannot be installed: {}", e);
+    }
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.compression_level = args.get_one::<u32>("compression-level").copied();
+    options.strip_owners = args.get_flag("strip-owners");
+    options.drop_acls = args.get_flag("no-acl");
+    options.progress = if show_progress { Some(&print_progress) } else { None };
+    options.output_dir = output_dir.as_deref();
+    options.hard_link_data_files = args.get_flag("hard-link");
+    options.verify_roundtrip = args.get_flag("check-roundtrip");
+    options.allow_mixed_case = args.get_flag("allow-mixed-case");
+    options.allow_long_names = args.get_flag("allow-long-names");
+    options.write_new_files = args.get_flag("write-new");
+    options.force = args.get_flag("force");
+    options.preserve_mtime = args.get_flag("preserve-mtime");
+    options.durable = !args.get_flag("no-fsync");
+    options.follow_symlinks = args.get_flag("follow-symlinks");
+    options.allow_name_collisions = args.get_flag("allow-collisions");
+    options.target_version = target_version;
+    options.postgres_dbname = args.get_one::<String>("header-dbname").map(|s| s.as_str());
+    options.touch_timestamp = args.get_flag("touch-timestamp");
+    options.toc_only = args.get_flag("toc-only");
+    options.schema_map = schema_map.as_ref();
+    options.owner_map = owner_map.as_ref();
+    options.cancellation = Some(&cancellation);
+    if args.get_flag("print-maps") {
+        return match pgdump_toc_rewrite::derive_rename_maps_with_options(&toc_file, &name, &options) {
+            Ok(maps) => {
+                print_rename_maps(&maps);
+                EXIT_SUCCESS
+            },
+            Err(e) => print_error(error_format, "TOC rewrite error", &e)
+        };
+    }
+    // warnings from the summary are already logged by the library
+    let res = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_file, &name, &options);
+    if show_progress {
+        eprint!("\r\x1b[K");
+    }
+    match res {
+        Ok(_) => EXIT_SUCCESS,
+        Err(e) => print_error(error_format, "TOC rewrite error", &e)
+    }
+}
+
+fn run_to_json(toc_file: &str, output: Option<&String>, error_format: ErrorFormat) -> i32 {
+    let res = if toc_file == STDIN_PATH {
+        pgdump_toc_rewrite::read_toc_to_json_from_reader(io::stdin().lock())
+    } else {
+        pgdump_toc_rewrite::read_toc_to_json(toc_file)
+    };
+    let res = res.and_then(|json| {
+        match output {
+            Some(path) => fs::write(path, json)?,
+            None => writeln!(io::stdout(), "{}", json)?
+        };
+        Ok(())
+    });
+    match res {
+        Ok(_) => EXIT_SUCCESS,
+        Err(e) if is_broken_pipe(&e) => EXIT_SUCCESS,
+        Err(e) => print_error(error_format, "TOC to JSON error", &e)
+    }
+}
+
+fn run_from_json(json_file: &str, toc_file: &str, allow_nonstandard_header: bool, error_format: ErrorFormat) -> i32 {
+    let json = if json_file == STDIN_PATH {
+        read_stdin().and_then(|bytes| String::from_utf8(bytes).map_err(TocError::from))
+    } else {
+        fs::read_to_string(json_file).map_err(TocError::from)
+    };
+    let res = json.and_then(|json| match allow_nonstandard_header {
+        true => pgdump_toc_rewrite::write_toc_from_json_allow_nonstandard_header(toc_file, &json),
+        false => pgdump_toc_rewrite::write_toc_from_json(toc_file, &json)
+    });
+    match res {
+        Ok(_) => EXIT_SUCCESS,
+        Err(e) => print_error(error_format, "TOC from JSON error", &e)
+    }
+}
+
+fn run_create_script(toc_file: &str, dbname: Option<&String>, error_format: ErrorFormat) -> i32 {
+    let res = match dbname {
+        Some(dbname) => pgdump_toc_rewrite::dump_create_script_with_dbname(toc_file, dbname, &mut io::stdout()),
+        None => pgdump_toc_rewrite::dump_create_script(toc_file, &mut io::stdout())
+    };
+    match res {
+        Ok(_) => EXIT_SUCCESS,
+        Err(e) if is_broken_pipe(&e) => EXIT_SUCCESS,
+        Err(e) => print_error(error_format, "Create script error", &e)
+    }
+}
+
+fn run_check(toc_file: &str, error_format: ErrorFormat) -> i32 {
+    match pgdump_toc_rewrite::check_dump_dir(toc_file) {
+        Ok(report) => {
+            if report.is_ok() {
+                println!("Dump directory check passed");
+                return EXIT_SUCCESS;
+            }
+            eprint!("{}", report);
+            print_failure(error_format, "Dump directory check failed", "check_failed");
+            EXIT_FAILURE
+        },
+        Err(e) => print_error(error_format, "Dump directory check error", &e)
+    }
+}
+
+fn run_check_roundtrip(toc_file: &str, error_format: ErrorFormat) -> i32 {
+    let res = if toc_file == STDIN_PATH {
+        read_stdin().map_err(pgdump_toc_rewrite::RoundtripMismatch::Read)
+            .and_then(|bytes| pgdump_toc_rewrite::verify_roundtrip_bytes(&bytes))
+    } else {
+        pgdump_toc_rewrite::verify_roundtrip(toc_file)
+    };
+    match res {
+        Ok(_) => {
+            println!("TOC round-trip check passed");
+            EXIT_SUCCESS
+        },
+        Err(pgdump_toc_rewrite::RoundtripMismatch::Read(e)) => print_error(error_format, "TOC round-trip check error", &e),
+        Err(mismatch) => {
+            print_failure(error_format, &mismatch.to_string(), "roundtrip_mismatch");
+            EXIT_UNSUPPORTED_DUMP
+        }
+    }
+}
+
+fn run_verify(toc_file: &str, error_format: ErrorFormat) -> i32 {
+    if toc_file == STDIN_PATH {
+        log::warn!("TOC is read from stdin, data files check is skipped");
+        return run_check_roundtrip(toc_file, error_format);
+    }
+    match run_check(toc_file, error_format) {
+        EXIT_SUCCESS => run_check_roundtrip(toc_file, error_format),
+        code => code
+    }
+}
+
+fn run_verify_rewritten(toc_file: &str, orig_dbname: &str, error_format: ErrorFormat) -> i32 {
+    if let Some(code) = reject_stdin(toc_file, "verify-rewritten", error_format) {
+        return code;
+    }
+    match pgdump_toc_rewrite::verify_rewritten(toc_file, orig_dbname) {
+        Ok(stale) => {
+            if stale.is_empty() {
+                println!("Rewritten dump check passed");
+                return EXIT_SUCCESS;
+            }
+            for sr in stale {
+                eprintln!("{}", sr);
+            }
+            print_failure(error_format, "Rewritten dump check failed", "stale_references");
+            EXIT_FAILURE
+        },
+        Err(e) => print_error(error_format, "Rewritten dump check error", &e)
+    }
+}
 
-    let toc_file = args.get_one::<String>("toc.dat").map(|s| s.to_string()).expect("toc.dat not specified");
-    let dbname = args.get_one::<String>("dbname").map(|s| s.to_string());
-    let print = args.get_one::<bool>("print").map_or(false, |b| *b);
+fn confirm(question: &str) -> io::Result<bool> {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
 
-    if print {
-        match pgdump_toc_rewrite::print_toc(&toc_file, &mut io::stdout()) {
-            Ok(_) => process::exit(0),
-            Err(e) => eprintln!("TOC print error: {}", e)
+fn run_orphans(args: &ArgMatches, error_format: ErrorFormat) -> i32 {
+    let toc_file = toc_file(args);
+    if let Some(code) = reject_stdin(&toc_file, "orphans", error_format) {
+        return code;
+    }
+    let files = match pgdump_toc_rewrite::list_dump_files(&toc_file) {
+        Ok(files) => files,
+        Err(e) => return print_error(error_format, "Dump directory listing error", &e)
+    };
+    let mut to_delete = 0;
+    for (path, kind) in files.iter() {
+        match kind {
+            DumpFileKind::Referenced => continue,
+            DumpFileKind::Backup | DumpFileKind::Temp => to_delete += 1,
+            DumpFileKind::Unknown => {}
         }
-    } else if let Some(name) = dbname {
-        match pgdump_toc_rewrite::rewrite_toc(&toc_file, &name) {
-            Ok(_) => process::exit(0),
-            Err(e) => eprintln!("TOC rewrite error: {}", e)
+        println!("{}\t{}", kind, path.to_string_lossy());
+    }
+    if !args.get_flag("clean-backups") || 0 == to_delete {
+        return EXIT_SUCCESS;
+    }
+    if !args.get_flag("yes") {
+        if !io::stdin().is_terminal() {
+            print_failure(error_format, "Confirmation cannot be read, stdin is not a terminal, use '--yes' to delete files", "usage");
+            return EXIT_USAGE;
+        }
+        match confirm(&format!("Delete {} backup and temporary files?", to_delete)) {
+            Ok(true) => {},
+            Ok(false) => return EXIT_SUCCESS,
+            Err(e) => return print_error(error_format, "Dump directory cleanup error", &TocError::from(e))
         }
+    }
+    match pgdump_toc_rewrite::clean_backups(&toc_file) {
+        Ok(deleted) => {
+            for path in deleted {
+                log::info!("file {} deleted", path.to_string_lossy());
+            }
+            EXIT_SUCCESS
+        },
+        Err(e) => print_error(error_format, "Dump directory cleanup error", &e)
+    }
+}
+
+fn run_filter(args: &ArgMatches, error_format: ErrorFormat) -> i32 {
+    let toc_file = toc_file(args);
+    if let Some(code) = reject_stdin(&toc_file, "filter", error_format) {
+        return code;
+    }
+    let output_dir = args.get_one::<String>("output-dir").map(PathBuf::from);
+    let exclude_table_data: Vec<String> = args.get_many::<String>("exclude-table-data")
+        .map(|tags| tags.cloned().collect())
+        .unwrap_or_default();
+    let dest_toc_file = match &output_dir {
+        Some(dir) => dir.join("toc.dat"),
+        None => PathBuf::from(&toc_file)
+    };
+    let options = pgdump_toc_rewrite::FilterOptions {
+        hard_link_data_files: args.get_flag("hard-link"),
+        ..Default::default()
+    };
+    let predicate = pgdump_toc_rewrite::exclude_table_data(&exclude_table_data);
+    match pgdump_toc_rewrite::filter_toc_with_options(&toc_file, &dest_toc_file, predicate, &options) {
+        Ok(summary) => {
+            for dump_id in summary.removed_entries {
+                log::info!("entry {} removed", dump_id);
+            }
+            if output_dir.is_none() {
+                for filename in summary.unreferenced_files {
+                    log::warn!("file {} is not referenced in TOC", filename);
+                }
+            }
+            EXIT_SUCCESS
+        },
+        Err(e) => print_error(error_format, "TOC filter error", &e)
+    }
+}
+
+fn run_set_compression(toc_file: &str, spec: &str, error_format: ErrorFormat) -> i32 {
+    if let Some(code) = reject_stdin(toc_file, "set-compression", error_format) {
+        return code;
+    }
+    let res = spec.parse::<pgdump_toc_rewrite::CompressionSpec>()
+        .and_then(|spec| pgdump_toc_rewrite::set_compression(toc_file, spec));
+    match res {
+        Ok(_) => EXIT_SUCCESS,
+        Err(e) => print_error(error_format, "Set compression error", &e)
+    }
+}
+
+fn run_restore_backup(toc_file: &str, error_format: ErrorFormat) -> i32 {
+    if let Some(code) = reject_stdin(toc_file, "restore-backup", error_format) {
+        return code;
+    }
+    let toc_path = match pgdump_toc_rewrite::resolve_toc_path(toc_file) {
+        Ok(path) => path,
+        Err(e) => return print_error(error_format, "Undo error", &e)
+    };
+    let dump_dir = match toc_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from(".")
+    };
+    match pgdump_toc_rewrite::undo_rewrite(&dump_dir) {
+        Ok(restored) => {
+            for filename in restored {
+                log::info!("file {} restored", filename);
+            }
+            EXIT_SUCCESS
+        },
+        Err(e) => print_error(error_format, "Undo error", &e)
+    }
+}
+
+fn run_renumber(toc_file: &str, error_format: ErrorFormat) -> i32 {
+    if let Some(code) = reject_stdin(toc_file, "renumber", error_format) {
+        return code;
+    }
+    match pgdump_toc_rewrite::renumber_toc(toc_file) {
+        Ok(messages) => {
+            for msg in messages {
+                log::info!("{}", msg);
+            }
+            EXIT_SUCCESS
+        },
+        Err(e) => print_error(error_format, "TOC renumber error", &e)
+    }
+}
+
+fn run_deps_dot(toc_file: &str, dot_file: &str, error_format: ErrorFormat) -> i32 {
+    let res = pgdump_toc_rewrite::Toc::read_from_path(toc_file).and_then(|toc| {
+        let graph = toc.dependency_graph();
+        let mut writer = io::BufWriter::new(File::create(dot_file)?);
+        graph.to_dot(&mut writer)?;
+        writer.flush()?;
+        Ok(graph.validate())
+    });
+    match res {
+        Ok(report) => {
+            // pg_dump writes dependencies on objects that are not dumped
+            for (dump_id, dep) in report.unknown_deps.iter() {
+                log::info!("entry {}: dependency {} not found in TOC", dump_id, dep);
+            }
+            if report.cycles.is_empty() {
+                return EXIT_SUCCESS;
+            }
+            for cycle in report.cycles.iter() {
+                let ids: Vec<String> = cycle.iter().map(|id| id.to_string()).collect();
+                let msg = format!("Dependency cycle: {} -> {}", ids.join(" -> "), ids[0]);
+                print_failure(error_format, &msg, "dependency_cycle");
+            }
+            EXIT_FAILURE
+        },
+        Err(e) => print_error(error_format, "Dependency graph error", &e)
+    }
+}
+
+fn run_legacy(args: &ArgMatches, error_format: ErrorFormat) -> i32 {
+    let toc_file = match args.get_one::<String>("toc.dat") {
+        Some(toc_file) => toc_file.to_string(),
+        None => {
+            print_failure(error_format, "Error: subcommand must be specified, see '--help' for details", "usage");
+            return EXIT_USAGE;
+        }
+    };
+    if args.get_flag("check") {
+        run_check(&toc_file, error_format)
+    } else if args.get_flag("print") {
+        run_print(&toc_file, error_format)
+    } else if args.contains_id("dbname") {
+        run_rewrite(args, error_format)
+    } else if args.get_flag("check-roundtrip") {
+        run_check_roundtrip(&toc_file, error_format)
+    } else if args.contains_id("exclude-table-data") {
+        run_filter(args, error_format)
+    } else if let Some(spec) = args.get_one::<String>("set-compression") {
+        run_set_compression(&toc_file, spec, error_format)
+    } else if args.get_flag("undo") {
+        run_restore_backup(&toc_file, error_format)
+    } else if args.get_flag("renumber") {
+        run_renumber(&toc_file, error_format)
+    } else if let Some(dot_file) = args.get_one::<String>("deps-dot") {
+        run_deps_dot(&toc_file, dot_file, error_format)
     } else {
-        eprintln!("Error: either 'rewrite' or 'print' flag must be specified")
+        print_failure(error_format, "Error: subcommand must be specified, see '--help' for details", "usage");
+        EXIT_USAGE
+    }
+}
+
+fn run_completions(shell: &str) -> i32 {
+    let shell: Shell = shell.parse().expect("unsupported shell");
+    clap_complete::generate(shell, &mut cli_command(), BIN_NAME, &mut io::stdout());
+    EXIT_SUCCESS
+}
+
+// legacy flags are not included to keep them out of the completions,
+// shared with tests that check the generated completions
+fn cli_command() -> Command {
+    Command::new("pg_dump TOC rewriter")
+        .author("WiltonDB Software")
+        .version("1.0.6")
+        .about("Changes Babelfish logical DB name in pg_dump files")
+        .after_help(EXIT_CODES_HELP)
+        .subcommands(subcommands())
+        .arg(Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .action(ArgAction::Count)
+            .global(true)
+            .help("Print rewritten catalogs to stderr, '-vv' also prints every modified TOC entry")
+        )
+        .arg(Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .action(ArgAction::SetTrue)
+            .global(true)
+            .conflicts_with("verbose")
+            .help("Do not print warnings to stderr, errors are still printed")
+        )
+        .arg(Arg::new("error-format")
+            .long("error-format")
+            .value_name("FORMAT")
+            .value_parser(["text", "json"])
+            .default_value("text")
+            .global(true)
+            .help("Format of the errors printed to stderr, 'json' prints a single object with \
+                'error_kind', 'message', 'entry' and 'path' fields")
+        )
+        .arg(Arg::new("json-errors")
+            .long("json-errors")
+            .action(ArgAction::SetTrue)
+            .global(true)
+            .hide(true)
+            .help("Print errors to stderr as JSON objects with 'error' and 'kind' fields")
+        )
+}
+
+fn main() {
+    let args = cli_command().args(legacy_args()).get_matches();
+
+    init_logger(args.get_count("verbose"), args.get_flag("quiet"));
+    let error_format = if args.get_flag("json-errors") {
+        ErrorFormat::LegacyJson
+    } else if args.get_one::<String>("error-format").is_some_and(|format| "json" == format) {
+        ErrorFormat::Json
+    } else {
+        ErrorFormat::Text
+    };
+
+    let code = match args.subcommand() {
+        Some(("print", sub)) => run_print(&toc_file(sub), error_format),
+        Some(("rewrite", sub)) => run_rewrite(sub, error_format),
+        Some(("to-json", sub)) => run_to_json(&toc_file(sub), sub.get_one::<String>("output"), error_format),
+        Some(("from-json", sub)) => {
+            let json_file = sub.get_one::<String>("json").expect("JSON file not specified");
+            run_from_json(json_file, &toc_file(sub), sub.get_flag("allow-nonstandard-header"), error_format)
+        },
+        Some(("create-script", sub)) => run_create_script(&toc_file(sub), sub.get_one::<String>("dbname"), error_format),
+        Some(("verify", sub)) => run_verify(&toc_file(sub), error_format),
+        Some(("verify-rewritten", sub)) => {
+            let orig_dbname = sub.get_one::<String>("orig-dbname").expect("orig-dbname not specified");
+            run_verify_rewritten(&toc_file(sub), orig_dbname, error_format)
+        },
+        Some(("orphans", sub)) => run_orphans(sub, error_format),
+        Some(("restore-backup", sub)) => run_restore_backup(&toc_file(sub), error_format),
+        Some(("filter", sub)) => run_filter(sub, error_format),
+        Some(("set-compression", sub)) => {
+            let spec = sub.get_one::<String>("set-compression").expect("SPEC not specified");
+            run_set_compression(&toc_file(sub), spec, error_format)
+        },
+        Some(("renumber", sub)) => run_renumber(&toc_file(sub), error_format),
+        Some(("deps-dot", sub)) => {
+            let dot_file = sub.get_one::<String>("deps-dot").expect("DOT file not specified");
+            run_deps_dot(&toc_file(sub), dot_file, error_format)
+        },
+        Some(("completions", sub)) => run_completions(sub.get_one::<String>("shell").expect("SHELL not specified")),
+        _ => run_legacy(&args, error_format)
+    };
+    process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completions(shell: Shell) -> String {
+        let mut buf = Vec::new();
+        clap_complete::generate(shell, &mut cli_command(), BIN_NAME, &mut buf);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn cli_command_is_valid() {
+        cli_command().args(legacy_args()).debug_assert();
+    }
+
+    #[test]
+    fn completions_contain_subcommands_and_flags() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell] {
+            let script = completions(shell);
+            for name in ["print", "rewrite", "to-json", "from-json", "create-script", "verify",
+                "restore-backup", "filter", "set-compression", "renumber", "deps-dot", "completions"] {
+                assert!(script.contains(name), "shell: {}, subcommand: {}", shell, name);
+            }
+            let long_flag = |name: &str| match shell {
+                Shell::Fish => format!("-l {}", name),
+                _ => format!("--{}", name)
+            };
+            for name in ["dbname", "schema-map", "output-dir", "error-format", "verbose"] {
+                assert!(script.contains(&long_flag(name)), "shell: {}, flag: {}", shell, name);
+            }
+            // legacy flags are not completed
+            assert!(!script.contains(&long_flag("undo")), "shell: {}", shell);
+        }
+    }
+
+    #[test]
+    fn completions_hint_paths() {
+        // TOC argument accepts both the 'toc.dat' file and the dump directory
+        let zsh = completions(Shell::Zsh);
+        assert!(zsh.contains("':toc.dat -- TOC file or dump directory:_files'"));
+        assert!(zsh.contains("into the specified directory leaving the source dump unchanged]:PATH:_files -/'"));
+        assert!(completions(Shell::Fish).contains("-l output-dir -d 'Write rewritten or filtered dump into the specified directory leaving the source dump unchanged' -r -f -a \"(__fish_complete_directories)\""));
     }
-    process::exit(1);
 }
@@ -14,8 +14,10 @@
  * limitations under the License.
  */
 
+use std::borrow::Cow;
 use std::fs;
 use std::fs::File;
+use std::io;
 use std::io::BufReader;
 use std::io::BufRead;
 use std::io::BufWriter;
@@ -23,82 +25,572 @@ use std::io::Read;
 use std::io::Write;
 use std::path::Path;
 
-use flate2::write::GzEncoder;
 use flate2::bufread::GzDecoder;
 use flate2::Compression;
+use flate2::GzBuilder;
 
+use crate::checksum::ChecksumReader;
+use crate::checksum::ChecksumWriter;
+use crate::copy_data::split_copy_line;
+use crate::copy_data::split_copy_line_raw;
+use crate::temp_file::copy_file_metadata;
+use crate::temp_file::sync_dir;
+use crate::temp_file::BackupRestore;
+use crate::temp_file::TempPath;
 use crate::toc_error::TocError;
 use crate::utils;
 
 
-fn rewrite_catalog_internal<F: Fn(Vec<String>) -> Result<Vec<String>, TocError>>
-(dir_path: &Path, filename: &str, compression: i32, line_by_line: bool, fun: F) -> Result<(), TocError> {
-    let rewrite_line = |line: String| -> Result<String, TocError> {
-        let res = if "\\." == line || line.is_empty() {
-            line
+pub(crate) struct CatalogFile<'a> {
+    pub(crate) dir_path: &'a Path,
+    pub(crate) catalog: &'a str,
+    pub(crate) filename: &'a str,
+    // compression level from TOC header or, when it does not
+    // match the file on disk, the one from detect_file_compression
+    pub(crate) compression: i32,
+    pub(crate) force_level: Option<u32>,
+    // when specified, rewritten file is written into this directory
+    // and the source file is left as is
+    pub(crate) dest_dir_path: Option<&'a Path>,
+    // when set, rewritten file is written next to the source one
+    // with '.new' suffix and the source file is left as is
+    pub(crate) new_file: bool,
+    // compute checksums of the decompressed contents
+    pub(crate) checksums: bool,
+    // copy modification time of the source file to the rewritten one
+    pub(crate) preserve_mtime: bool,
+    // flush the rewritten file and the directory to disk
+    pub(crate) durable: bool,
+    // column delimiter from the COPY statement of the TOC entry
+    pub(crate) delimiter: char,
+    // receives the backup of the source file right after it is renamed,
+    // only used when the file is rewritten in place
+    pub(crate) restore: Option<&'a BackupRestore>
+}
+
+// SHA-256 of decompressed contents before and after the rewrite
+pub(crate) type ContentChecksums = (String, String);
+
+#[derive(Debug)]
+pub(crate) struct CatalogRewrite {
+    pub(crate) rows_count: usize,
+    // 0 for uncompressed files
+    pub(crate) compression: u32,
+    pub(crate) checksums: Option<ContentChecksums>
+}
+
+// some pipelines produce dumps where header compression does not match
+// the data files, file on disk wins when only one of the variants exists
+pub(crate) fn detect_file_compression(dir_path: &Path, filename: &str, header_compression: i32) -> i32 {
+    let plain_exists = dir_path.join(filename).exists();
+    let gz_exists = dir_path.join(format!("{}.gz", filename)).exists();
+    if header_compression <= 0 && !plain_exists && gz_exists {
+        Compression::default().level() as i32
+    } else if header_compression > 0 && plain_exists && !gz_exists {
+        0
+    } else {
+        header_compression
+    }
+}
+
+// deflate level can only be inferred from the XFL byte when zlib
+// used the fastest or the best compression
+fn gzip_level_from_xfl(xfl: u8, default_level: u32) -> u32 {
+    match xfl {
+        2 => 9,
+        4 => 1,
+        _ => default_level
+    }
+}
+
+fn read_gzip_xfl(path: &Path) -> Result<u8, TocError> {
+    let mut fixed = [0u8; 10];
+    File::open(path)?.read_exact(&mut fixed)?;
+    if fixed[0] != 0x1f || fixed[1] != 0x8b {
+        return Err(TocError::new(&format!(
+            "Invalid gzip header, file: {}", path.to_string_lossy())));
+    }
+    Ok(fixed[8])
+}
+
+// reproduces mtime, OS, filename, extra and comment fields of the source file
+fn gzip_builder<R: BufRead>(decoder: &GzDecoder<R>) -> GzBuilder {
+    let mut builder = GzBuilder::new();
+    if let Some(header) = decoder.header() {
+        builder = builder
+            .mtime(header.mtime())
+            .operating_system(header.operating_system());
+        if let Some(filename) = header.filename() {
+            builder = builder.filename(filename);
+        }
+        if let Some(extra) = header.extra() {
+            builder = builder.extra(extra);
+        }
+        if let Some(comment) = header.comment() {
+            builder = builder.comment(comment);
+        }
+    }
+    builder
+}
+
+const UTF8_BOM: &str = "\u{FEFF}";
+
+// reports the number of bytes read from the source file
+// after every PROGRESS_STEP bytes
+const PROGRESS_STEP: u64 = 1 << 20;
+
+struct ProgressReader<'a, R: Read> {
+    inner: R,
+    processed: u64,
+    reported: u64,
+    total: u64,
+    progress: &'a dyn Fn(u64, u64)
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.processed += read as u64;
+        if self.processed - self.reported >= PROGRESS_STEP {
+            (self.progress)(self.processed, self.total);
+            self.reported = self.processed;
+        }
+        Ok(read)
+    }
+}
+
+fn open_with_progress<'a>(path: &Path, progress: &'a dyn Fn(u64, u64)) -> Result<ProgressReader<'a, File>, TocError> {
+    let file = File::open(path)?;
+    let total = file.metadata()?.len();
+    Ok(ProgressReader { inner: file, processed: 0, reported: 0, total, progress })
+}
+
+fn is_data_line(line: &str) -> bool {
+    "\\." != line && !line.is_empty()
+}
+
+fn rewrite_error(catalog: &str, filename: &str, line_num: Option<usize>, e: TocError) -> TocError {
+    if let TocError::DataFile { .. } = e {
+        return e;
+    }
+    TocError::DataFile {
+        table: if catalog.is_empty() { None } else { Some(catalog.to_string()) },
+        filename: filename.to_string(),
+        line: line_num,
+        source: Box::new(e)
+    }
+}
+
+// SQL statement can span multiple lines, it is complete when all string
+// literals are closed (escaped quotes are doubled) and it ends with a semicolon
+fn is_complete_statement(stmt: &str) -> bool {
+    // every quote toggles between inside and outside of a literal
+    let literals_closed = stmt.matches('\'').fold(true, |closed, _| !closed);
+    literals_closed && stmt.trim_end().ends_with(';')
+}
+
+fn rewrite_stream<R: BufRead, W: Write, F: FnMut(Vec<String>) -> Result<Vec<String>, TocError>>
+(file: &CatalogFile, mut reader: R, writer: &mut W, sql_statements: bool, mut fun: F) -> Result<usize, TocError> {
+    let CatalogFile { catalog, filename, delimiter, .. } = *file;
+    let mut count = 0usize;
+    // leading BOM is not a part of the data, it is stripped before
+    // processing and is written back to keep the file layout unchanged
+    let buf = reader.fill_buf()?;
+    if buf.starts_with(UTF8_BOM.as_bytes()) {
+        reader.consume(UTF8_BOM.len());
+        writer.write_all(UTF8_BOM.as_bytes())?;
+    }
+    let mut line_num = 0usize;
+    let mut buf: Vec<u8> = Vec::new();
+    let mut stmt = String::new();
+    let mut stmt_line_num = 0usize;
+    loop {
+        buf.clear();
+        if 0 == reader.read_until(b'\n', &mut buf)? {
+            break;
+        }
+        line_num += 1;
+        // only LF is stripped, last line may have no EOL
+        let has_eol = buf.ends_with(b"\n");
+        let line_bytes = if has_eol {
+            &buf[..buf.len() - 1]
         } else {
-            let parts = line.split('\t').map(|st| st.to_string()).collect();
-            let parts_replaced = fun(parts)?;
-            parts_replaced.join("\t")
+            &buf[..]
         };
-        Ok(res)
+        if sql_statements {
+            // statements are parsed as SQL and must be valid UTF-8
+            let text = String::from_utf8(buf.clone())
+                .map_err(|e| rewrite_error(catalog, filename, Some(line_num), e.into()))?;
+            if stmt.is_empty() {
+                if !is_data_line(text.strip_suffix('\n').unwrap_or(&text)) {
+                    writer.write_all(&buf)?;
+                    continue;
+                }
+                stmt_line_num = line_num;
+            }
+            stmt.push_str(&text);
+            if is_complete_statement(&stmt) {
+                count += 1;
+                let rewritten = fun(vec!(std::mem::take(&mut stmt)))
+                    .map_err(|e| rewrite_error(catalog, filename, Some(stmt_line_num), e))?;
+                writer.write_all(rewritten.concat().as_bytes())?;
+            }
+        } else {
+            // rows in non-UTF8 server encodings are passed to the function decoded lossily,
+            // fields that are not changed by the function keep their original bytes
+            let line = String::from_utf8_lossy(line_bytes);
+            let rewritten = if is_data_line(&line) {
+                count += 1;
+                let parts: Vec<String> = split_copy_line(&line, delimiter).into_iter().map(|st| st.to_string()).collect();
+                let parts_count = parts.len();
+                let raw_parts = match line {
+                    Cow::Owned(_) if delimiter.is_ascii() => split_copy_line_raw(line_bytes, delimiter as u8),
+                    _ => Vec::new()
+                };
+                let parts_lossy = if raw_parts.len() == parts_count { parts.clone() } else { Vec::new() };
+                let parts_replaced: Vec<String> = fun(parts).map_err(|e| rewrite_error(catalog, filename, Some(line_num), e))?;
+                // row that changed shape no longer matches the COPY column list
+                if parts_replaced.len() != parts_count {
+                    return Err(rewrite_error(catalog, filename, Some(line_num), TocError::new(&format!(
+                        "Column count changed by rewrite, expected: {}, actual: {}", parts_count, parts_replaced.len()))));
+                }
+                let fields: Vec<&[u8]> = parts_replaced.iter().enumerate().map(|(idx, part)| {
+                    match (parts_lossy.get(idx), raw_parts.get(idx)) {
+                        (Some(lossy), Some(raw)) if lossy == part => *raw,
+                        _ => part.as_bytes()
+                    }
+                }).collect();
+                fields.join(delimiter.encode_utf8(&mut [0u8; 4]).as_bytes())
+            } else {
+                line_bytes.to_vec()
+            };
+            writer.write_all(&rewritten)?;
+            if has_eol {
+                writer.write_all("\n".as_bytes())?;
+            }
+        }
+    }
+    // unterminated statement at the end of file
+    if !stmt.is_empty() {
+        count += 1;
+        let rewritten = fun(vec!(stmt))
+            .map_err(|e| rewrite_error(catalog, filename, Some(stmt_line_num), e))?;
+        writer.write_all(rewritten.concat().as_bytes())?;
+    }
+    Ok(count)
+}
+
+fn rewrite_stream_with_checksums<R: Read, W: Write, F: FnMut(Vec<String>) -> Result<Vec<String>, TocError>>
+(file: &CatalogFile, reader: R, writer: W, sql_statements: bool, fun: F) -> Result<(usize, Option<ContentChecksums>, W), TocError> {
+    let mut reader = BufReader::new(ChecksumReader::new(reader, file.checksums)?);
+    let mut writer = ChecksumWriter::new(writer, file.checksums)?;
+    let rows_count = rewrite_stream(file, &mut reader, &mut writer, sql_statements, fun)?;
+    let before = reader.into_inner().hex_digest();
+    let (writer, after) = writer.into_parts();
+    let checksums = match (before, after) {
+        (Some(before), Some(after)) => Some((before, after)),
+        _ => None
+    };
+    Ok((rows_count, checksums, writer))
+}
+
+fn rewrite_catalog_internal<F: FnMut(Vec<String>) -> Result<Vec<String>, TocError>>
+(file: &CatalogFile, sql_statements: bool, progress: &dyn Fn(u64, u64), fun: F) -> Result<CatalogRewrite, TocError> {
+    let CatalogFile { dir_path, filename, compression, force_level, dest_dir_path, new_file, preserve_mtime, durable, restore, .. } = *file;
+    let mut src_path = dir_path.join(filename);
+    let mut final_path = if new_file {
+        dir_path.join(format!("{}.new", filename))
+    } else {
+        dest_dir_path.unwrap_or(dir_path).join(filename)
     };
-    let mut src_path = dir_path.join(format!("{}", filename));
-    let mut dest_path = dir_path.join(format!("{}.rewritten", filename));
     let mut orig_path = dir_path.join(format!("{}.orig", filename));
     if compression > 0 {
-        for path in vec!(&mut src_path, &mut dest_path, &mut orig_path).iter_mut() {
+        for path in [&mut src_path, &mut final_path, &mut orig_path] {
             utils::path_filename_append(path, ".gz")?;
         }
-        let mut reader = BufReader::new(GzDecoder::new(BufReader::new(File::open(&src_path)?)));
-        let mut writer = GzEncoder::new(BufWriter::new(File::create(&dest_path)?), Compression::new(compression as u32));
-        if line_by_line {
-            for ln in reader.lines() {
-                let line = ln?;
-                let rewritten = rewrite_line(line)?;
-                writer.write_all(rewritten.as_bytes())?;
-                writer.write_all("\n".as_bytes())?;
+    }
+    // removed if the rewrite fails
+    let dest_path = TempPath::new(&final_path)?;
+    let res = if compression > 0 {
+        let level = match force_level {
+            Some(level) => level,
+            None => gzip_level_from_xfl(read_gzip_xfl(&src_path)?, compression as u32)
+        };
+        let decoder = GzDecoder::new(BufReader::new(open_with_progress(&src_path, progress)?));
+        let writer = gzip_builder(&decoder).write(BufWriter::new(File::create(dest_path.path())?), Compression::new(level));
+        let (rows_count, checksums, writer) = rewrite_stream_with_checksums(file, decoder, writer, sql_statements, fun)?;
+        writer.finish()?.flush()?;
+        CatalogRewrite { rows_count, compression: level, checksums }
+    } else {
+        let reader = open_with_progress(&src_path, progress)?;
+        let writer = BufWriter::new(File::create(dest_path.path())?);
+        let (rows_count, checksums, mut writer) = rewrite_stream_with_checksums(file, reader, writer, sql_statements, fun)?;
+        writer.flush()?;
+        CatalogRewrite { rows_count, compression: 0, checksums }
+    };
+    let total = fs::metadata(&src_path)?.len();
+    progress(total, total);
+    if durable {
+        dest_path.sync()?;
+    }
+    copy_file_metadata(&src_path, dest_path.path(), preserve_mtime)?;
+    if dest_dir_path.is_none() && !new_file {
+        fs::rename(&src_path, &orig_path)?;
+        if let Some(restore) = restore {
+            restore.push(orig_path.clone(), src_path.clone());
+        }
+    }
+    dest_path.persist(&final_path)?;
+    if durable {
+        if let Some(parent) = final_path.parent() {
+            sync_dir(parent)?;
+        }
+    }
+    Ok(res)
+}
+
+// read-only counterpart of rewrite_catalog_internal
+pub(crate) fn read_catalog_contents(dir_path: &Path, filename: &str, compression: i32) -> Result<String, TocError> {
+    let mut bytes = Vec::new();
+    if compression > 0 {
+        let path = dir_path.join(format!("{}.gz", filename));
+        GzDecoder::new(BufReader::new(File::open(&path)?)).read_to_end(&mut bytes)?;
+    } else {
+        File::open(dir_path.join(filename))?.read_to_end(&mut bytes)?;
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+// counts data lines without loading the whole file into memory
+pub(crate) fn count_data_lines(dir_path: &Path, filename: &str, compression: i32) -> Result<usize, TocError> {
+    let mut reader: Box<dyn BufRead> = if compression > 0 {
+        let path = dir_path.join(format!("{}.gz", filename));
+        Box::new(BufReader::new(GzDecoder::new(BufReader::new(File::open(&path)?))))
+    } else {
+        Box::new(BufReader::new(File::open(dir_path.join(filename))?))
+    };
+    let mut count = 0usize;
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        if 0 == reader.read_until(b'\n', &mut buf)? {
+            break;
+        }
+        let line = buf.strip_suffix(b"\n").unwrap_or(&buf);
+        if !line.is_empty() && b"\\." != line {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+pub(crate) fn rewrite_catalog<F: FnMut(Vec<String>) -> Result<Vec<String>, TocError>>
+(file: &CatalogFile, progress: &dyn Fn(u64, u64), fun: F) -> Result<CatalogRewrite, TocError> {
+    rewrite_catalog_internal(file, false, progress, fun)
+        .map_err(|e| rewrite_error(file.catalog, file.filename, None, e))
+}
+
+// processes data files that contain SQL statements (like INSERTs) instead of COPY rows,
+// each statement is passed to the specified function separately
+pub(crate) fn rewrite_catalog_statements<F: Fn(String) -> Result<String, TocError>>
+(file: &CatalogFile, progress: &dyn Fn(u64, u64), fun: F) -> Result<CatalogRewrite, TocError> {
+    rewrite_catalog_internal(file, true, progress, |mut list| {
+        let stmt = list.remove(0);
+        let rewritten = fun(stmt)?;
+        Ok(vec!(rewritten))
+    }).map_err(|e| rewrite_error(file.catalog, file.filename, None, e))
+}
+
+// copies data file contents changing its compression, gzip header
+// fields are kept when both source and destination are compressed
+pub(crate) fn recompress_data_file(src_path: &Path, dest_path: &Path, src_compressed: bool, dest_level: Option<u32>) -> Result<(), TocError> {
+    let reader = BufReader::new(File::open(src_path)?);
+    let mut writer = BufWriter::new(File::create(dest_path)?);
+    if src_compressed {
+        let mut decoder = GzDecoder::new(reader);
+        match dest_level {
+            Some(level) => {
+                let mut encoder = gzip_builder(&decoder).write(writer, Compression::new(level));
+                io::copy(&mut decoder, &mut encoder)?;
+                encoder.finish()?.flush()?;
+            },
+            None => {
+                io::copy(&mut decoder, &mut writer)?;
+                writer.flush()?;
             }
-        } else {
-            let mut text = String::new();
-            let _ = reader.read_to_string(&mut text)?;
-            let single = vec!(text);
-            let rewritten_vec = fun(single)?;
-            writer.write_all(&rewritten_vec[0].as_bytes())?;
         }
     } else {
-        let mut reader = BufReader::new(File::open(&src_path)?);
-        let mut writer = BufWriter::new(File::create(&dest_path)?);
-        if line_by_line {
-            for ln in reader.lines() {
-                let line = ln?;
-                let rewritten = rewrite_line(line)?;
-                writer.write_all(rewritten.as_bytes())?;
-                writer.write_all("\n".as_bytes())?;
+        let mut reader = reader;
+        match dest_level {
+            Some(level) => {
+                let mut encoder = GzBuilder::new().write(writer, Compression::new(level));
+                io::copy(&mut reader, &mut encoder)?;
+                encoder.finish()?.flush()?;
+            },
+            None => {
+                io::copy(&mut reader, &mut writer)?;
+                writer.flush()?;
             }
-        } else {
-            let mut text = String::new();
-            let _ = reader.read_to_string(&mut text)?;
-            let single = vec!(text);
-            let rewritten_vec = fun(single)?;
-            writer.write_all(&rewritten_vec[0].as_bytes())?;
         }
     }
-    fs::rename(&src_path, &orig_path)?;
-    fs::rename(&dest_path, &src_path)?;
     Ok(())
 }
 
-pub(crate) fn rewrite_catalog<F: Fn(Vec<String>) -> Result<Vec<String>, TocError>>
-(dir_path: &Path, filename: &str, compression: i32, fun: F) -> Result<(), TocError> {
-    rewrite_catalog_internal(dir_path, filename, compression, true, fun)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
 
-pub(crate) fn rewrite_catalog_all_at_once<F: Fn(String) -> Result<String, TocError>>
-(dir_path: &Path, filename: &str, compression: i32, fun: F) -> Result<(), TocError> {
-    rewrite_catalog_internal(dir_path, filename, compression, false, |mut list| {
-        let text = list.remove(0);
-        let rewritten = fun(text)?;
-        Ok(vec!(rewritten))
-    })
+    fn catalog_file<'a>(dir_path: &'a Path, filename: &'a str, compression: i32, force_level: Option<u32>) -> CatalogFile<'a> {
+        CatalogFile { dir_path, catalog: "tab1", filename, compression, force_level, dest_dir_path: None, new_file: false, checksums: false, preserve_mtime: false, durable: false, delimiter: '\t', restore: None }
+    }
+
+    fn write_file(path: &Path, data: &[u8], compression: i32) {
+        if compression > 0 {
+            let mut writer = GzEncoder::new(File::create(path).unwrap(), Compression::new(compression as u32));
+            writer.write_all(data).unwrap();
+            writer.finish().unwrap();
+        } else {
+            fs::write(path, data).unwrap();
+        }
+    }
+
+    fn read_file(path: &Path, compression: i32) -> Vec<u8> {
+        let mut data = Vec::new();
+        if compression > 0 {
+            let mut reader = GzDecoder::new(BufReader::new(File::open(path).unwrap()));
+            reader.read_to_end(&mut data).unwrap();
+        } else {
+            data = fs::read(path).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn noop_rewrite_preserves_content() {
+        let work_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/rewrite_catalog_noop_test");
+        if work_dir.exists() {
+            fs::remove_dir_all(&work_dir).unwrap();
+        }
+        fs::create_dir_all(&work_dir).unwrap();
+
+        let contents: [&[u8]; 5] = [
+            b"test1_dbo\tdbo\t{}\n\\.\n\n\n",
+            b"test1_dbo\tdbo\t{}\n\\.",
+            b"test1_dbo\tdbo\t{}\r\ntest1_guest\tguest\t{}",
+            b"",
+            b"\xEF\xBB\xBFtest1_dbo\tdbo\t{}\n\\.\n",
+        ];
+        for compression in [0, 6] {
+            for (i, data) in contents.iter().enumerate() {
+                let filename = format!("{}_{}.dat", compression, i);
+                let mut path = work_dir.join(&filename);
+                if compression > 0 {
+                    utils::path_filename_append(&mut path, ".gz").unwrap();
+                }
+                write_file(&path, data, compression);
+                rewrite_catalog(&catalog_file(&work_dir, &filename, compression, None), &|_, _| {}, |rec| {
+                    Ok(rec)
+                }).unwrap();
+                assert_eq!(*data, read_file(&path, compression).as_slice());
+            }
+        }
+    }
+
+    #[test]
+    fn column_count_changed() {
+        let work_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/rewrite_catalog_column_count_test");
+        if work_dir.exists() {
+            fs::remove_dir_all(&work_dir).unwrap();
+        }
+        fs::create_dir_all(&work_dir).unwrap();
+        let data = b"test1_dbo\tdbo\t{}\ntest1_guest\tguest\t{}\n\\.\n";
+        write_file(&work_dir.join("1.dat"), data, 0);
+        let err = rewrite_catalog(&catalog_file(&work_dir, "1.dat", 0, None), &|_, _| {}, |mut rec| {
+            if "test1_guest" == rec[0] {
+                rec.pop();
+            }
+            Ok(rec)
+        }).unwrap_err();
+        match err {
+            TocError::DataFile { table, filename, line, source } => {
+                assert_eq!(table.as_deref(), Some("tab1"));
+                assert_eq!(filename, "1.dat");
+                assert_eq!(line, Some(2));
+                assert_eq!(source.to_string(), "Column count changed by rewrite, expected: 3, actual: 2");
+            },
+            _ => panic!("unexpected error: {}", err)
+        }
+        // source file is not modified
+        assert_eq!(data.as_slice(), fs::read(work_dir.join("1.dat")).unwrap().as_slice());
+        assert!(!work_dir.join("1.dat.orig").exists());
+    }
+
+    #[test]
+    fn statements_split() {
+        let work_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/rewrite_catalog_statements_test");
+        if work_dir.exists() {
+            fs::remove_dir_all(&work_dir).unwrap();
+        }
+        fs::create_dir_all(&work_dir).unwrap();
+        let stmt1 = "INSERT INTO tab1 (a, b) VALUES\n\t('foo', 'bar');\n";
+        let stmt2 = "INSERT INTO tab1 (a, b) VALUES\n\t('foo', 'multi\nline'');\n\nvalue');\n";
+        let data = format!("{}{}\n\n", stmt1, stmt2);
+        fs::write(work_dir.join("1.dat"), &data).unwrap();
+
+        let stmts = std::cell::RefCell::new(Vec::new());
+        let res = rewrite_catalog_statements(&catalog_file(&work_dir, "1.dat", 0, None), &|_, _| {}, |stmt| {
+            stmts.borrow_mut().push(stmt.clone());
+            Ok(stmt.replace("foo", "baz"))
+        }).unwrap();
+        assert_eq!(2, res.rows_count);
+        assert_eq!(vec!(stmt1.to_string(), stmt2.to_string()), stmts.into_inner());
+        assert_eq!(data.replace("foo", "baz"), fs::read_to_string(work_dir.join("1.dat")).unwrap());
+
+        let err = rewrite_catalog_statements(&catalog_file(&work_dir, "1.dat", 0, None), &|_, _| {}, |stmt| {
+            if stmt.contains("multi") {
+                Err(TocError::from_str("fail"))
+            } else {
+                Ok(stmt)
+            }
+        }).unwrap_err();
+        assert_eq!("Catalog rewrite error, table: tab1, file: 1.dat, line: 3, message: fail", err.to_string());
+    }
+
+    #[test]
+    fn gzip_header_preserved() {
+        let work_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/rewrite_catalog_gzip_header_test");
+        if work_dir.exists() {
+            fs::remove_dir_all(&work_dir).unwrap();
+        }
+        fs::create_dir_all(&work_dir).unwrap();
+        let path = work_dir.join("1.dat.gz");
+        let data = b"test1_dbo\tdbo\t{}\n\\.\n\n";
+        let header_len = 10 + "1.dat".len() + 1;
+
+        for (level, force_level, expected_level) in [(9, None, 9), (1, None, 1), (5, None, 6), (9, Some(1), 1)] {
+            let mut writer = GzBuilder::new()
+                .mtime(1700000000)
+                .operating_system(3)
+                .filename("1.dat")
+                .write(File::create(&path).unwrap(), Compression::new(level));
+            writer.write_all(data).unwrap();
+            writer.finish().unwrap();
+            let orig = fs::read(&path).unwrap();
+
+            let res = rewrite_catalog(&catalog_file(&work_dir, "1.dat", 6, force_level), &|_, _| {}, |rec| {
+                Ok(rec)
+            }).unwrap();
+            assert_eq!(expected_level, res.compression);
+            let rewritten = fs::read(&path).unwrap();
+            if force_level.is_none() {
+                assert_eq!(orig[..header_len], rewritten[..header_len]);
+            } else {
+                assert_eq!(orig[..8], rewritten[..8]);
+                assert_eq!(4, rewritten[8]);
+            }
+            assert_eq!(data, read_file(&path, 6).as_slice());
+            fs::remove_file(work_dir.join("1.dat.orig.gz")).unwrap();
+        }
+    }
 }
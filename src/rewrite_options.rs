@@ -0,0 +1,244 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+use std::panic;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use log::Level;
+
+use crate::archive_version::ArchiveVersion;
+use crate::rewrite_sql::SqlDialect;
+use crate::toc_error::TocError;
+
+/// Progress of the TOC rewrite reported to the progress callback.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressEvent {
+    /// TOC entries processed so far
+    TocEntries {
+        processed: usize,
+        total: usize
+    },
+    /// Bytes of the (compressed) catalog file processed so far
+    CatalogBytes {
+        catalog: String,
+        processed: u64,
+        total: u64
+    },
+    /// Data files copied into the output directory so far
+    CopyingFiles {
+        processed: usize,
+        total: usize
+    },
+    /// Rewritten TOC file is being moved in place of the original one
+    RenamingFiles,
+}
+
+/// Flag that stops the rewrite when set from another thread, for example, from a signal handler.
+///
+/// Clones share the same flag. The flag is checked between the rewrite steps (TOC entries,
+/// catalog and data files), the rewrite fails with `TocError::Cancelled` after the catalog files
+/// replaced in place are restored from backups, output directory is left as is.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests the cancellation, can be called multiple times.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Checks whether the cancellation was requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Options for `rewrite_toc_with_options`.
+///
+/// `Default` implementation corresponds to the behaviour of `rewrite_toc`, new options
+/// can be added in minor versions, so the instances are created with `RewriteOptions::default()`
+/// and then the required fields are set.
+#[derive(Default, Clone)]
+#[non_exhaustive]
+pub struct RewriteOptions<'a> {
+    /// Directory to write the rewritten dump into instead of modifying the source dump in place,
+    /// it is created if it does not exist and must be empty if it does
+    pub output_dir: Option<&'a Path>,
+    /// Write rewritten TOC into `toc.dat.new` and rewritten Babelfish catalogs into
+    /// `<file>.new` (`<file>.new.gz` for compressed dumps) files next to the originals,
+    /// original files are not modified and no backups are created, the caller is expected
+    /// to move `.new` files in place of the originals; cannot be used with `output_dir`
+    pub write_new_files: bool,
+    /// Resolve symlinks in the TOC path before the rewrite, so when `toc.dat` is a symlink
+    /// to a file in other dump directory, that directory is rewritten; by default catalogs
+    /// are read from, and backups and rewritten files are written to, the directory
+    /// of the specified path
+    pub follow_symlinks: bool,
+    /// Rewrite only the TOC file and leave Babelfish catalog files as is, for the case when
+    /// catalogs are stored separately or are already rewritten; catalogs are copied unchanged
+    /// when `output_dir` is specified
+    pub toc_only: bool,
+    /// Create hard links to the data files in the output directory instead of copying them,
+    /// files are copied if hard link cannot be created (for example, on a different file system)
+    pub hard_link_data_files: bool,
+    /// Copy files that are not referenced by TOC entries into the output directory,
+    /// by default these files are only reported
+    pub copy_unreferenced_files: bool,
+    /// Compression level from `0` to `9` to use for re-compressing Babelfish catalog files
+    /// instead of the level detected from the source files, has no effect on the uncompressed dumps
+    pub compression_level: Option<u32>,
+    /// New value for the `postgres_dbname` field in the TOC header, this is the name of the
+    /// physical PostgreSQL database the dump was taken from (for example, `wilton`), it is
+    /// distinct from the Babelfish logical DB name passed to `rewrite_toc_with_options`
+    /// and is left as is by default
+    pub postgres_dbname: Option<&'a str>,
+    /// Set the timestamp in the TOC header to the current local time, by default the time
+    /// of the original dump is kept; DST flag of the new timestamp is written as unknown
+    pub touch_timestamp: bool,
+    /// Compute SHA-256 of the decompressed contents of every Babelfish catalog file before
+    /// and after the rewrite and collect them into `RewriteSummary::catalog_checksums`,
+    /// requires `manifest` feature, rewrite fails if this feature is not enabled
+    pub catalog_checksums: bool,
+    /// Blank the owners of all TOC entries and remove `ALTER ... OWNER TO` statements instead
+    /// of remapping them, ACL entries that reference the original DB roles are removed (except
+    /// the ACL of the physical DB that is shared with other logical DBs), similar to
+    /// `pg_dump --no-owner`, `babelfish_authid_user_ext` catalog is still rewritten
+    pub strip_owners: bool,
+    /// Remove all `ACL` and `DEFAULT ACL` entries from TOC, similar to `pg_dump --no-acl`,
+    /// dump IDs of the remaining entries are not changed
+    pub drop_acls: bool,
+    /// Sort TOC entries by ascending dump ID before the rewrite, Babelfish catalogs data
+    /// is still reordered after sorting; `pg_restore` restores entries in TOC order, so
+    /// the rewrite fails if sorting would put any entry before one of its dependencies
+    pub sort_by_dump_id: bool,
+    /// Mapping from original tablespace names to new tablespace names, applied to the
+    /// tablespace field of TOC entries and to `TABLESPACE` clauses in their SQL,
+    /// tablespaces are left as is by default
+    pub tablespaces: Option<&'a HashMap<String, String>>,
+    /// Mapping from original schema names to new schema names that replaces or extends
+    /// the renames derived from the new DB name, for example to give a custom schema
+    /// a name that does not follow the `<dbname>_<schema>` pattern
+    pub schema_map: Option<&'a HashMap<String, String>>,
+    /// Mapping from original role names to new role names that replaces or extends
+    /// the renames derived from the new DB name
+    pub owner_map: Option<&'a HashMap<String, String>>,
+    /// Function applied to the suffix of every derived schema and role name, for example,
+    /// to `dbo` in `<dbname>_dbo`, the new name is `<dbname>_<transformed suffix>`;
+    /// suffixes are kept as is by default, `schema_map` and `owner_map` take precedence
+    pub suffix_transform: Option<&'a dyn Fn(&str) -> String>,
+    /// Validate the new DB name with `check_dbname_relaxed` instead of `check_dbname`,
+    /// this allows mixed case names, reserved keywords and `@`, `#`, `$` characters,
+    /// the name is lower-cased before it is used in the dump
+    pub allow_mixed_case: bool,
+    /// Add a warning to the summary instead of failing the rewrite when a schema or role
+    /// name derived from the new DB name is longer than `MAX_IDENTIFIER_LEN` bytes,
+    /// such names are truncated by PostgreSQL on restore and no longer match
+    /// the names in Babelfish catalogs
+    pub allow_long_names: bool,
+    /// Add a warning to the summary instead of failing the rewrite when Babelfish catalogs
+    /// already contain rows of other logical DB that use the new DB, schema or role names,
+    /// for the rare case when such DBs are merged intentionally
+    pub allow_name_collisions: bool,
+    /// Rewrite the dump in place even if it already uses the specified DB name, by default
+    /// nothing is written in this case and `RewriteSummary::no_change_needed` is set; like any
+    /// in-place rewrite, the forced one fails if backups of the previous run are present in
+    /// the dump directory; `output_dir` and `write_new_files` modes always write their output
+    pub force: bool,
+    /// Set the modification time of the rewritten TOC and Babelfish catalog files to the one
+    /// of the source files, by default they get the current time; permissions of the source
+    /// files (the read-only attribute on Windows) are kept in both cases
+    pub preserve_mtime: bool,
+    /// Flush the rewritten TOC and Babelfish catalog files to disk before they are renamed
+    /// in place of the originals and flush the dump directory after the renames, so a power
+    /// loss cannot leave a partially written `toc.dat`; with `output_dir` and `write_new_files`
+    /// the written and copied files are flushed the same way; disabled by default in the library
+    /// and enabled by default in the CLI. Only the rewrite uses this option, `set_compression`,
+    /// `renumber_toc` and `filter_toc` do not flush the files they write
+    pub durable: bool,
+    /// Archive format version to write the rewritten TOC with, can be used to restore
+    /// the dump into an older PostgreSQL; the rewrite fails if the dump contains tables
+    /// with access methods other than `heap` and the version does not support them,
+    /// the version of the source TOC is kept by default
+    pub target_version: Option<ArchiveVersion>,
+    /// Check with `verify_roundtrip` that TOC can be written back without changes before
+    /// modifying anything, the rewrite fails if the check fails
+    pub verify_roundtrip: bool,
+    /// Maximum length of a single string in the TOC file, reading fails if a longer
+    /// string length is encountered (usually in truncated or corrupted files),
+    /// `DEFAULT_MAX_STRING_LEN` is used by default
+    pub max_string_len: Option<usize>,
+    /// Maximum length in bytes of a single SQL statement in TOC entries and Babelfish catalogs
+    /// to tokenize when rewriting schema names, longer statements fail the rewrite with
+    /// `TocError::SqlTooLong`, there is no limit by default
+    pub max_sql_len: Option<usize>,
+    /// Dialect used to tokenize SQL of TOC entries when rewriting schema names,
+    /// `SqlDialect::PostgreSql` accepts PostgreSQL operators (like `#` and `@`) that
+    /// the default `SqlDialect::Generic` mistakes for the start of an identifier
+    pub sql_dialect: SqlDialect,
+    /// Dialect used to tokenize T-SQL stored in Babelfish catalogs (function signatures
+    /// and extended properties) when rewriting schema names, `SqlDialect::Tsql` also
+    /// rewrites names delimited with square brackets, `SqlDialect::Generic` is used by default
+    pub catalog_sql_dialect: SqlDialect,
+    /// Leave SQL statements that exceed `max_sql_len` unmodified and add a warning
+    /// to the summary instead of failing the rewrite
+    pub keep_oversized_sql: bool,
+    /// Function that receives a single-line message for every modified TOC entry (for example:
+    /// `entry 1234 SCHEMA tag test1_dbo -> foobar_dbo, owner test1_dbo -> foobar_dbo`)
+    /// and for every rewritten Babelfish catalog file, the same messages are also emitted
+    /// through the `log` facade: `debug` for modified entries, `info` for rewritten catalogs
+    /// and `warn` for the warnings added to the summary
+    pub log: Option<&'a dyn Fn(&str)>,
+    /// Function that receives progress events, panics raised by this function are ignored
+    pub progress: Option<&'a dyn Fn(ProgressEvent)>,
+    /// Token to stop the rewrite from another thread
+    pub cancellation: Option<&'a CancellationToken>,
+}
+
+impl RewriteOptions<'_> {
+    pub(crate) fn log(&self, level: Level, msg: &str) {
+        log::log!(target: "pgdump_toc_rewrite", level, "{}", msg);
+        if let Some(log) = self.log {
+            log(msg);
+        }
+    }
+
+    pub(crate) fn check_cancelled(&self) -> Result<(), TocError> {
+        match self.cancellation {
+            Some(token) if token.is_cancelled() => Err(TocError::Cancelled {
+                in_place: self.output_dir.is_none() && !self.write_new_files
+            }),
+            _ => Ok(())
+        }
+    }
+
+    pub(crate) fn progress(&self, event: ProgressEvent) {
+        if let Some(progress) = self.progress {
+            // progress reporting must not affect the rewrite
+            let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| progress(event)));
+        }
+    }
+}
@@ -16,121 +16,475 @@
 
 use std::collections::HashMap;
 
+use sqlparser::dialect::Dialect;
 use sqlparser::dialect::GenericDialect;
+use sqlparser::dialect::MsSqlDialect;
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::keywords::Keyword;
+use sqlparser::tokenizer::Word;
 use sqlparser::tokenizer::Token;
 use sqlparser::tokenizer::Tokenizer;
 use sqlparser::tokenizer::TokenWithLocation;
 
+use crate::keywords::KEYWORDS;
 use crate::toc_error::TocError;
 
 
-fn location_to_idx(lines: &Vec<&str>, twl: &TokenWithLocation) -> usize {
-    let TokenWithLocation{ token, location } = twl;
-    let mut res = 0usize;
-    for i in 0..location.line - 1 {
-        res += lines[i as usize].chars().count();
-    }
-    res += (location.line - 1) as usize; // EOLs
-    res += (location.column - 1) as usize;
-    if let Token::Word(word) = token {
-        if word.quote_style.is_some() {
-            res += 1;
+/// Dialect used to tokenize SQL when rewriting schema names.
+///
+/// Dialects differ in the characters that can start an identifier and in the delimited
+/// identifier syntax, SQL that is tokenized differently can fail to rewrite or can leave
+/// some names not rewritten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SqlDialect {
+    /// `sqlparser` generic dialect, `#` and `@` can start identifiers, `b'...'` and `r'...'`
+    /// are byte and raw string literals
+    #[default]
+    Generic,
+    /// PostgreSQL dialect, `#` and `@` are operators, suitable for the DDL in TOC entries
+    PostgreSql,
+    /// T-SQL dialect, identifiers can be delimited with square brackets (like `[dbo].[tab1]`),
+    /// suitable for the T-SQL stored in Babelfish catalogs, but not for PostgreSQL DDL
+    /// that uses array types
+    Tsql,
+}
+
+// char indices of the line starts, tokenizer counts every char (including tabs
+// and CRs) as a single column and only starts a new line on LF, so locations
+// are mapped using the same char iteration
+fn line_starts(sql: &str) -> Vec<usize> {
+    let mut res = vec!(0usize);
+    for (idx, ch) in sql.chars().enumerate() {
+        if '\n' == ch {
+            res.push(idx + 1);
         }
-    } else if let Token::SingleQuotedString(_) = token {
-        res += 1;
     }
     res
 }
 
-fn rewrite_schema_in_sql_internal(schemas: &HashMap<String, String>,
-                                  sql: &str,
-                                  qualified_only: bool,
-                                  single_quoted_only: bool
-) -> Result<String, TocError> {
-    let dialect = GenericDialect {};
-    let lines: Vec<&str> = sql.split('\n').collect();
-    let tokens = match Tokenizer::new(&dialect, sql).tokenize_with_location() {
-        Ok(tokens) => tokens,
-        Err(e) => return Err(TocError::new(&format!(
-            "Tokenizer error: {}, sql: {}", e, sql)))
+fn location_to_idx(line_starts: &[usize], twl: &TokenWithLocation) -> usize {
+    let TokenWithLocation{ location, .. } = twl;
+    line_starts[(location.line - 1) as usize] + (location.column - 1) as usize
+}
+
+fn quote_len(token: &Token) -> usize {
+    match token {
+        Token::Word(word) if word.quote_style.is_some() => 1,
+        Token::SingleQuotedString(_) => 1,
+        _ => 0
+    }
+}
+
+struct Replacement<'a> {
+    value_orig: &'a str,
+    value_replaced: &'a str,
+    start_idx: usize,
+    end_idx: usize,
+    // set for identifiers, quote char is set if the original identifier was quoted
+    identifier: Option<Option<char>>
+}
+
+// same rules as in postgres quote_identifier
+fn needs_quoting(ident: &str) -> bool {
+    let mut chars = ident.chars();
+    match chars.next() {
+        Some(ch) if ch.is_ascii_lowercase() || '_' == ch => {},
+        _ => return true
+    }
+    if !chars.all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || '_' == ch) {
+        return true;
+    }
+    KEYWORDS.contains(&ident)
+}
+
+// quotes of the original identifier are always kept (some Babelfish catalogs quote all
+// identifiers in signatures), quotes are added when the new name requires them
+fn format_replaced(rep: &Replacement) -> String {
+    match rep.identifier {
+        Some(Some('[')) => rep.value_replaced.replace(']', "]]"),
+        Some(Some(quote)) => rep.value_replaced.replace(quote, &format!("{}{}", quote, quote)),
+        Some(None) if needs_quoting(rep.value_replaced) => format!("\"{}\"", rep.value_replaced.replace('"', "\"\"")),
+        _ => rep.value_replaced.to_string()
+    }
+}
+
+// number of chars to include into error context before and after the error location
+const ERROR_CONTEXT_CHARS: usize = 40;
+
+// part of the line around the error location, only LF is
+// considered a line break, same as in tokenizer
+fn error_context(sql: &str, line: u64, column: u64) -> String {
+    let line_st = sql.split('\n').nth(line.saturating_sub(1) as usize).unwrap_or("");
+    let chars: Vec<char> = line_st.chars().collect();
+    let idx = (column.saturating_sub(1) as usize).min(chars.len());
+    let start = idx.saturating_sub(ERROR_CONTEXT_CHARS);
+    let end = (idx + ERROR_CONTEXT_CHARS).min(chars.len());
+    chars[start..end].iter().collect()
+}
+
+fn tokenize(sql: &str, dialect: SqlDialect) -> Result<Vec<TokenWithLocation>, TocError> {
+    let dialect: &dyn Dialect = match dialect {
+        SqlDialect::Generic => &GenericDialect {},
+        SqlDialect::PostgreSql => &PostgreSqlDialect {},
+        SqlDialect::Tsql => &MsSqlDialect {},
     };
-    let mut to_replace: Vec<(&str, &str, usize)> = Vec::new();
-    for i in 0..tokens.len() {
-        if qualified_only && !single_quoted_only {
-            if i >= tokens.len() - 1 {
-                continue;
-            }
-            let TokenWithLocation{ token, .. } = &tokens[i + 1];
-            if let Token::Period = token {
-                // success
-            } else {
-                continue;
-            }
-        }
-        let twl = &tokens[i];
-        let loc_idx = location_to_idx(&lines, twl);
-        let TokenWithLocation{ token, .. } = twl;
-        if single_quoted_only {
-            if let Token::SingleQuotedString(st) = token {
-                let old_schema = if qualified_only {
-                    let idx = st.find('.').ok_or(TocError::new(&format!(
-                        "Unexpected unqualified single-quoted entry: {}", st)))?;
-                    &st[..idx]
-                } else {
-                    st
-                };
-                if let Some(schema) = schemas.get(old_schema) {
-                    to_replace.push((old_schema, schema, loc_idx));
-                }
-            }
-        } else {
-            if let Token::Word(word) = token {
-                if let Some(schema) = schemas.get(&word.value) {
-                    to_replace.push((&word.value, schema, loc_idx));
-                }
-            }
-        }
+    match Tokenizer::new(dialect, sql).tokenize_with_location() {
+        Ok(tokens) => Ok(tokens),
+        Err(e) => Err(TocError::Tokenizer {
+            message: e.message,
+            line: e.location.line,
+            column: e.location.column,
+            context: error_context(sql, e.location.line, e.location.column)
+        })
     }
+}
 
-    let orig: Vec<char> = sql.chars().collect();
+// returns start and end indices of the token contents (without quotes)
+fn token_content_span(line_starts: &[usize], tokens: &[TokenWithLocation], i: usize, sql_len: usize) -> (usize, usize) {
+    let twl = &tokens[i];
+    let token_start_idx = location_to_idx(line_starts, twl);
+    let token_end_idx = if i < tokens.len() - 1 {
+        location_to_idx(line_starts, &tokens[i + 1])
+    } else {
+        sql_len
+    };
+    (token_start_idx + quote_len(&twl.token), token_end_idx - quote_len(&twl.token))
+}
+
+fn followed_by_period(tokens: &[TokenWithLocation], i: usize) -> bool {
+    if i >= tokens.len() - 1 {
+        return false;
+    }
+    matches!(tokens[i + 1].token, Token::Period)
+}
+
+fn preceded_by_period(tokens: &[TokenWithLocation], i: usize) -> bool {
+    i > 0 && matches!(tokens[i - 1].token, Token::Period)
+}
+
+// first part of 'database.schema.object' name
+fn is_three_part_name_start(tokens: &[TokenWithLocation], i: usize) -> bool {
+    if preceded_by_period(tokens, i) {
+        return false;
+    }
+    tokens.len() > i + 4 &&
+        matches!(tokens[i].token, Token::Word(_)) &&
+        matches!(tokens[i + 1].token, Token::Period) &&
+        matches!(tokens[i + 2].token, Token::Word(_)) &&
+        matches!(tokens[i + 3].token, Token::Period) &&
+        matches!(tokens[i + 4].token, Token::Word(_))
+}
+
+fn apply_replacements(sql: &str, orig: &[char], to_replace: Vec<Replacement>) -> Result<String, TocError> {
     let mut rewritten: Vec<char> = Vec::new();
     let mut last_idx = 0;
-    for (schema_orig, schema_replaced, start_idx) in to_replace {
-        for i in last_idx..start_idx {
-            rewritten.push(orig[i]);
+    for rep in to_replace {
+        // source text of the matched token (or of the schema part of a qualified
+        // literal) must be exactly the original value, otherwise we would rewrite
+        // only a part of a longer identifier
+        let orig_check: String = match orig.get(rep.start_idx..rep.end_idx) {
+            Some(chars) => chars.iter().collect(),
+            None => String::new()
+        };
+        if orig_check != rep.value_orig {
+            return Err(TocError::SqlRewrite(format!(
+                "Replace error, sql: {}, location: {}", sql, rep.start_idx)))
         }
-        for ch in schema_replaced.chars() {
-            rewritten.push(ch);
-        }
-        let orig_check: String = orig.iter().skip(start_idx).take(schema_orig.chars().count()).collect();
-        if orig_check != *schema_orig {
-            return Err(TocError::new(&format!(
-                "Replace error, sql: {}, location: {}", sql, start_idx)))
-        }
-        last_idx = start_idx + schema_orig.chars().count();
+        rewritten.extend_from_slice(&orig[last_idx..rep.start_idx]);
+        rewritten.extend(format_replaced(&rep).chars());
+        last_idx = rep.end_idx;
     }
 
     // tail
-    for i in last_idx..orig.len() {
-        rewritten.push(orig[i]);
-    }
+    rewritten.extend_from_slice(&orig[last_idx..]);
 
     let res: String = rewritten.into_iter().collect();
     Ok(res)
 }
 
+pub(crate) fn rewrite_schema_in_sql_internal(schemas: &HashMap<String, String>,
+                                             sql: &str,
+                                             qualified_only: bool,
+                                             single_quoted_only: bool,
+                                             max_len: Option<usize>,
+                                             dialect: SqlDialect
+) -> Result<String, TocError> {
+    // tokenizer memory usage grows with the input size
+    if let Some(limit) = max_len {
+        if sql.len() > limit {
+            return Err(TocError::SqlTooLong { length: sql.len(), limit });
+        }
+    }
+    let line_starts = line_starts(sql);
+    let tokens = tokenize(sql, dialect)?;
+    let orig: Vec<char> = sql.chars().collect();
+    let mut to_replace: Vec<Replacement> = Vec::new();
+    for i in 0..tokens.len() {
+        if qualified_only && !single_quoted_only && !followed_by_period(&tokens, i) {
+            continue;
+        }
+        let TokenWithLocation{ token, .. } = &tokens[i];
+        let (start_idx, content_end_idx) = token_content_span(&line_starts, &tokens, i, orig.len());
+        if single_quoted_only {
+            if let Token::SingleQuotedString(st) = token {
+                let period_idx = st.find('.');
+                if qualified_only && period_idx.is_none() {
+                    return Err(TocError::new(&format!(
+                        "Unexpected unqualified single-quoted entry: {}", st)));
+                }
+                let whole_match = if qualified_only { None } else { schemas.get(st) };
+                if let Some(schema) = whole_match {
+                    to_replace.push(Replacement {
+                        value_orig: st,
+                        value_replaced: schema,
+                        start_idx,
+                        end_idx: content_end_idx,
+                        identifier: None
+                    });
+                } else if let Some(idx) = period_idx {
+                    // qualified name inside the literal, like 'schema.object' in setval
+                    let old_schema = &st[..idx];
+                    if let Some(schema) = schemas.get(old_schema) {
+                        to_replace.push(Replacement {
+                            value_orig: old_schema,
+                            value_replaced: schema,
+                            start_idx,
+                            end_idx: start_idx + old_schema.chars().count(),
+                            identifier: None
+                        });
+                    }
+                }
+            }
+        } else if let Token::Word(word) = token {
+            // object or column part of a qualified name, like 'tab' in 'OWNED BY schema.tab.col',
+            // schema in the middle of 'database.schema.object' name is still rewritten
+            if preceded_by_period(&tokens, i) && i > 1 &&
+                matches!(&tokens[i - 2].token, Token::Word(prev) if schemas.contains_key(&prev.value)) {
+                continue;
+            }
+            if let Some(schema) = schemas.get(&word.value) {
+                to_replace.push(Replacement {
+                    value_orig: &word.value,
+                    value_replaced: schema,
+                    start_idx,
+                    end_idx: content_end_idx,
+                    identifier: Some(word.quote_style)
+                });
+            }
+        }
+    }
+
+    apply_replacements(sql, &orig, to_replace)
+}
+
+fn is_role_list_start(word: &Word) -> bool {
+    word.quote_style.is_none() && matches!(word.keyword,
+        Keyword::TO | Keyword::FROM | Keyword::BY | Keyword::AUTHORIZATION | Keyword::ROLE)
+}
+
+/// Rewrites schema names in qualified references in the specified SQL.
+///
+/// SQL is tokenized and only identifier tokens that are immediately followed by a period
+/// (like `schema.table` or `"schema".table`) are considered. Token value (without quotes)
+/// must be exactly equal to one of the keys in `schemas` map, identifiers that only contain
+/// the schema name as a prefix or a substring (like `schema_other.table`) are left as is.
+/// Quotes around the identifier are preserved, quotes are added if the new name
+/// requires quoting (for example, it contains upper case characters or is a keyword).
+/// Identifiers are treated as Unicode strings, names and surrounding SQL can contain
+/// any non-ASCII characters, non-ASCII names always require quoting.
+///
+/// # Arguments
+///
+/// * `schemas` - Mapping from original schema names to new schema names
+/// * `sql` - SQL text
 pub fn rewrite_schema_in_sql(schemas: &HashMap<String, String>, sql: &str) -> Result<String, TocError> {
-    rewrite_schema_in_sql_internal(schemas, sql, true, false)
+    rewrite_schema_in_sql_internal(schemas, sql, true, false, None, SqlDialect::Generic)
+}
+
+/// Rewrites schema names in qualified identifiers in the specified SQL tokenizing it with the specified dialect.
+///
+/// Same as `rewrite_schema_in_sql`, that uses `SqlDialect::Generic`.
+///
+/// # Arguments
+///
+/// * `schemas` - Mapping from original schema names to new schema names
+/// * `sql` - SQL text
+/// * `dialect` - Dialect to tokenize SQL with
+pub fn rewrite_schema_in_sql_with_dialect(schemas: &HashMap<String, String>, sql: &str, dialect: SqlDialect) -> Result<String, TocError> {
+    rewrite_schema_in_sql_internal(schemas, sql, true, false, None, dialect)
 }
 
+/// Rewrites schema names in all identifiers in the specified SQL.
+///
+/// Same as `rewrite_schema_in_sql`, but identifier tokens are rewritten even if they
+/// are not followed by a period. Token value must still be exactly equal to one of the
+/// keys in `schemas` map.
+///
+/// # Arguments
+///
+/// * `schemas` - Mapping from original schema names to new schema names
+/// * `sql` - SQL text
 pub fn rewrite_schema_in_sql_unqualified(schemas: &HashMap<String, String>, sql: &str) -> Result<String, TocError> {
-    rewrite_schema_in_sql_internal(schemas, sql, false, false)
+    rewrite_schema_in_sql_internal(schemas, sql, false, false, None, SqlDialect::Generic)
 }
 
+/// Rewrites schema names in single-quoted string literals in the specified SQL.
+///
+/// Literals whose whole content is exactly equal to one of the keys in `schemas`
+/// map (like `'schema'`) are rewritten. In literals that contain a qualified name
+/// (like `'schema.object'`) the part before the first period is rewritten when it is
+/// exactly equal to one of the keys, the rest of the literal is left as is. Identifiers
+/// are left as is. Literals with a prefix (like `E'schema'` or `N'schema'`) are not considered.
+///
+/// # Arguments
+///
+/// * `schemas` - Mapping from original schema names to new schema names
+/// * `sql` - SQL text
 pub fn rewrite_schema_in_sql_single_quoted(schemas: &HashMap<String, String>, sql: &str) -> Result<String, TocError> {
-    rewrite_schema_in_sql_internal(schemas, sql, false, true)
+    rewrite_schema_in_sql_internal(schemas, sql, false, true, None, SqlDialect::Generic)
 }
 
+/// Rewrites schema names in qualified names inside single-quoted string literals in the specified SQL.
+///
+/// Every single-quoted literal must contain a period (like `'schema.object'`), the part
+/// before the first period is rewritten when it is exactly equal to one of the keys
+/// in `schemas` map.
+///
+/// # Arguments
+///
+/// * `schemas` - Mapping from original schema names to new schema names
+/// * `sql` - SQL text
 pub fn rewrite_schema_in_sql_qualified_single_quoted(schemas: &HashMap<String, String>, sql: &str) -> Result<String, TocError> {
-    rewrite_schema_in_sql_internal(schemas, sql, true, true)
+    rewrite_schema_in_sql_internal(schemas, sql, true, true, None, SqlDialect::Generic)
+}
+
+/// Rewrites logical DB names in three-part qualified references in the specified SQL.
+///
+/// Only identifier tokens that start a `database.schema.object` name are considered,
+/// token value (without quotes) must be exactly equal to `dbname_from`. Schema part
+/// is not rewritten, use `rewrite_schema_in_sql` for it.
+///
+/// # Arguments
+///
+/// * `dbname_from` - Original logical DB name
+/// * `dbname_to` - New logical DB name
+/// * `sql` - SQL text
+pub fn rewrite_dbname_in_sql(dbname_from: &str, dbname_to: &str, sql: &str) -> Result<String, TocError> {
+    let line_starts = line_starts(sql);
+    let tokens = tokenize(sql, SqlDialect::Generic)?;
+    let orig: Vec<char> = sql.chars().collect();
+    let mut to_replace: Vec<Replacement> = Vec::new();
+    for i in 0..tokens.len() {
+        if !is_three_part_name_start(&tokens, i) {
+            continue;
+        }
+        if let Token::Word(word) = &tokens[i].token {
+            if word.value == dbname_from {
+                let (start_idx, end_idx) = token_content_span(&line_starts, &tokens, i, orig.len());
+                to_replace.push(Replacement {
+                    value_orig: &word.value,
+                    value_replaced: dbname_to,
+                    start_idx,
+                    end_idx,
+                    identifier: Some(word.quote_style)
+                });
+            }
+        }
+    }
+
+    apply_replacements(sql, &orig, to_replace)
+}
+
+fn is_tablespace_word(word: &Word) -> bool {
+    word.quote_style.is_none() && word.value.eq_ignore_ascii_case("tablespace")
+}
+
+/// Rewrites tablespace names in the specified SQL.
+///
+/// Only identifiers that immediately follow the `TABLESPACE` keyword are considered
+/// (like in `CREATE INDEX ... TABLESPACE ts1`, `USING INDEX TABLESPACE ts1` or
+/// `ALTER TABLE ... SET TABLESPACE ts1`). Token value must be exactly equal to one
+/// of the keys in `tablespaces` map.
+///
+/// # Arguments
+///
+/// * `tablespaces` - Mapping from original tablespace names to new tablespace names
+/// * `sql` - SQL text
+pub fn rewrite_tablespace_in_sql(tablespaces: &HashMap<String, String>, sql: &str) -> Result<String, TocError> {
+    let line_starts = line_starts(sql);
+    let tokens = tokenize(sql, SqlDialect::Generic)?;
+    let orig: Vec<char> = sql.chars().collect();
+    let mut to_replace: Vec<Replacement> = Vec::new();
+    let mut after_keyword = false;
+    for i in 0..tokens.len() {
+        match &tokens[i].token {
+            Token::Word(word) if after_keyword => {
+                after_keyword = false;
+                if let Some(tablespace) = tablespaces.get(&word.value) {
+                    let (start_idx, end_idx) = token_content_span(&line_starts, &tokens, i, orig.len());
+                    to_replace.push(Replacement {
+                        value_orig: &word.value,
+                        value_replaced: tablespace,
+                        start_idx,
+                        end_idx,
+                        identifier: Some(word.quote_style)
+                    });
+                }
+            },
+            Token::Word(word) => after_keyword = is_tablespace_word(word),
+            Token::Whitespace(_) => {},
+            _ => after_keyword = false
+        }
+    }
+
+    apply_replacements(sql, &orig, to_replace)
+}
+
+/// Rewrites role names in the specified SQL.
+///
+/// Only identifiers in role positions are considered: the lists that follow `TO`, `FROM`,
+/// `BY`, `AUTHORIZATION` and `ROLE` keywords (like in `GRANT ... TO role1, role2`,
+/// `ALTER ... OWNER TO role` or `SET SESSION AUTHORIZATION role`). The list ends with
+/// the first keyword or a non-identifier token. Qualified names are never rewritten. Token
+/// value must be exactly equal to one of the keys in `roles` map.
+///
+/// # Arguments
+///
+/// * `roles` - Mapping from original role names to new role names
+/// * `sql` - SQL text
+pub fn rewrite_role_in_sql(roles: &HashMap<String, String>, sql: &str) -> Result<String, TocError> {
+    let line_starts = line_starts(sql);
+    let tokens = tokenize(sql, SqlDialect::Generic)?;
+    let orig: Vec<char> = sql.chars().collect();
+    let mut to_replace: Vec<Replacement> = Vec::new();
+    let mut in_role_list = false;
+    for i in 0..tokens.len() {
+        match &tokens[i].token {
+            Token::Word(word) => {
+                if is_role_list_start(word) {
+                    in_role_list = true;
+                } else if in_role_list {
+                    match roles.get(&word.value) {
+                        Some(role) if !followed_by_period(&tokens, i) => {
+                            let (start_idx, end_idx) = token_content_span(&line_starts, &tokens, i, orig.len());
+                            to_replace.push(Replacement {
+                                value_orig: &word.value,
+                                value_replaced: role,
+                                start_idx,
+                                end_idx,
+                                identifier: Some(word.quote_style)
+                            });
+                        },
+                        _ => if word.quote_style.is_none() && Keyword::NoKeyword != word.keyword {
+                            in_role_list = false;
+                        }
+                    }
+                }
+            },
+            Token::Whitespace(_) | Token::Comma => {},
+            _ => in_role_list = false
+        }
+    }
+
+    apply_replacements(sql, &orig, to_replace)
 }
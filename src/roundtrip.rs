@@ -0,0 +1,142 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::error::Error;
+use std::fmt;
+use std::io::Cursor;
+use std::path::Path;
+
+use crate::resolve_toc_path;
+use crate::toc_error::TocError;
+use crate::toc_reader::TocReader;
+use crate::toc_writer::TocWriter;
+
+/// Failure of the TOC round-trip check.
+#[derive(Debug)]
+pub enum RoundtripMismatch {
+    /// TOC file cannot be read
+    Read(TocError),
+    /// TOC written back from the parsed header and entries differs from the original file
+    Bytes {
+        /// Offset of the first differing byte
+        offset: u64,
+        /// Index of the entry the offset falls inside, `None` for the header
+        /// and for the bytes after the last entry
+        entry_index: Option<usize>,
+        /// Size of the original file
+        original_len: u64,
+        /// Size of the written TOC
+        written_len: u64
+    },
+}
+
+impl fmt::Display for RoundtripMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(e) => write!(f, "{}", e),
+            Self::Bytes { offset, entry_index, original_len, written_len } => {
+                let location = match entry_index {
+                    Some(idx) => format!("entry index: {}", idx),
+                    None => "outside of entries".to_string()
+                };
+                write!(f, "TOC round-trip mismatch, offset: {}, {}, original size: {}, written size: {}",
+                       offset, location, original_len, written_len)
+            }
+        }
+    }
+}
+
+impl Error for RoundtripMismatch {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Read(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<TocError> for RoundtripMismatch {
+    fn from(value: TocError) -> Self {
+        Self::Read(value)
+    }
+}
+
+impl From<std::io::Error> for RoundtripMismatch {
+    fn from(value: std::io::Error) -> Self {
+        Self::Read(TocError::from(value))
+    }
+}
+
+impl From<RoundtripMismatch> for TocError {
+    fn from(value: RoundtripMismatch) -> Self {
+        match value {
+            RoundtripMismatch::Read(e) => e,
+            bytes => TocError::new(&bytes)
+        }
+    }
+}
+
+/// Checks that TOC file can be written back without changes.
+///
+/// TOC is parsed and written into memory, written bytes are compared with the contents
+/// of the original file. Mismatch means that the TOC uses some format feature that is
+/// not supported and the rewritten TOC would be broken.
+///
+/// # Arguments
+///
+/// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+pub fn verify_roundtrip<P: AsRef<Path>>(toc_path: P) -> Result<(), RoundtripMismatch> {
+    let original = std::fs::read(resolve_toc_path(toc_path)?)?;
+    verify_roundtrip_bytes(&original)
+}
+
+/// Checks that TOC contents can be written back without changes.
+///
+/// Same as `verify_roundtrip`, but TOC contents are passed in memory (for example, read from stdin).
+///
+/// # Arguments
+///
+/// * `original` - Contents of `pg_dump` TOC file
+pub fn verify_roundtrip_bytes(original: &[u8]) -> Result<(), RoundtripMismatch> {
+    let mut reader = TocReader::new(Cursor::new(original));
+    let (header, entries) = reader.build_index()?;
+
+    let mut written: Vec<u8> = Vec::with_capacity(original.len());
+    TocWriter::new(&mut written).write_toc(&header, &entries)?;
+
+    let mismatch = original.iter().zip(written.iter())
+        .position(|(a, b)| a != b)
+        .or(if original.len() != written.len() {
+            Some(original.len().min(written.len()))
+        } else {
+            None
+        });
+    let offset = match mismatch {
+        Some(offset) => offset as u64,
+        None => return Ok(())
+    };
+    let entry_index = if offset < reader.end_offset() {
+        reader.entry_offsets().iter().rposition(|start| *start <= offset)
+    } else {
+        None
+    };
+    Err(RoundtripMismatch::Bytes {
+        offset,
+        entry_index,
+        original_len: original.len() as u64,
+        written_len: written.len() as u64
+    })
+}
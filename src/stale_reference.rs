@@ -0,0 +1,76 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt;
+
+/// Reference to the original logical DB name left in a rewritten dump, found by `verify_rewritten`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaleReference {
+    /// Dump ID of the TOC entry, for Babelfish catalogs this is the `TABLE DATA` entry of the catalog
+    pub dump_id: i32,
+    /// Description of the entry, like `TABLE` or `TABLE DATA`
+    pub description: String,
+    /// Tag of the entry
+    pub tag: String,
+    /// Entry field that contains the reference (like `namespace` or `create_stmt`)
+    /// or the line number in the catalog data file (like `line 3`)
+    pub location: String,
+    /// Contents of the field or of the data line
+    pub value: String,
+}
+
+impl fmt::Display for StaleReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "entry {} {} {}, {}: {}", self.dump_id, self.description, self.tag, self.location, self.value.trim_end())
+    }
+}
+
+// '<dbname>_' at the start of an identifier, 'xtest1_dbo' does not match 'test1_'
+// names that are or start with the 'exclude' name are skipped, 'test1_copy' and 'test1_copy_dbo'
+// do not match 'test1_' when the dump was rewritten from 'test1' to 'test1_copy'
+pub(crate) fn contains_name_prefix(text: &str, prefix: &str, exclude: Option<&str>) -> bool {
+    text.match_indices(prefix).any(|(idx, _)| {
+        if let Some(rest) = exclude.and_then(|excl| text[idx..].strip_prefix(excl)) {
+            match rest.chars().next() {
+                Some(ch) if ch.is_alphanumeric() || '$' == ch => {},
+                _ => return false
+            }
+        }
+        match text[..idx].chars().next_back() {
+            Some(ch) => !(ch.is_alphanumeric() || '_' == ch || '$' == ch),
+            None => true
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_prefix() {
+        assert!(contains_name_prefix("test1_dbo", "test1_", None));
+        assert!(contains_name_prefix("CREATE TABLE \"test1_dbo\".tab1", "test1_", None));
+        assert!(contains_name_prefix("foobar_dbo\ttest1_dbo", "test1_", None));
+        assert!(!contains_name_prefix("CREATE TABLE xtest1_dbo.tab1", "test1_", None));
+        assert!(!contains_name_prefix("foobar_test1_dbo", "test1_", None));
+        assert!(!contains_name_prefix("test1", "test1_", None));
+        assert!(!contains_name_prefix("CREATE TABLE test1_copy_dbo.tab1", "test1_", Some("test1_copy")));
+        assert!(!contains_name_prefix("wilton\ttest1_copy\t{}", "test1_", Some("test1_copy")));
+        assert!(contains_name_prefix("test1_copy_dbo\ttest1_dbo", "test1_", Some("test1_copy")));
+        assert!(contains_name_prefix("test1_copyx_dbo", "test1_", Some("test1_copy")));
+    }
+}
@@ -0,0 +1,261 @@
+/*
+ * Copyright 2023, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use crate::toc_error::TocError;
+
+/// Name of the lock file that is created in the dump directory while it is being modified.
+pub const LOCK_FILENAME: &str = ".pgdump_toc_rewrite.lock";
+
+const TEMP_EXTENSION: &str = ".tmp";
+
+// pid and a random part, like '1234-5f3a9c0e'
+fn unique_suffix() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(COUNTER.fetch_add(1, Ordering::Relaxed));
+    format!("{}-{:08x}", process::id(), hasher.finish() as u32)
+}
+
+pub(crate) fn is_temp_filename(filename: &str) -> bool {
+    let suffix = match filename.strip_suffix(TEMP_EXTENSION).and_then(|name| name.rsplit_once('.')) {
+        Some((name, suffix)) if !name.is_empty() => suffix,
+        _ => return false
+    };
+    match suffix.split_once('-') {
+        Some((pid, random)) => !pid.is_empty() && pid.chars().all(|ch| ch.is_ascii_digit())
+            && 8 == random.len() && random.chars().all(|ch| ch.is_ascii_hexdigit()),
+        None => false
+    }
+}
+
+/// Checks whether the file in dump directory is the lock file or a temporary file
+/// created by this library, such files are not considered a part of the dump.
+pub(crate) fn is_service_file(filename: &str) -> bool {
+    LOCK_FILENAME == filename || is_temp_filename(filename)
+}
+
+/// Copies permissions (only the read-only attribute on Windows) and, optionally, the modification
+/// time of the source file to the rewritten file, so it does not get the default umask
+/// permissions in place of the restricted ones of the original.
+pub(crate) fn copy_file_metadata(src_path: &Path, dest_path: &Path, mtime: bool) -> Result<(), TocError> {
+    let meta = fs::metadata(src_path)?;
+    if mtime {
+        // before the permissions, read-only file cannot be opened for writing
+        let file = OpenOptions::new().write(true).open(dest_path)?;
+        file.set_modified(meta.modified()?)?;
+    }
+    fs::set_permissions(dest_path, meta.permissions())?;
+    Ok(())
+}
+
+/// Flushes renames of the files in the directory to disk, directories cannot be opened
+/// this way on Windows, where it does nothing.
+pub(crate) fn sync_dir(dir_path: &Path) -> Result<(), TocError> {
+    if cfg!(unix) {
+        File::open(dir_path)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Flushes the contents of a copied file to disk, on Windows only the files that can be
+/// opened for writing are flushed, read-only copies are left to the OS there.
+pub(crate) fn sync_file(path: &Path) -> Result<(), TocError> {
+    if cfg!(unix) {
+        File::open(path)?.sync_all()?;
+    } else if !fs::metadata(path)?.permissions().readonly() {
+        OpenOptions::new().write(true).open(path)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Temporary file next to the destination file, removed when dropped unless persisted.
+pub(crate) struct TempPath {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl TempPath {
+    // 'toc.dat' -> 'toc.dat.1234-5f3a9c0e.tmp'
+    pub(crate) fn new(dest_path: &Path) -> Result<Self, TocError> {
+        let filename = match dest_path.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return Err(TocError::new(&format!(
+                "Invalid file path: {}", dest_path.to_string_lossy())))
+        };
+        let path = dest_path.with_file_name(format!("{}.{}{}", filename, unique_suffix(), TEMP_EXTENSION));
+        Ok(Self { path, persisted: false })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Flushes the written contents to disk, must be called before the file is made read-only.
+    pub(crate) fn sync(&self) -> Result<(), TocError> {
+        OpenOptions::new().write(true).open(&self.path)?.sync_all()?;
+        Ok(())
+    }
+
+    pub(crate) fn persist(mut self, dest_path: &Path) -> Result<(), TocError> {
+        fs::rename(&self.path, dest_path)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Original files replaced with the rewritten ones during the operation, backups are
+/// renamed back over the rewritten files when dropped unless the operation is completed.
+///
+/// Files are registered through a shared reference, so the file writers can register
+/// each backup right after it is renamed.
+#[derive(Default)]
+pub(crate) struct BackupRestore {
+    // backup file and the rewritten file
+    files: RefCell<Vec<(PathBuf, PathBuf)>>,
+    // new files that do not replace the original ones (for example, when
+    // the compression suffix is changed), deleted before backups are renamed back
+    created: RefCell<Vec<PathBuf>>,
+    completed: bool,
+}
+
+impl BackupRestore {
+    pub(crate) fn push(&self, backup_path: PathBuf, path: PathBuf) {
+        self.files.borrow_mut().push((backup_path, path));
+    }
+
+    pub(crate) fn push_created(&self, path: PathBuf) {
+        self.created.borrow_mut().push(path);
+    }
+
+    pub(crate) fn complete(mut self) {
+        self.completed = true;
+    }
+}
+
+impl Drop for BackupRestore {
+    fn drop(&mut self) {
+        if self.completed {
+            return;
+        }
+        for path in self.created.borrow().iter() {
+            if let Err(e) = fs::remove_file(path) {
+                log::error!(target: "pgdump_toc_rewrite", "file {} cannot be removed: {}", path.to_string_lossy(), e);
+            }
+        }
+        for (backup_path, path) in self.files.borrow().iter().rev() {
+            match fs::rename(backup_path, path) {
+                Ok(_) => log::warn!(target: "pgdump_toc_rewrite", "file {} restored from backup",
+                    path.to_string_lossy()),
+                Err(e) => log::error!(target: "pgdump_toc_rewrite", "file {} cannot be restored from backup {}: {}",
+                    path.to_string_lossy(), backup_path.to_string_lossy(), e)
+            }
+        }
+    }
+}
+
+/// Lock file with the pid of the owning process, prevents concurrent modifications
+/// of the same dump directory, removed when dropped.
+pub(crate) struct DumpLock {
+    path: PathBuf,
+    // stale temporary files removed when the lock was acquired
+    removed_temp_files: Vec<PathBuf>,
+}
+
+impl DumpLock {
+    /// Creates the lock file and removes temporary files left by the previous runs
+    /// that crashed, fails if the lock file already exists.
+    pub(crate) fn acquire(dir_path: &Path) -> Result<Self, TocError> {
+        let path = dir_path.join(LOCK_FILENAME);
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                let pid = fs::read_to_string(&path).unwrap_or_default();
+                return Err(TocError::new(&format!(
+                    "Dump directory is being modified by another process, pid: {}, lock file: {}, \
+                    remove the lock file if that process is no longer running",
+                    pid.trim(), path.to_string_lossy())));
+            },
+            Err(e) => return Err(e.into())
+        };
+        let mut lock = Self { path, removed_temp_files: Vec::new() };
+        write!(file, "{}", process::id())?;
+        for en in dir_path.read_dir()? {
+            let filename = en?.file_name().to_string_lossy().to_string();
+            if is_temp_filename(&filename) {
+                log::warn!(target: "pgdump_toc_rewrite", "stale temporary file {} removed", filename);
+                let temp_path = dir_path.join(&filename);
+                fs::remove_file(&temp_path)?;
+                lock.removed_temp_files.push(temp_path);
+            }
+        }
+        Ok(lock)
+    }
+
+    pub(crate) fn removed_temp_files(&self) -> &[PathBuf] {
+        &self.removed_temp_files
+    }
+}
+
+impl Drop for DumpLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temp_filenames() {
+        let temp = TempPath::new(Path::new("dump/toc.dat")).unwrap();
+        let filename = temp.path().file_name().unwrap().to_string_lossy().to_string();
+        assert!(filename.starts_with(&format!("toc.dat.{}-", process::id())));
+        assert!(is_temp_filename(&filename));
+        assert!(is_service_file(&filename));
+        assert_ne!(temp.path(), TempPath::new(Path::new("dump/toc.dat")).unwrap().path());
+
+        assert!(is_temp_filename("1234.dat.gz.42-0123abcd.tmp"));
+        assert!(!is_temp_filename("1234.dat.gz"));
+        assert!(!is_temp_filename("notes.tmp"));
+        assert!(!is_temp_filename("1234.dat.42-xyz.tmp"));
+        assert!(!is_temp_filename(".42-0123abcd.tmp"));
+        assert!(is_service_file(LOCK_FILENAME));
+    }
+}
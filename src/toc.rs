@@ -0,0 +1,588 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::path::Path;
+
+use chrono::Local;
+use chrono::NaiveDateTime;
+
+use crate::archive_version::ArchiveVersion;
+use crate::compression_spec::CompressionSpec;
+use crate::dependency_graph::DependencyGraph;
+use crate::resolve_toc_path;
+use crate::toc_entry::TocEntry;
+use crate::toc_error::TocError;
+use crate::toc_filter::TocSection;
+use crate::toc_header::TocHeader;
+use crate::toc_datetime::TocDateTime;
+use crate::toc_reader::TocReader;
+use crate::toc_string::TocString;
+use crate::toc_writer::TocWriter;
+
+/// In-memory `pg_dump` TOC that can be modified and written back.
+///
+/// Entries count in TOC header is not stored separately, it is
+/// always derived from the list of entries when TOC is written.
+/// Any bytes that follow the last entry are kept as is and written back.
+#[derive(Debug, Clone)]
+pub struct Toc {
+    pub(crate) header: TocHeader,
+    pub(crate) entries: Vec<TocEntry>,
+    pub(crate) trailer: Vec<u8>,
+}
+
+impl Toc {
+    /// Reads `pg_dump` TOC file.
+    ///
+    /// # Arguments
+    ///
+    /// * `toc_path` - Path to `pg_dump` TOC file or to the dump directory containing it
+    pub fn read_from_path<P: AsRef<Path>>(toc_path: P) -> Result<Self, TocError> {
+        let toc_file = File::open(resolve_toc_path(toc_path)?)?;
+        let mut reader = TocReader::new(BufReader::new(toc_file));
+        let header = reader.read_header()?;
+        let mut entries = Vec::with_capacity(header.toc_count.max(0) as usize);
+        for _ in 0..header.toc_count {
+            entries.push(reader.read_entry()?);
+        }
+        let trailer = reader.read_trailer()?;
+        Ok(Self { header, entries, trailer })
+    }
+
+    /// Archive format version of this TOC.
+    pub fn archive_version(&self) -> ArchiveVersion {
+        ArchiveVersion::of_header(&self.header)
+    }
+
+    /// Changes archive format version this TOC is written with.
+    ///
+    /// Table access method `heap` is removed from entries when the version does not
+    /// support access methods, any other access method fails the change and the TOC
+    /// is left as is.
+    ///
+    /// # Arguments
+    ///
+    /// * `version` - Archive format version
+    pub fn set_archive_version(&mut self, version: ArchiveVersion) -> Result<(), TocError> {
+        version.apply(&mut self.header, &mut self.entries)
+    }
+
+    /// Changes the name of the physical PostgreSQL database stored in the TOC header.
+    ///
+    /// # Arguments
+    ///
+    /// * `dbname` - Database name
+    pub fn set_postgres_dbname(&mut self, dbname: &str) {
+        self.header.postgres_dbname = TocString::from_str(dbname);
+    }
+
+    /// Changes the PostgreSQL server version string stored in the TOC header.
+    ///
+    /// # Arguments
+    ///
+    /// * `version` - Version string, for example `15.4`
+    pub fn set_server_version(&mut self, version: &str) {
+        self.header.version_server = TocString::from_str(version);
+    }
+
+    /// Changes the `pg_dump` version string stored in the TOC header.
+    ///
+    /// # Arguments
+    ///
+    /// * `version` - Version string, for example `15.4`
+    pub fn set_pgdump_version(&mut self, version: &str) {
+        self.header.version_pgdump = TocString::from_str(version);
+    }
+
+    /// Writes TOC into the specified file, existing file is overwritten.
+    ///
+    /// # Arguments
+    ///
+    /// * `toc_path` - Path to destination TOC file
+    pub fn write_to_path<P: AsRef<Path>>(&self, toc_path: P) -> Result<(), TocError> {
+        let toc_file = File::create(toc_path)?;
+        let mut writer = TocWriter::new(BufWriter::new(toc_file));
+        writer.write_toc_with_trailer(&self.header, &self.entries, &self.trailer)
+    }
+
+    /// Builds the graph of dependencies between entries.
+    pub fn dependency_graph(&self) -> DependencyGraph {
+        DependencyGraph::from_entries(&self.entries)
+    }
+
+    /// Sorts entries by ascending dump ID.
+    ///
+    /// `pg_restore` restores entries in TOC order, so sorting can break the restore
+    /// when entry dependencies are not respected. Entries are left unchanged
+    /// and an error is returned if any entry would be put before one of its dependencies.
+    pub fn sort_by_dump_id(&mut self) -> Result<(), TocError> {
+        sort_entries_by_dump_id(&mut self.entries)
+    }
+
+    /// Compression of data files as specified in TOC header.
+    pub fn compression(&self) -> CompressionSpec {
+        CompressionSpec::from_header_value(self.header.compression)
+    }
+
+    /// Bytes that follow the last entry in TOC file, empty for TOC files written by `pg_dump`.
+    pub fn trailer(&self) -> &[u8] {
+        &self.trailer
+    }
+
+    /// Number of entries in TOC.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if TOC has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Dump IDs of all entries in TOC order.
+    pub fn dump_ids(&self) -> Vec<i32> {
+        self.entries.iter().map(|te| te.dump_id).collect()
+    }
+
+    /// Inserts a new entry into TOC at the specified position.
+    ///
+    /// New entry gets a dump ID that is greater than dump IDs of all existing entries.
+    /// All dependencies declared in the builder must refer to existing entries.
+    ///
+    /// Returns dump ID of the inserted entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `position` - Index in TOC entries list, entries starting from this index are shifted
+    /// * `builder` - Contents of the new entry
+    pub fn insert_entry(&mut self, position: usize, builder: TocEntryBuilder) -> Result<i32, TocError> {
+        if position > self.entries.len() {
+            return Err(TocError::new(&format!(
+                "Invalid entry position: {}, entries count: {}", position, self.entries.len())));
+        }
+        for dep in builder.deps.iter() {
+            if !self.entries.iter().any(|te| te.dump_id == *dep) {
+                return Err(TocError::new(&format!(
+                    "Dependency not found in TOC, dump_id: {}", dep)));
+            }
+        }
+        let dump_id = self.entries.iter().map(|te| te.dump_id).max().unwrap_or(0) + 1;
+        let te = builder.build(dump_id);
+        self.entries.insert(position, te);
+        Ok(dump_id)
+    }
+
+    /// Removes dependencies on dump IDs that are not present in TOC.
+    ///
+    /// `pg_dump` writes such dependencies for objects that are not dumped (for example,
+    /// data of extension configuration tables depends on the table itself), `pg_restore`
+    /// ignores them.
+    ///
+    /// Returns pairs of the entry dump ID and the removed dependency.
+    pub fn remove_missing_deps(&mut self) -> Vec<(i32, String)> {
+        let ids: Vec<String> = self.entries.iter().map(|te| te.dump_id.to_string()).collect();
+        let mut removed = Vec::new();
+        for te in self.entries.iter_mut() {
+            let dump_id = te.dump_id;
+            te.deps.retain(|dep| {
+                let dep_st = dep.to_string_lossy();
+                let present = ids.contains(&dep_st);
+                if !present {
+                    removed.push((dump_id, dep_st));
+                }
+                present
+            });
+        }
+        removed
+    }
+
+    /// Removes the entry with the specified dump ID along with dependencies of other entries on it.
+    ///
+    /// Returns `false` if there is no such entry in TOC.
+    ///
+    /// # Arguments
+    ///
+    /// * `dump_id` - Dump ID of the entry to remove
+    pub fn remove_entry(&mut self, dump_id: i32) -> bool {
+        let len_before = self.entries.len();
+        self.entries.retain(|te| te.dump_id != dump_id);
+        if self.entries.len() == len_before {
+            return false;
+        }
+        let dump_id_st = dump_id.to_string();
+        for te in self.entries.iter_mut() {
+            te.deps.retain(|dep| dep.to_string_lossy() != dump_id_st);
+        }
+        true
+    }
+}
+
+// pairs of dump ID and its dependency for entries that come before their dependencies
+pub(crate) fn dependency_order_violations(entries: &[TocEntry]) -> Vec<(i32, String)> {
+    let positions: HashMap<String, usize> = entries.iter().enumerate()
+        .map(|(idx, te)| (te.dump_id.to_string(), idx))
+        .collect();
+    let mut res = Vec::new();
+    for (idx, te) in entries.iter().enumerate() {
+        for dep in te.deps.iter() {
+            let dep_st = dep.to_string_lossy();
+            if let Some(dep_idx) = positions.get(&dep_st) {
+                if *dep_idx > idx {
+                    res.push((te.dump_id, dep_st));
+                }
+            }
+        }
+    }
+    res
+}
+
+pub(crate) fn dependency_order_error(violation: &(i32, String)) -> TocError {
+    TocError::new(&format!(
+        "Invalid entries order, dump_id: {} comes before its dependency: {}", violation.0, violation.1))
+}
+
+// entries that are present in TOC must come before the entries that depend on them
+pub(crate) fn validate_dependency_order(entries: &[TocEntry]) -> Result<(), TocError> {
+    match dependency_order_violations(entries).first() {
+        Some(violation) => Err(dependency_order_error(violation)),
+        None => Ok(())
+    }
+}
+
+pub(crate) fn sort_entries_by_dump_id(entries: &mut Vec<TocEntry>) -> Result<(), TocError> {
+    let mut sorted = entries.clone();
+    sorted.sort_by_key(|te| te.dump_id);
+    validate_dependency_order(&sorted)?;
+    *entries = sorted;
+    Ok(())
+}
+
+/// Assigns sequential dump IDs (starting from `1`) to TOC entries in their current order.
+///
+/// Dependencies of all entries are updated to the new dump IDs. TOC is left unchanged
+/// if any of the dependencies refers to a dump ID that is not present in TOC.
+/// Such dependencies are written by `pg_dump` for objects that are not dumped,
+/// they can be removed with `Toc::remove_missing_deps` before renumbering.
+///
+/// # Arguments
+///
+/// * `toc` - TOC to renumber
+pub fn renumber_dump_ids(toc: &mut Toc) -> Result<(), TocError> {
+    let mut ids: HashMap<String, i32> = HashMap::new();
+    for (idx, te) in toc.entries.iter().enumerate() {
+        ids.insert(te.dump_id.to_string(), idx as i32 + 1);
+    }
+    let mut deps_renumbered = Vec::with_capacity(toc.entries.len());
+    for te in toc.entries.iter() {
+        let mut deps = Vec::with_capacity(te.deps.len());
+        for dep in te.deps.iter() {
+            let dep_st = dep.to_string()?;
+            match ids.get(&dep_st) {
+                Some(id) => deps.push(TocString::from_string(id.to_string())),
+                None => return Err(TocError::new(&format!(
+                    "Dependency not found in TOC, dump_id: {}, dependency: {}", te.dump_id, dep_st)))
+            }
+        }
+        deps_renumbered.push(deps);
+    }
+    for (idx, (te, deps)) in toc.entries.iter_mut().zip(deps_renumbered).enumerate() {
+        te.dump_id = idx as i32 + 1;
+        te.deps = deps;
+    }
+    Ok(())
+}
+
+/// Contents of a new TOC entry without data, to be added with `Toc::insert_entry`
+/// or `TocBuilder::entry`.
+///
+/// Fields that are not set are written the same way as `pg_dump` writes
+/// them for entries like `ENCODING` or `SEARCHPATH`.
+#[derive(Debug, Clone)]
+pub struct TocEntryBuilder {
+    tag: String,
+    description: String,
+    section: TocSection,
+    create_stmt: Option<String>,
+    drop_stmt: Option<String>,
+    namespace: Option<String>,
+    owner: Option<String>,
+    deps: Vec<i32>,
+}
+
+impl TocEntryBuilder {
+    /// Creates a builder for the entry with the specified tag and description.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - Object name, like `SEARCHPATH` or table name
+    /// * `description` - Object type, like `TABLE`, `FUNCTION` or a custom string
+    pub fn new(tag: &str, description: &str) -> Self {
+        Self {
+            tag: tag.to_string(),
+            description: description.to_string(),
+            section: TocSection::PreData,
+            create_stmt: None,
+            drop_stmt: None,
+            namespace: None,
+            owner: None,
+            deps: Vec::new(),
+        }
+    }
+
+    /// Section of the entry, `PreData` by default.
+    pub fn section(mut self, section: TocSection) -> Self {
+        self.section = section;
+        self
+    }
+
+    /// SQL that is run by `pg_restore` to create the object.
+    pub fn create_stmt(mut self, sql: &str) -> Self {
+        self.create_stmt = Some(sql.to_string());
+        self
+    }
+
+    /// SQL that is run by `pg_restore --clean` to drop the object.
+    pub fn drop_stmt(mut self, sql: &str) -> Self {
+        self.drop_stmt = Some(sql.to_string());
+        self
+    }
+
+    /// Schema name of the object.
+    pub fn namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_string());
+        self
+    }
+
+    /// Owner role of the object.
+    pub fn owner(mut self, owner: &str) -> Self {
+        self.owner = Some(owner.to_string());
+        self
+    }
+
+    /// Adds a dependency on the existing entry with the specified dump ID, can be called multiple times.
+    pub fn dependency(mut self, dump_id: i32) -> Self {
+        self.deps.push(dump_id);
+        self
+    }
+
+    fn build(self, dump_id: i32) -> TocEntry {
+        TocEntry {
+            dump_id,
+            had_dumper: 0,
+            table_oid: TocString::from_str("0"),
+            catalog_oid: TocString::from_str("0"),
+            tag: TocString::from_string(self.tag),
+            description: TocString::from_string(self.description),
+            section: self.section.to_i32(),
+            create_stmt: TocString::from_string_opt(&self.create_stmt),
+            drop_stmt: TocString::from_string_opt(&self.drop_stmt),
+            copy_stmt: TocString::none(),
+            namespace: TocString::from_string_opt(&self.namespace),
+            tablespace: TocString::none(),
+            tableam: TocString::none(),
+            owner: TocString::from_string_opt(&self.owner),
+            table_with_oids: TocString::from_str("false"),
+            deps: self.deps.iter().map(|dep| TocString::from_string(dep.to_string())).collect(),
+            filename: TocString::empty(),
+        }
+    }
+}
+
+/// Builder for a new `pg_dump` TOC, for example, for synthetic test dumps.
+///
+/// Header describes a directory format dump of archive version 1.14 without
+/// compression created now by `pg_dump` 15. Entries get sequential dump IDs
+/// starting from 1 in the order they are added.
+#[derive(Debug, Clone)]
+pub struct TocBuilder {
+    version: ArchiveVersion,
+    compression: i32,
+    timestamp: NaiveDateTime,
+    postgres_dbname: String,
+    version_server: String,
+    version_pgdump: String,
+    entries: Vec<TocEntryBuilder>,
+}
+
+impl Default for TocBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TocBuilder {
+    /// Creates a builder for TOC without entries.
+    pub fn new() -> Self {
+        Self {
+            version: ArchiveVersion::V1_14,
+            compression: 0,
+            timestamp: Local::now().naive_local(),
+            postgres_dbname: "postgres".to_string(),
+            version_server: "15.4".to_string(),
+            version_pgdump: "15.4".to_string(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Archive format version, 1.14 by default.
+    pub fn archive_version(mut self, version: ArchiveVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Compression level of data files, `0` (no compression) by default.
+    pub fn compression(mut self, level: i32) -> Self {
+        self.compression = level;
+        self
+    }
+
+    /// Dump creation time, current local time by default.
+    pub fn timestamp(mut self, timestamp: NaiveDateTime) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Name of the PostgreSQL database the dump was taken from, `postgres` by default.
+    pub fn postgres_dbname(mut self, dbname: &str) -> Self {
+        self.postgres_dbname = dbname.to_string();
+        self
+    }
+
+    /// PostgreSQL server version, `15.4` by default.
+    pub fn server_version(mut self, version: &str) -> Self {
+        self.version_server = version.to_string();
+        self
+    }
+
+    /// `pg_dump` version, `15.4` by default.
+    pub fn pgdump_version(mut self, version: &str) -> Self {
+        self.version_pgdump = version.to_string();
+        self
+    }
+
+    /// Adds an entry to the end of TOC, the entry gets dump ID equal to
+    /// the number of entries added so far (including this one).
+    pub fn entry(mut self, entry: TocEntryBuilder) -> Self {
+        self.entries.push(entry);
+        self
+    }
+
+    /// Creates TOC that can be modified and written with `Toc::write_to_path`.
+    ///
+    /// Fails if any dependency refers to a dump ID that is not present in TOC.
+    pub fn build(self) -> Result<Toc, TocError> {
+        let count = self.entries.len() as i32;
+        let mut entries = Vec::with_capacity(self.entries.len());
+        for (idx, builder) in self.entries.into_iter().enumerate() {
+            let dump_id = idx as i32 + 1;
+            if let Some(dep) = builder.deps.iter().find(|dep| **dep < 1 || **dep > count) {
+                return Err(TocError::new(&format!(
+                    "Dependency not found in TOC, dump_id: {}, dependency: {}", dump_id, dep)));
+            }
+            entries.push(builder.build(dump_id));
+        }
+        let mut header = TocHeader {
+            magic: b"PGDMP".to_vec(),
+            version: Vec::new(),
+            // int size, offset size, directory format
+            flags: vec!(4, 8, 3),
+            compression: self.compression,
+            timestamp: TocDateTime::from_naive_date_time(&self.timestamp, Some(false)),
+            postgres_dbname: TocString::from_string(self.postgres_dbname),
+            version_server: TocString::from_string(self.version_server),
+            version_pgdump: TocString::from_string(self.version_pgdump),
+            toc_count: count,
+        };
+        self.version.apply(&mut header, &mut entries)?;
+        Ok(Toc { header, entries, trailer: Vec::new() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn two_entry_toc() -> Toc {
+        TocBuilder::new()
+            .timestamp(NaiveDate::from_ymd_opt(2024, 3, 1).unwrap().and_hms_opt(10, 20, 30).unwrap())
+            .postgres_dbname("wilton")
+            .entry(TocEntryBuilder::new("test1_dbo", "SCHEMA")
+                .create_stmt("CREATE SCHEMA test1_dbo;\n")
+                .drop_stmt("DROP SCHEMA test1_dbo;\n")
+                .owner("test1_db_owner"))
+            .entry(TocEntryBuilder::new("tab1", "TABLE")
+                .create_stmt("CREATE TABLE test1_dbo.tab1 (id integer);\n")
+                .namespace("test1_dbo")
+                .owner("test1_dbo")
+                .dependency(1))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn build_and_roundtrip() {
+        let toc = two_entry_toc();
+        assert_eq!(toc.dump_ids(), [1, 2]);
+        assert_eq!(toc.archive_version(), ArchiveVersion::V1_14);
+
+        let mut buf = Vec::new();
+        TocWriter::new(&mut buf).write_toc_with_trailer(&toc.header, &toc.entries, &toc.trailer).unwrap();
+        let mut reader = TocReader::new(Cursor::new(buf.clone()));
+        let header = reader.read_header().unwrap();
+        assert_eq!(header.magic, b"PGDMP");
+        assert_eq!(header.version, [1, 14, 0]);
+        assert_eq!(header.toc_count, 2);
+        assert_eq!(header.postgres_dbname.to_string().unwrap(), "wilton");
+        assert_eq!(header.timestamp.to_naive_date_time().unwrap().0.to_string(), "2024-03-01 10:20:30");
+        let entries = [reader.read_entry().unwrap(), reader.read_entry().unwrap()];
+        assert!(reader.read_trailer().unwrap().is_empty());
+        assert_eq!(entries[0].tag.to_string().unwrap(), "test1_dbo");
+        assert_eq!(entries[0].owner.to_string().unwrap(), "test1_db_owner");
+        assert_eq!(entries[1].dump_id, 2);
+        assert_eq!(entries[1].description.to_string().unwrap(), "TABLE");
+        assert_eq!(entries[1].namespace.to_string().unwrap(), "test1_dbo");
+        assert_eq!(entries[1].deps.iter().map(|dep| dep.to_string_lossy()).collect::<Vec<_>>(), ["1"]);
+
+        // written again without changes
+        let mut rewritten = Vec::new();
+        TocWriter::new(&mut rewritten).write_toc(&header, &entries).unwrap();
+        assert_eq!(buf, rewritten);
+    }
+
+    #[test]
+    fn build_errors() {
+        let err = TocBuilder::new()
+            .entry(TocEntryBuilder::new("tab1", "TABLE").dependency(2))
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("dump_id: 1, dependency: 2"));
+
+        let toc = TocBuilder::new()
+            .archive_version(ArchiveVersion::V1_12)
+            .entry(TocEntryBuilder::new("ENCODING", "ENCODING"))
+            .build()
+            .unwrap();
+        assert_eq!(toc.archive_version(), ArchiveVersion::V1_12);
+    }
+}
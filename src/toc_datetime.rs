@@ -14,6 +14,7 @@
  * limitations under the License.
  */
 
+use std::convert::TryFrom;
 use std::fmt;
 
 use chrono::Datelike;
@@ -24,6 +25,7 @@ use chrono::Timelike;
 
 use crate::toc_error::TocError;
 
+// same fields as C 'struct tm' that pg_dump writes, month is 0-based and year is counted from 1900
 #[derive(Default, Debug, Clone, PartialEq)]
 pub(crate) struct TocDateTime {
     pub(crate) second: i32,
@@ -48,29 +50,39 @@ impl TocDateTime {
         }
     }
 
-    pub(crate) fn from_naive_date_time(ndt: &NaiveDateTime, is_dst: bool) -> Self {
+    // is_dst is None when DST is unknown, pg_dump writes -1 in this case
+    pub(crate) fn from_naive_date_time(ndt: &NaiveDateTime, is_dst: Option<bool>) -> Self {
         Self {
             second: ndt.second() as i32,
             minute: ndt.minute() as i32,
             hour: ndt.hour() as i32,
             day: ndt.day() as i32,
-            month: ndt.month() as i32,
-            year: (ndt.year() - 1900) as i32,
+            month: ndt.month0() as i32,
+            year: ndt.year() - 1900,
             is_dst: match is_dst {
-                true => 1,
-                false => 0
+                Some(true) => 1,
+                Some(false) => 0,
+                None => -1
             }
         }
     }
 
-    pub(crate) fn to_naive_date_time(&self) -> Result<(NaiveDateTime, bool), TocError> {
-        let date = NaiveDate::from_ymd_opt(self.year + 1900, self.month as u32, self.day as u32)
+    pub(crate) fn to_naive_date_time(&self) -> Result<(NaiveDateTime, Option<bool>), TocError> {
+        // fields come from the dump as is, out of range values must not overflow
+        let date = self.month.checked_add(1)
+            .and_then(|month| u32::try_from(month).ok())
+            .zip(self.year.checked_add(1900))
+            .and_then(|(month, year)| NaiveDate::from_ymd_opt(year, month, self.day as u32))
             .ok_or(TocError::new(&format!(
-                "Invalid date: {}-{}-{}" , self.year + 1900, self.month, self.day)))?;
+                "Invalid date: {}-{}-{}" , i64::from(self.year) + 1900, i64::from(self.month) + 1, self.day)))?;
         let time = NaiveTime::from_hms_opt(self.hour as u32, self.minute as u32, self.second as u32)
             .ok_or(TocError::new(&format!(
                 "Invalid time: {}:{}:{}", self.hour, self.minute, self.second)))?;
-        Ok((NaiveDateTime::new(date, time), self.is_dst > 0))
+        let is_dst = match self.is_dst {
+            dst if dst < 0 => None,
+            dst => Some(dst > 0)
+        };
+        Ok((NaiveDateTime::new(date, time), is_dst))
     }
 }
 
@@ -83,3 +95,28 @@ impl fmt::Display for TocDateTime {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn month_boundaries() {
+        // January 2024 as written by pg_dump
+        let january = TocDateTime::new(30, 20, 10, 15, 0, 124, 0);
+        let (ndt, _) = january.to_naive_date_time().unwrap();
+        assert_eq!(ndt.to_string(), "2024-01-15 10:20:30");
+        assert_eq!(TocDateTime::from_naive_date_time(&ndt, Some(false)), january);
+
+        let december = TocDateTime::new(55, 42, 17, 22, 11, 123, 0);
+        let (ndt, _) = december.to_naive_date_time().unwrap();
+        assert_eq!(ndt.to_string(), "2023-12-22 17:42:55");
+        assert_eq!(TocDateTime::from_naive_date_time(&ndt, Some(false)), december);
+
+        assert!(TocDateTime::new(0, 0, 0, 1, 12, 123, 0).to_naive_date_time().is_err());
+        assert!(TocDateTime::new(0, 0, 0, 1, -1, 123, 0).to_naive_date_time().is_err());
+        let err = TocDateTime::new(0, 0, 0, 1, i32::MAX, i32::MAX, 0).to_naive_date_time().unwrap_err();
+        assert_eq!(err.to_string(), format!("Invalid date: {}-{}-1", i64::from(i32::MAX) + 1900, i64::from(i32::MAX) + 1));
+        assert_eq!(TocDateTime::new(0, 0, 0, 1, i32::MAX, 123, 0).to_string(), "Invalid date");
+    }
+}
@@ -73,6 +73,11 @@ impl TocEntry {
     pub(crate) fn from_json(json: &TocEntryJson) -> Result<Self, TocError> {
         let mut deps = Vec::with_capacity(json.deps.len());
         for opt in &json.deps {
+            // null string terminates the list of dependencies in TOC
+            if opt.is_none() {
+                return Err(TocError::new(&format!(
+                    "Dependency cannot be null, dump_id: {}", json.dump_id)));
+            }
             deps.push(TocString::from_string_opt(opt));
         }
         Ok(Self {
@@ -145,7 +150,6 @@ pub(crate) struct TocEntryJson {
 
 #[cfg(test)]
 mod tests {
-    use serde_json;
     use super::*;
 
     #[test]
@@ -166,7 +170,7 @@ mod tests {
             tableam: TocString::from_str("foobar10"),
             owner: TocString::from_str("foobar11"),
             table_with_oids: TocString::from_str("foobar12"),
-            deps: vec!(TocString::from_str("foobar13"), TocString::from_str("foobar14"), TocString::none()),
+            deps: vec!(TocString::from_str("foobar13"), TocString::from_str("foobar14"), TocString::empty()),
             filename: TocString::from_str("foobar15"),
         };
 
@@ -192,4 +196,31 @@ mod tests {
         assert_eq!(orig.deps, parsed.deps);
         assert_eq!(orig.filename, parsed.filename);
     }
+
+    #[test]
+    fn json_empty_and_null() {
+        let orig = TocEntry {
+            tablespace: TocString::empty(),
+            tableam: TocString::none(),
+            owner: TocString::empty(),
+            deps: vec!(TocString::empty()),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(orig.to_json().unwrap()).unwrap();
+        assert_eq!(json["tablespace"], "");
+        assert!(json["tableam"].is_null());
+        assert_eq!(json["owner"], "");
+        assert!(json["filename"].is_null());
+        let parsed = TocEntry::from_json(&serde_json::from_value(json.clone()).unwrap()).unwrap();
+        assert_eq!(orig.tablespace, parsed.tablespace);
+        assert_eq!(orig.tableam, parsed.tableam);
+        assert_eq!(orig.owner, parsed.owner);
+        assert_eq!(orig.deps, parsed.deps);
+        assert_eq!(orig.filename, parsed.filename);
+
+        let mut json = json;
+        json["deps"] = serde_json::json!(["1", null]);
+        let err = TocEntry::from_json(&serde_json::from_value(json).unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "Dependency cannot be null, dump_id: 0");
+    }
 }
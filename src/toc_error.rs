@@ -14,57 +14,234 @@
  * limitations under the License.
  */
 
+use std::error::Error;
 use std::fmt;
 
-use serde_json;
+use crate::dbname::MAX_IDENTIFIER_LEN;
 
+/// Error returned by TOC reading, writing and rewriting functions.
 #[derive(Debug)]
-pub struct TocError {
-    message: String
+#[non_exhaustive]
+pub enum TocError {
+    /// File system or other I/O error
+    Io(std::io::Error),
+    /// TOC string or data file contents are not valid UTF-8
+    Utf8(std::string::FromUtf8Error),
+    /// Invalid timestamp in TOC header
+    DateTime(chrono::format::ParseError),
+    /// JSON parsing or serialization error
+    Json(serde_json::Error),
+    /// TOC file does not start with `PGDMP` magic
+    MagicMismatch,
+    /// Archive version of TOC file is not supported
+    UnsupportedVersion,
+    /// Integer size, offset size or archive format in TOC header are not supported
+    UnsupportedFormat(String),
+    /// TOC file contains invalid values (for example, it is truncated or corrupted)
+    Corrupt {
+        /// Offset in TOC file of the field that cannot be read
+        offset: u64,
+        /// Description of the problem and the field being read
+        context: String
+    },
+    /// Dump does not contain Babelfish catalogs or schemas
+    NotBabelfishDump(String),
+    /// Babelfish catalog table data is not found in TOC
+    CatalogMissing(String),
+    /// Rewritten SQL does not match the original text at the location of the replaced value
+    SqlRewrite(String),
+    /// SQL statement cannot be tokenized
+    Tokenizer {
+        /// Tokenizer error message
+        message: String,
+        /// Line number of the error location, starting from 1
+        line: u64,
+        /// Column number of the error location, starting from 1
+        column: u64,
+        /// Part of the SQL text line around the error location
+        context: String
+    },
+    /// Error processing TOC entry
+    Entry {
+        /// Dump ID of the entry
+        dump_id: i32,
+        /// Description of the entry, like `TABLE` or `VIEW`
+        description: String,
+        /// Tag of the entry
+        tag: String,
+        /// Cause of the error
+        source: Box<TocError>
+    },
+    /// Error processing Babelfish catalog or table data file
+    DataFile {
+        /// Name of the Babelfish catalog table, `None` for table data files
+        table: Option<String>,
+        /// Data file name as specified in TOC (like `1234.dat`)
+        filename: String,
+        /// Line number in the decompressed file, starting from 1
+        line: Option<usize>,
+        /// Cause of the error
+        source: Box<TocError>
+    },
+    /// SQL statement is longer than the configured limit and was not tokenized
+    SqlTooLong {
+        /// Length of the statement in bytes
+        length: usize,
+        /// Configured limit in bytes
+        limit: usize
+    },
+    /// Schema or role name derived from the new DB name exceeds `MAX_IDENTIFIER_LEN`
+    NameTooLong {
+        /// Original schema or role name
+        orig_name: String,
+        /// New schema or role name
+        name: String,
+        /// Length of the new name in bytes
+        length: usize
+    },
+    /// Names that the rewrite would produce are already used by other logical DB in Babelfish
+    /// catalogs, contains the list of conflicts like `babelfish_sysdatabases: foobar`
+    NameCollision(Vec<String>),
+    /// JSON passed to `write_toc_from_json` does not describe a valid TOC
+    InvalidJson {
+        /// Path to the problem field, like `header.toc_count` or `entries[2].deps`
+        field: String,
+        /// Description of the problem
+        message: String
+    },
+    /// Specified DB name cannot be used
+    InvalidDbName(String),
+    /// Rewrite was stopped with `CancellationToken`
+    Cancelled {
+        /// Whether the dump was rewritten in place, files replaced so far were restored
+        /// then, otherwise the files written into the output directory or with `.new`
+        /// suffix so far are left as is
+        in_place: bool
+    },
+    /// Other errors
+    Generic(String),
 }
 
 impl TocError {
+    /// Creates `Generic` error with the message of the specified value.
     pub fn new<E: fmt::Display>(e: &E) -> Self {
-        Self {
-            message: format!("{}", e)
-        }
+        Self::Generic(format!("{}", e))
     }
 
+    /// Creates `Generic` error with the specified message.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(st: &str) -> Self {
-        Self {
-            message: format!("{}", st)
+        Self::Generic(st.to_string())
+    }
+
+    /// Short snake-case name of the error variant, like `io` or `invalid_db_name`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Io(_) => "io",
+            Self::Utf8(_) => "utf8",
+            Self::DateTime(_) => "date_time",
+            Self::Json(_) => "json",
+            Self::MagicMismatch => "magic_mismatch",
+            Self::UnsupportedVersion => "unsupported_version",
+            Self::UnsupportedFormat(_) => "unsupported_format",
+            Self::Corrupt { .. } => "corrupt",
+            Self::NotBabelfishDump(_) => "not_babelfish_dump",
+            Self::CatalogMissing(_) => "catalog_missing",
+            Self::SqlRewrite(_) => "sql_rewrite",
+            Self::Tokenizer { .. } => "tokenizer",
+            Self::Entry { .. } => "entry",
+            Self::DataFile { .. } => "data_file",
+            Self::SqlTooLong { .. } => "sql_too_long",
+            Self::NameTooLong { .. } => "name_too_long",
+            Self::NameCollision(_) => "name_collision",
+            Self::InvalidJson { .. } => "invalid_json",
+            Self::InvalidDbName(_) => "invalid_db_name",
+            Self::Cancelled { .. } => "cancelled",
+            Self::Generic(_) => "generic",
         }
     }
 }
 
 impl fmt::Display for TocError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.message)
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Utf8(e) => write!(f, "{}", e),
+            Self::DateTime(e) => write!(f, "{}", e),
+            Self::Json(e) => write!(f, "{}", e),
+            Self::MagicMismatch => write!(f, "Magic check failure"),
+            Self::UnsupportedVersion => write!(f, "Version check failure"),
+            Self::UnsupportedFormat(message) => write!(f, "{}", message),
+            Self::Corrupt { offset, context } => write!(f, "{}, offset: {}", context, offset),
+            Self::NotBabelfishDump(message) => write!(f, "{}", message),
+            Self::CatalogMissing(catalog) => write!(f, "Catalog table not found: {}", catalog),
+            Self::SqlRewrite(message) => write!(f, "{}", message),
+            Self::Tokenizer { message, line, column, context } => write!(f,
+                "Tokenizer error: {}, line: {}, column: {}, context: {}", message, line, column, context),
+            Self::Entry { dump_id, description, tag, source } => write!(f,
+                "TOC entry error, dump_id: {}, description: {}, tag: {}, message: {}", dump_id, description, tag, source),
+            Self::DataFile { table, filename, line, source } => {
+                match table {
+                    Some(table) => write!(f, "Catalog rewrite error, table: {}, ", table)?,
+                    None => write!(f, "Table data rewrite error, ")?
+                }
+                write!(f, "file: {}, ", filename)?;
+                if let Some(line) = line {
+                    write!(f, "line: {}, ", line)?;
+                }
+                write!(f, "message: {}", source)
+            },
+            Self::SqlTooLong { length, limit } => write!(f,
+                "SQL statement is too long, length: {}, limit: {}", length, limit),
+            Self::NameTooLong { orig_name, name, length } => write!(f,
+                "Name is too long, original name: {}, new name: {}, length: {}, limit: {}",
+                orig_name, name, length, MAX_IDENTIFIER_LEN),
+            Self::NameCollision(conflicts) => write!(f,
+                "New names already exist in the dump: {}", conflicts.join(", ")),
+            Self::InvalidJson { field, message } => write!(f,
+                "Invalid TOC JSON, field: {}, message: {}", field, message),
+            Self::InvalidDbName(dbname) => write!(f, "Invalid db name specified: [{}]", dbname),
+            Self::Cancelled { in_place: true } => write!(f, "Operation cancelled, original files were restored"),
+            Self::Cancelled { in_place: false } => write!(f, "Operation cancelled, source files were not modified, files written so far were left as is"),
+            Self::Generic(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for TocError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Utf8(e) => Some(e),
+            Self::DateTime(e) => Some(e),
+            Self::Json(e) => Some(e),
+            Self::Entry { source, .. } => Some(source.as_ref()),
+            Self::DataFile { source, .. } => Some(source.as_ref()),
+            _ => None
+        }
     }
 }
 
 impl From<std::io::Error> for TocError {
     fn from(value: std::io::Error) -> Self {
-        Self::new(&value)
+        Self::Io(value)
     }
 }
 
 impl From<std::string::FromUtf8Error> for TocError {
     fn from(value: std::string::FromUtf8Error) -> Self {
-        Self::new(&value)
+        Self::Utf8(value)
     }
 }
 
 impl From<chrono::format::ParseError> for TocError {
     fn from(value: chrono::format::ParseError) -> Self {
-        Self::new(&value)
+        Self::DateTime(value)
     }
 }
 
 impl From<serde_json::Error> for TocError {
     fn from(value: serde_json::Error) -> Self {
-        Self::new(&value)
+        Self::Json(value)
     }
 }
-
-
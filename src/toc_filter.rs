@@ -0,0 +1,154 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::toc_entry::TocEntry;
+use crate::toc_error::TocError;
+
+
+/// Section of the TOC entry, same as `teSection` in `pg_dump`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TocSection {
+    /// Entries that are not a part of any section (like `ENCODING` or `ACL`)
+    None,
+    /// Schema definitions that are restored before data (like `TABLE` or `FUNCTION`)
+    PreData,
+    /// Table data and large objects
+    Data,
+    /// Schema definitions that are restored after data (like `INDEX` or `CONSTRAINT`)
+    PostData,
+}
+
+impl TocSection {
+    pub(crate) fn from_i32(section: i32) -> Option<Self> {
+        match section {
+            1 => Some(Self::None),
+            2 => Some(Self::PreData),
+            3 => Some(Self::Data),
+            4 => Some(Self::PostData),
+            _ => None
+        }
+    }
+
+    pub(crate) fn to_i32(self) -> i32 {
+        match self {
+            Self::None => 1,
+            Self::PreData => 2,
+            Self::Data => 3,
+            Self::PostData => 4,
+        }
+    }
+}
+
+/// Read-only view of the TOC entry that is passed to `filter_toc` predicates.
+///
+/// Missing string fields are represented as empty strings.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct TocEntryView {
+    /// Dump ID of the entry
+    pub dump_id: i32,
+    /// Section of the entry, `None` if section value is not known
+    pub section: Option<TocSection>,
+    /// Description (object type) of the entry, like `TABLE` or `TABLE DATA`
+    pub description: String,
+    /// Schema name of the object
+    pub namespace: String,
+    /// Object name
+    pub tag: String,
+    /// Owner role of the object
+    pub owner: String,
+    /// Name of the data file (without `.gz` extension)
+    pub filename: String,
+}
+
+impl TocEntryView {
+    pub(crate) fn from_entry(te: &TocEntry) -> Result<Self, TocError> {
+        Ok(Self {
+            dump_id: te.dump_id,
+            section: TocSection::from_i32(te.section),
+            description: te.description.to_string()?,
+            namespace: te.namespace.to_string()?,
+            tag: te.tag.to_string()?,
+            owner: te.owner.to_string()?,
+            filename: te.filename.to_string()?,
+        })
+    }
+}
+
+/// Options for `filter_toc_with_options`.
+#[derive(Default, Debug, Clone)]
+pub struct FilterOptions {
+    /// Delete data files that are no longer referenced by TOC entries, only applies
+    /// when the filtered TOC is written into the source dump directory
+    pub delete_unreferenced_files: bool,
+    /// Create hard links to the data files in the destination directory instead of copying them,
+    /// files are copied if hard link cannot be created (for example, on a different file system)
+    pub hard_link_data_files: bool,
+}
+
+/// Summary of the TOC filtering.
+#[derive(Default, Debug, Clone)]
+pub struct FilterSummary {
+    /// Dump IDs of the entries that were removed from TOC
+    pub removed_entries: Vec<i32>,
+    /// Names of the data files that are no longer referenced by TOC entries
+    pub unreferenced_files: Vec<String>,
+}
+
+/// Predicate for `filter_toc` that keeps only entries from the specified sections.
+///
+/// Schema-only dump can be produced by keeping `None`, `PreData` and `PostData` sections,
+/// `None` section contains `ENCODING`, `SEARCHPATH` and `ACL` entries that are
+/// usually needed on restore.
+///
+/// # Arguments
+///
+/// * `sections` - Sections to keep
+pub fn include_sections(sections: &[TocSection]) -> impl Fn(&TocEntryView) -> bool {
+    let sections = sections.to_vec();
+    move |te| match te.section {
+        Some(section) => sections.contains(&section),
+        None => false
+    }
+}
+
+/// Predicate for `filter_toc` that removes all entries with the specified tags.
+///
+/// # Arguments
+///
+/// * `tags` - Tags (object names) of the entries to remove
+pub fn exclude_tags<S: AsRef<str>>(tags: &[S]) -> impl Fn(&TocEntryView) -> bool {
+    let tags: Vec<String> = tags.iter().map(|tag| tag.as_ref().to_string()).collect();
+    move |te| !tags.contains(&te.tag)
+}
+
+/// Predicate for `filter_toc` that removes `TABLE DATA` entries for the specified tables.
+///
+/// Table definitions are kept. Table names can be specified either as plain
+/// tags (like `tab1`) or qualified with the schema name (like `test1_dbo.tab1`).
+///
+/// # Arguments
+///
+/// * `tags` - Names of the tables to remove data for
+pub fn exclude_table_data<S: AsRef<str>>(tags: &[S]) -> impl Fn(&TocEntryView) -> bool {
+    let tags: Vec<String> = tags.iter().map(|tag| tag.as_ref().to_string()).collect();
+    move |te| {
+        if "TABLE DATA" != te.description {
+            return true;
+        }
+        let qualified = format!("{}.{}", te.namespace, te.tag);
+        !tags.iter().any(|tag| *tag == te.tag || *tag == qualified)
+    }
+}
@@ -20,6 +20,10 @@ use chrono::naive::NaiveDateTime;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::json_check::bytes_from_hex;
+use crate::json_check::MAGIC_LEN;
+use crate::json_check::FLAGS_LEN;
+use crate::json_check::VERSION_LEN;
 use crate::toc_datetime::TocDateTime;
 use crate::toc_error::TocError;
 use crate::toc_string::TocString;
@@ -59,9 +63,9 @@ impl TocHeader {
     pub(crate) fn from_json(json: &TocHeaderJson) -> Result<Self, TocError> {
         let ndt = NaiveDateTime::parse_from_str(&json.timestamp, "%Y-%m-%d %H:%M:%S")?;
         Ok(Self {
-            magic: json.magic.iter().map(|hex| u8::from_str_radix(hex, 16).unwrap_or(0)).collect(),
-            version: json.version.iter().map(|hex| u8::from_str_radix(hex, 16).unwrap_or(0)).collect(),
-            flags: json.flags.iter().map(|hex| u8::from_str_radix(hex, 16).unwrap_or(0)).collect(),
+            magic: bytes_from_hex(&json.magic, "header.magic", Some(MAGIC_LEN))?,
+            version: bytes_from_hex(&json.version, "header.version", Some(VERSION_LEN))?,
+            flags: bytes_from_hex(&json.flags, "header.flags", Some(FLAGS_LEN))?,
             compression: json.compression,
             timestamp: TocDateTime::from_naive_date_time(&ndt, json.is_dst),
             postgres_dbname: TocString::from_string_opt(&json.postgres_dbname),
@@ -81,7 +85,10 @@ impl fmt::Display for TocHeader {
         match self.timestamp.to_naive_date_time() {
             Ok((ndt, is_dst)) => {
                 writeln!(f, "Timestamp: {}", ndt)?;
-                writeln!(f, "DST: {}", is_dst)?;
+                match is_dst {
+                    Some(dst) => writeln!(f, "DST: {}", dst)?,
+                    None => writeln!(f, "DST: unknown")?
+                };
             },
             Err(_) => writeln!(f, "Invalid date")?
         };
@@ -100,7 +107,8 @@ pub(crate) struct TocHeaderJson {
     flags: Vec<String>,
     compression: i32,
     timestamp: String,
-    is_dst: bool,
+    // null when DST is unknown
+    is_dst: Option<bool>,
     postgres_dbname: Option<String>,
     version_server: Option<String>,
     version_pgdump: Option<String>,
@@ -109,13 +117,12 @@ pub(crate) struct TocHeaderJson {
 
 #[cfg(test)]
 mod tests {
-    use serde_json;
     use super::*;
 
     #[test]
     fn json_roundtrip() {
         let orig = TocHeader {
-            magic: vec!(41, 42, 43),
+            magic: vec!(41, 42, 43, 44, 45),
             version: vec!(42, 43, 44),
             flags: vec!(43, 44, 45),
             compression: 6,
@@ -139,4 +146,40 @@ mod tests {
         assert_eq!(orig.version_pgdump, parsed.version_pgdump);
         assert_eq!(orig.toc_count, parsed.toc_count);
     }
+
+    #[test]
+    fn json_unknown_dst() {
+        let orig = TocHeader {
+            magic: b"PGDMP".to_vec(),
+            version: vec!(1, 14, 0),
+            flags: vec!(4, 8, 3),
+            timestamp: TocDateTime::new(1, 2, 3, 4, 5, 120, -1),
+            ..Default::default()
+        };
+
+        let json = orig.to_json().unwrap();
+        assert_eq!(json.is_dst, None);
+        let parsed = TocHeader::from_json(&json).unwrap();
+        assert_eq!(parsed.timestamp.is_dst, -1);
+    }
+
+    #[test]
+    fn json_invalid_hex() {
+        let orig = TocHeader {
+            magic: b"PGDMP".to_vec(),
+            version: vec!(1, 14, 0),
+            flags: vec!(4, 8, 3),
+            timestamp: TocDateTime::new(1, 2, 3, 4, 5, 120, 0),
+            ..Default::default()
+        };
+        let mut json = orig.to_json().unwrap();
+        json.flags[1] = "1g".to_string();
+        let err = TocHeader::from_json(&json).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid TOC JSON, field: header.flags, message: expected hex byte at index: 1, found: '1g'");
+        json.flags[1] = "+8".to_string();
+        assert!(TocHeader::from_json(&json).is_err());
+        json.flags.pop();
+        let err = TocHeader::from_json(&json).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid TOC JSON, field: header.flags, message: expected length: 3, actual: 2");
+    }
 }
\ No newline at end of file
@@ -14,8 +14,12 @@
  * limitations under the License.
  */
 
+use std::io;
 use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 
+use crate::archive_version::ArchiveVersion;
 use crate::toc_entry::TocEntry;
 use crate::toc_error::TocError;
 use crate::toc_header::TocHeader;
@@ -23,70 +27,150 @@ use crate::toc_string::TocString;
 use crate::toc_datetime::TocDateTime;
 use crate::utils;
 
+/// Default limit for the length of a single string read from TOC file.
+///
+/// Largest strings in TOC are `CREATE` statements, this limit is much larger than any
+/// real-world statement and only protects from allocating garbage lengths read from
+/// truncated or corrupted files.
+pub const DEFAULT_MAX_STRING_LEN: usize = 8 * 1024 * 1024;
+
 pub(crate) struct TocReader<R: Read> {
-    reader: R
+    reader: R,
+    offsets: Vec<u64>,
+    end_offset: u64,
+    max_string_len: usize,
+    // size of the integer magnitude in bytes, from header flags
+    int_size: usize,
+    // entries of older versions have no table access method field
+    version: ArchiveVersion,
+    // parse position, used only for error messages
+    position: u64,
+    field: &'static str,
+    field_offset: u64,
+    entry_index: Option<usize>,
+    next_entry_index: usize,
 }
 
 impl<R: Read> TocReader<R> {
 
     pub(crate) fn new(reader: R) -> Self {
         Self {
-            reader
+            reader,
+            offsets: Vec::new(),
+            end_offset: 0,
+            max_string_len: DEFAULT_MAX_STRING_LEN,
+            int_size: 4,
+            version: ArchiveVersion::V1_14,
+            position: 0,
+            field: "magic",
+            field_offset: 0,
+            entry_index: None,
+            next_entry_index: 0,
+        }
+    }
+
+    pub(crate) fn with_max_string_len(mut self, max_string_len: usize) -> Self {
+        self.max_string_len = max_string_len;
+        self
+    }
+
+    fn begin_field(&mut self, field: &'static str) {
+        self.field = field;
+        self.field_offset = self.position;
+    }
+
+    fn location(&self) -> String {
+        match self.entry_index {
+            Some(idx) => format!("entry index: {}, field: {}", idx, self.field),
+            None => format!("header field: {}", self.field)
         }
     }
 
+    fn context(&self) -> String {
+        format!("{}, offset: {}", self.location(), self.field_offset)
+    }
+
+    fn corrupt(&self, message: &str) -> TocError {
+        TocError::Corrupt {
+            offset: self.field_offset,
+            context: format!("{}, {}", message, self.location())
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), TocError> {
+        if let Err(e) = self.reader.read_exact(buf) {
+            return Err(TocError::Io(io::Error::new(e.kind(), format!(
+                "Error reading TOC, {}, message: {}", self.context(), e))));
+        }
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
     pub(crate) fn read_magic(&mut self) -> Result<Vec<u8>, TocError> {
         let mut buf  = utils::zero_vec(5usize);
-        self.reader.read_exact( buf.as_mut_slice())?;
+        self.read_exact( buf.as_mut_slice())?;
         if [b'P', b'G', b'D', b'M', b'P'] != buf.as_slice() {
-            return Err(TocError::from_str("Magic check failure"))
+            return Err(TocError::MagicMismatch)
         };
         Ok(buf)
     }
 
     pub(crate) fn read_version(&mut self) -> Result<Vec<u8>, TocError> {
         let mut buf  = utils::zero_vec(3usize);
-        self.reader.read_exact( buf.as_mut_slice())?;
-        if 1u8 != buf[0] || 14u8 != buf[1] {
-            return Err(TocError::from_str("Version check failure"))
-        }
+        self.read_exact( buf.as_mut_slice())?;
+        let version = match ArchiveVersion::from_minor(buf[1]) {
+            Some(version) if 1u8 == buf[0] => version,
+            _ => return Err(TocError::UnsupportedVersion)
+        };
+        self.version = version;
         Ok(buf)
     }
 
     pub(crate) fn read_flags(&mut self) -> Result<Vec<u8>, TocError> {
         let mut buf = utils::zero_vec(3usize);
-        self.reader.read_exact( &mut buf)?;
-        if 4u8 != buf[0] {
-            return Err(TocError::from_str("Int size check failed"))
+        self.read_exact( &mut buf)?;
+        // values are always 32-bit, wider ints only have zero upper bytes
+        if 4u8 != buf[0] && 8u8 != buf[0] {
+            return Err(TocError::UnsupportedFormat(format!(
+                "Int size check failed, expected: 4 or 8, actual: {}", buf[0])))
         }
-        if 8u8 != buf[1] {
-            return Err(TocError::from_str("Offset check failed"))
+        // offsets are not stored in TOC of directory format dumps
+        if 4u8 != buf[1] && 8u8 != buf[1] {
+            return Err(TocError::UnsupportedFormat(format!(
+                "Offset size check failed, expected: 4 or 8, actual: {}", buf[1])))
         }
         if 3u8 != buf[2] {
-            return Err(TocError::from_str("Format check failed"))
+            return Err(TocError::UnsupportedFormat(format!(
+                "Format check failed, expected: 3 (directory), actual: {}", buf[2])))
         }
+        self.int_size = buf[0] as usize;
         Ok(buf)
     }
 
     pub(crate) fn read_int(&mut self) -> Result<i32, TocError> {
-        let mut buf = [0u8; 5];
-        self.reader.read_exact( &mut buf)?;
-        let mut res: u32 = 0;
+        let mut buf = [0u8; 9];
+        let len = 1 + self.int_size;
+        self.read_exact(&mut buf[..len])?;
+        let mut res: u64 = 0;
         let mut shift: u32 = 0;
-        for i in 1..buf.len() {
-            let bv: u8 = buf[i];
-            let iv: u32 = (bv as u32) & 0xFF;
+        for bv in buf[..len].iter().skip(1) {
+            let iv: u64 = (*bv as u64) & 0xFF;
             if iv != 0 {
-                res = res + (iv << shift);
+                res += iv << shift;
             }
             shift += 8;
         }
-        let res_signed = res as i32;
-        if buf[0] > 0 {
-            Ok(-res_signed)
-        } else {
-            Ok(res_signed)
-        }
+        // magnitude of i32::MIN does not fit into i32
+        let res_signed = match buf[0] {
+            0 if res <= i32::MAX as u64 => res as i32,
+            1 if res <= i32::MAX as u64 => -(res as i32),
+            1 if res == i32::MIN.unsigned_abs() as u64 => i32::MIN,
+            0 | 1 => return Err(self.corrupt(&format!(
+                "Integer magnitude out of range, sign: {}, magnitude: {}", buf[0], res))),
+            _ => return Err(self.corrupt(&format!(
+                "Invalid integer sign byte: {}", buf[0])))
+        };
+        Ok(res_signed)
     }
 
     pub(crate) fn read_datetime(&mut self) -> Result<TocDateTime, TocError> {
@@ -108,24 +192,41 @@ impl<R: Read> TocReader<R> {
         if 0 == len {
             return Ok(TocString::empty())
         }
-        let mut buf: Vec<u8> = Vec::with_capacity(len as usize);
-        for _ in 0..len {
-            buf.push(0u8);
+        if len as usize > self.max_string_len {
+            return Err(self.corrupt(&format!(
+                "String length exceeds the limit, the file may be truncated or corrupted, \
+                length: {}, limit: {}", len, self.max_string_len)));
         }
-        self.reader.read_exact(buf.as_mut_slice())?;
+        let mut buf = utils::zero_vec(len as usize);
+        self.read_exact(buf.as_mut_slice())?;
         Ok(TocString::new(buf))
     }
 
+    fn read_int_field(&mut self, field: &'static str) -> Result<i32, TocError> {
+        self.begin_field(field);
+        self.read_int()
+    }
+
+    fn read_string_field(&mut self, field: &'static str) -> Result<TocString, TocError> {
+        self.begin_field(field);
+        self.read_string()
+    }
+
     pub(crate) fn read_header(&mut self) -> Result<TocHeader, TocError> {
+        self.entry_index = None;
+        self.begin_field("magic");
         let magic = self.read_magic()?;
+        self.begin_field("version");
         let version = self.read_version()?;
+        self.begin_field("flags");
         let flags = self.read_flags()?;
-        let compression = self.read_int()?;
+        let compression = self.read_int_field("compression")?;
+        self.begin_field("timestamp");
         let timestamp = self.read_datetime()?;
-        let postgres_dbname = self.read_string()?;
-        let version_server = self.read_string()?;
-        let version_pgdump = self.read_string()?;
-        let toc_count = self.read_int()?;
+        let postgres_dbname = self.read_string_field("postgres_dbname")?;
+        let version_server = self.read_string_field("version_server")?;
+        let version_pgdump = self.read_string_field("version_pgdump")?;
+        let toc_count = self.read_int_field("toc_count")?;
         Ok(TocHeader {
             magic,
             version,
@@ -139,31 +240,52 @@ impl<R: Read> TocReader<R> {
         })
     }
 
+    // number of bytes left in the input, used to detect entries
+    // not covered by toc_count from header
+    pub(crate) fn count_remaining_bytes(&mut self) -> Result<u64, TocError> {
+        let count = std::io::copy(&mut self.reader, &mut std::io::sink())?;
+        Ok(count)
+    }
+
+    // bytes after the last entry, pg_dump does not write anything there
+    // currently, but they are kept in case future versions add a trailer
+    pub(crate) fn read_trailer(&mut self) -> Result<Vec<u8>, TocError> {
+        let mut buf = Vec::new();
+        self.reader.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
     pub(crate) fn read_entry(&mut self) -> Result<TocEntry, TocError> {
-        let dump_id = self.read_int()?;
-        let had_dumper = self.read_int()?;
-        let table_oid = self.read_string()?;
-        let catalog_oid = self.read_string()?;
-        let tag = self.read_string()?;
-        let description = self.read_string()?;
-        let section = self.read_int()?;
-        let create_stmt = self.read_string()?;
-        let drop_stmt = self.read_string()?;
-        let copy_stmt = self.read_string()?;
-        let namespace = self.read_string()?;
-        let tablespace = self.read_string()?;
-        let tableam = self.read_string()?;
-        let owner = self.read_string()?;
-        let table_with_oids = self.read_string()?;
+        self.entry_index = Some(self.next_entry_index);
+        self.next_entry_index += 1;
+        let dump_id = self.read_int_field("dump_id")?;
+        let had_dumper = self.read_int_field("had_dumper")?;
+        let table_oid = self.read_string_field("table_oid")?;
+        let catalog_oid = self.read_string_field("catalog_oid")?;
+        let tag = self.read_string_field("tag")?;
+        let description = self.read_string_field("description")?;
+        let section = self.read_int_field("section")?;
+        let create_stmt = self.read_string_field("create_stmt")?;
+        let drop_stmt = self.read_string_field("drop_stmt")?;
+        let copy_stmt = self.read_string_field("copy_stmt")?;
+        let namespace = self.read_string_field("namespace")?;
+        let tablespace = self.read_string_field("tablespace")?;
+        let tableam = if self.version.has_tableam() {
+            self.read_string_field("tableam")?
+        } else {
+            TocString::none()
+        };
+        let owner = self.read_string_field("owner")?;
+        let table_with_oids = self.read_string_field("table_with_oids")?;
         let mut deps: Vec<TocString> = Vec::new();
         loop {
-            let st = self.read_string()?;
+            let st = self.read_string_field("deps")?;
             if st.opt.is_none() {
                 break
             }
             deps.push(st);
         }
-        let filename = self.read_string()?;
+        let filename = self.read_string_field("filename")?;
         Ok(TocEntry {
             dump_id,
             had_dumper,
@@ -184,4 +306,104 @@ impl<R: Read> TocReader<R> {
             filename,
         })
     }
-}
\ No newline at end of file
+}
+impl<R: Read + Seek> TocReader<R> {
+
+    // reads the whole TOC from the start recording the byte offset of every entry,
+    // leaves the reader positioned at the end of the last entry
+    pub(crate) fn build_index(&mut self) -> Result<(TocHeader, Vec<TocEntry>), TocError> {
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.position = 0;
+        self.next_entry_index = 0;
+        let header = self.read_header()?;
+        let mut offsets = Vec::with_capacity(header.toc_count.max(0) as usize);
+        let mut entries = Vec::with_capacity(header.toc_count.max(0) as usize);
+        for _ in 0..header.toc_count {
+            offsets.push(self.reader.stream_position()?);
+            entries.push(self.read_entry()?);
+        }
+        self.end_offset = self.reader.stream_position()?;
+        self.offsets = offsets;
+        Ok((header, entries))
+    }
+
+    pub(crate) fn entry_offsets(&self) -> &[u64] {
+        &self.offsets
+    }
+
+    pub(crate) fn end_offset(&self) -> u64 {
+        self.end_offset
+    }
+
+    pub(crate) fn read_entry_at(&mut self, index: usize) -> Result<TocEntry, TocError> {
+        let offset = match self.offsets.get(index) {
+            Some(offset) => *offset,
+            None => return Err(TocError::new(&format!(
+                "Entry index out of range, index: {}, entries count: {}", index, self.offsets.len())))
+        };
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.position = offset;
+        self.next_entry_index = index;
+        self.read_entry()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toc_writer::TocWriter;
+
+    fn read_int_bytes(bytes: &[u8]) -> Result<i32, TocError> {
+        let mut reader = TocReader::new(bytes);
+        reader.begin_field("test");
+        reader.read_int()
+    }
+
+    #[test]
+    fn int_roundtrip() {
+        for val in [0, 1, -1, 42, -42, i32::MAX, i32::MAX - 1, i32::MIN, i32::MIN + 1] {
+            let mut buf: Vec<u8> = Vec::new();
+            TocWriter::new(&mut buf).write_int(val).unwrap();
+            assert_eq!(5, buf.len());
+            assert_eq!(val, read_int_bytes(&buf).unwrap());
+        }
+    }
+
+    #[test]
+    fn int_boundaries() {
+        assert_eq!(i32::MAX, read_int_bytes(&[0, 0xFF, 0xFF, 0xFF, 0x7F]).unwrap());
+        assert_eq!(-i32::MAX, read_int_bytes(&[1, 0xFF, 0xFF, 0xFF, 0x7F]).unwrap());
+        assert_eq!(i32::MIN, read_int_bytes(&[1, 0, 0, 0, 0x80]).unwrap());
+        assert_eq!(0, read_int_bytes(&[1, 0, 0, 0, 0]).unwrap());
+
+        let err = read_int_bytes(&[0, 0, 0, 0, 0x80]).unwrap_err();
+        assert_eq!(err.to_string(),
+            "Integer magnitude out of range, sign: 0, magnitude: 2147483648, header field: test, offset: 0");
+        let err = read_int_bytes(&[1, 1, 0, 0, 0x80]).unwrap_err();
+        assert!(err.to_string().starts_with("Integer magnitude out of range, sign: 1, magnitude: 2147483649"));
+        let err = read_int_bytes(&[0, 0xFF, 0xFF, 0xFF, 0xFF]).unwrap_err();
+        assert!(err.to_string().starts_with("Integer magnitude out of range"));
+        let err = read_int_bytes(&[2, 1, 0, 0, 0]).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid integer sign byte: 2, header field: test, offset: 0");
+        assert!(read_int_bytes(&[0xFF, 1, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn flags() {
+        let read_flags = |bytes: &[u8]| TocReader::new(bytes).read_flags().map_err(|e| e.to_string());
+        assert!(read_flags(&[4, 8, 3]).is_ok());
+        assert!(read_flags(&[8, 4, 3]).is_ok());
+        assert!(read_flags(&[2, 8, 3]).unwrap_err().ends_with("Int size check failed, expected: 4 or 8, actual: 2"));
+        assert!(read_flags(&[4, 16, 3]).unwrap_err().ends_with("Offset size check failed, expected: 4 or 8, actual: 16"));
+        assert!(read_flags(&[4, 8, 1]).unwrap_err().ends_with("Format check failed, expected: 3 (directory), actual: 1"));
+    }
+
+    #[test]
+    fn int_8_bytes() {
+        let bytes = [[8u8, 8, 3].as_slice(), &[1, 42, 0, 0, 0, 0, 0, 0, 0], &[0, 0, 0, 0, 0, 1, 0, 0, 0]].concat();
+        let mut reader = TocReader::new(bytes.as_slice());
+        reader.read_flags().unwrap();
+        assert_eq!(-42, reader.read_int().unwrap());
+        assert!(reader.read_int().unwrap_err().to_string().starts_with("Integer magnitude out of range"));
+    }
+}
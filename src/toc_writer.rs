@@ -16,6 +16,7 @@
 
 use std::io::Write;
 
+use crate::archive_version::ArchiveVersion;
 use crate::toc_entry::TocEntry;
 use crate::toc_error::TocError;
 use crate::toc_header::TocHeader;
@@ -23,42 +24,47 @@ use crate::toc_string::TocString;
 use crate::toc_datetime::TocDateTime;
 
 pub(crate) struct TocWriter<W: Write> {
-   writer: W
+   writer: W,
+   // size of the integer magnitude in bytes, from header flags
+   int_size: usize,
+   // from header version, older versions have no table access method field
+   tableam: bool
 }
 
 impl<W: Write> TocWriter<W> {
 
     pub(crate) fn new(writer: W) -> Self {
         Self {
-            writer
+            writer,
+            int_size: 4,
+            tableam: true
         }
     }
 
     pub(crate) fn write_int(&mut self, val: i32) -> Result<(), TocError> {
-        let mut buf = [0u8; 5];
+        let mut buf = [0u8; 9];
         let uval = if val >= 0 {
             buf[0] = 0;
             val as u32
         } else {
             buf[0] = 1;
-            -val as u32
+            // cannot overflow for i32::MIN
+            val.unsigned_abs()
         };
         let uval_bytes = uval.to_le_bytes();
-        for i in 0..uval_bytes.len() {
-            buf[i + 1] = uval_bytes[i];
-        }
-        self.writer.write_all(&buf)?;
+        buf[1..5].copy_from_slice(&uval_bytes);
+        self.writer.write_all(&buf[..1 + self.int_size])?;
         Ok(())
     }
 
     pub(crate) fn write_timestamp(&mut self, tm: &TocDateTime) -> Result<(), TocError> {
-        self.write_int(tm.second as i32)?;
-        self.write_int(tm.minute as i32)?;
-        self.write_int(tm.hour as i32)?;
-        self.write_int(tm.day as i32)?;
-        self.write_int(tm.month as i32)?;
-        self.write_int(tm.year as i32)?;
-        self.write_int(tm.is_dst as i32)?;
+        self.write_int(tm.second)?;
+        self.write_int(tm.minute)?;
+        self.write_int(tm.hour)?;
+        self.write_int(tm.day)?;
+        self.write_int(tm.month)?;
+        self.write_int(tm.year)?;
+        self.write_int(tm.is_dst)?;
         Ok(())
     }
 
@@ -69,22 +75,46 @@ impl<W: Write> TocWriter<W> {
                 self.writer.write_all(bytes.as_slice())?;
             },
             None => {
-                self.write_int(-1 as i32)?;
+                self.write_int(-1)?;
             }
         };
         Ok(())
     }
 
-    pub(crate) fn write_header(&mut self, header: &TocHeader) -> Result<(), TocError> {
+    // entry count is passed separately, header value may be stale
+    fn write_header(&mut self, header: &TocHeader, toc_count: usize) -> Result<(), TocError> {
         self.writer.write_all(header.magic.as_slice())?;
         self.writer.write_all(header.version.as_slice())?;
         self.writer.write_all(header.flags.as_slice())?;
+        if let Some(8) = header.flags.first() {
+            self.int_size = 8;
+        }
+        self.tableam = ArchiveVersion::of_header(header).has_tableam();
         self.write_int(header.compression)?;
         self.write_timestamp(&header.timestamp)?;
         self.write_string(&header.postgres_dbname)?;
         self.write_string(&header.version_server)?;
         self.write_string(&header.version_pgdump)?;
-        self.write_int(header.toc_count)?;
+        self.write_int(toc_count as i32)?;
+        Ok(())
+    }
+
+    // toc_count from header is ignored, it is always derived from the entries
+    pub(crate) fn write_toc(&mut self, header: &TocHeader, entries: &[TocEntry]) -> Result<(), TocError> {
+        self.write_toc_with_trailer(header, entries, &[])
+    }
+
+    pub(crate) fn write_toc_with_trailer(&mut self, header: &TocHeader, entries: &[TocEntry], trailer: &[u8]) -> Result<(), TocError> {
+        self.write_header(header, entries.len())?;
+        for te in entries {
+            self.write_toc_entry(te)?;
+        }
+        self.writer.write_all(trailer)?;
+        self.flush()
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<(), TocError> {
+        self.writer.flush()?;
         Ok(())
     }
 
@@ -101,7 +131,12 @@ impl<W: Write> TocWriter<W> {
         self.write_string(&te.copy_stmt)?;
         self.write_string(&te.namespace)?;
         self.write_string(&te.tablespace)?;
-        self.write_string(&te.tableam)?;
+        if self.tableam {
+            self.write_string(&te.tableam)?;
+        } else if te.tableam.opt.is_some() {
+            return Err(TocError::UnsupportedFormat(format!(
+                "Table access method cannot be written with archive version before 1.14, dump_id: {}", te.dump_id)));
+        }
         self.write_string(&te.owner)?;
         self.write_string(&te.table_with_oids)?;
         for dp in &te.deps {
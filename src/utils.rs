@@ -18,18 +18,14 @@ use std::io;
 use std::path::PathBuf;
 
 pub(crate) fn zero_vec(len: usize) -> Vec<u8> {
-    let mut vec: Vec<u8> = Vec::with_capacity(len);
-    for _ in 0..len {
-        vec.push(0u8);
-    };
-    vec
+    vec![0u8; len]
 }
 
 pub(crate) fn path_filename_append(path: &mut PathBuf, suffix: &str) -> Result<(), io::Error> {
     let fname = match path.file_name() {
         Some(fname) => fname,
-        None => return Err(io::Error::new(io::ErrorKind::Other, format!(
-            "Path filename access error: {}", path.to_string_lossy().to_string())))
+        None => return Err(io::Error::other(format!(
+            "Path filename access error: {}", path.to_string_lossy())))
     };
     let mut fname_updated = fname.to_os_string();
     fname_updated.push(suffix);
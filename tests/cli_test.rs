@@ -0,0 +1,435 @@
+/*
+ * Copyright 2024, WiltonDB Software
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(feature = "cli")]
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn json_errors_test() {
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work_dir = project_dir.join("target/json_errors_test");
+    if work_dir.exists() {
+        fs::remove_dir_all(&work_dir).unwrap();
+    }
+    fs::create_dir(&work_dir).unwrap();
+    let bad_toc = work_dir.join("toc.dat");
+    fs::write(&bad_toc, b"NOT A TOC FILE").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pgdump_toc_rewrite"))
+        .arg("--json-errors")
+        .arg("--print")
+        .arg(&bad_toc)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let err: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(err["kind"], "magic_mismatch");
+    assert_eq!(err["error"], "Magic check failure");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pgdump_toc_rewrite"))
+        .arg("--json-errors")
+        .arg("--dbname")
+        .arg("foobar")
+        .arg(work_dir.join("missing.dat"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let err: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(err["kind"], "io");
+
+    // plain text by default
+    let output = Command::new(env!("CARGO_BIN_EXE_pgdump_toc_rewrite"))
+        .arg("--print")
+        .arg(&bad_toc)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8(output.stderr).unwrap().trim_end(), "TOC print error: Magic check failure");
+}
+
+#[test]
+fn check_roundtrip_test() {
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work_dir = project_dir.join("target/check_roundtrip_test");
+    if work_dir.exists() {
+        fs::remove_dir_all(&work_dir).unwrap();
+    }
+    fs::create_dir(&work_dir).unwrap();
+    let toc_dat = work_dir.join("toc.dat");
+    fs::copy(project_dir.join("resources/dump/toc.dat"), &toc_dat).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pgdump_toc_rewrite"))
+        .arg("--check-roundtrip")
+        .arg(&toc_dat)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim_end(), "TOC round-trip check passed");
+
+    let mut bytes = fs::read(&toc_dat).unwrap();
+    bytes.push(0);
+    fs::write(&toc_dat, &bytes).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_pgdump_toc_rewrite"))
+        .arg("--json-errors")
+        .arg("--check-roundtrip")
+        .arg(&toc_dat)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let err: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(err["kind"], "roundtrip_mismatch");
+}
+
+#[test]
+fn subcommands_test() {
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work_dir = project_dir.join("target/subcommands_test");
+    if work_dir.exists() {
+        fs::remove_dir_all(&work_dir).unwrap();
+    }
+    let dump_dir = work_dir.join("dump");
+    fs::create_dir_all(&dump_dir).unwrap();
+    for entry in fs::read_dir(project_dir.join("resources/dump")).unwrap() {
+        let entry = entry.unwrap();
+        fs::copy(entry.path(), dump_dir.join(entry.file_name())).unwrap();
+    }
+    let toc_dat = dump_dir.join("toc.dat");
+    let orig_toc = fs::read(&toc_dat).unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_pgdump_toc_rewrite"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let toc_path = toc_dat.to_str().unwrap();
+
+    let output = run(&["print", toc_path]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, run(&["--print", toc_path]).stdout);
+
+    let output = run(&["verify", toc_path]);
+    assert!(output.status.success());
+
+    let json_file = work_dir.join("toc.json");
+    let output = run(&["to-json", "--output", json_file.to_str().unwrap(), toc_path]);
+    assert!(output.status.success());
+    let copy_toc = work_dir.join("toc_copy.dat");
+    let output = run(&["from-json", json_file.to_str().unwrap(), copy_toc.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(fs::read(&copy_toc).unwrap(), orig_toc);
+
+    let output = run(&["rewrite", "--dbname", "foobar", "--header-dbname", "wilton2", toc_path]);
+    assert!(output.status.success());
+    assert_ne!(fs::read(&toc_dat).unwrap(), orig_toc);
+    let output = run(&["print", toc_path]);
+    assert!(String::from_utf8(output.stdout).unwrap().contains("Postgres DB: wilton2\n"));
+    let output = run(&["verify-rewritten", "--orig-dbname", "test1", toc_path]);
+    assert!(output.status.success());
+    let output = run(&["verify-rewritten", "--orig-dbname", "foobar", toc_path]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("entry 18 SCHEMA foobar_dbo, tag: foobar_dbo"));
+    let output = run(&["orphans", toc_path]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().lines().any(|line| line.starts_with("backup\t") && line.ends_with("toc.dat.orig")));
+    // stdin is not a terminal
+    assert_eq!(run(&["orphans", "--clean-backups", toc_path]).status.code(), Some(2));
+    assert!(dump_dir.join("toc.dat.orig").exists());
+    let output = run(&["restore-backup", toc_path]);
+    assert!(output.status.success());
+    assert_eq!(fs::read(&toc_dat).unwrap(), orig_toc);
+    assert!(run(&["rewrite", "--dbname", "foobar", toc_path]).status.success());
+    assert!(run(&["orphans", "--clean-backups", "--yes", toc_path]).status.success());
+    assert!(!dump_dir.join("toc.dat.orig").exists());
+    assert!(run(&["orphans", toc_path]).stdout.is_empty());
+    for entry in fs::read_dir(project_dir.join("resources/dump")).unwrap() {
+        let entry = entry.unwrap();
+        fs::copy(entry.path(), dump_dir.join(entry.file_name())).unwrap();
+    }
+
+    // TOC file in current directory
+    let run_in_dump_dir = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_pgdump_toc_rewrite"))
+            .args(args)
+            .current_dir(&dump_dir)
+            .output()
+            .unwrap()
+    };
+    assert!(run_in_dump_dir(&["rewrite", "--dbname", "foobar", "toc.dat"]).status.success());
+    assert!(dump_dir.join("toc.dat.orig").exists());
+    assert!(run_in_dump_dir(&["restore-backup", "toc.dat"]).status.success());
+    assert_eq!(fs::read(&toc_dat).unwrap(), orig_toc);
+
+    // hidden legacy flags
+    let output = run(&["--dbname", "foobar", toc_path]);
+    assert!(output.status.success());
+    let output = run(&["--undo", toc_path]);
+    assert!(output.status.success());
+    assert_eq!(fs::read(&toc_dat).unwrap(), orig_toc);
+
+    // exit codes
+    assert_eq!(run(&["rewrite", toc_path]).status.code(), Some(2));
+    assert_eq!(run(&["print", "--dbname", "foobar", toc_path]).status.code(), Some(2));
+    assert_eq!(run(&[toc_path]).status.code(), Some(2));
+    assert_eq!(run(&["print", work_dir.join("missing.dat").to_str().unwrap()]).status.code(), Some(4));
+    let help = String::from_utf8(run(&["--help"]).stdout).unwrap();
+    assert!(help.contains("Exit codes:"));
+    assert!(!help.contains("--dbname"));
+}
+
+#[test]
+fn stdin_test() {
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let toc_dat = project_dir.join("resources/dump/toc.dat");
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_pgdump_toc_rewrite"))
+            .args(args)
+            .stdin(fs::File::open(&toc_dat).unwrap())
+            .output()
+            .unwrap()
+    };
+    let toc_path = toc_dat.to_str().unwrap();
+
+    let output = run(&["print", "-"]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    let expected = run(&["print", toc_path]).stdout;
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), String::from_utf8(expected).unwrap());
+
+    let output = run(&["to-json", "-"]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    assert_eq!(output.stdout, run(&["to-json", toc_path]).stdout);
+
+    let output = run(&["verify", "-"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim_end(), "TOC round-trip check passed");
+    assert_eq!(String::from_utf8(output.stderr).unwrap().trim_end(),
+        "Warning: TOC is read from stdin, data files check is skipped");
+
+    let output = run(&["rewrite", "--dbname", "foobar", "-"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with("Error: 'rewrite' cannot read TOC from stdin"));
+}
+
+#[test]
+fn exit_codes_test() {
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work_dir = project_dir.join("target/exit_codes_test");
+    if work_dir.exists() {
+        fs::remove_dir_all(&work_dir).unwrap();
+    }
+    fs::create_dir(&work_dir).unwrap();
+    let toc_dat = project_dir.join("resources/dump/toc.dat");
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_pgdump_toc_rewrite"))
+            .arg("--error-format")
+            .arg("json")
+            .args(args)
+            .output()
+            .unwrap();
+        let err: serde_json::Value = if output.stderr.is_empty() {
+            serde_json::Value::Null
+        } else {
+            serde_json::from_slice(&output.stderr).unwrap()
+        };
+        (output.status.code().unwrap(), err)
+    };
+
+    // invalid input
+    let (code, err) = run(&["rewrite", "--dbname", "1foo", toc_dat.to_str().unwrap()]);
+    assert_eq!(code, 3);
+    assert_eq!(err["error_kind"], "invalid_db_name");
+    assert_eq!(err["message"], "Invalid db name specified: [1foo]");
+    assert!(err["entry"].is_null());
+    assert!(err["path"].is_null());
+
+    // I/O error
+    let (code, err) = run(&["print", work_dir.join("missing.dat").to_str().unwrap()]);
+    assert_eq!(code, 4);
+    assert_eq!(err["error_kind"], "io");
+
+    // not a Babelfish dump
+    let orig_json = read_toc_json(&toc_dat);
+    let mut toc_json = orig_json.clone();
+    toc_json["entries"].as_array_mut().unwrap().truncate(3);
+    let plain_dir = work_dir.join("plain");
+    fs::create_dir(&plain_dir).unwrap();
+    let json_file = work_dir.join("plain.json");
+    fs::write(&json_file, toc_json.to_string()).unwrap();
+    let plain_toc = plain_dir.join("toc.dat");
+    let (code, _) = run(&["from-json", json_file.to_str().unwrap(), plain_toc.to_str().unwrap()]);
+    assert_eq!(code, 0);
+    let (code, err) = run(&["rewrite", "--dbname", "foobar", plain_dir.to_str().unwrap()]);
+    assert_eq!(code, 5);
+    assert_eq!(err["error_kind"], "not_babelfish_dump");
+
+    // corrupt and truncated TOC
+    let bad_toc = work_dir.join("bad.dat");
+    fs::write(&bad_toc, b"NOT A TOC FILE").unwrap();
+    let (code, err) = run(&["print", bad_toc.to_str().unwrap()]);
+    assert_eq!(code, 6);
+    assert_eq!(err["error_kind"], "magic_mismatch");
+    let truncated_toc = work_dir.join("truncated.dat");
+    fs::write(&truncated_toc, &fs::read(&toc_dat).unwrap()[..300]).unwrap();
+    let (code, _) = run(&["print", truncated_toc.to_str().unwrap()]);
+    assert_eq!(code, 6);
+
+    // failed entry is reported separately from the cause
+    let dump_dir = work_dir.join("dump");
+    copy_dir::copy_dir(project_dir.join("resources/dump"), &dump_dir).unwrap();
+    let mut toc_json = orig_json.clone();
+    for en in toc_json["entries"].as_array_mut().unwrap() {
+        if en["dump_id"] == 372 {
+            en["create_stmt"] = serde_json::json!("CREATE TABLE test1_dbo.\"tab1 (id int);");
+        }
+    }
+    fs::write(&json_file, toc_json.to_string()).unwrap();
+    fs::remove_file(dump_dir.join("toc.dat")).unwrap();
+    let (code, _) = run(&["from-json", json_file.to_str().unwrap(), dump_dir.join("toc.dat").to_str().unwrap()]);
+    assert_eq!(code, 0);
+    let (code, err) = run(&["rewrite", "--dbname", "foobar", dump_dir.to_str().unwrap()]);
+    assert_eq!(code, 6);
+    assert_eq!(err["error_kind"], "tokenizer");
+    assert_eq!(err["entry"], serde_json::json!({ "dump_id": 372, "description": "TABLE", "tag": "tab1" }));
+    assert!(err["message"].as_str().unwrap().starts_with("TOC entry error, dump_id: 372"));
+
+    let help = Command::new(env!("CARGO_BIN_EXE_pgdump_toc_rewrite")).arg("--help").output().unwrap();
+    let help = String::from_utf8(help.stdout).unwrap();
+    assert!(help.contains("5  dump is not a Babelfish dump"));
+}
+
+#[test]
+fn verbosity_test() {
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work_dir = project_dir.join("target/verbosity_test");
+    if work_dir.exists() {
+        fs::remove_dir_all(&work_dir).unwrap();
+    }
+    let dump_dir = work_dir.join("dump");
+    fs::create_dir_all(&dump_dir).unwrap();
+    for entry in fs::read_dir(project_dir.join("resources/dump")).unwrap() {
+        let entry = entry.unwrap();
+        fs::copy(entry.path(), dump_dir.join(entry.file_name())).unwrap();
+    }
+    let toc_dat = dump_dir.join("toc.dat");
+    let toc_path = toc_dat.to_str().unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_pgdump_toc_rewrite"))
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    // successful rewrite is silent by default
+    assert_eq!(run(&["rewrite", "--dbname", "foobar", toc_path]), "");
+    assert_eq!(run(&["restore-backup", toc_path]), "");
+
+    let stderr = run(&["rewrite", "-v", "--dbname", "foobar", toc_path]);
+    assert!(stderr.contains("rewriting DB name test1 -> foobar"));
+    assert!(stderr.contains("catalog babelfish_sysdatabases (5981.dat.gz): 1 rows rewritten"));
+    assert!(!stderr.contains("entry 18 SCHEMA"));
+    assert!(run(&["restore-backup", "-v", toc_path]).contains("file toc.dat restored"));
+
+    let stderr = run(&["-vv", "rewrite", "--dbname", "foobar", toc_path]);
+    assert!(stderr.contains("catalog babelfish_sysdatabases (5981.dat.gz): 1 rows rewritten"));
+    assert!(stderr.contains("entry 18 SCHEMA tag test1_dbo -> foobar_dbo"));
+    assert_eq!(run(&["restore-backup", toc_path]), "");
+
+    // warnings are printed by default and suppressed with '--quiet'
+    let filter = ["filter", "--exclude-table-data", "test1_dbo.tab1", toc_path];
+    assert_eq!(run(&filter).trim_end(), "Warning: file 5991.dat.gz is not referenced in TOC");
+    assert_eq!(run(&["restore-backup", toc_path]), "");
+    assert_eq!(run(&[&["-q"], &filter[..]].concat()), "");
+    assert_eq!(run(&["restore-backup", toc_path]), "");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pgdump_toc_rewrite"))
+        .args(["-q", "-v", "print", toc_path])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn rename_map_files_test() {
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work_dir = project_dir.join("target/rename_map_files_test");
+    if work_dir.exists() {
+        fs::remove_dir_all(&work_dir).unwrap();
+    }
+    fs::create_dir_all(&work_dir).unwrap();
+    let toc_dat = project_dir.join("resources/dump/toc.dat");
+    let toc_path = toc_dat.to_str().unwrap();
+    let write_map = |name: &str, contents: &str| {
+        let path = work_dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    };
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_pgdump_toc_rewrite"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let schema_map = write_map("schemas.txt", "# custom schemas\n\ntest1_schema1   sales\n");
+    let owner_map = write_map("owners.txt", "test1_guest guest_role\n");
+    let output = run(&["rewrite", "--dbname", "foobar", "--schema-map", &schema_map,
+        "--owner-map", &owner_map, "--print-maps", toc_path]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "\
+        # schemas\n\
+        test1_dbo foobar_dbo\n\
+        test1_guest foobar_guest\n\
+        test1_schema1 sales\n\
+        # owners\n\
+        test1_db_owner foobar_db_owner\n\
+        test1_dbo foobar_dbo\n\
+        test1_guest guest_role\n");
+
+    for (contents, message) in [
+        ("a b\n\nc d\na e\n", "line: 4, message: duplicate original name 'a', first specified at line: 1"),
+        ("a\n", "line: 1, message: expected original and new names, found 1 column(s)"),
+        ("a b c\n", "line: 1, message: expected original and new names, found 3 column(s)"),
+        ("# comment\na Sales\n", "line: 2, message: new name 'Sales' must be a lower case SQL identifier"),
+        ("a select\n", "line: 1, message: new name 'select' must be a lower case SQL identifier"),
+    ] {
+        let map = write_map("invalid.txt", contents);
+        let output = run(&["rewrite", "--dbname", "foobar", "--owner-map", &map, toc_path]);
+        assert_eq!(output.status.code(), Some(3));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.starts_with(&format!("Map file error, path: {}, {}", map, message)), "{}", stderr);
+    }
+    let missing = work_dir.join("missing.txt");
+    let output = run(&["rewrite", "--dbname", "foobar", "--schema-map", missing.to_str().unwrap(), toc_path]);
+    assert_eq!(output.status.code(), Some(4));
+}
+
+fn read_toc_json(toc_dat: &Path) -> serde_json::Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_pgdump_toc_rewrite"))
+        .arg("to-json")
+        .arg(toc_dat)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    serde_json::from_slice(&output.stdout).unwrap()
+}
@@ -13,8 +13,6 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
-use pgdump_toc_rewrite;
-
 use std::fs;
 use std::fs::File;
 use std::io::{BufWriter, Write};
@@ -43,6 +41,8 @@ fn json_test() {
     let toc_json_orig = resources_dir.join("toc.json");
     let toc_json_orig_st = fs::read_to_string(&toc_json_orig).unwrap();
     assert_eq!(toc_json_orig_st, toc_json_st);
+    let toc_json_reader_st = pgdump_toc_rewrite::read_toc_to_json_from_reader(File::open(&toc_dat).unwrap()).unwrap();
+    assert_eq!(toc_json_reader_st, toc_json_st);
 
     pgdump_toc_rewrite::write_toc_from_json(&toc_dat_dest, &toc_json_st).unwrap();
 
@@ -60,3 +60,156 @@ fn json_test() {
 
     assert_eq!(toc_orig_st, toc_dest_st);
 }
+
+#[test]
+fn json_empty_and_null_test() {
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work_dir = project_dir.join("target/json_empty_and_null_test");
+    if work_dir.exists() {
+        std::fs::remove_dir_all(&work_dir).unwrap();
+    }
+    std::fs::create_dir(&work_dir).unwrap();
+    let toc_dat = project_dir.join("resources/dump/toc.dat");
+
+    // byte-exact re-write of the original TOC
+    let toc_json_st = pgdump_toc_rewrite::read_toc_to_json(&toc_dat).unwrap();
+    let toc_dat_dest = work_dir.join("toc.dat");
+    pgdump_toc_rewrite::write_toc_from_json(&toc_dat_dest, &toc_json_st).unwrap();
+    assert_eq!(fs::read(&toc_dat).unwrap(), fs::read(&toc_dat_dest).unwrap());
+
+    // empty and null values in adjacent fields
+    let mut toc_json: serde_json::Value = serde_json::from_str(&toc_json_st).unwrap();
+    toc_json["header"]["version_server"] = serde_json::json!("");
+    for en in toc_json["entries"].as_array_mut().unwrap() {
+        if en["dump_id"] == 372 {
+            en["tablespace"] = serde_json::json!("");
+            en["tableam"] = serde_json::Value::Null;
+            en["owner"] = serde_json::json!("");
+            en["table_with_oids"] = serde_json::Value::Null;
+            en["deps"] = serde_json::json!(["", "235"]);
+        }
+    }
+    let toc_dat_modified = work_dir.join("toc_modified.dat");
+    pgdump_toc_rewrite::write_toc_from_json(&toc_dat_modified, &toc_json.to_string()).unwrap();
+    let toc_json_st_modified = pgdump_toc_rewrite::read_toc_to_json(&toc_dat_modified).unwrap();
+    let toc_json_modified: serde_json::Value = serde_json::from_str(&toc_json_st_modified).unwrap();
+    assert_eq!(toc_json_modified, toc_json);
+    let tab1 = toc_json_modified["entries"].as_array().unwrap().iter().find(|en| en["dump_id"] == 372).unwrap();
+    assert_eq!(tab1["tablespace"], "");
+    assert!(tab1["tableam"].is_null());
+    assert_eq!(tab1["owner"], "");
+    assert!(tab1["table_with_oids"].is_null());
+
+    let toc_dat_modified_again = work_dir.join("toc_modified_again.dat");
+    pgdump_toc_rewrite::write_toc_from_json(&toc_dat_modified_again, &toc_json_st_modified).unwrap();
+    assert_eq!(fs::read(&toc_dat_modified).unwrap(), fs::read(&toc_dat_modified_again).unwrap());
+    pgdump_toc_rewrite::verify_roundtrip(&toc_dat_modified).unwrap();
+
+    // unknown DST is written as -1
+    let mut toc_json: serde_json::Value = serde_json::from_str(&toc_json_st).unwrap();
+    toc_json["header"]["is_dst"] = serde_json::Value::Null;
+    let toc_dat_unknown_dst = work_dir.join("toc_unknown_dst.dat");
+    pgdump_toc_rewrite::write_toc_from_json(&toc_dat_unknown_dst, &toc_json.to_string()).unwrap();
+    let toc_json_st_unknown_dst = pgdump_toc_rewrite::read_toc_to_json(&toc_dat_unknown_dst).unwrap();
+    let toc_json_unknown_dst: serde_json::Value = serde_json::from_str(&toc_json_st_unknown_dst).unwrap();
+    assert!(toc_json_unknown_dst["header"]["is_dst"].is_null());
+    let toc_dat_unknown_dst_again = work_dir.join("toc_unknown_dst_again.dat");
+    pgdump_toc_rewrite::write_toc_from_json(&toc_dat_unknown_dst_again, &toc_json_st_unknown_dst).unwrap();
+    assert_eq!(fs::read(&toc_dat_unknown_dst).unwrap(), fs::read(&toc_dat_unknown_dst_again).unwrap());
+    pgdump_toc_rewrite::verify_roundtrip(&toc_dat_unknown_dst).unwrap();
+    let mut printed = Vec::new();
+    pgdump_toc_rewrite::print_toc_from_reader(fs::File::open(&toc_dat_unknown_dst).unwrap(), &mut printed).unwrap();
+    assert!(String::from_utf8(printed).unwrap().contains("DST: unknown\n"));
+}
+
+#[test]
+fn json_validation_test() {
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work_dir = project_dir.join("target/json_validation_test");
+    if work_dir.exists() {
+        std::fs::remove_dir_all(&work_dir).unwrap();
+    }
+    std::fs::create_dir(&work_dir).unwrap();
+    let toc_json_st = pgdump_toc_rewrite::read_toc_to_json(project_dir.join("resources/dump/toc.dat")).unwrap();
+    let toc_json: serde_json::Value = serde_json::from_str(&toc_json_st).unwrap();
+    let toc_dat_dest = work_dir.join("toc.dat");
+    let write_err = |json: &serde_json::Value| {
+        let err = pgdump_toc_rewrite::write_toc_from_json(&toc_dat_dest, &json.to_string()).unwrap_err();
+        assert_eq!(err.kind(), "invalid_json");
+        assert!(!toc_dat_dest.exists());
+        err.to_string()
+    };
+
+    let mut json = toc_json.clone();
+    json["header"].as_object_mut().unwrap().remove("toc_count");
+    assert_eq!(write_err(&json), "Invalid TOC JSON, field: header.toc_count, message: field is missing");
+
+    let mut json = toc_json.clone();
+    json["header"]["timestamp"] = serde_json::json!("2024-13-01 10:20:30");
+    assert!(write_err(&json).starts_with(
+        "Invalid TOC JSON, field: header.timestamp, message: expected 'YYYY-MM-DD HH:MM:SS', found: '2024-13-01 10:20:30'"));
+
+    let mut json = toc_json.clone();
+    json["header"]["magic"].as_array_mut().unwrap().pop();
+    assert_eq!(write_err(&json), "Invalid TOC JSON, field: header.magic, message: expected length: 5, actual: 4");
+
+    let mut json = toc_json.clone();
+    json["header"]["flags"][1] = serde_json::json!("1g");
+    assert_eq!(write_err(&json), "Invalid TOC JSON, field: header.flags, message: expected hex byte at index: 1, found: '1g'");
+
+    let mut json = toc_json.clone();
+    json["header"]["magic"][4] = serde_json::json!("51");
+    assert_eq!(write_err(&json), "Invalid TOC JSON, field: header.magic, message: expected 'PGDMP' (50 47 44 4d 50), found: 50 47 44 4d 51");
+    pgdump_toc_rewrite::write_toc_from_json_allow_nonstandard_header(&toc_dat_dest, &json.to_string()).unwrap();
+    assert_eq!(&fs::read(&toc_dat_dest).unwrap()[..5], b"PGDMQ");
+    fs::remove_file(&toc_dat_dest).unwrap();
+
+    let mut json = toc_json.clone();
+    json["entries"][2].as_object_mut().unwrap().remove("tag");
+    assert_eq!(write_err(&json), "Invalid TOC JSON, field: entries[2].tag, message: field is missing");
+
+    let mut json = toc_json.clone();
+    json["entries"][0]["dump_id"] = serde_json::json!("42");
+    assert_eq!(write_err(&json), "Invalid TOC JSON, field: entries[0].dump_id, message: expected 32-bit integer, found: \"42\"");
+
+    let mut json = toc_json.clone();
+    json["trailer"] = serde_json::json!(["00", "zz"]);
+    assert_eq!(write_err(&json), "Invalid TOC JSON, field: trailer, message: expected hex byte at index: 1, found: 'zz'");
+
+    pgdump_toc_rewrite::write_toc_from_json(&toc_dat_dest, &toc_json_st).unwrap();
+}
+
+// with 'parallel' feature entries are converted on multiple threads
+#[test]
+fn json_entries_order_test() {
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work_dir = project_dir.join("target/json_entries_order_test");
+    if work_dir.exists() {
+        std::fs::remove_dir_all(&work_dir).unwrap();
+    }
+    std::fs::create_dir(&work_dir).unwrap();
+    let toc_dat = project_dir.join("resources/dump/toc.dat");
+
+    // many entries with dump IDs in descending order
+    let toc_json_st = pgdump_toc_rewrite::read_toc_to_json(&toc_dat).unwrap();
+    let mut toc_json: serde_json::Value = serde_json::from_str(&toc_json_st).unwrap();
+    let template = toc_json["entries"].as_array().unwrap().clone();
+    let mut entries = Vec::new();
+    for idx in 0..5000 {
+        let mut en = template[idx % template.len()].clone();
+        en["dump_id"] = serde_json::json!(100000 - idx as i32);
+        en["deps"] = serde_json::json!([]);
+        entries.push(en);
+    }
+    toc_json["header"]["toc_count"] = serde_json::json!(entries.len());
+    toc_json["entries"] = serde_json::Value::Array(entries);
+    let toc_dat_dest = work_dir.join("toc.dat");
+    pgdump_toc_rewrite::write_toc_from_json(&toc_dat_dest, &toc_json.to_string()).unwrap();
+
+    let toc_json_read: serde_json::Value = serde_json::from_str(
+        &pgdump_toc_rewrite::read_toc_to_json(&toc_dat_dest).unwrap()).unwrap();
+    assert_eq!(toc_json_read, toc_json);
+    let toc_json_reader: serde_json::Value = serde_json::from_str(
+        &pgdump_toc_rewrite::read_toc_to_json_from_reader(File::open(&toc_dat_dest).unwrap()).unwrap()).unwrap();
+    assert_eq!(toc_json_reader, toc_json);
+}
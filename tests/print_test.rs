@@ -14,8 +14,7 @@
  * limitations under the License.
  */
 
-use pgdump_toc_rewrite;
-
+use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
 use std::io::BufWriter;
@@ -45,4 +44,72 @@ fn print_test() {
     let toc_dest_st = fs::read_to_string(&toc_txt_dest).unwrap();
 
     assert_eq!(toc_orig_st, toc_dest_st);
-}
\ No newline at end of file
+}
+
+#[test]
+fn print_header_setters_test() {
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work_dir = project_dir.join("target/print_header_setters_test");
+    if work_dir.exists() {
+        std::fs::remove_dir_all(&work_dir).unwrap();
+    }
+    std::fs::create_dir(&work_dir).unwrap();
+
+    let mut toc = pgdump_toc_rewrite::Toc::read_from_path(project_dir.join("resources/dump/toc.dat")).unwrap();
+    toc.set_postgres_dbname("wilton2");
+    toc.set_server_version("16.1");
+    toc.set_pgdump_version("16.2");
+    let toc_dat = work_dir.join("toc.dat");
+    toc.write_to_path(&toc_dat).unwrap();
+
+    let mut printed = Vec::new();
+    pgdump_toc_rewrite::print_toc_from_reader(File::open(&toc_dat).unwrap(), &mut printed).unwrap();
+    let printed = String::from_utf8(printed).unwrap();
+    assert!(printed.contains("Postgres DB: wilton2\n"));
+    assert!(printed.contains("Server version: 16.1\n"));
+    assert!(printed.contains("pg_dump version: 16.2\n"));
+}
+
+#[test]
+fn create_script_test() {
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let toc_dat = project_dir.join("resources/dump/toc.dat");
+
+    let mut script = Vec::new();
+    pgdump_toc_rewrite::dump_create_script(&toc_dat, &mut script).unwrap();
+    let script = String::from_utf8(script).unwrap();
+    let position = |st: &str| script.find(st).unwrap_or_else(|| panic!("not found: {}", st));
+    let expected = [
+        "SET client_encoding = 'UTF8';",
+        "-- Name: test1_dbo; Type: SCHEMA; Schema: -; Owner: test1_db_owner\n--\n\nCREATE SCHEMA test1_dbo;\n",
+        "CREATE DOMAIN test1_dbo.domain1",
+        "CREATE TABLE test1_dbo.tab1 (",
+        "CREATE VIEW test1_dbo.view1 AS",
+        "ADD CONSTRAINT tab1_pkey PRIMARY KEY (id);",
+        "CREATE INDEX index1tab18df17c77af23061ef4322d47d6c431fa",
+        "CREATE TRIGGER trig1 AFTER INSERT OR UPDATE ON",
+        "GRANT ALL ON DATABASE wilton TO sysadmin",
+        "GRANT USAGE ON SCHEMA test1_dbo TO PUBLIC;",
+    ];
+    for pair in expected.windows(2) {
+        assert!(position(pair[0]) < position(pair[1]), "order: {} / {}", pair[0], pair[1]);
+    }
+    assert!(!script.contains("Type: TABLE DATA"));
+
+    let mut script = Vec::new();
+    pgdump_toc_rewrite::dump_create_script_with_dbname(&toc_dat, "foobar", &mut script).unwrap();
+    let script = String::from_utf8(script).unwrap();
+    assert!(script.contains("-- Name: foobar_dbo; Type: SCHEMA; Schema: -; Owner: foobar_db_owner\n--\n\nCREATE SCHEMA foobar_dbo;\n"));
+    assert!(script.contains("CREATE TABLE foobar_dbo.tab1 ("));
+    assert!(!script.contains("test1_dbo."));
+
+    let schema_map = HashMap::from([("test1_dbo".to_string(), "sales".to_string())]);
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.schema_map = Some(&schema_map);
+    let mut script = Vec::new();
+    pgdump_toc_rewrite::dump_create_script_with_options(&toc_dat, "foobar", &options, &mut script).unwrap();
+    let script = String::from_utf8(script).unwrap();
+    assert!(script.contains("CREATE SCHEMA sales;\n"));
+    assert!(script.contains("CREATE TABLE sales.tab1 ("));
+    assert!(!script.contains("foobar_dbo."));
+}
@@ -14,8 +14,6 @@
  * limitations under the License.
  */
 
-use pgdump_toc_rewrite;
-
 use std::collections::HashMap;
 
 fn check_rewritten(schema_from: &str, schema_to: &str, sql_from: &str, sql_to: &str) {
@@ -33,6 +31,18 @@ fn check_rewritten_qualified_single_quoted(schema_from: &str, schema_to: &str, s
     assert_eq!(rewritten, sql_to);
 }
 
+fn check_rewritten_unqualified(schema_from: &str, schema_to: &str, sql_from: &str, sql_to: &str) {
+    let schemas = HashMap::from([(schema_from.to_string(), schema_to.to_string())]);
+    let rewritten = pgdump_toc_rewrite::rewrite_schema_in_sql_unqualified(&schemas, sql_from).unwrap();
+    assert_eq!(rewritten, sql_to);
+}
+
+fn check_rewritten_single_quoted(schema_from: &str, schema_to: &str, sql_from: &str, sql_to: &str) {
+    let schemas = HashMap::from([(schema_from.to_string(), schema_to.to_string())]);
+    let rewritten = pgdump_toc_rewrite::rewrite_schema_in_sql_single_quoted(&schemas, sql_from).unwrap();
+    assert_eq!(rewritten, sql_to);
+}
+
 #[test]
 fn rewrite_sql_test() {
     // Replace error
@@ -59,3 +69,257 @@ END$_$;
             "SELECT pg_catalog.setval('foo1.foobar', 1, true);",
             "SELECT pg_catalog.setval('bar42.foobar', 1, true);")
 }
+
+
+#[test]
+fn rewrite_sql_non_ascii_test() {
+    // non-ASCII schema names
+    check_rewritten("схема1", "schema1",
+                    "select * from схема1.таблица where имя = 'ёж'",
+                    "select * from schema1.таблица where имя = 'ёж'");
+    check_rewritten("foo1", "bär",
+                    "select 'ä' from foo1.tab",
+                    "select 'ä' from \"bär\".tab");
+    check_rewritten("日本", "にほん",
+                    "select '🎉', \"🎉\" from \"日本\".\"表\" join 日本.表2 on true",
+                    "select '🎉', \"🎉\" from \"にほん\".\"表\" join \"にほん\".表2 on true");
+    // multi-byte chars on the preceding lines and before the name on the same line
+    check_rewritten("foo1", "bar42",
+                    "-- Ünïcödé ¥¥\nselect 'ж' as \"ß\",\n  '€' || foo1.f('€')\nfrom foo1.tab",
+                    "-- Ünïcödé ¥¥\nselect 'ж' as \"ß\",\n  '€' || bar42.f('€')\nfrom bar42.tab");
+    // only the whole identifier is replaced
+    check_rewritten("схема", "sch",
+                    "select * from схема1.tab, схема.tab",
+                    "select * from схема1.tab, sch.tab");
+
+    check_rewritten_qualified_single_quoted("схема1", "schema1",
+            "SELECT pg_catalog.setval('схема1.послед', 1, true);",
+            "SELECT pg_catalog.setval('schema1.послед', 1, true);");
+    check_rewritten_single_quoted("日本", "foo",
+                    "select '€', '日本', '日本1'",
+                    "select '€', 'foo', '日本1'");
+    check_rewritten_unqualified("ßchema", "sch",
+                    "CREATE SCHEMA ßchema AUTHORIZATION ß",
+                    "CREATE SCHEMA sch AUTHORIZATION ß");
+    check_rewritten_role("ролль", "role1",
+                    "GRANT ALL ON TABLE \"таб\" TO ролль;",
+                    "GRANT ALL ON TABLE \"таб\" TO role1;");
+}
+
+#[test]
+fn rewrite_sql_boundaries_test() {
+    // qualified
+    check_rewritten("test1_dbo", "foobar_dbo",
+                    "select * from test1_dbo_ext.tab",
+                    "select * from test1_dbo_ext.tab");
+    check_rewritten("test1_dbo", "foobar_dbo",
+                    "select * from xtest1_dbo.tab",
+                    "select * from xtest1_dbo.tab");
+    check_rewritten("test1_dbo", "foobar_dbo",
+                    "select * from test1_dbo.test1_dbo_tab",
+                    "select * from foobar_dbo.test1_dbo_tab");
+    check_rewritten("test1_dbo", "foobar_dbo",
+                    "select * from \"test1_dbo\".\"tab\"",
+                    "select * from \"foobar_dbo\".\"tab\"");
+    check_rewritten("test1_dbo", "foobar_dbo",
+                    "select * from \"test1_dbo.tab\"",
+                    "select * from \"test1_dbo.tab\"");
+    check_rewritten("test1_dbo", "foobar_dbo",
+                    "select * from test1_dbo$x.tab",
+                    "select * from test1_dbo$x.tab");
+
+    // unqualified
+    check_rewritten_unqualified("test1_dbo", "foobar_dbo",
+                    "CREATE SCHEMA test1_dbo_ext",
+                    "CREATE SCHEMA test1_dbo_ext");
+    check_rewritten_unqualified("test1_dbo", "foobar_dbo",
+                    "CREATE SCHEMA test1_dbo",
+                    "CREATE SCHEMA foobar_dbo");
+
+    // escaped quote inside quoted identifier, token value matches, source text does not
+    let schemas = HashMap::from([("test1_dbo\"".to_string(), "foobar_dbo".to_string())]);
+    assert!(pgdump_toc_rewrite::rewrite_schema_in_sql_unqualified(&schemas, "CREATE SCHEMA \"test1_dbo\"\"\"").is_err());
+
+    // single-quoted
+    check_rewritten_single_quoted("test1_dbo", "foobar_dbo",
+                    "SELECT 'test1_dbo', 'test1_dbo_ext', 'xtest1_dbo', E'test1_dbo'",
+                    "SELECT 'foobar_dbo', 'test1_dbo_ext', 'xtest1_dbo', E'test1_dbo'");
+    check_rewritten_single_quoted("test1_dbo", "foobar_dbo",
+                    "SELECT pg_catalog.setval('test1_dbo.seq', 1, true), 'test1_dbo.tab.col', 'test1_dbo_ext.tab', 'xtest1_dbo.tab'",
+                    "SELECT pg_catalog.setval('foobar_dbo.seq', 1, true), 'foobar_dbo.tab.col', 'test1_dbo_ext.tab', 'xtest1_dbo.tab'");
+    check_rewritten_single_quoted("test1_dbo", "foobar_dbo",
+                    "SELECT 'test1_dbo', 'test1_dbo.tab', 'tab.test1_dbo', '.test1_dbo', 'test1_dbo.'",
+                    "SELECT 'foobar_dbo', 'foobar_dbo.tab', 'tab.test1_dbo', '.test1_dbo', 'foobar_dbo.'");
+    check_rewritten_qualified_single_quoted("test1_dbo", "foobar_dbo",
+                    "SELECT pg_catalog.setval('test1_dbo_ext.seq', 1, true);",
+                    "SELECT pg_catalog.setval('test1_dbo_ext.seq', 1, true);");
+}
+
+#[test]
+fn rewrite_sql_owned_by_test() {
+    check_rewritten("test1_dbo", "foobar_dbo",
+                    "ALTER SEQUENCE test1_dbo.tab_id_seq OWNED BY test1_dbo.tab.col;",
+                    "ALTER SEQUENCE foobar_dbo.tab_id_seq OWNED BY foobar_dbo.tab.col;");
+    check_rewritten("test1_dbo", "foobar_dbo",
+                    "ALTER SEQUENCE \"test1_dbo\".\"Tab_Id_seq\" OWNED BY \"test1_dbo\".\"Tab\".\"Id\";",
+                    "ALTER SEQUENCE \"foobar_dbo\".\"Tab_Id_seq\" OWNED BY \"foobar_dbo\".\"Tab\".\"Id\";");
+    // table and column named as the schema
+    check_rewritten("test1_dbo", "foobar_dbo",
+                    "ALTER SEQUENCE test1_dbo.test1_dbo_seq OWNED BY test1_dbo.test1_dbo.test1_dbo;",
+                    "ALTER SEQUENCE foobar_dbo.test1_dbo_seq OWNED BY foobar_dbo.test1_dbo.test1_dbo;");
+    check_rewritten("test1_dbo", "foobar_dbo",
+                    "ALTER SEQUENCE test1_dbo.seq OWNED BY NONE;",
+                    "ALTER SEQUENCE foobar_dbo.seq OWNED BY NONE;");
+}
+
+fn check_rewritten_role(role_from: &str, role_to: &str, sql_from: &str, sql_to: &str) {
+    let roles = HashMap::from([(role_from.to_string(), role_to.to_string())]);
+    let rewritten = pgdump_toc_rewrite::rewrite_role_in_sql(&roles, sql_from).unwrap();
+    assert_eq!(rewritten, sql_to);
+}
+
+#[test]
+fn rewrite_role_test() {
+    check_rewritten_role("test1_dbo", "foobar_dbo",
+                    "GRANT ALL ON DATABASE wilton TO test1_dbo;",
+                    "GRANT ALL ON DATABASE wilton TO foobar_dbo;");
+    check_rewritten_role("test1_dbo", "foobar_dbo",
+                    "SET SESSION AUTHORIZATION test1_dbo;\nGRANT ALL ON SCHEMA test1_dbo TO test1_guest, \"test1_dbo\" WITH GRANT OPTION;\nRESET SESSION AUTHORIZATION;",
+                    "SET SESSION AUTHORIZATION foobar_dbo;\nGRANT ALL ON SCHEMA test1_dbo TO test1_guest, \"foobar_dbo\" WITH GRANT OPTION;\nRESET SESSION AUTHORIZATION;");
+    check_rewritten_role("test1_dbo", "foobar_dbo",
+                    "REVOKE ALL ON FUNCTION test1_dbo.func1(\"@param1\" integer) FROM test1_dbo;",
+                    "REVOKE ALL ON FUNCTION test1_dbo.func1(\"@param1\" integer) FROM foobar_dbo;");
+    check_rewritten_role("test1_dbo", "foobar_dbo",
+                    "ALTER DEFAULT PRIVILEGES FOR ROLE test1_dbo IN SCHEMA test1_dbo GRANT SELECT ON TABLES TO test1_dbo;",
+                    "ALTER DEFAULT PRIVILEGES FOR ROLE foobar_dbo IN SCHEMA test1_dbo GRANT SELECT ON TABLES TO foobar_dbo;");
+    check_rewritten_role("test1_dbo", "foobar_dbo",
+                    "ALTER TABLE test1_dbo.tab1 OWNER TO test1_dbo_ext;",
+                    "ALTER TABLE test1_dbo.tab1 OWNER TO test1_dbo_ext;");
+}
+
+#[test]
+fn rewrite_sql_quoted_test() {
+    // quotes are kept
+    check_rewritten("test1_dbo", "foobar_dbo",
+                    "select * from \"test1_dbo\".tab",
+                    "select * from \"foobar_dbo\".tab");
+    check_rewritten("test1_dbo", "foobar_dbo",
+                    "select * from \"test1_dbo\".\"Tab 1\", test1_dbo.tab2",
+                    "select * from \"foobar_dbo\".\"Tab 1\", foobar_dbo.tab2");
+    check_rewritten("Test1 Dbo", "foobar_dbo",
+                    "select * from \"Test1 Dbo\".tab",
+                    "select * from \"foobar_dbo\".tab");
+    // quotes are added when required
+    check_rewritten("test1_dbo", "Foobar Dbo",
+                    "select * from test1_dbo.tab, \"test1_dbo\".tab2",
+                    "select * from \"Foobar Dbo\".tab, \"Foobar Dbo\".tab2");
+    check_rewritten("test1_dbo", "select",
+                    "select * from test1_dbo.tab",
+                    "select * from \"select\".tab");
+    check_rewritten("test1_dbo", "foo\"bar",
+                    "select * from test1_dbo.tab, \"test1_dbo\".tab2",
+                    "select * from \"foo\"\"bar\".tab, \"foo\"\"bar\".tab2");
+    check_rewritten_unqualified("test1_dbo", "Foobar",
+                    "CREATE SCHEMA test1_dbo;",
+                    "CREATE SCHEMA \"Foobar\";");
+    // single-quoted literals are not identifiers
+    check_rewritten_single_quoted("test1_dbo", "Foobar",
+                    "SELECT 'test1_dbo';",
+                    "SELECT 'Foobar';");
+}
+
+#[test]
+fn rewrite_sql_tabs_test() {
+    check_rewritten("test1_dbo", "foobar_dbo",
+                    "CREATE TABLE test1_dbo.tab1 (\n\tid integer NOT NULL,\n\t\tval test1_dbo.domain1\t,\n\tval2 \"test1_dbo\".domain2\n);",
+                    "CREATE TABLE foobar_dbo.tab1 (\n\tid integer NOT NULL,\n\t\tval foobar_dbo.domain1\t,\n\tval2 \"foobar_dbo\".domain2\n);");
+    check_rewritten("test1_dbo", "foobar_dbo",
+                    "\t\tSELECT\t'\t'\t, test1_dbo.func1(\t)\r\n\tFROM\ttest1_dbo.tab1\t\r\n",
+                    "\t\tSELECT\t'\t'\t, foobar_dbo.func1(\t)\r\n\tFROM\tfoobar_dbo.tab1\t\r\n");
+}
+
+#[test]
+fn rewrite_sql_tokenizer_error_test() {
+    let schemas = HashMap::from([("foo1".to_string(), "bar42".to_string())]);
+    let body = "select 1;\n".repeat(1000);
+    let sql = format!("CREATE FUNCTION foo1.func1() RETURNS integer\n    LANGUAGE plpgsql\n    AS $$\n{}{}$$ || 'unterminated;\n",
+                      body, "x".repeat(100));
+    let err = pgdump_toc_rewrite::rewrite_schema_in_sql(&schemas, &sql).unwrap_err();
+    match err {
+        pgdump_toc_rewrite::TocError::Tokenizer { ref message, line, column, ref context } => {
+            assert_eq!(message, "Unterminated string literal");
+            assert_eq!(line, 1004);
+            assert_eq!(column, 107);
+            assert_eq!(context, &format!("{}$$ || 'unterminated;", "x".repeat(34)));
+        },
+        _ => panic!("unexpected error: {}", err)
+    }
+    assert!(err.to_string().len() < 200, "{}", err);
+}
+
+#[test]
+fn rewrite_dbname_test() {
+    let sql = "COPY test1.test1_dbo.tab1 (id, \"test1\") FROM stdin;";
+    assert_eq!(pgdump_toc_rewrite::rewrite_dbname_in_sql("test1", "foobar", sql).unwrap(),
+               "COPY foobar.test1_dbo.tab1 (id, \"test1\") FROM stdin;");
+    let schemas = HashMap::from([("test1_dbo".to_string(), "foobar_dbo".to_string())]);
+    let rewritten = pgdump_toc_rewrite::rewrite_schema_in_sql(&schemas, sql).unwrap();
+    assert_eq!(pgdump_toc_rewrite::rewrite_dbname_in_sql("test1", "foobar", &rewritten).unwrap(),
+               "COPY foobar.foobar_dbo.tab1 (id, \"test1\") FROM stdin;");
+
+    // quotes are kept
+    assert_eq!(pgdump_toc_rewrite::rewrite_dbname_in_sql("test1", "foobar",
+                    "COPY \"test1\".\"test1_dbo\".\"tab1\" FROM stdin;").unwrap(),
+               "COPY \"foobar\".\"test1_dbo\".\"tab1\" FROM stdin;");
+    // two-part and four-part names, other names are left as is
+    for sql in ["COPY test1.tab1 FROM stdin;",
+                "COPY srv.test1.test1_dbo.tab1 FROM stdin;",
+                "COPY test1_ext.test1_dbo.tab1 FROM stdin;",
+                "SELECT test1 FROM tab1"] {
+        assert_eq!(pgdump_toc_rewrite::rewrite_dbname_in_sql("test1", "foobar", sql).unwrap(), sql);
+    }
+    // new name is quoted when required
+    assert_eq!(pgdump_toc_rewrite::rewrite_dbname_in_sql("test1", "Foo",
+                    "COPY test1.test1_dbo.tab1 FROM stdin;").unwrap(),
+               "COPY \"Foo\".test1_dbo.tab1 FROM stdin;");
+}
+
+#[test]
+fn rewrite_tablespace_test() {
+    let tablespaces = HashMap::from([("ts1".to_string(), "ts_new".to_string())]);
+    let check = |sql_from: &str, sql_to: &str| {
+        assert_eq!(pgdump_toc_rewrite::rewrite_tablespace_in_sql(&tablespaces, sql_from).unwrap(), sql_to);
+    };
+    check("CREATE INDEX idx1 ON dbo.tab1 USING btree (id) TABLESPACE ts1;",
+          "CREATE INDEX idx1 ON dbo.tab1 USING btree (id) TABLESPACE ts_new;");
+    check("ALTER TABLE ONLY dbo.tab1 ADD CONSTRAINT tab1_pkey PRIMARY KEY (id) USING INDEX tablespace \"ts1\";",
+          "ALTER TABLE ONLY dbo.tab1 ADD CONSTRAINT tab1_pkey PRIMARY KEY (id) USING INDEX tablespace \"ts_new\";");
+    check("ALTER TABLE dbo.tab1 SET TABLESPACE\n    ts1;",
+          "ALTER TABLE dbo.tab1 SET TABLESPACE\n    ts_new;");
+    // only names that follow TABLESPACE keyword
+    check("CREATE TABLE ts1.ts1 (ts1 int) TABLESPACE ts2;",
+          "CREATE TABLE ts1.ts1 (ts1 int) TABLESPACE ts2;");
+    check("SELECT 'TABLESPACE ts1', \"tablespace\" ts1 FROM tab1",
+          "SELECT 'TABLESPACE ts1', \"tablespace\" ts1 FROM tab1");
+}
+
+#[test]
+fn rewrite_sql_dialect_test() {
+    use pgdump_toc_rewrite::SqlDialect;
+    let schemas = HashMap::from([("foo1".to_string(), "bar42".to_string())]);
+
+    // '#' starts an identifier in generic dialect and swallows the string quote
+    let sql = "CREATE VIEW foo1.v1 AS SELECT flags #'1'::integer AS f FROM foo1.tab1;";
+    let err = pgdump_toc_rewrite::rewrite_schema_in_sql_with_dialect(&schemas, sql, SqlDialect::Generic).unwrap_err();
+    assert!(err.to_string().contains("Unterminated string literal"));
+    assert!(pgdump_toc_rewrite::rewrite_schema_in_sql(&schemas, sql).is_err());
+    let rewritten = pgdump_toc_rewrite::rewrite_schema_in_sql_with_dialect(&schemas, sql, SqlDialect::PostgreSql).unwrap();
+    assert_eq!(rewritten, "CREATE VIEW bar42.v1 AS SELECT flags #'1'::integer AS f FROM bar42.tab1;");
+
+    // square brackets are only delimiters in T-SQL
+    let sql = "EXEC [foo1].[proc1] @p1 = 42";
+    let rewritten = pgdump_toc_rewrite::rewrite_schema_in_sql_with_dialect(&schemas, sql, SqlDialect::Tsql).unwrap();
+    assert_eq!(rewritten, "EXEC [bar42].[proc1] @p1 = 42");
+    let rewritten = pgdump_toc_rewrite::rewrite_schema_in_sql_with_dialect(&schemas, sql, SqlDialect::Generic).unwrap();
+    assert_eq!(rewritten, sql);
+}
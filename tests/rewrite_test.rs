@@ -13,16 +13,52 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
-use pgdump_toc_rewrite;
-
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Read, Write};
 use std::io::BufWriter;
 use std::path::Path;
+use std::path::PathBuf;
 
 use copy_dir::copy_dir;
 use flate2::bufread::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+fn prepare_dump(test_name: &str) -> PathBuf {
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let dump_dir = project_dir.join("resources").join("dump");
+    let work_dir = project_dir.join("target").join(test_name);
+    if work_dir.exists() {
+        std::fs::remove_dir_all(&work_dir).unwrap();
+    }
+    std::fs::create_dir(&work_dir).unwrap();
+    let dest_dump_dir = work_dir.join("dump");
+    copy_dir(&dump_dir, &dest_dump_dir).unwrap();
+    dest_dump_dir
+}
+
+fn read_gz(path: &Path) -> String {
+    let mut reader = BufReader::new(GzDecoder::new(BufReader::new(File::open(path).unwrap())));
+    let mut st = String::new();
+    reader.read_to_string(&mut st).unwrap();
+    st
+}
+
+fn assert_gz_content_eq(path: &Path, expected_path: &Path, replace_from: &str, replace_to: &str) {
+    let expected = read_gz(expected_path).replace(replace_from, replace_to);
+    let actual = read_gz(path);
+    assert_eq!(expected, actual, "content mismatch, file: {}", path.to_string_lossy());
+}
+
+fn write_gz(path: &Path, st: &str) {
+    let mut writer = GzEncoder::new(BufWriter::new(File::create(path).unwrap()), Compression::new(6));
+    writer.write_all(st.as_bytes()).unwrap();
+    writer.finish().unwrap().flush().unwrap();
+}
 
 #[test]
 fn rewrite_test() {
@@ -92,3 +128,2498 @@ fn rewrite_test() {
     function_ext_reader.read_to_string(&mut function_ext_st).unwrap();
     assert_eq!(function_ext_orig_st_replaced, function_ext_st);
 }
+
+#[test]
+fn rewrite_log_test() {
+    let dest_dump_dir = prepare_dump("rewrite_log_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    let messages = RefCell::new(Vec::new());
+    pgdump_toc_rewrite::rewrite_toc_with_log(&toc_dat, "foobar", |msg| {
+        messages.borrow_mut().push(msg.to_string());
+    }).unwrap();
+
+    let messages = messages.into_inner();
+    assert!(messages.contains(&"entry 18 SCHEMA tag test1_dbo -> foobar_dbo, owner test1_db_owner -> foobar_db_owner, create_stmt rewritten, drop_stmt rewritten".to_string()));
+    assert!(messages.contains(&"catalog babelfish_function_ext (5982.dat.gz): 16 rows rewritten, compression level: 6".to_string()));
+    assert!(messages.contains(&"catalog babelfish_sysdatabases (5981.dat.gz): 1 rows rewritten, compression level: 6".to_string()));
+}
+
+#[test]
+#[allow(deprecated)]
+fn rewrite_deprecated_functions_test() {
+    let dest_dump_dir = prepare_dump("rewrite_deprecated_functions_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    pgdump_toc_rewrite::rewrite_toc_with_compression_level(&toc_dat, "foobar", 9, |_| {}).unwrap();
+    assert!(read_gz(&dest_dump_dir.join("5983.dat.gz")).contains("foobar_dbo"));
+    let summary = pgdump_toc_rewrite::rewrite_toc_skip_missing_catalogs(&toc_dat, "foobar", |_| {}).unwrap();
+    assert!(summary.no_change_needed);
+}
+
+#[test]
+fn rewrite_short_record_test() {
+    let dest_dump_dir = prepare_dump("rewrite_short_record_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    // babelfish_authid_user_ext with the second record truncated
+    let authid_user_ext = dest_dump_dir.join("5984.dat.gz");
+    let mut lines: Vec<String> = read_gz(&authid_user_ext).split('\n').map(|st| st.to_string()).collect();
+    lines[1] = lines[1].split('\t').take(5).collect::<Vec<&str>>().join("\t");
+    write_gz(&authid_user_ext, &lines.join("\n"));
+
+    let err = pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap_err();
+    assert_eq!(err.to_string(), "Catalog rewrite error, table: babelfish_authid_user_ext, file: 5984.dat, line: 2, \
+        message: Column index out of range, expected index: 11, columns count: 5");
+    match err {
+        pgdump_toc_rewrite::TocError::DataFile { table, filename, line, .. } => {
+            assert_eq!(table.as_deref(), Some("babelfish_authid_user_ext"));
+            assert_eq!(filename, "5984.dat");
+            assert_eq!(line, Some(2));
+        },
+        _ => panic!("unexpected error: {}", err)
+    }
+}
+
+#[test]
+fn rewrite_catalog_layout_test() {
+    let dest_dump_dir = prepare_dump("rewrite_catalog_layout_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    // babelfish_sysdatabases with 'owner' and 'name' columns swapped
+    modify_toc_json(&toc_dat, |toc_json| {
+        for en in toc_json["entries"].as_array_mut().unwrap() {
+            if en["tag"] == "babelfish_sysdatabases" && en["description"] == "TABLE DATA" {
+                en["copy_stmt"] = serde_json::Value::String("COPY sys.babelfish_sysdatabases \
+                    (status, status2, name, default_collation, owner, crdate, properties) FROM stdin;\n".to_string());
+            }
+        }
+    });
+    let sysdatabases = dest_dump_dir.join("5981.dat.gz");
+    let sysdatabases_st = read_gz(&sysdatabases).replacen("0\t0\twilton\tbbf_unicode_cp1_ci_as\ttest1\t", "0\t0\ttest1\tbbf_unicode_cp1_ci_as\twilton\t", 1);
+    write_gz(&sysdatabases, &sysdatabases_st);
+
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+
+    let rewritten = read_gz(&sysdatabases);
+    assert!(rewritten.starts_with("0\t0\tfoobar\tbbf_unicode_cp1_ci_as\twilton\t"));
+}
+
+#[test]
+fn rewrite_catalogs_content_test() {
+    let dest_dump_dir = prepare_dump("rewrite_catalogs_content_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+
+    // compressed bytes may differ, decompressed content must only differ in renamed values
+    for filename in ["5981.dat", "5982.dat", "5983.dat", "5984.dat", "5986.dat"] {
+        let orig = dest_dump_dir.join(format!("{}.orig.gz", filename));
+        let rewritten = dest_dump_dir.join(format!("{}.gz", filename));
+        assert_gz_content_eq(&rewritten, &orig, "test1", "foobar");
+    }
+    // gzip headers are preserved
+    for filename in ["5981.dat", "5982.dat", "5983.dat", "5984.dat", "5986.dat"] {
+        let orig = fs::read(dest_dump_dir.join(format!("{}.orig.gz", filename))).unwrap();
+        let rewritten = fs::read(dest_dump_dir.join(format!("{}.gz", filename))).unwrap();
+        assert_eq!(orig[..10], rewritten[..10], "gzip header mismatch, file: {}", filename);
+    }
+    // not a Babelfish catalog, left untouched
+    let domain_mapping = dest_dump_dir.join("5985.dat.gz");
+    assert!(!dest_dump_dir.join("5985.dat.orig.gz").exists());
+    assert_eq!(fs::read(&domain_mapping).unwrap(), fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/dump/5985.dat.gz")).unwrap());
+}
+
+#[test]
+fn rewrite_owners_test() {
+    let dest_dump_dir = prepare_dump("rewrite_owners_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    let owners = HashMap::from([("test1_dbo".to_string(), "owner1".to_string())]);
+    pgdump_toc_rewrite::rewrite_owners(&toc_dat, &owners).unwrap();
+
+    let mut toc_buf: Vec<u8> = Vec::new();
+    pgdump_toc_rewrite::print_toc(&toc_dat, &mut toc_buf).unwrap();
+    let toc_st = String::from_utf8(toc_buf).unwrap();
+    assert!(toc_st.contains("owner: owner1\n"));
+    assert!(!toc_st.contains("owner: test1_dbo\n"));
+    assert!(toc_st.contains("owner: test1_db_owner\n"));
+    assert!(toc_st.contains("GRANT ALL ON DATABASE wilton TO owner1;"));
+    // schemas are unchanged
+    assert!(toc_st.contains("tag: test1_dbo\n"));
+    assert!(toc_st.contains("create_stmt: CREATE SCHEMA test1_dbo;\n"));
+    assert!(toc_st.contains("namespace: test1_dbo\n"));
+    assert!(!toc_st.contains("owner1_"));
+
+    let authid_user_ext = dest_dump_dir.join("5984.dat.gz");
+    assert_gz_content_eq(&authid_user_ext, &dest_dump_dir.join("5984.dat.orig.gz"), "test1_dbo\t", "owner1\t");
+    // other catalogs are not touched
+    assert!(!dest_dump_dir.join("5983.dat.orig.gz").exists());
+}
+
+#[test]
+fn rewrite_single_schema_test() {
+    let dest_dump_dir = prepare_dump("rewrite_single_schema_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    assert!(pgdump_toc_rewrite::rewrite_single_schema(&toc_dat, "test1_schema1", "test1_dbo").is_err());
+    assert!(pgdump_toc_rewrite::rewrite_single_schema(&toc_dat, "test1_fail", "test1_schema2").is_err());
+    assert!(pgdump_toc_rewrite::rewrite_single_schema(&toc_dat, "test1_schema1", "").is_err());
+
+    pgdump_toc_rewrite::rewrite_single_schema(&toc_dat, "test1_schema1", "test1_schema2").unwrap();
+
+    let mut toc_buf: Vec<u8> = Vec::new();
+    pgdump_toc_rewrite::print_toc(&toc_dat, &mut toc_buf).unwrap();
+    let toc_st = String::from_utf8(toc_buf).unwrap();
+    assert!(!toc_st.contains("test1_schema1"));
+    assert!(toc_st.contains("tag: test1_schema2\n"));
+    assert!(toc_st.contains("create_stmt: CREATE SCHEMA test1_schema2;\n"));
+    assert!(toc_st.contains("tag: SCHEMA test1_schema2\n"));
+    assert!(toc_st.contains("GRANT USAGE ON SCHEMA test1_schema2 TO PUBLIC;"));
+    assert!(toc_st.contains("namespace: test1_schema2\n"));
+    // other schemas, owners and DB name are unchanged
+    assert!(toc_st.contains("create_stmt: CREATE SCHEMA test1_dbo;\n"));
+    assert!(toc_st.contains("GRANT USAGE ON SCHEMA test1_guest TO PUBLIC;"));
+    assert!(toc_st.contains("namespace: test1_dbo\n"));
+    assert!(toc_st.contains("owner: test1_dbo\n"));
+    assert!(toc_st.contains("GRANT ALL ON DATABASE wilton TO test1_dbo;"));
+
+    for filename in ["5982.dat", "5983.dat", "5986.dat"] {
+        let orig = dest_dump_dir.join(format!("{}.orig.gz", filename));
+        let rewritten = dest_dump_dir.join(format!("{}.gz", filename));
+        assert_gz_content_eq(&rewritten, &orig, "test1_schema1", "test1_schema2");
+    }
+    assert!(!dest_dump_dir.join("5981.dat.orig.gz").exists());
+    assert!(!dest_dump_dir.join("5984.dat.orig.gz").exists());
+}
+
+#[test]
+fn rewrite_missing_catalog_test() {
+    let dest_dump_dir = prepare_dump("rewrite_missing_catalog_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    // dump without babelfish_extended_properties
+    modify_toc_json(&toc_dat, |toc_json| {
+        toc_json["entries"].as_array_mut().unwrap().retain(|en| en["tag"] != "babelfish_extended_properties");
+        let count = toc_json["entries"].as_array().unwrap().len();
+        toc_json["header"]["toc_count"] = serde_json::Value::from(count);
+    });
+    fs::remove_file(dest_dump_dir.join("5986.dat.gz")).unwrap();
+
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &Default::default()).unwrap();
+    assert_eq!(summary.warnings, vec!("catalog babelfish_extended_properties not found in TOC, skipped".to_string()));
+    assert_eq!(summary.catalogs_rewritten.len(), 4);
+    assert!(!summary.catalogs_rewritten.contains(&"babelfish_extended_properties".to_string()));
+    for filename in ["5981.dat", "5982.dat", "5983.dat", "5984.dat"] {
+        let orig = dest_dump_dir.join(format!("{}.orig.gz", filename));
+        let rewritten = dest_dump_dir.join(format!("{}.gz", filename));
+        assert_gz_content_eq(&rewritten, &orig, "test1", "foobar");
+    }
+}
+
+#[test]
+fn rewrite_missing_sysdatabases_test() {
+    let dest_dump_dir = prepare_dump("rewrite_missing_sysdatabases_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    modify_toc_json(&toc_dat, |toc_json| {
+        toc_json["entries"].as_array_mut().unwrap().retain(|en| en["tag"] != "babelfish_sysdatabases");
+    });
+
+    let err = pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap_err();
+    assert!(err.to_string().contains("babelfish_sysdatabases"));
+    assert_eq!(err.kind(), "not_babelfish_dump");
+    assert!(!dest_dump_dir.join("5984.dat.orig.gz").exists());
+    assert!(!dest_dump_dir.join("toc.dat.orig").exists());
+}
+
+// removes all data entries and data files, like in dumps created with 'pg_dump -s'
+fn make_schema_only(dump_dir: &Path) {
+    let toc_dat = dump_dir.join("toc.dat");
+    modify_toc_json(&toc_dat, |toc_json| {
+        toc_json["entries"].as_array_mut().unwrap().retain(|en| en["description"] != "TABLE DATA");
+    });
+    for en in fs::read_dir(dump_dir).unwrap() {
+        let path = en.unwrap().path();
+        if path.to_string_lossy().ends_with(".dat.gz") {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
+
+#[test]
+fn rename_schema_without_catalogs_test() {
+    let dest_dump_dir = prepare_dump("rename_schema_without_catalogs_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    make_schema_only(&dest_dump_dir);
+
+    pgdump_toc_rewrite::rewrite_single_schema(&toc_dat, "test1_schema1", "test1_schema2").unwrap();
+    let toc_json = read_toc_json(&toc_dat);
+    let entries = toc_json["entries"].as_array().unwrap();
+    assert!(entries.iter().any(|en| en["description"] == "SCHEMA" && en["tag"] == "test1_schema2"));
+    assert!(!entries.iter().any(|en| en["tag"] == "test1_schema1" || en["namespace"] == "test1_schema1"));
+}
+
+#[test]
+fn rewrite_table_data_test() {
+    let dest_dump_dir = prepare_dump("rewrite_table_data_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    let filename = pgdump_toc_rewrite::table_data_filename(&toc_dat, "sys", "babelfish_function_ext").unwrap();
+    assert_eq!(filename, "5982.dat");
+    assert!(pgdump_toc_rewrite::table_data_filename(&toc_dat, "public", "babelfish_function_ext").is_err());
+
+    let mut definitions = Vec::new();
+    let count = pgdump_toc_rewrite::rewrite_table_data(&dest_dump_dir, &filename, pgdump_toc_rewrite::CompressionSpec::Gzip(6), |mut fields| {
+        assert_eq!(fields[4], None);
+        definitions.push(fields[9].take().unwrap());
+        fields[9] = Some("masked\tdefinition\\".to_string());
+        Ok(fields)
+    }).unwrap();
+    assert_eq!(count, 16);
+    assert!(definitions[0].starts_with("create function func1(@param1 int) returns int as\r\nbegin\r\n"));
+
+    let orig = read_gz(&dest_dump_dir.join("5982.dat.orig.gz"));
+    let rewritten = read_gz(&dest_dump_dir.join("5982.dat.gz"));
+    let expected: Vec<String> = orig.split('\n').map(|line| {
+        let mut fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() > 1 {
+            fields[9] = "masked\\tdefinition\\\\";
+        }
+        fields.join("\t")
+    }).collect();
+    assert_eq!(expected.join("\n"), rewritten);
+}
+
+#[test]
+fn rewrite_table_data_non_utf8_test() {
+    let dest_dump_dir = prepare_dump("rewrite_table_data_non_utf8_test");
+    let data_file = dest_dump_dir.join("9999.dat");
+    fs::write(&data_file, b"1\tcaf\xe9\tfoo\n2\tbar\tfoo\n\\.\n\n").unwrap();
+
+    let mut names = Vec::new();
+    let count = pgdump_toc_rewrite::rewrite_table_data(&dest_dump_dir, "9999.dat", pgdump_toc_rewrite::CompressionSpec::None, |mut fields| {
+        names.push(fields[1].clone().unwrap());
+        fields[2] = Some("qux".to_string());
+        Ok(fields)
+    }).unwrap();
+    assert_eq!(count, 2);
+    assert_eq!(names, vec!("caf\u{FFFD}".to_string(), "bar".to_string()));
+
+    // unchanged field keeps the original byte
+    assert_eq!(fs::read(&data_file).unwrap(), b"1\tcaf\xe9\tqux\n2\tbar\tqux\n\\.\n\n");
+    assert_eq!(fs::read(dest_dump_dir.join("9999.dat.orig")).unwrap(), b"1\tcaf\xe9\tfoo\n2\tbar\tfoo\n\\.\n\n");
+
+    // changed field is written as UTF-8
+    pgdump_toc_rewrite::clean_backups(dest_dump_dir.join("toc.dat")).unwrap();
+    pgdump_toc_rewrite::rewrite_table_data(&dest_dump_dir, "9999.dat", pgdump_toc_rewrite::CompressionSpec::None, |mut fields| {
+        fields[1] = fields[1].take().map(|name| name.to_uppercase());
+        Ok(fields)
+    }).unwrap();
+    assert_eq!(fs::read(&data_file).unwrap(), "1\tCAF\u{FFFD}\tqux\n2\tBAR\tqux\n\\.\n\n".as_bytes());
+}
+
+#[test]
+fn rewrite_bom_test() {
+    let dest_dump_dir = prepare_dump("rewrite_bom_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    let namespace_ext = dest_dump_dir.join("5983.dat.gz");
+    let namespace_ext_st = read_gz(&namespace_ext);
+    assert!(namespace_ext_st.starts_with("test1_dbo\t"));
+    write_gz(&namespace_ext, &format!("\u{FEFF}{}", namespace_ext_st));
+
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+
+    // BOM is preserved, first row is rewritten
+    let rewritten = read_gz(&namespace_ext);
+    assert_eq!(rewritten, format!("\u{FEFF}{}", namespace_ext_st.replace("test1", "foobar")));
+}
+
+#[test]
+fn rewrite_progress_test() {
+    let dest_dump_dir = prepare_dump("rewrite_progress_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    let events = RefCell::new(Vec::new());
+    let progress = |event: pgdump_toc_rewrite::ProgressEvent| {
+        let first = events.borrow().is_empty();
+        events.borrow_mut().push(event);
+        // must not affect the rewrite
+        if first {
+            panic!("progress callback failure");
+        }
+    };
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.progress = Some(&progress);
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+
+    let events = events.into_inner();
+    assert!(events.contains(&pgdump_toc_rewrite::ProgressEvent::TocEntries { processed: 1, total: 81 }));
+    assert!(events.contains(&pgdump_toc_rewrite::ProgressEvent::TocEntries { processed: 81, total: 81 }));
+    for (catalog, filename) in [("babelfish_sysdatabases", "5981.dat.orig.gz"), ("babelfish_function_ext", "5982.dat.orig.gz")] {
+        let total = fs::metadata(dest_dump_dir.join(filename)).unwrap().len();
+        assert!(events.contains(&pgdump_toc_rewrite::ProgressEvent::CatalogBytes {
+            catalog: catalog.to_string(),
+            processed: total,
+            total
+        }));
+    }
+    assert_eq!(events.last(), Some(&pgdump_toc_rewrite::ProgressEvent::RenamingFiles));
+}
+
+#[test]
+fn rewrite_to_dir_test() {
+    let dest_dump_dir = prepare_dump("rewrite_to_dir_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    fs::write(dest_dump_dir.join("notes.txt"), "foo").unwrap();
+    let output_dir = dest_dump_dir.parent().unwrap().join("output");
+
+    let list_dir = |dir: &Path| {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap()
+            .map(|en| en.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    };
+    let src_files = list_dir(&dest_dump_dir);
+    let src_contents: Vec<Vec<u8>> = src_files.iter().map(|name| fs::read(dest_dump_dir.join(name)).unwrap()).collect();
+
+    let summary = pgdump_toc_rewrite::rewrite_toc_to_dir(&toc_dat, &output_dir, "foobar").unwrap();
+    assert_eq!(summary.unreferenced_files, vec!("notes.txt".to_string()));
+    assert_eq!(summary.warnings, vec!("file notes.txt is not referenced in TOC".to_string()));
+
+    // source dump is not modified
+    assert_eq!(src_files, list_dir(&dest_dump_dir));
+    for (name, content) in src_files.iter().zip(src_contents.iter()) {
+        assert_eq!(*content, fs::read(dest_dump_dir.join(name)).unwrap(), "file modified: {}", name);
+    }
+
+    let mut expected_files: Vec<String> = src_files.into_iter().filter(|name| "notes.txt" != name).collect();
+    expected_files.sort();
+    assert_eq!(expected_files, list_dir(&output_dir));
+    let mut toc_buf: Vec<u8> = Vec::new();
+    pgdump_toc_rewrite::print_toc(output_dir.join("toc.dat"), &mut toc_buf).unwrap();
+    let toc_foobar = fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/toc_foobar.txt")).unwrap();
+    assert_eq!(toc_foobar, String::from_utf8(toc_buf).unwrap());
+    for filename in ["5981.dat.gz", "5982.dat.gz", "5983.dat.gz", "5984.dat.gz", "5986.dat.gz"] {
+        assert_gz_content_eq(&output_dir.join(filename), &dest_dump_dir.join(filename), "test1", "foobar");
+    }
+    assert_eq!(fs::read(dest_dump_dir.join("5985.dat.gz")).unwrap(), fs::read(output_dir.join("5985.dat.gz")).unwrap());
+
+    // output directory must be empty
+    assert!(pgdump_toc_rewrite::rewrite_toc_to_dir(&toc_dat, &output_dir, "foobar").is_err());
+}
+
+#[test]
+fn check_dump_dir_test() {
+    let dest_dump_dir = prepare_dump("check_dump_dir_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    let report = pgdump_toc_rewrite::check_dump_dir(&toc_dat).unwrap();
+    assert!(report.is_ok(), "{}", report);
+
+    fs::remove_file(dest_dump_dir.join("5985.dat.gz")).unwrap();
+    fs::write(dest_dump_dir.join("5983.dat.gz"), "test1_dbo\tdbo\t{}\n").unwrap();
+    fs::write(dest_dump_dir.join("9999.dat.gz"), "").unwrap();
+    fs::write(dest_dump_dir.join("notes.txt"), "foo").unwrap();
+    for filename in ["toc.dat.orig", "5982.dat.orig.gz", "5982.dat.new.gz", "toc.dat.new"] {
+        fs::write(dest_dump_dir.join(filename), "").unwrap();
+    }
+
+    let report = pgdump_toc_rewrite::check_dump_dir(&toc_dat).unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(report, pgdump_toc_rewrite::DumpDirReport {
+        missing_files: vec!("5985.dat.gz".to_string()),
+        orphan_files: vec!("9999.dat.gz".to_string()),
+        duplicate_files: vec!(),
+        compression_mismatches: vec!("5983.dat.gz".to_string()),
+    });
+    assert_eq!(report.to_string(), "Missing file: 5985.dat.gz\nOrphan file: 9999.dat.gz\nCompression mismatch: 5983.dat.gz\n");
+}
+
+#[test]
+fn derive_rename_maps_test() {
+    let dest_dump_dir = prepare_dump("derive_rename_maps_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let toc_orig = fs::read(&toc_dat).unwrap();
+
+    assert!(pgdump_toc_rewrite::derive_rename_maps(&toc_dat, "select").is_err());
+    let maps = pgdump_toc_rewrite::derive_rename_maps(&toc_dat, "foobar").unwrap();
+    assert_eq!(maps.orig_dbname, "test1");
+    assert_eq!(maps.schemas, HashMap::from([
+        ("test1_dbo".to_string(), "foobar_dbo".to_string()),
+        ("test1_guest".to_string(), "foobar_guest".to_string()),
+        ("test1_schema1".to_string(), "foobar_schema1".to_string()),
+    ]));
+    assert_eq!(maps.owners, HashMap::from([
+        ("test1_dbo".to_string(), "foobar_dbo".to_string()),
+        ("test1_db_owner".to_string(), "foobar_db_owner".to_string()),
+        ("test1_guest".to_string(), "foobar_guest".to_string()),
+    ]));
+    // nothing is written
+    assert_eq!(toc_orig, fs::read(&toc_dat).unwrap());
+    assert!(!dest_dump_dir.join("toc.dat.orig").exists());
+}
+
+#[test]
+fn rewrite_postgres_dbname_test() {
+    let dest_dump_dir = prepare_dump("rewrite_postgres_dbname_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.postgres_dbname = Some("wilton2");
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+
+    let toc_json_st = pgdump_toc_rewrite::read_toc_to_json(&toc_dat).unwrap();
+    let toc_json: serde_json::Value = serde_json::from_str(&toc_json_st).unwrap();
+    assert_eq!(toc_json["header"]["postgres_dbname"], "wilton2");
+    let mut printed = Vec::new();
+    pgdump_toc_rewrite::print_toc(&toc_dat, &mut printed).unwrap();
+    assert!(String::from_utf8(printed).unwrap().contains("Postgres DB: wilton2\n"));
+    let orig_json_st = pgdump_toc_rewrite::read_toc_to_json(dest_dump_dir.join("toc.dat.orig")).unwrap();
+    let orig_json: serde_json::Value = serde_json::from_str(&orig_json_st).unwrap();
+    assert_eq!(orig_json["header"]["postgres_dbname"], "wilton");
+}
+
+#[test]
+fn rewrite_toc_only_test() {
+    let dest_dump_dir = prepare_dump("rewrite_toc_only_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let src_dump_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/dump");
+    let catalogs = ["5981.dat.gz", "5982.dat.gz", "5983.dat.gz", "5984.dat.gz", "5986.dat.gz"];
+
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.toc_only = true;
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+    assert!(summary.catalogs_rewritten.is_empty());
+    assert!(dest_dump_dir.join("toc.dat.orig").exists());
+    for filename in catalogs {
+        assert_eq!(fs::read(dest_dump_dir.join(filename)).unwrap(), fs::read(src_dump_dir.join(filename)).unwrap());
+        assert!(!dest_dump_dir.join(filename.replace(".dat", ".dat.orig")).exists());
+    }
+    let toc_json = read_toc_json(&toc_dat);
+    let schema = toc_json["entries"].as_array().unwrap().iter().find(|en| en["dump_id"] == 18).unwrap();
+    assert_eq!(schema["tag"], "foobar_dbo");
+
+    // catalogs are copied unchanged into the output directory
+    let copy_dump_dir = prepare_dump("rewrite_toc_only_test_copy");
+    let output_dir = copy_dump_dir.with_file_name("output");
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.toc_only = true;
+    options.output_dir = Some(&output_dir);
+    pgdump_toc_rewrite::rewrite_toc_with_options(copy_dump_dir.join("toc.dat"), "foobar", &options).unwrap();
+    assert_eq!(fs::read(output_dir.join("toc.dat")).unwrap(), fs::read(&toc_dat).unwrap());
+    for filename in catalogs {
+        assert_eq!(fs::read(output_dir.join(filename)).unwrap(), fs::read(src_dump_dir.join(filename)).unwrap());
+    }
+}
+
+#[test]
+fn rewrite_touch_timestamp_test() {
+    let dest_dump_dir = prepare_dump("rewrite_touch_timestamp_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let orig_json = read_toc_json(&toc_dat);
+
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.touch_timestamp = true;
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+
+    let toc_json = read_toc_json(&toc_dat);
+    assert_ne!(toc_json["header"]["timestamp"], orig_json["header"]["timestamp"]);
+    let timestamp = chrono::NaiveDateTime::parse_from_str(
+        toc_json["header"]["timestamp"].as_str().unwrap(), "%Y-%m-%d %H:%M:%S").unwrap();
+    let age = chrono::Local::now().naive_local() - timestamp;
+    assert!(age.num_seconds() >= 0 && age.num_seconds() < 600);
+    assert!(toc_json["header"]["is_dst"].is_null());
+    pgdump_toc_rewrite::verify_roundtrip(&toc_dat).unwrap();
+}
+
+fn read_toc_json(toc_path: &Path) -> serde_json::Value {
+    let toc_json_st = pgdump_toc_rewrite::read_toc_to_json(toc_path).unwrap();
+    serde_json::from_str(&toc_json_st).unwrap()
+}
+
+// replaces TOC file with the one written from its edited JSON
+fn modify_toc_json<F: FnOnce(&mut serde_json::Value)>(toc_path: &Path, edit: F) {
+    let mut toc_json = read_toc_json(toc_path);
+    edit(&mut toc_json);
+    fs::remove_file(toc_path).unwrap();
+    pgdump_toc_rewrite::write_toc_from_json(toc_path, &toc_json.to_string()).unwrap();
+}
+
+#[test]
+fn filter_toc_test() {
+    use pgdump_toc_rewrite::exclude_tags;
+    let dest_dump_dir = prepare_dump("filter_toc_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let toc_filtered = dest_dump_dir.join("toc_filtered.dat");
+
+    let summary = pgdump_toc_rewrite::filter_toc(&toc_dat, &toc_filtered, exclude_tags(&["tab1"])).unwrap();
+    assert_eq!(summary.removed_entries, vec!(372, 5991));
+    assert_eq!(summary.unreferenced_files, vec!("5991.dat.gz".to_string()));
+    assert!(dest_dump_dir.join("5991.dat.gz").exists());
+
+    let toc_json = read_toc_json(&toc_filtered);
+    let entries = toc_json["entries"].as_array().unwrap();
+    assert_eq!(toc_json["header"]["toc_count"], 79);
+    assert_eq!(entries.len(), 79);
+    assert!(entries.iter().all(|en| en["tag"] != "tab1"));
+    let view1 = entries.iter().find(|en| en["tag"] == "view1" && en["description"] == "VIEW").unwrap();
+    assert!(!view1["deps"].as_array().unwrap().is_empty());
+    for en in entries {
+        assert!(en["deps"].as_array().unwrap().iter().all(|dep| dep != "372"));
+    }
+    // source TOC is unchanged
+    assert_eq!(read_toc_json(&toc_dat)["header"]["toc_count"], 81);
+}
+
+#[test]
+fn filter_toc_in_place_test() {
+    use pgdump_toc_rewrite::exclude_table_data;
+    let dest_dump_dir = prepare_dump("filter_toc_in_place_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    let options = pgdump_toc_rewrite::FilterOptions {
+        delete_unreferenced_files: true,
+        ..Default::default()
+    };
+    let predicate = exclude_table_data(&["test1_dbo.tab1", "tab2"]);
+    let summary = pgdump_toc_rewrite::filter_toc_with_options(&toc_dat, &toc_dat, predicate, &options).unwrap();
+    assert_eq!(summary.removed_entries, vec!(5991, 5992));
+    assert!(dest_dump_dir.join("toc.dat.orig").exists());
+    assert!(!dest_dump_dir.join("5991.dat.gz").exists());
+    assert!(!dest_dump_dir.join("5992.dat.gz").exists());
+
+    let toc_json = read_toc_json(&toc_dat);
+    let entries = toc_json["entries"].as_array().unwrap();
+    assert_eq!(toc_json["header"]["toc_count"], 79);
+    assert!(entries.iter().any(|en| en["tag"] == "tab1" && en["description"] == "TABLE"));
+    assert!(entries.iter().all(|en| !(en["tag"] == "tab1" && en["description"] == "TABLE DATA")));
+    let report = pgdump_toc_rewrite::check_dump_dir(&toc_dat).unwrap();
+    assert!(report.missing_files.is_empty());
+}
+
+#[test]
+fn filter_toc_schema_only_test() {
+    use pgdump_toc_rewrite::TocSection;
+    let dest_dump_dir = prepare_dump("filter_toc_schema_only_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let output_dir = dest_dump_dir.parent().unwrap().join("schema_only");
+    let toc_output = output_dir.join("toc.dat");
+
+    let predicate = pgdump_toc_rewrite::include_sections(&[TocSection::None, TocSection::PreData, TocSection::PostData]);
+    let summary = pgdump_toc_rewrite::filter_toc(&toc_dat, &toc_output, predicate).unwrap();
+    assert_eq!(summary.unreferenced_files.len(), 14);
+
+    let entries = read_toc_json(&toc_output)["entries"].as_array().unwrap().clone();
+    assert!(!entries.is_empty());
+    assert!(entries.iter().all(|en| en["description"] != "TABLE DATA"));
+    let files: Vec<String> = fs::read_dir(&output_dir).unwrap()
+        .map(|en| en.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(files, vec!("toc.dat".to_string()));
+    let report = pgdump_toc_rewrite::check_dump_dir(&toc_output).unwrap();
+    assert!(report.is_ok(), "{}", report);
+}
+
+#[test]
+fn rewrite_strip_owners_test() {
+    let dest_dump_dir = prepare_dump("rewrite_strip_owners_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    // ownership statement inside the create statement
+    modify_toc_json(&toc_dat, |toc_json| {
+        for en in toc_json["entries"].as_array_mut().unwrap() {
+            if en["tag"] == "view1" && en["description"] == "VIEW" {
+                let create_stmt = en["create_stmt"].as_str().unwrap().to_string();
+                en["create_stmt"] = serde_json::Value::String(format!(
+                    "{}ALTER VIEW test1_dbo.view1 OWNER TO test1_dbo;\n", create_stmt));
+            }
+        }
+    });
+    let orig_entries = read_toc_json(&toc_dat)["entries"].as_array().unwrap().clone();
+    let acls: Vec<i64> = orig_entries.iter()
+        .filter(|en| en["description"] == "ACL" && !en["tag"].as_str().unwrap().starts_with("DATABASE "))
+        .map(|en| en["dump_id"].as_i64().unwrap())
+        .collect();
+    assert!(acls.len() > 3);
+
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.strip_owners = true;
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+    let removed: Vec<i64> = summary.removed_entries.iter().map(|id| *id as i64).collect();
+    assert_eq!(removed, acls);
+
+    let toc_json = read_toc_json(&toc_dat);
+    let entries = toc_json["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), orig_entries.len() - acls.len());
+    assert_eq!(toc_json["header"]["toc_count"], entries.len());
+    for en in entries {
+        assert_eq!(en["owner"], "");
+        assert!(!removed.contains(&en["dump_id"].as_i64().unwrap()));
+        if let Some(create_stmt) = en["create_stmt"].as_str() {
+            assert!(!create_stmt.contains("OWNER TO"), "{}", create_stmt);
+        }
+    }
+    // physical DB ACL is kept
+    assert!(entries.iter().any(|en| en["description"] == "ACL" && en["tag"] == "DATABASE wilton"));
+    // catalog roles are still remapped
+    let authid_user_ext = read_gz(&dest_dump_dir.join("5984.dat.gz"));
+    assert!(authid_user_ext.contains("foobar_dbo"));
+    assert!(!authid_user_ext.contains("test1_dbo"));
+}
+
+#[test]
+fn rewrite_drop_acls_test() {
+    let dest_dump_dir = prepare_dump("rewrite_drop_acls_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let orig_entries = read_toc_json(&toc_dat)["entries"].as_array().unwrap().clone();
+    let acls: Vec<i64> = orig_entries.iter()
+        .filter(|en| en["description"] == "ACL")
+        .map(|en| en["dump_id"].as_i64().unwrap())
+        .collect();
+
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.drop_acls = true;
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+    let removed: Vec<i64> = summary.removed_entries.iter().map(|id| *id as i64).collect();
+    assert_eq!(removed, acls);
+
+    let mut printed = Vec::new();
+    pgdump_toc_rewrite::print_toc(&toc_dat, &mut printed).unwrap();
+    let printed = String::from_utf8(printed).unwrap();
+    assert_eq!(printed.lines().filter(|line| *line == "description: ACL").count(), 0);
+    assert!(printed.contains(&format!("TOC entries: {}", orig_entries.len() - acls.len())));
+
+    let entries = read_toc_json(&toc_dat)["entries"].as_array().unwrap().clone();
+    // dump IDs are not changed, catalogs entries are reordered by rewrite
+    let mut kept_ids: Vec<i64> = orig_entries.iter()
+        .map(|en| en["dump_id"].as_i64().unwrap())
+        .filter(|id| !acls.contains(id))
+        .collect();
+    kept_ids.sort();
+    let mut ids: Vec<i64> = entries.iter().map(|en| en["dump_id"].as_i64().unwrap()).collect();
+    ids.sort();
+    assert_eq!(ids, kept_ids);
+    for en in entries.iter() {
+        for dep in en["deps"].as_array().unwrap() {
+            assert!(!acls.contains(&dep.as_str().unwrap().parse::<i64>().unwrap()));
+        }
+    }
+}
+
+#[test]
+fn set_compression_test() {
+    use pgdump_toc_rewrite::CompressionSpec;
+    let dest_dump_dir = prepare_dump("set_compression_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let orig_contents = read_gz(&dest_dump_dir.join("5991.dat.gz"));
+
+    assert!("gzip:10".parse::<CompressionSpec>().is_err());
+    assert!("zip".parse::<CompressionSpec>().is_err());
+    let zstd_err = "zstd:3".parse::<CompressionSpec>().unwrap_err();
+    assert!(zstd_err.to_string().contains("only supports gzip"));
+    assert_eq!("gzip".parse::<CompressionSpec>().unwrap(), CompressionSpec::Gzip(6));
+
+    // decompress
+    pgdump_toc_rewrite::set_compression(&toc_dat, "none".parse().unwrap()).unwrap();
+    assert_eq!(read_toc_json(&toc_dat)["header"]["compression"], 0);
+    assert_eq!(read_toc_json(&dest_dump_dir.join("toc.dat.orig"))["header"]["compression"], 6);
+    assert!(!dest_dump_dir.join("5991.dat.gz").exists());
+    assert!(dest_dump_dir.join("5991.dat.orig.gz").exists());
+    assert_eq!(fs::read_to_string(dest_dump_dir.join("5991.dat")).unwrap(), orig_contents);
+    let report = pgdump_toc_rewrite::check_dump_dir(&toc_dat).unwrap();
+    assert!(report.missing_files.is_empty() && report.compression_mismatches.is_empty(), "{}", report);
+
+    // backups of the previous run are not overwritten
+    let toc_before = fs::read(&toc_dat).unwrap();
+    let err = pgdump_toc_rewrite::set_compression(&toc_dat, CompressionSpec::Gzip(9)).unwrap_err();
+    assert!(err.to_string().starts_with("Backup file already exists: "), "{}", err);
+    assert_eq!(fs::read(&toc_dat).unwrap(), toc_before);
+    assert!(dest_dump_dir.join("5991.dat").exists());
+
+    // failure in the middle restores the files replaced so far
+    let missing = dest_dump_dir.join("5986.dat");
+    pgdump_toc_rewrite::clean_backups(&toc_dat).unwrap();
+    fs::rename(&missing, dest_dump_dir.join("5986.dat.bak")).unwrap();
+    let files_before = pgdump_toc_rewrite::list_dump_files(&toc_dat).unwrap();
+    assert!(pgdump_toc_rewrite::set_compression(&toc_dat, CompressionSpec::Gzip(9)).is_err());
+    assert_eq!(pgdump_toc_rewrite::list_dump_files(&toc_dat).unwrap(), files_before);
+    assert_eq!(fs::read(&toc_dat).unwrap(), toc_before);
+    fs::rename(dest_dump_dir.join("5986.dat.bak"), &missing).unwrap();
+
+    // compress again with the best compression
+    pgdump_toc_rewrite::set_compression(&toc_dat, CompressionSpec::Gzip(9)).unwrap();
+    assert_eq!(read_toc_json(&toc_dat)["header"]["compression"], 9);
+    assert!(!dest_dump_dir.join("5991.dat").exists());
+    assert!(dest_dump_dir.join("5991.dat.orig").exists());
+    assert_eq!(read_gz(&dest_dump_dir.join("5991.dat.gz")), orig_contents);
+    let gz_header = fs::read(dest_dump_dir.join("5991.dat.gz")).unwrap();
+    assert_eq!(gz_header[8], 2);
+    let report = pgdump_toc_rewrite::check_dump_dir(&toc_dat).unwrap();
+    assert!(report.missing_files.is_empty() && report.compression_mismatches.is_empty(), "{}", report);
+
+    // rewrite works with the re-compressed dump after the backups are deleted
+    assert!(pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").is_err());
+    pgdump_toc_rewrite::clean_backups(&toc_dat).unwrap();
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+}
+
+#[test]
+fn undo_rewrite_test() {
+    let dest_dump_dir = prepare_dump("undo_rewrite_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let src_dump_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources").join("dump");
+    let list_dir = |dir: &Path| {
+        let mut files: Vec<String> = fs::read_dir(dir).unwrap()
+            .map(|en| en.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        files
+    };
+
+    // nothing to undo
+    assert!(pgdump_toc_rewrite::undo_rewrite(&dest_dump_dir).is_err());
+
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+
+    // inconsistent backups
+    fs::rename(dest_dump_dir.join("5981.dat.gz"), dest_dump_dir.join("5981.dat.gz.bak")).unwrap();
+    let err = pgdump_toc_rewrite::undo_rewrite(&dest_dump_dir).unwrap_err();
+    assert_eq!(err.to_string(), "Rewritten file not found for backup: 5981.dat.orig.gz, expected file: 5981.dat.gz");
+    assert!(dest_dump_dir.join("toc.dat.orig").exists());
+    fs::rename(dest_dump_dir.join("5981.dat.gz.bak"), dest_dump_dir.join("5981.dat.gz")).unwrap();
+
+    // second rewrite is refused and does not affect the undo
+    assert!(pgdump_toc_rewrite::rewrite_toc(&toc_dat, "baz").is_err());
+
+    let restored = pgdump_toc_rewrite::undo_rewrite(&dest_dump_dir).unwrap();
+    assert_eq!(restored.len(), 6);
+    let files = list_dir(&dest_dump_dir);
+    assert_eq!(files, list_dir(&src_dump_dir));
+    for filename in files {
+        assert_eq!(fs::read(dest_dump_dir.join(&filename)).unwrap(), fs::read(src_dump_dir.join(&filename)).unwrap(), "{}", filename);
+    }
+}
+
+#[test]
+fn rewrite_twice_test() {
+    let dest_dump_dir = prepare_dump("rewrite_twice_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let src_toc = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources").join("dump").join("toc.dat");
+
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+    let read_dir_contents = || {
+        let mut files: Vec<(String, Vec<u8>, std::time::SystemTime)> = fs::read_dir(&dest_dump_dir).unwrap()
+            .map(|en| {
+                let path = en.unwrap().path();
+                let modified = fs::metadata(&path).unwrap().modified().unwrap();
+                (path.file_name().unwrap().to_string_lossy().to_string(), fs::read(&path).unwrap(), modified)
+            })
+            .collect();
+        files.sort();
+        files
+    };
+    let contents = read_dir_contents();
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &Default::default()).unwrap();
+    assert!(summary.no_change_needed);
+    assert!(summary.catalogs_rewritten.is_empty());
+    assert_eq!(summary.warnings, vec!("dump already uses DB name 'foobar', no change needed".to_string()));
+
+    // directory is untouched, backups are kept
+    assert_eq!(read_dir_contents(), contents);
+    assert_eq!(fs::read(dest_dump_dir.join("toc.dat.orig")).unwrap(), fs::read(&src_toc).unwrap());
+    assert_eq!(fs::read(dest_dump_dir.join("5981.dat.orig.gz")).unwrap(), fs::read(src_toc.with_file_name("5981.dat.gz")).unwrap());
+
+    // output directory is written without force
+    let output_dir = dest_dump_dir.with_file_name("output");
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.output_dir = Some(&output_dir);
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+    assert!(!summary.no_change_needed);
+    assert_eq!(fs::read(output_dir.join("toc.dat")).unwrap(), fs::read(&toc_dat).unwrap());
+    assert_eq!(read_dir_contents(), contents);
+
+    // in-place rewrite, forced or to another name, does not replace the backups
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.force = true;
+    let err = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap_err();
+    assert!(err.to_string().contains("Backup file already exists"), "{}", err);
+    assert!(pgdump_toc_rewrite::rewrite_toc(&toc_dat, "baz").is_err());
+    assert_eq!(read_dir_contents(), contents);
+    assert_eq!(fs::read(dest_dump_dir.join("toc.dat.orig")).unwrap(), fs::read(&src_toc).unwrap());
+    assert_eq!(fs::read(dest_dump_dir.join("5981.dat.orig.gz")).unwrap(), fs::read(src_toc.with_file_name("5981.dat.gz")).unwrap());
+
+    // forced rewrite works after the backups are deleted
+    pgdump_toc_rewrite::clean_backups(&toc_dat).unwrap();
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+    assert!(!summary.no_change_needed);
+    assert_eq!(summary.catalogs_rewritten.len(), 5);
+}
+
+// adds BLOBS entry with two large objects
+fn add_blobs(dump_dir: &Path) -> PathBuf {
+    let toc_dat = dump_dir.join("toc.dat");
+    modify_toc_json(&toc_dat, |toc_json| {
+        let mut blobs = toc_json["entries"].as_array().unwrap().iter()
+            .find(|en| en["description"] == "TABLE DATA")
+            .unwrap().clone();
+        blobs["dump_id"] = serde_json::json!(7000);
+        blobs["tag"] = serde_json::json!("BLOBS");
+        blobs["description"] = serde_json::json!("BLOBS");
+        blobs["create_stmt"] = serde_json::json!("");
+        blobs["copy_stmt"] = serde_json::Value::Null;
+        blobs["namespace"] = serde_json::json!("");
+        blobs["deps"] = serde_json::json!([]);
+        blobs["filename"] = serde_json::json!("blobs.toc");
+        toc_json["entries"].as_array_mut().unwrap().push(blobs);
+        toc_json["header"]["toc_count"] = serde_json::json!(82);
+    });
+    fs::write(dump_dir.join("blobs.toc"), "16385 blob_16385.dat\n16386 blob_16386.dat\n").unwrap();
+    write_gz(&dump_dir.join("blob_16385.dat.gz"), "foo");
+    write_gz(&dump_dir.join("blob_16386.dat.gz"), "bar");
+    toc_dat
+}
+
+#[test]
+fn blobs_test() {
+    let dest_dump_dir = prepare_dump("blobs_test");
+    let toc_dat = add_blobs(&dest_dump_dir);
+
+    let blobs = pgdump_toc_rewrite::read_blobs_toc(dest_dump_dir.join("blobs.toc")).unwrap();
+    assert_eq!(blobs, BTreeMap::from([
+        (16385, "blob_16385.dat".to_string()),
+        (16386, "blob_16386.dat".to_string()),
+    ]));
+
+    let mut printed = Vec::new();
+    pgdump_toc_rewrite::print_toc(&toc_dat, &mut printed).unwrap();
+    assert!(String::from_utf8(printed).unwrap().contains("TOC entries: 82\nLarge objects: 2\n"));
+
+    let report = pgdump_toc_rewrite::check_dump_dir(&toc_dat).unwrap();
+    assert!(report.is_ok(), "{}", report);
+    fs::remove_file(dest_dump_dir.join("blob_16386.dat.gz")).unwrap();
+    let report = pgdump_toc_rewrite::check_dump_dir(&toc_dat).unwrap();
+    assert_eq!(report.missing_files, vec!("blob_16386.dat.gz".to_string()));
+    write_gz(&dest_dump_dir.join("blob_16386.dat.gz"), "bar");
+
+    // blob files are removed together with the entry
+    let options = pgdump_toc_rewrite::FilterOptions {
+        delete_unreferenced_files: true,
+        ..Default::default()
+    };
+    let predicate = pgdump_toc_rewrite::exclude_tags(&["BLOBS"]);
+    let summary = pgdump_toc_rewrite::filter_toc_with_options(&toc_dat, &toc_dat, predicate, &options).unwrap();
+    assert_eq!(summary.removed_entries, vec!(7000));
+    assert_eq!(summary.unreferenced_files, vec!(
+        "blob_16385.dat.gz".to_string(), "blob_16386.dat.gz".to_string(), "blobs.toc".to_string()));
+    for filename in summary.unreferenced_files {
+        assert!(!dest_dump_dir.join(filename).exists());
+    }
+
+    fs::write(dest_dump_dir.join("blobs.toc"), "16385\n").unwrap();
+    assert!(pgdump_toc_rewrite::read_blobs_toc(dest_dump_dir.join("blobs.toc")).is_err());
+}
+
+#[test]
+fn indexed_reader_test() {
+    let toc_dat = PathBuf::from("resources").join("dump").join("toc.dat");
+    let toc_json = read_toc_json(&toc_dat);
+    let entries = toc_json["entries"].as_array().unwrap();
+    let debug_json: serde_json::Value = serde_json::from_str(
+        &pgdump_toc_rewrite::read_toc_to_json_with_offsets(&toc_dat).unwrap()).unwrap();
+    let debug_entries = debug_json["entries"].as_array().unwrap();
+    let toc_bytes = fs::read(&toc_dat).unwrap();
+
+    let mut reader = pgdump_toc_rewrite::IndexedTocReader::open(&toc_dat).unwrap();
+    assert_eq!(reader.len(), entries.len());
+    assert_eq!(debug_entries.len(), entries.len());
+    // read backwards to make sure that every read seeks
+    for idx in (0..reader.len()).rev() {
+        let te = reader.read_entry_at(idx).unwrap();
+        assert_eq!(te.dump_id as i64, entries[idx]["dump_id"].as_i64().unwrap());
+        assert_eq!(te.tag, entries[idx]["tag"].as_str().unwrap_or(""));
+        let offset = reader.offset(idx).unwrap();
+        assert_eq!(offset, debug_entries[idx]["offset"].as_u64().unwrap());
+        assert_eq!(debug_entries[idx]["dump_id"], entries[idx]["dump_id"]);
+        // dump_id is the first int of the entry: sign byte followed by 4 bytes little-endian
+        let off = offset as usize;
+        assert_eq!(toc_bytes[off], 0);
+        let dump_id = toc_bytes[off + 1..off + 5].iter().rev().fold(0u32, |acc, b| (acc << 8) | *b as u32);
+        assert_eq!(dump_id as i32, te.dump_id);
+    }
+    let last = debug_entries.last().unwrap();
+    assert_eq!(last["offset"].as_u64().unwrap() + last["length"].as_u64().unwrap(), toc_bytes.len() as u64);
+    assert!(reader.offset(reader.len()).is_none());
+    assert!(reader.read_entry_at(reader.len()).is_err());
+}
+
+fn set_header_compression(toc_dat: &Path, compression: i32) {
+    modify_toc_json(toc_dat, |toc_json| {
+        toc_json["header"]["compression"] = serde_json::Value::from(compression);
+    });
+}
+
+#[test]
+fn rewrite_gzip_files_without_header_compression_test() {
+    let dest_dump_dir = prepare_dump("rewrite_gzip_files_without_header_compression_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    set_header_compression(&toc_dat, 0);
+
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &Default::default()).unwrap();
+    assert_eq!(summary.catalogs_rewritten.len(), 5);
+    let mismatch_warnings: Vec<&String> = summary.warnings.iter()
+        .filter(|w| w.ends_with("reading it as gzip"))
+        .collect();
+    assert_eq!(mismatch_warnings.len(), 5);
+    assert!(mismatch_warnings.iter().any(|w| w.contains("babelfish_sysdatabases") && w.contains("5981.dat.gz")));
+    assert!(read_gz(&dest_dump_dir.join("5981.dat.gz")).contains("foobar"));
+    assert!(read_gz(&dest_dump_dir.join("5981.dat.orig.gz")).contains("test1"));
+    assert!(!dest_dump_dir.join("5981.dat").exists());
+}
+
+#[test]
+fn rewrite_plain_files_with_header_compression_test() {
+    let dest_dump_dir = prepare_dump("rewrite_plain_files_with_header_compression_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    for id in 5981..=5986 {
+        let gz_path = dest_dump_dir.join(format!("{}.dat.gz", id));
+        fs::write(dest_dump_dir.join(format!("{}.dat", id)), read_gz(&gz_path)).unwrap();
+        fs::remove_file(&gz_path).unwrap();
+    }
+
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &Default::default()).unwrap();
+    assert_eq!(summary.catalogs_rewritten.len(), 5);
+    let mismatch_warnings: Vec<&String> = summary.warnings.iter()
+        .filter(|w| w.ends_with("reading it as plain text"))
+        .collect();
+    assert_eq!(mismatch_warnings.len(), 5);
+    assert!(mismatch_warnings.iter().any(|w| w.contains("babelfish_sysdatabases") && w.contains("5981.dat ")));
+    assert!(fs::read_to_string(dest_dump_dir.join("5981.dat")).unwrap().contains("foobar"));
+    assert!(fs::read_to_string(dest_dump_dir.join("5981.dat.orig")).unwrap().contains("test1"));
+    assert!(!dest_dump_dir.join("5981.dat.gz").exists());
+}
+
+#[test]
+fn read_catalog_test() {
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let dump_dir = project_dir.join("resources").join("dump");
+
+    let catalogs = pgdump_toc_rewrite::list_catalogs(&dump_dir).unwrap();
+    assert_eq!(catalogs.len(), 5);
+    assert_eq!(catalogs["babelfish_function_ext"], "5982.dat");
+    assert_eq!(catalogs["babelfish_sysdatabases"], "5981.dat");
+
+    let compression = pgdump_toc_rewrite::Toc::read_from_path(&dump_dir).unwrap().compression();
+    assert_eq!(compression, pgdump_toc_rewrite::CompressionSpec::Gzip(6));
+    let function_ext = pgdump_toc_rewrite::read_catalog(&dump_dir, "babelfish_function_ext", compression).unwrap();
+    assert_eq!(function_ext, read_gz(&dump_dir.join("5982.dat.gz")));
+    assert!(function_ext.lines().next().unwrap().starts_with("test1_dbo\t"));
+    assert!(function_ext.contains("\n\\.\n"));
+
+    assert!(pgdump_toc_rewrite::read_catalog(&dump_dir, "babelfish_foo", pgdump_toc_rewrite::CompressionSpec::Gzip(6)).is_err());
+    assert!(pgdump_toc_rewrite::read_catalog(&dump_dir, "babelfish_function_ext", pgdump_toc_rewrite::CompressionSpec::None).is_err());
+}
+
+#[test]
+fn toc_count_test() {
+    let dest_dump_dir = prepare_dump("toc_count_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    // entry deleted from JSON by hand, toc_count is left unchanged
+    let mut toc_json = read_toc_json(&toc_dat);
+    toc_json["entries"].as_array_mut().unwrap().retain(|en| en["dump_id"] != 375);
+    assert_eq!(toc_json["header"]["toc_count"], 81);
+    let toc_edited = dest_dump_dir.join("toc_edited.dat");
+    pgdump_toc_rewrite::write_toc_from_json(&toc_edited, &toc_json.to_string()).unwrap();
+    let edited_json_st = pgdump_toc_rewrite::read_toc_to_json_strict(&toc_edited).unwrap();
+    let edited_json: serde_json::Value = serde_json::from_str(&edited_json_st).unwrap();
+    assert_eq!(edited_json["header"]["toc_count"], 80);
+    assert_eq!(edited_json["entries"].as_array().unwrap().len(), 80);
+
+    // header claims fewer entries than the file contains
+    let mut toc_bytes = fs::read(&toc_edited).unwrap();
+    let toc_tail = fs::read(&toc_dat).unwrap();
+    assert!(toc_bytes.len() < toc_tail.len());
+    toc_bytes.extend_from_slice(&toc_tail[toc_bytes.len()..]);
+    let toc_trailing = dest_dump_dir.join("toc_trailing.dat");
+    fs::write(&toc_trailing, &toc_bytes).unwrap();
+    assert!(pgdump_toc_rewrite::read_toc_to_json(&toc_trailing).is_ok());
+    let err = pgdump_toc_rewrite::read_toc_to_json_strict(&toc_trailing).unwrap_err();
+    assert!(err.to_string().contains("TOC entries count mismatch, header toc_count: 80"));
+
+    // container recomputes the count
+    let mut toc = pgdump_toc_rewrite::Toc::read_from_path(&toc_dat).unwrap();
+    assert_eq!(toc.len(), 81);
+    assert!(toc.remove_entry(375));
+    assert!(!toc.remove_entry(375));
+    let toc_removed = dest_dump_dir.join("toc_removed.dat");
+    toc.write_to_path(&toc_removed).unwrap();
+    let removed_json = read_toc_json(&toc_removed);
+    assert_eq!(removed_json["header"]["toc_count"], 80);
+    assert_eq!(pgdump_toc_rewrite::Toc::read_from_path(&toc_removed).unwrap().dump_ids(), toc.dump_ids());
+}
+
+#[test]
+fn insert_entry_test() {
+    use pgdump_toc_rewrite::{TocEntryBuilder, TocSection};
+    let dest_dump_dir = prepare_dump("insert_entry_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    let mut toc = pgdump_toc_rewrite::Toc::read_from_path(&toc_dat).unwrap();
+    let max_dump_id = *toc.dump_ids().iter().max().unwrap();
+    // right after the table and before its data
+    let position = toc.dump_ids().iter().position(|id| *id == 372).unwrap() + 1;
+    let builder = TocEntryBuilder::new("tab1_settings", "SQL")
+        .section(TocSection::PreData)
+        .create_stmt("SET lock_timeout = 0;\n")
+        .drop_stmt("RESET lock_timeout;\n")
+        .owner("test1_dbo")
+        .dependency(18)
+        .dependency(372);
+    assert!(toc.insert_entry(toc.len() + 1, builder.clone()).is_err());
+    assert!(toc.insert_entry(0, builder.clone().dependency(424242)).is_err());
+    let dump_id = toc.insert_entry(position, builder).unwrap();
+    assert_eq!(dump_id, max_dump_id + 1);
+    assert_eq!(toc.len(), 82);
+    fs::remove_file(&toc_dat).unwrap();
+    toc.write_to_path(&toc_dat).unwrap();
+
+    let mut printed = Vec::new();
+    pgdump_toc_rewrite::print_toc(&toc_dat, &mut printed).unwrap();
+    let printed = String::from_utf8(printed).unwrap();
+    assert!(printed.contains("TOC entries: 82\n"));
+    let expected = format!("Entry: {}\n\
+        dump_id: {}\n\
+        had_dumper: 0\n\
+        table_oid: 0\n\
+        catalog_oid: 0\n\
+        tag: tab1_settings\n\
+        description: SQL\n\
+        section: 2\n\
+        create_stmt: SET lock_timeout = 0;\n\n\
+        drop_stmt: RESET lock_timeout;\n\n\
+        copy_stmt: \n\
+        namespace: \n\
+        tablespace: \n\
+        tableam: \n\
+        owner: test1_dbo\n\
+        table_with_oids: false\n\
+        dep 1: 18\n\
+        dep 2: 372\n\
+        filename: \n", position + 1, dump_id);
+    assert!(printed.contains(&expected));
+    let table_pos = printed.find("dump_id: 372\n").unwrap();
+    let inserted_pos = printed.find("tag: tab1_settings\n").unwrap();
+    let data_pos = printed.find("dump_id: 5991\n").unwrap();
+    assert!(table_pos < inserted_pos && inserted_pos < data_pos);
+    // the rest of the dump is still consistent
+    assert!(pgdump_toc_rewrite::check_dump_dir(&toc_dat).unwrap().missing_files.is_empty());
+}
+
+#[test]
+fn renumber_test() {
+    let dest_dump_dir = prepare_dump("renumber_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let orig_json = read_toc_json(&toc_dat);
+    let orig_entries = orig_json["entries"].as_array().unwrap();
+    let tab1_idx = orig_entries.iter().position(|en| en["dump_id"] == 372).unwrap();
+    let view1_idx = orig_entries.iter().position(|en| en["dump_id"] == 375).unwrap();
+
+    let messages = pgdump_toc_rewrite::renumber_toc(&toc_dat).unwrap();
+    assert!(dest_dump_dir.join("toc.dat.orig").exists());
+    // catalogs data depends on extension tables that are not dumped
+    assert_eq!(messages.len(), 7);
+    assert!(messages.contains(&"entry 5981: dependency 237 not found in TOC, removed".to_string()));
+
+    let toc_json = read_toc_json(&toc_dat);
+    let entries = toc_json["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), orig_entries.len());
+    for (idx, en) in entries.iter().enumerate() {
+        assert_eq!(en["dump_id"], idx + 1);
+        assert_eq!(en["tag"], orig_entries[idx]["tag"]);
+        assert_eq!(en["filename"], orig_entries[idx]["filename"]);
+        let expected_deps = match en["description"].as_str() {
+            Some("TABLE DATA") if en["tag"].as_str().unwrap().starts_with("babelfish_") => 0,
+            _ => orig_entries[idx]["deps"].as_array().unwrap().len()
+        };
+        assert_eq!(en["deps"].as_array().unwrap().len(), expected_deps);
+    }
+    let tab1_id = (tab1_idx + 1).to_string();
+    assert!(entries[view1_idx]["deps"].as_array().unwrap().iter().any(|dep| dep == &tab1_id));
+
+    let mut printed = Vec::new();
+    pgdump_toc_rewrite::print_toc(&toc_dat, &mut printed).unwrap();
+    let printed = String::from_utf8(printed).unwrap();
+    let dump_ids: Vec<&str> = printed.lines().filter_map(|line| line.strip_prefix("dump_id: ")).collect();
+    assert_eq!(dump_ids.len(), entries.len());
+    for line in printed.lines().filter(|line| line.starts_with("dep ")) {
+        let dep = line.split(": ").nth(1).unwrap();
+        assert!(dump_ids.contains(&dep), "{}", line);
+    }
+
+    // dangling dependency
+    let mut toc_json = read_toc_json(&toc_dat);
+    toc_json["entries"][view1_idx]["deps"] = serde_json::json!(["424242"]);
+    let toc_dangling = dest_dump_dir.join("toc_dangling.dat");
+    pgdump_toc_rewrite::write_toc_from_json(&toc_dangling, &toc_json.to_string()).unwrap();
+    let mut toc = pgdump_toc_rewrite::Toc::read_from_path(&toc_dangling).unwrap();
+    let err = pgdump_toc_rewrite::renumber_dump_ids(&mut toc).unwrap_err();
+    assert!(err.to_string().contains("dependency: 424242"));
+    assert_eq!(toc.dump_ids(), (1..=entries.len() as i32).collect::<Vec<i32>>());
+}
+
+#[test]
+fn trailer_test() {
+    let dest_dump_dir = prepare_dump("trailer_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let toc_bytes = fs::read(&toc_dat).unwrap();
+
+    let toc = pgdump_toc_rewrite::Toc::read_from_path(&toc_dat).unwrap();
+    assert!(toc.trailer().is_empty());
+    let toc_copy = dest_dump_dir.join("toc_copy.dat");
+    toc.write_to_path(&toc_copy).unwrap();
+    assert_eq!(fs::read(&toc_copy).unwrap(), toc_bytes);
+
+    let mut trailed_bytes = toc_bytes.clone();
+    trailed_bytes.extend_from_slice(b"\x00TRAILER\x01\x02");
+    let toc_trailed = dest_dump_dir.join("toc_trailed.dat");
+    fs::write(&toc_trailed, &trailed_bytes).unwrap();
+    let toc = pgdump_toc_rewrite::Toc::read_from_path(&toc_trailed).unwrap();
+    assert_eq!(toc.trailer(), b"\x00TRAILER\x01\x02");
+    assert_eq!(toc.len(), 81);
+    let toc_trailed_copy = dest_dump_dir.join("toc_trailed_copy.dat");
+    toc.write_to_path(&toc_trailed_copy).unwrap();
+    assert_eq!(fs::read(&toc_trailed_copy).unwrap(), trailed_bytes);
+
+    // trailer is kept by the functions that write TOC
+    let trailer: &[u8] = b"\x00TRAILER\x01\x02";
+    fs::write(&toc_dat, &trailed_bytes).unwrap();
+    let toc_json = pgdump_toc_rewrite::read_toc_to_json(&toc_dat).unwrap();
+    assert!(pgdump_toc_rewrite::read_toc_to_json_strict(&toc_dat).is_err());
+    let toc_json_copy = dest_dump_dir.join("toc_json_copy.dat");
+    pgdump_toc_rewrite::write_toc_from_json(&toc_json_copy, &toc_json).unwrap();
+    assert_eq!(fs::read(&toc_json_copy).unwrap(), trailed_bytes);
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+    assert!(fs::read(&toc_dat).unwrap().ends_with(trailer));
+    pgdump_toc_rewrite::renumber_toc(&toc_dat).unwrap();
+    assert!(fs::read(&toc_dat).unwrap().ends_with(trailer));
+    let toc_filtered = dest_dump_dir.join("toc_filtered.dat");
+    pgdump_toc_rewrite::filter_toc(&toc_dat, &toc_filtered, |te| "ACL" != te.description).unwrap();
+    assert!(fs::read(&toc_filtered).unwrap().ends_with(trailer));
+    pgdump_toc_rewrite::clean_backups(&toc_dat).unwrap();
+    pgdump_toc_rewrite::set_compression(&toc_dat, pgdump_toc_rewrite::CompressionSpec::None).unwrap();
+    assert!(fs::read(&toc_dat).unwrap().ends_with(trailer));
+}
+
+#[test]
+fn sort_by_dump_id_test() {
+    let dest_dump_dir = prepare_dump("sort_by_dump_id_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let mut sorted_options = pgdump_toc_rewrite::RewriteOptions::default();
+    sorted_options.sort_by_dump_id = true;
+
+    // tables have lower dump IDs than the domains they use
+    let mut toc = pgdump_toc_rewrite::Toc::read_from_path(&toc_dat).unwrap();
+    let orig_ids = toc.dump_ids();
+    let err = toc.sort_by_dump_id().unwrap_err();
+    assert!(err.to_string().contains("comes before its dependency: 2584"));
+    assert_eq!(toc.dump_ids(), orig_ids);
+    assert!(pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &sorted_options).is_err());
+    assert!(!dest_dump_dir.join("toc.dat.orig").exists());
+
+    // sequential IDs, then two independent entries swapped by hand
+    pgdump_toc_rewrite::renumber_toc(&toc_dat).unwrap();
+    fs::remove_file(dest_dump_dir.join("toc.dat.orig")).unwrap();
+    modify_toc_json(&toc_dat, |toc_json| {
+        toc_json["entries"].as_array_mut().unwrap().swap(0, 1);
+    });
+    assert_eq!(read_toc_json(&toc_dat)["entries"][0]["dump_id"], 2);
+
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &sorted_options).unwrap();
+    let entries = read_toc_json(&toc_dat)["entries"].as_array().unwrap().clone();
+    assert_eq!(entries[0]["dump_id"], 1);
+    assert_eq!(entries[1]["dump_id"], 2);
+    // catalogs data is still reordered after sorting
+    let position = |tag: &str| entries.iter().position(|en| en["tag"] == tag).unwrap();
+    assert!(position("babelfish_sysdatabases") < position("babelfish_namespace_ext"));
+    let ids: Vec<i64> = entries.iter()
+        .filter(|en| !en["tag"].as_str().unwrap().starts_with("babelfish_"))
+        .map(|en| en["dump_id"].as_i64().unwrap())
+        .collect();
+    let mut ids_sorted = ids.clone();
+    ids_sorted.sort();
+    assert_eq!(ids, ids_sorted);
+}
+
+#[test]
+fn dependency_graph_test() {
+    let dest_dump_dir = prepare_dump("dependency_graph_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    let toc = pgdump_toc_rewrite::Toc::read_from_path(&toc_dat).unwrap();
+    let graph = toc.dependency_graph();
+    assert_eq!(graph.dump_ids().len(), 81);
+    assert_eq!(graph.dependencies(5991).unwrap(), &[372]);
+    assert!(graph.dependencies(375).unwrap().contains(&372));
+    assert!(graph.dependencies(424242).is_none());
+    let report = graph.validate();
+    assert!(report.cycles.is_empty());
+    // catalogs data depends on extension tables that are not dumped
+    assert_eq!(report.unknown_deps.len(), 7);
+    assert!(report.unknown_deps.contains(&(5981, "237".to_string())));
+    assert!(report.to_string().contains("Unknown dependency: entry 5981 depends on 237\n"));
+
+    let mut dot = Vec::new();
+    graph.to_dot(&mut dot).unwrap();
+    let dot = String::from_utf8(dot).unwrap();
+    assert!(dot.starts_with("digraph toc {\n"));
+    assert!(dot.contains("  372 [label=\"372\\nTABLE: tab1\"];\n"));
+    assert!(dot.contains("  5991 -> 372;\n"));
+    assert!(!dot.contains("-> 237;"));
+    assert!(dot.ends_with("}\n"));
+
+    // cycle introduced by hand
+    let mut toc_json = read_toc_json(&toc_dat);
+    let entries = toc_json["entries"].as_array_mut().unwrap();
+    let tab1 = entries.iter_mut().find(|en| en["dump_id"] == 372).unwrap();
+    tab1["deps"].as_array_mut().unwrap().push(serde_json::Value::from("5991"));
+    let toc_cycle = dest_dump_dir.join("toc_cycle.dat");
+    pgdump_toc_rewrite::write_toc_from_json(&toc_cycle, &toc_json.to_string()).unwrap();
+    let report = pgdump_toc_rewrite::Toc::read_from_path(&toc_cycle).unwrap().dependency_graph().validate();
+    assert_eq!(report.cycles, vec![vec![372, 5991]]);
+    assert!(report.to_string().contains("Dependency cycle: 372 -> 5991 -> 372\n"));
+}
+
+#[test]
+fn rewrite_schema_after_objects_test() {
+    let dest_dump_dir = prepare_dump("rewrite_schema_after_objects_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    // table entry is moved before all SCHEMA entries
+    let mut schema_idx = 0;
+    modify_toc_json(&toc_dat, |toc_json| {
+        let entries = toc_json["entries"].as_array_mut().unwrap();
+        let table_idx = entries.iter().position(|en| en["dump_id"] == 372).unwrap();
+        schema_idx = entries.iter().position(|en| en["description"] == "SCHEMA").unwrap();
+        let table = entries.remove(table_idx);
+        entries.insert(schema_idx, table);
+    });
+
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+    let toc_json = read_toc_json(&toc_dat);
+    let entries = toc_json["entries"].as_array().unwrap();
+    let table = &entries[schema_idx];
+    assert_eq!(table["dump_id"], 372);
+    assert_eq!(table["namespace"], "foobar_dbo");
+    assert_eq!(table["owner"], "foobar_dbo");
+    let create_stmt = table["create_stmt"].as_str().unwrap();
+    assert!(create_stmt.contains("foobar_dbo.tab1"));
+    assert!(!create_stmt.contains("test1_"));
+}
+
+#[test]
+fn toc_histogram_test() {
+    let toc_dat = PathBuf::from("resources").join("dump").join("toc.dat");
+    let histogram = pgdump_toc_rewrite::toc_histogram(&toc_dat).unwrap();
+    let expected: BTreeMap<String, usize> = [
+        ("ACL", 22), ("BABELFISHGUCS", 1), ("CONSTRAINT", 3), ("DATABASE", 1), ("DATABASE PROPERTIES", 1),
+        ("DOMAIN", 2), ("ENCODING", 1), ("FK CONSTRAINT", 1), ("FUNCTION", 10), ("INDEX", 2),
+        ("PROCEDURE", 6), ("SCHEMA", 3), ("SEARCHPATH", 1), ("STDSTRINGS", 1), ("TABLE", 7),
+        ("TABLE DATA", 14), ("TRIGGER", 2), ("VIEW", 3),
+    ].iter().map(|(description, count)| (description.to_string(), *count)).collect();
+    assert_eq!(histogram, expected);
+    assert_eq!(histogram.values().sum::<usize>(), 81);
+}
+
+#[test]
+fn rewrite_schema_only_test() {
+    let dest_dump_dir = prepare_dump("rewrite_schema_only_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    make_schema_only(&dest_dump_dir);
+    let orig_json = read_toc_json(&toc_dat);
+
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &Default::default()).unwrap();
+    assert!(summary.catalogs_rewritten.is_empty());
+    assert_eq!(summary.warnings, vec!["dump contains no table data, Babelfish catalogs were not rewritten".to_string()]);
+
+    let toc_json = read_toc_json(&toc_dat);
+    let entries = toc_json["entries"].as_array().unwrap();
+    let orig_entries = orig_json["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), orig_entries.len());
+    for (en, orig) in entries.iter().zip(orig_entries.iter()) {
+        assert_eq!(en["dump_id"], orig["dump_id"]);
+    }
+    let schemas: Vec<&str> = entries.iter()
+        .filter(|en| en["description"] == "SCHEMA")
+        .map(|en| en["tag"].as_str().unwrap())
+        .collect();
+    assert_eq!(schemas, ["foobar_dbo", "foobar_guest", "foobar_schema1"]);
+    for en in entries.iter().filter(|en| !en["tag"].as_str().unwrap_or("").starts_with("DATABASE")) {
+        assert!(!en.to_string().contains("test1_"));
+    }
+    assert!(pgdump_toc_rewrite::check_dump_dir(&toc_dat).unwrap().missing_files.is_empty());
+}
+
+#[test]
+fn truncated_toc_test() {
+    let dest_dump_dir = prepare_dump("truncated_toc_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let bytes = fs::read(&toc_dat).unwrap();
+
+    fs::write(&toc_dat, &bytes[..20]).unwrap();
+    let err = pgdump_toc_rewrite::read_toc_to_json(&toc_dat).unwrap_err();
+    assert_eq!(err.kind(), "io");
+    assert!(err.to_string().starts_with("Error reading TOC, header field: timestamp, offset: 16, message: "));
+
+    fs::write(&toc_dat, &bytes[..bytes.len() / 2]).unwrap();
+    let err = pgdump_toc_rewrite::read_toc_to_json(&toc_dat).unwrap_err();
+    assert_eq!(err.kind(), "io");
+    assert!(err.to_string().contains(", field: "));
+    assert!(err.to_string().starts_with("Error reading TOC, entry index: "));
+
+    fs::write(&toc_dat, &bytes).unwrap();
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.max_string_len = Some(16);
+    let err = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap_err();
+    assert!(matches!(err, pgdump_toc_rewrite::TocError::Corrupt { offset: 138, .. }));
+    assert!(err.to_string().starts_with("String length exceeds the limit"));
+    assert!(err.to_string().ends_with("length: 30, limit: 16, entry index: 0, field: create_stmt, offset: 138"));
+}
+
+#[test]
+fn rewrite_oversized_sql_test() {
+    let dest_dump_dir = prepare_dump("rewrite_oversized_sql_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    modify_toc_json(&toc_dat, |toc_json| {
+        for en in toc_json["entries"].as_array_mut().unwrap() {
+            if en["tag"] == "view1" && en["description"] == "VIEW" {
+                let padding = "x".repeat(1024 * 1024);
+                let create_stmt = format!("/* {} */\n{}", padding, en["create_stmt"].as_str().unwrap());
+                en["create_stmt"] = serde_json::Value::String(create_stmt);
+            }
+        }
+    });
+
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.max_sql_len = Some(64 * 1024);
+    let err = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap_err();
+    assert_eq!(err.kind(), "entry");
+    assert!(err.to_string().ends_with("limit: 65536"));
+    assert!(err.to_string().contains("tag: view1"));
+
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.max_sql_len = Some(64 * 1024);
+    options.keep_oversized_sql = true;
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+    assert_eq!(summary.warnings.len(), 1);
+    assert!(summary.warnings[0].starts_with("SQL statement is too long, left unmodified"));
+    let toc_json = read_toc_json(&toc_dat);
+    let view1 = toc_json["entries"].as_array().unwrap().iter()
+        .find(|en| en["description"] == "VIEW" && en["tag"] == "view1").unwrap();
+    assert_eq!(view1["namespace"], "foobar_dbo");
+    assert!(view1["create_stmt"].as_str().unwrap().contains("test1_dbo.view1"));
+}
+
+#[test]
+fn verify_roundtrip_test() {
+    use pgdump_toc_rewrite::RoundtripMismatch;
+    let dest_dump_dir = prepare_dump("verify_roundtrip_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    pgdump_toc_rewrite::verify_roundtrip(&toc_dat).unwrap();
+    let orig = fs::read(&toc_dat).unwrap();
+
+    // negative zero in had_dumper field is read as 0 and written back with zero sign byte
+    let offset = pgdump_toc_rewrite::IndexedTocReader::open(&toc_dat).unwrap().offset(3).unwrap() as usize;
+    let mut bytes = orig.clone();
+    assert_eq!(&bytes[offset + 5..offset + 10], &[0u8, 0, 0, 0, 0]);
+    bytes[offset + 5] = 1;
+    fs::write(&toc_dat, &bytes).unwrap();
+    let err = pgdump_toc_rewrite::verify_roundtrip(&toc_dat).unwrap_err();
+    assert!(matches!(err, RoundtripMismatch::Bytes { entry_index: Some(3), .. }));
+    assert_eq!(err.to_string(), format!(
+        "TOC round-trip mismatch, offset: {}, entry index: 3, original size: {}, written size: {}",
+        offset + 5, orig.len(), orig.len()));
+
+    // bytes after the last entry are not written back
+    let mut bytes = orig.clone();
+    bytes.extend_from_slice(b"trailer");
+    fs::write(&toc_dat, &bytes).unwrap();
+    let err = pgdump_toc_rewrite::verify_roundtrip(&toc_dat).unwrap_err();
+    assert!(matches!(err, RoundtripMismatch::Bytes { entry_index: None, .. }));
+    assert!(err.to_string().contains("outside of entries"));
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.verify_roundtrip = true;
+    let err = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap_err();
+    assert!(err.to_string().starts_with("TOC round-trip mismatch"));
+    assert_eq!(fs::read(&toc_dat).unwrap(), bytes);
+    assert!(!dest_dump_dir.join("toc.dat.orig").exists());
+
+    let err = pgdump_toc_rewrite::verify_roundtrip(dest_dump_dir.join("fail.dat")).unwrap_err();
+    assert!(matches!(err, RoundtripMismatch::Read(ref e) if e.kind() == "io"));
+
+    fs::write(&toc_dat, &orig).unwrap();
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+    pgdump_toc_rewrite::verify_roundtrip(&toc_dat).unwrap();
+}
+
+#[test]
+fn rewrite_comma_delimited_catalogs_test() {
+    let dest_dump_dir = prepare_dump("rewrite_comma_delimited_catalogs_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    modify_toc_json(&toc_dat, |toc_json| {
+        for en in toc_json["entries"].as_array_mut().unwrap() {
+            if en["tag"] == "babelfish_sysdatabases" || en["tag"] == "babelfish_authid_user_ext" {
+                let copy_stmt = en["copy_stmt"].as_str().unwrap().replace(" FROM stdin;", " FROM stdin WITH (DELIMITER ',');");
+                en["copy_stmt"] = serde_json::Value::String(copy_stmt);
+            }
+        }
+    });
+    for filename in ["5981.dat.gz", "5984.dat.gz"] {
+        let path = dest_dump_dir.join(filename);
+        let content = read_gz(&path);
+        assert!(!content.contains(','));
+        // escaped delimiter in the value of login_name column
+        write_gz(&path, &content.replacen("test1_dbo\t\t", "test1_dbo\ta\\,b\t", 1).replace('\t', ","));
+    }
+
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+
+    for filename in ["5981.dat", "5984.dat"] {
+        let orig = dest_dump_dir.join(format!("{}.orig.gz", filename));
+        let rewritten = dest_dump_dir.join(format!("{}.gz", filename));
+        assert_gz_content_eq(&rewritten, &orig, "test1", "foobar");
+    }
+    let sysdatabases = read_gz(&dest_dump_dir.join("5981.dat.gz"));
+    assert!(sysdatabases.starts_with("0,0,wilton,bbf_unicode_cp1_ci_as,foobar,"));
+}
+
+#[test]
+fn rewrite_three_part_copy_stmt_test() {
+    let dest_dump_dir = prepare_dump("rewrite_three_part_copy_stmt_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    modify_toc_json(&toc_dat, |toc_json| {
+        for en in toc_json["entries"].as_array_mut().unwrap() {
+            if en["tag"] == "tab1" && en["description"] == "TABLE DATA" {
+                let copy_stmt = en["copy_stmt"].as_str().unwrap().replace("COPY test1_dbo.", "COPY test1.test1_dbo.");
+                en["copy_stmt"] = serde_json::Value::String(copy_stmt);
+            }
+        }
+    });
+
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+    let toc_json = read_toc_json(&toc_dat);
+    let tab1 = toc_json["entries"].as_array().unwrap().iter()
+        .find(|en| en["tag"] == "tab1" && en["description"] == "TABLE DATA").unwrap();
+    assert!(tab1["copy_stmt"].as_str().unwrap().starts_with("COPY foobar.foobar_dbo.tab1 ("));
+}
+
+#[test]
+fn catalog_line_count_test() {
+    let dest_dump_dir = prepare_dump("catalog_line_count_test");
+    let function_ext = read_gz(&dest_dump_dir.join("5982.dat.gz"));
+    let expected = function_ext.split('\n').take_while(|line| *line != "\\.").count();
+    assert_eq!(expected, 16);
+    assert_eq!(pgdump_toc_rewrite::catalog_line_count(&dest_dump_dir, "babelfish_function_ext", pgdump_toc_rewrite::CompressionSpec::Gzip(6)).unwrap(), expected);
+    assert_eq!(pgdump_toc_rewrite::catalog_line_count(&dest_dump_dir, "babelfish_sysdatabases", pgdump_toc_rewrite::CompressionSpec::Gzip(6)).unwrap(), 1);
+
+    // uncompressed file without trailing EOL
+    fs::write(dest_dump_dir.join("5982.dat"), "a\tb\nc\td").unwrap();
+    assert_eq!(pgdump_toc_rewrite::catalog_line_count(&dest_dump_dir, "babelfish_function_ext", pgdump_toc_rewrite::CompressionSpec::None).unwrap(), 2);
+
+    let err = pgdump_toc_rewrite::catalog_line_count(&dest_dump_dir, "babelfish_foo", pgdump_toc_rewrite::CompressionSpec::Gzip(6)).unwrap_err();
+    assert_eq!(err.kind(), "catalog_missing");
+}
+
+#[test]
+fn check_dbname_test() {
+    use pgdump_toc_rewrite::check_dbname;
+    use pgdump_toc_rewrite::DbNameError;
+    assert!(check_dbname("foobar").is_ok());
+    assert!(check_dbname("_foo_42").is_ok());
+    assert_eq!(check_dbname(""), Err(DbNameError::Empty));
+    assert_eq!(check_dbname(" foo"), Err(DbNameError::Whitespace));
+    assert_eq!(check_dbname("foo\n"), Err(DbNameError::Whitespace));
+    assert_eq!(check_dbname("Foo"), Err(DbNameError::InvalidFirstChar('F')));
+    assert_eq!(check_dbname("1foo"), Err(DbNameError::InvalidFirstChar('1')));
+    assert_eq!(check_dbname("foo-bar"), Err(DbNameError::InvalidChar { ch: '-', position: 4 }));
+    assert_eq!(check_dbname("fooБ"), Err(DbNameError::InvalidChar { ch: 'Б', position: 4 }));
+    assert_eq!(check_dbname("select"), Err(DbNameError::ReservedKeyword));
+    assert_eq!(check_dbname("foo bar").unwrap_err().to_string(),
+        "DB name contains invalid character, found: ' ' at position: 4");
+
+    // same rules are used by the rewrite
+    let dest_dump_dir = prepare_dump("check_dbname_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    for dbname in ["", " foo", "Foo", "foo-bar", "select"] {
+        assert!(check_dbname(dbname).is_err());
+        let err = pgdump_toc_rewrite::rewrite_toc(&toc_dat, dbname).unwrap_err();
+        assert_eq!(err.kind(), "invalid_db_name");
+    }
+}
+
+#[test]
+fn check_dbname_relaxed_test() {
+    use pgdump_toc_rewrite::check_dbname_relaxed;
+    use pgdump_toc_rewrite::DbNameError;
+    assert_eq!(check_dbname_relaxed("FooBar").unwrap(), "foobar");
+    assert_eq!(check_dbname_relaxed("Select").unwrap(), "select");
+    assert_eq!(check_dbname_relaxed("_Foo@1#$").unwrap(), "_foo@1#$");
+    assert_eq!(check_dbname_relaxed("Баз").unwrap(), "баз");
+    assert_eq!(check_dbname_relaxed(""), Err(DbNameError::Empty));
+    assert_eq!(check_dbname_relaxed("Foo "), Err(DbNameError::Whitespace));
+    assert_eq!(check_dbname_relaxed("1foo"), Err(DbNameError::InvalidFirstChar('1')));
+    assert_eq!(check_dbname_relaxed("@foo"), Err(DbNameError::InvalidFirstChar('@')));
+    assert_eq!(check_dbname_relaxed("foo bar"), Err(DbNameError::InvalidChar { ch: ' ', position: 4 }));
+    assert_eq!(check_dbname_relaxed("foo\"bar"), Err(DbNameError::InvalidChar { ch: '"', position: 4 }));
+    assert_eq!(check_dbname_relaxed("foo.bar"), Err(DbNameError::InvalidChar { ch: '.', position: 4 }));
+}
+
+#[test]
+fn rewrite_allow_mixed_case_test() {
+    let dest_dump_dir = prepare_dump("rewrite_allow_mixed_case_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let err = pgdump_toc_rewrite::rewrite_toc(&toc_dat, "Foo$Bar").unwrap_err();
+    assert_eq!(err.kind(), "invalid_db_name");
+
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.allow_mixed_case = true;
+    let err = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "Foo.Bar", &options).unwrap_err();
+    assert_eq!(err.kind(), "invalid_db_name");
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "Foo$Bar", &options).unwrap();
+
+    let toc_json = read_toc_json(&toc_dat);
+    let entries = toc_json["entries"].as_array().unwrap();
+    let schema = entries.iter().find(|en| en["description"] == "SCHEMA" && en["tag"] == "foo$bar_dbo").unwrap();
+    assert_eq!(schema["create_stmt"], "CREATE SCHEMA \"foo$bar_dbo\";\n");
+    let index1 = entries.iter().find(|en| en["dump_id"] == 5703).unwrap();
+    assert!(index1["create_stmt"].as_str().unwrap().contains(" ON \"foo$bar_dbo\".tab1 "));
+    let sysdatabases = read_gz(&dest_dump_dir.join("5981.dat.gz"));
+    assert!(sysdatabases.contains("\tfoo$bar\t"));
+    assert!(!sysdatabases.contains("Foo$Bar"));
+}
+
+#[test]
+fn rewrite_non_ascii_dbname_test() {
+    let dest_dump_dir = prepare_dump("rewrite_non_ascii_dbname_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.allow_mixed_case = true;
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "База", &options).unwrap();
+    let maps = pgdump_toc_rewrite::derive_rename_maps(&toc_dat, "foobar").unwrap();
+    assert_eq!(maps.orig_dbname, "база");
+    assert_eq!(maps.schemas["база_dbo"], "foobar_dbo");
+    assert_eq!(maps.schemas["база_schema1"], "foobar_schema1");
+    assert_eq!(maps.owners["база_db_owner"], "foobar_db_owner");
+
+    // rewriting back restores the original names, quotes added for
+    // the non-ASCII names are kept
+    for en in fs::read_dir(&dest_dump_dir).unwrap() {
+        let path = en.unwrap().path();
+        if path.to_string_lossy().ends_with(".orig") || path.to_string_lossy().ends_with(".orig.gz") {
+            fs::remove_file(path).unwrap();
+        }
+    }
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "test1", &options).unwrap();
+    let toc_json = read_toc_json(&toc_dat);
+    assert!(!toc_json.to_string().contains("база"));
+    let orig_json = read_toc_json(&PathBuf::from("resources/dump/toc.dat"));
+    // Babelfish catalogs are reordered by the rewrite
+    let unquoted_entries = |toc_json: &serde_json::Value| {
+        let mut entries: Vec<String> = toc_json["entries"].as_array().unwrap().iter()
+            .map(|en| en.to_string().replace(['"', '\\'], ""))
+            .collect();
+        entries.sort();
+        entries
+    };
+    assert_eq!(unquoted_entries(&toc_json), unquoted_entries(&orig_json));
+    assert!(!read_gz(&dest_dump_dir.join("5983.dat.gz")).contains("база"));
+}
+
+#[test]
+fn rewrite_write_new_files_test() {
+    let dest_dump_dir = prepare_dump("rewrite_write_new_files_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let mut files_orig = Vec::new();
+    for en in fs::read_dir(&dest_dump_dir).unwrap() {
+        let path = en.unwrap().path();
+        files_orig.push((path.clone(), fs::read(&path).unwrap()));
+    }
+
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.write_new_files = true;
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+
+    for (path, contents) in &files_orig {
+        assert_eq!(&fs::read(path).unwrap(), contents, "{}", path.to_string_lossy());
+    }
+    assert!(!dest_dump_dir.join("toc.dat.orig").exists());
+    let toc_json = read_toc_json(&dest_dump_dir.join("toc.dat.new"));
+    assert!(toc_json["entries"].as_array().unwrap().iter().any(|en| en["tag"] == "foobar_dbo"));
+    for filename in ["5981.dat", "5982.dat", "5983.dat", "5984.dat", "5986.dat"] {
+        assert!(!dest_dump_dir.join(format!("{}.orig.gz", filename)).exists());
+        let rewritten = read_gz(&dest_dump_dir.join(format!("{}.new.gz", filename)));
+        assert!(!rewritten.contains("test1_"), "{}", filename);
+    }
+    let count = fs::read_dir(&dest_dump_dir).unwrap().count();
+    assert_eq!(count, files_orig.len() + 6);
+
+    let output_dir = dest_dump_dir.join("out");
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.write_new_files = true;
+    options.output_dir = Some(&output_dir);
+    assert!(pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).is_err());
+}
+
+#[test]
+fn rewrite_name_collisions_test() {
+    let dest_dump_dir = prepare_dump("rewrite_name_collisions_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    // other logical DB 'foobar' that shares the physical DB
+    let sysdatabases = dest_dump_dir.join("5981.dat.gz");
+    let sysdatabases_st = read_gz(&sysdatabases).replacen("\\.",
+        "0\t0\twilton\tbbf_unicode_cp1_ci_as\tfoobar\t2023-12-22 17:41:58+00\t{}\n\\.", 1);
+    write_gz(&sysdatabases, &sysdatabases_st);
+    let namespace_ext = dest_dump_dir.join("5983.dat.gz");
+    let namespace_ext_st = read_gz(&namespace_ext).replacen("\\.", "foobar_dbo\tdbo\t{}\n\\.", 1);
+    write_gz(&namespace_ext, &namespace_ext_st);
+    let mut files_orig = Vec::new();
+    for en in fs::read_dir(&dest_dump_dir).unwrap() {
+        let path = en.unwrap().path();
+        files_orig.push((path.clone(), fs::read(&path).unwrap()));
+    }
+
+    let err = pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap_err();
+    assert_eq!(err.kind(), "name_collision");
+    assert_eq!(err.to_string(), "New names already exist in the dump: \
+        babelfish_sysdatabases: foobar, babelfish_namespace_ext: foobar_dbo");
+    for (path, contents) in &files_orig {
+        assert_eq!(&fs::read(path).unwrap(), contents, "{}", path.to_string_lossy());
+    }
+    assert_eq!(fs::read_dir(&dest_dump_dir).unwrap().count(), files_orig.len());
+
+    // other names do not collide
+    pgdump_toc_rewrite::derive_rename_maps(&toc_dat, "foobar2").unwrap();
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.allow_name_collisions = true;
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+    assert!(summary.warnings.contains(&"new name already exists in the dump, babelfish_namespace_ext: foobar_dbo".to_string()));
+}
+
+#[test]
+fn rewrite_int_size_8_test() {
+    let dest_dump_dir = prepare_dump("rewrite_int_size_8_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let toc_len = fs::metadata(&toc_dat).unwrap().len();
+    let mut toc_json = read_toc_json(&toc_dat);
+    toc_json["header"]["flags"] = serde_json::json!(["08", "08", "03"]);
+    fs::remove_file(&toc_dat).unwrap();
+    pgdump_toc_rewrite::write_toc_from_json(&toc_dat, &toc_json.to_string()).unwrap();
+    assert!(fs::metadata(&toc_dat).unwrap().len() > toc_len);
+    assert_eq!(read_toc_json(&toc_dat), toc_json);
+    pgdump_toc_rewrite::verify_roundtrip(&toc_dat).unwrap();
+
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+    let toc_json = read_toc_json(&toc_dat);
+    assert_eq!(toc_json["header"]["flags"], serde_json::json!(["08", "08", "03"]));
+    assert!(toc_json["entries"].as_array().unwrap().iter().any(|en| en["tag"] == "foobar_dbo"));
+
+    modify_toc_json(&toc_dat, |toc_json| {
+        toc_json["header"]["flags"] = serde_json::json!(["02", "08", "03"]);
+    });
+    let err = pgdump_toc_rewrite::read_toc_to_json(&toc_dat).unwrap_err();
+    assert_eq!(err.kind(), "unsupported_format");
+    assert!(err.to_string().contains("Int size check failed, expected: 4 or 8, actual: 2"));
+}
+
+#[test]
+fn resolve_toc_path_test() {
+    let dump_dir = prepare_dump("resolve_toc_path_test");
+    let toc_dat = dump_dir.join("toc.dat");
+    assert_eq!(pgdump_toc_rewrite::resolve_toc_path(&toc_dat).unwrap(), toc_dat);
+    assert_eq!(pgdump_toc_rewrite::resolve_toc_path(&dump_dir).unwrap(), toc_dat);
+
+    // tests are run from the project directory
+    let relative_dir = Path::new("target").join("resolve_toc_path_test").join("dump");
+    assert_eq!(pgdump_toc_rewrite::resolve_toc_path(&relative_dir).unwrap(), relative_dir.join("toc.dat"));
+    pgdump_toc_rewrite::check_dump_dir(&relative_dir).unwrap();
+
+    // destination directory is resolved to the TOC file inside it
+    let filter_dir = dump_dir.parent().unwrap().join("filtered");
+    fs::create_dir(&filter_dir).unwrap();
+    pgdump_toc_rewrite::filter_toc(&dump_dir, &filter_dir, |_| true).unwrap();
+    assert!(filter_dir.join("toc.dat").exists());
+    assert!(filter_dir.join("5981.dat.gz").exists());
+    let filter_in_place_dir = prepare_dump("resolve_toc_path_filter_test");
+    pgdump_toc_rewrite::filter_toc(&filter_in_place_dir, &filter_in_place_dir, |_| true).unwrap();
+    assert!(filter_in_place_dir.join("toc.dat.orig").exists());
+
+    #[cfg(unix)]
+    {
+        let link_dir = dump_dir.parent().unwrap().join("dump_link");
+        std::os::unix::fs::symlink(&dump_dir, &link_dir).unwrap();
+        assert_eq!(pgdump_toc_rewrite::resolve_toc_path(&link_dir).unwrap(), link_dir.join("toc.dat"));
+        pgdump_toc_rewrite::rewrite_toc(&link_dir, "foobar").unwrap();
+        assert!(dump_dir.join("toc.dat.orig").exists());
+        assert!(link_dir.is_dir());
+    }
+
+    let empty_dir = dump_dir.parent().unwrap().join("empty");
+    fs::create_dir(&empty_dir).unwrap();
+    let err = pgdump_toc_rewrite::print_toc(&empty_dir, &mut Vec::new()).unwrap_err();
+    assert_eq!(err.to_string(), format!("TOC file 'toc.dat' not found in dump directory: {}", empty_dir.to_string_lossy()));
+}
+
+#[test]
+fn rewrite_target_version_test() {
+    let dump_dir = prepare_dump("rewrite_target_version_test");
+    let toc_dat = dump_dir.join("toc.dat");
+    let orig_json = read_toc_json(&toc_dat);
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.target_version = Some("1.13".parse().unwrap());
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+    let json = read_toc_json(&toc_dat);
+    assert_eq!(json["header"]["version"], serde_json::json!(["01", "0d", "00"]));
+    let entries = json["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), orig_json["entries"].as_array().unwrap().len());
+    assert!(entries.iter().all(|en| en["tableam"].is_null()));
+    pgdump_toc_rewrite::verify_roundtrip(&toc_dat).unwrap();
+
+    // round-trip through the in-memory model in both directions
+    let mut toc = pgdump_toc_rewrite::Toc::read_from_path(&toc_dat).unwrap();
+    assert_eq!(toc.archive_version(), pgdump_toc_rewrite::ArchiveVersion::V1_13);
+    toc.set_archive_version(pgdump_toc_rewrite::ArchiveVersion::V1_14).unwrap();
+    let toc_14 = dump_dir.join("toc_14.dat");
+    toc.write_to_path(&toc_14).unwrap();
+    let mut toc = pgdump_toc_rewrite::Toc::read_from_path(&toc_14).unwrap();
+    assert_eq!(toc.archive_version(), pgdump_toc_rewrite::ArchiveVersion::V1_14);
+    toc.set_archive_version(pgdump_toc_rewrite::ArchiveVersion::V1_13).unwrap();
+    let toc_13 = dump_dir.join("toc_13.dat");
+    toc.write_to_path(&toc_13).unwrap();
+    assert_eq!(fs::read(&toc_13).unwrap(), fs::read(&toc_dat).unwrap());
+
+    // access method other than heap cannot be written with 1.13
+    let dump_dir = prepare_dump("rewrite_target_version_test");
+    let toc_dat = dump_dir.join("toc.dat");
+    modify_toc_json(&toc_dat, |json| {
+        for en in json["entries"].as_array_mut().unwrap() {
+            if en["dump_id"] == 372 {
+                en["tableam"] = serde_json::json!("columnar");
+            }
+        }
+    });
+    let toc_bytes = fs::read(&toc_dat).unwrap();
+    let err = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap_err();
+    assert_eq!(err.kind(), "unsupported_format");
+    assert_eq!(err.to_string(),
+        "Archive version 1.13 cannot represent table access method, dump_id: 372, tag: tab1, access method: columnar");
+    assert_eq!(fs::read(&toc_dat).unwrap(), toc_bytes);
+    assert!(!dump_dir.join("toc.dat.orig").exists());
+
+    assert!("1.11".parse::<pgdump_toc_rewrite::ArchiveVersion>().is_err());
+}
+
+#[test]
+fn rewrite_rename_overrides_test() {
+    let dest_dump_dir = prepare_dump("rewrite_rename_overrides_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let schema_map = HashMap::from([("test1_schema1".to_string(), "sales".to_string())]);
+    let owner_map = HashMap::from([("test1_guest".to_string(), "guest_role".to_string())]);
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.schema_map = Some(&schema_map);
+    options.owner_map = Some(&owner_map);
+
+    let maps = pgdump_toc_rewrite::derive_rename_maps_with_options(&toc_dat, "foobar", &options).unwrap();
+    assert_eq!(maps.schemas["test1_schema1"], "sales");
+    assert_eq!(maps.schemas["test1_dbo"], "foobar_dbo");
+    assert_eq!(maps.owners["test1_guest"], "guest_role");
+    assert_eq!(maps.owners["test1_dbo"], "foobar_dbo");
+
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+    let toc = read_toc_json(&toc_dat);
+    let entries = toc["entries"].as_array().unwrap();
+    let schema = entries.iter().find(|te| te["dump_id"] == 20).unwrap();
+    assert_eq!(schema["tag"], "sales");
+    assert_eq!(schema["create_stmt"], "CREATE SCHEMA sales;\n");
+    assert_eq!(schema["owner"], "foobar_dbo");
+    let guest = entries.iter().find(|te| te["dump_id"] == 19).unwrap();
+    assert_eq!(guest["tag"], "foobar_guest");
+    assert_eq!(guest["owner"], "guest_role");
+    let namespace_ext = read_gz(&dest_dump_dir.join("5983.dat.gz"));
+    assert!(namespace_ext.contains("sales\tschema1"));
+    assert!(namespace_ext.contains("foobar_dbo\tdbo"));
+
+    // overrides are applied even if DB name is not changed
+    let dest_dump_dir = prepare_dump("rewrite_rename_overrides_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "test1", &options).unwrap();
+    assert!(!summary.no_change_needed);
+    let toc = read_toc_json(&toc_dat);
+    let schema = toc["entries"].as_array().unwrap().iter().find(|te| te["dump_id"] == 20).unwrap();
+    assert_eq!(schema["tag"], "sales");
+
+    // invalid override names are rejected before anything is written
+    let dest_dump_dir = prepare_dump("rewrite_rename_overrides_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let toc_before = fs::read(&toc_dat).unwrap();
+    let owner_map = HashMap::from([("test1_guest".to_string(), "".to_string())]);
+    options.owner_map = Some(&owner_map);
+    let err = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap_err();
+    assert!(err.to_string().contains("Invalid identifier specified"), "{}", err);
+    assert!(pgdump_toc_rewrite::derive_rename_maps_with_options(&toc_dat, "foobar", &options).is_err());
+    assert_eq!(fs::read(&toc_dat).unwrap(), toc_before);
+}
+
+#[test]
+fn rewrite_suffix_transform_test() {
+    let dest_dump_dir = prepare_dump("rewrite_suffix_transform_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let schema_map = HashMap::from([("test1_schema1".to_string(), "sales".to_string())]);
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.schema_map = Some(&schema_map);
+    options.suffix_transform = Some(&|suffix: &str| suffix.to_uppercase());
+
+    let maps = pgdump_toc_rewrite::derive_rename_maps_with_options(&toc_dat, "foobar", &options).unwrap();
+    assert_eq!(maps.schemas["test1_dbo"], "foobar_DBO");
+    assert_eq!(maps.schemas["test1_schema1"], "sales");
+    assert_eq!(maps.owners["test1_db_owner"], "foobar_DB_OWNER");
+    assert_eq!(maps.owners["test1_guest"], "foobar_GUEST");
+
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+    let toc = read_toc_json(&toc_dat);
+    let entries = toc["entries"].as_array().unwrap();
+    let schema = entries.iter().find(|te| te["dump_id"] == 18).unwrap();
+    assert_eq!(schema["tag"], "foobar_DBO");
+    assert_eq!(schema["create_stmt"], "CREATE SCHEMA \"foobar_DBO\";\n");
+    assert_eq!(schema["owner"], "foobar_DB_OWNER");
+    // SQL references are quoted to keep the case
+    let table = entries.iter().find(|te| te["dump_id"] == 372).unwrap();
+    assert_eq!(table["namespace"], "foobar_DBO");
+    assert!(table["create_stmt"].as_str().unwrap().contains("CREATE TABLE \"foobar_DBO\".tab1"));
+    assert!(!table["create_stmt"].as_str().unwrap().contains("test1_dbo"));
+    let namespace_ext = read_gz(&dest_dump_dir.join("5983.dat.gz"));
+    assert!(namespace_ext.contains("foobar_DBO\tdbo"));
+}
+
+#[test]
+fn rewrite_comments_test() {
+    let dest_dump_dir = prepare_dump("rewrite_comments_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    modify_toc_json(&toc_dat, |toc_json| {
+        let entries = toc_json["entries"].as_array_mut().unwrap();
+        let schema = entries.iter().find(|te| te["dump_id"] == 18).unwrap().clone();
+        let mut schema_comment = schema.clone();
+        schema_comment["dump_id"] = 7001.into();
+        schema_comment["tag"] = "SCHEMA test1_dbo".into();
+        schema_comment["description"] = "COMMENT".into();
+        schema_comment["create_stmt"] = "COMMENT ON SCHEMA test1_dbo IS 'test1_dbo schema, see test1_dbo.tab1';\n".into();
+        schema_comment["drop_stmt"] = "".into();
+        schema_comment["deps"] = serde_json::json!(["18"]);
+        let mut table_comment = schema_comment.clone();
+        table_comment["dump_id"] = 7002.into();
+        table_comment["tag"] = "TABLE tab1".into();
+        table_comment["namespace"] = "test1_dbo".into();
+        table_comment["owner"] = "test1_dbo".into();
+        table_comment["create_stmt"] = "COMMENT ON TABLE test1_dbo.tab1 IS 'copy of \"test1_dbo\".tab1 from test1_dbo';\n".into();
+        table_comment["deps"] = serde_json::json!(["372"]);
+        entries.push(schema_comment);
+        entries.push(table_comment);
+        toc_json["header"]["toc_count"] = (entries.len() as i64).into();
+    });
+
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+
+    // commented object is rewritten, comment text is preserved
+    let toc_json = read_toc_json(&toc_dat);
+    let entries = toc_json["entries"].as_array().unwrap();
+    let schema_comment = entries.iter().find(|te| te["dump_id"] == 7001).unwrap();
+    assert_eq!(schema_comment["tag"], "SCHEMA foobar_dbo");
+    assert_eq!(schema_comment["create_stmt"], "COMMENT ON SCHEMA foobar_dbo IS 'test1_dbo schema, see test1_dbo.tab1';\n");
+    assert_eq!(schema_comment["owner"], "foobar_db_owner");
+    let table_comment = entries.iter().find(|te| te["dump_id"] == 7002).unwrap();
+    assert_eq!(table_comment["tag"], "TABLE tab1");
+    assert_eq!(table_comment["namespace"], "foobar_dbo");
+    assert_eq!(table_comment["create_stmt"], "COMMENT ON TABLE foobar_dbo.tab1 IS 'copy of \"test1_dbo\".tab1 from test1_dbo';\n");
+    assert_eq!(table_comment["owner"], "foobar_dbo");
+}
+
+#[test]
+fn rewrite_sequence_owned_by_test() {
+    let dest_dump_dir = prepare_dump("rewrite_sequence_owned_by_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    modify_toc_json(&toc_dat, |toc_json| {
+        let entries = toc_json["entries"].as_array_mut().unwrap();
+        let mut owned_by = entries.iter().find(|te| te["dump_id"] == 372).unwrap().clone();
+        owned_by["dump_id"] = 7003.into();
+        owned_by["tag"] = "test1_dbo_id_seq".into();
+        owned_by["description"] = "SEQUENCE OWNED BY".into();
+        owned_by["create_stmt"] = "ALTER SEQUENCE test1_dbo.test1_dbo_id_seq OWNED BY test1_dbo.tab1.test1_dbo;\n".into();
+        owned_by["drop_stmt"] = "".into();
+        owned_by["deps"] = serde_json::json!(["372"]);
+        entries.push(owned_by);
+        toc_json["header"]["toc_count"] = (entries.len() as i64).into();
+    });
+
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+
+    // only the schema part of the column reference is rewritten
+    let toc_json = read_toc_json(&toc_dat);
+    let owned_by = toc_json["entries"].as_array().unwrap().iter().find(|te| te["dump_id"] == 7003).unwrap();
+    assert_eq!(owned_by["tag"], "test1_dbo_id_seq");
+    assert_eq!(owned_by["namespace"], "foobar_dbo");
+    assert_eq!(owned_by["create_stmt"], "ALTER SEQUENCE foobar_dbo.test1_dbo_id_seq OWNED BY foobar_dbo.tab1.test1_dbo;\n");
+}
+
+#[test]
+fn rewrite_cancelled_test() {
+    let dest_dump_dir = prepare_dump("rewrite_cancelled_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let read_files = || {
+        let mut files: Vec<(String, Vec<u8>)> = fs::read_dir(&dest_dump_dir).unwrap()
+            .map(|en| en.unwrap().path())
+            .map(|path| (path.file_name().unwrap().to_string_lossy().to_string(), fs::read(&path).unwrap()))
+            .collect();
+        files.sort();
+        files
+    };
+    let files_before = read_files();
+
+    // cancelled before the start
+    let token = pgdump_toc_rewrite::CancellationToken::new();
+    token.cancel();
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.cancellation = Some(&token);
+    let err = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap_err();
+    assert!(matches!(err, pgdump_toc_rewrite::TocError::Cancelled { in_place: true }));
+    assert_eq!("cancelled", err.kind());
+    assert_eq!("Operation cancelled, original files were restored", err.to_string());
+    assert!(files_before == read_files());
+
+    // cancelled after the first catalog is replaced
+    let token = pgdump_toc_rewrite::CancellationToken::new();
+    let catalogs = RefCell::new(Vec::new());
+    let progress = |event: pgdump_toc_rewrite::ProgressEvent| {
+        if let pgdump_toc_rewrite::ProgressEvent::CatalogBytes { catalog, processed, total } = event {
+            if processed == total {
+                catalogs.borrow_mut().push(catalog);
+                token.cancel();
+            }
+        }
+    };
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.cancellation = Some(&token);
+    options.progress = Some(&progress);
+    let err = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap_err();
+    assert!(matches!(err, pgdump_toc_rewrite::TocError::Cancelled { in_place: true }));
+    assert_eq!(catalogs.borrow().as_slice(), ["babelfish_authid_user_ext"]);
+    assert!(files_before == read_files());
+
+    // files written into the output directory are left as is
+    let output_dir = dest_dump_dir.with_file_name("output");
+    let token = pgdump_toc_rewrite::CancellationToken::new();
+    let progress = |event: pgdump_toc_rewrite::ProgressEvent| {
+        if let pgdump_toc_rewrite::ProgressEvent::CatalogBytes { processed, total, .. } = event {
+            if processed == total {
+                token.cancel();
+            }
+        }
+    };
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.cancellation = Some(&token);
+    options.progress = Some(&progress);
+    options.output_dir = Some(&output_dir);
+    let err = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap_err();
+    assert!(matches!(err, pgdump_toc_rewrite::TocError::Cancelled { in_place: false }));
+    assert!(err.to_string().contains("source files were not modified"));
+    assert!(files_before == read_files());
+    assert!(output_dir.join("5984.dat.gz").exists());
+
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+    assert!(files_before != read_files());
+}
+
+#[test]
+fn verify_rewritten_test() {
+    let dest_dump_dir = prepare_dump("verify_rewritten_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let stale = pgdump_toc_rewrite::verify_rewritten(&toc_dat, "test1").unwrap();
+    assert!(stale.iter().any(|sr| sr.dump_id == 18 && sr.location == "tag" && sr.value == "test1_dbo"));
+    assert!(stale.iter().any(|sr| sr.tag == "babelfish_namespace_ext" && sr.location.starts_with("line ")));
+
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+    assert!(pgdump_toc_rewrite::verify_rewritten(&dest_dump_dir, "test1").unwrap().is_empty());
+
+    // reference left unrewritten
+    let mut create_stmt = String::new();
+    modify_toc_json(&toc_dat, |toc_json| {
+        let table = toc_json["entries"].as_array_mut().unwrap().iter_mut().find(|te| te["dump_id"] == 372).unwrap();
+        create_stmt = table["create_stmt"].as_str().unwrap().replace("foobar_dbo.tab1", "test1_dbo.tab1");
+        table["create_stmt"] = create_stmt.clone().into();
+    });
+    let stale = pgdump_toc_rewrite::verify_rewritten(&toc_dat, "test1").unwrap();
+    assert_eq!(stale, [pgdump_toc_rewrite::StaleReference {
+        dump_id: 372,
+        description: "TABLE".to_string(),
+        tag: "tab1".to_string(),
+        location: "create_stmt".to_string(),
+        value: create_stmt
+    }]);
+    assert!(stale[0].to_string().starts_with("entry 372 TABLE tab1, create_stmt: "));
+
+    // new DB name that starts with the original one
+    let copy_dump_dir = prepare_dump("verify_rewritten_test_copy");
+    pgdump_toc_rewrite::rewrite_toc(&copy_dump_dir, "test1_copy").unwrap();
+    assert!(pgdump_toc_rewrite::verify_rewritten(&copy_dump_dir, "test1").unwrap().is_empty());
+
+    // catalog file cannot be decoded
+    fs::write(dest_dump_dir.join("5983.dat.gz"), "not gzip").unwrap();
+    let err = pgdump_toc_rewrite::verify_rewritten(&toc_dat, "test1").unwrap_err();
+    assert!(matches!(err, pgdump_toc_rewrite::TocError::DataFile { .. }));
+}
+
+#[test]
+fn list_orphans_test() {
+    use pgdump_toc_rewrite::DumpFileKind;
+
+    let dest_dump_dir = prepare_dump("list_orphans_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    assert!(pgdump_toc_rewrite::list_orphans(&toc_dat).unwrap().is_empty());
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+    fs::write(dest_dump_dir.join("9999.dat.gz"), "").unwrap();
+    fs::write(dest_dump_dir.join("5981.dat.gz.42-0123abcd.tmp"), "").unwrap();
+    fs::create_dir(dest_dump_dir.join("subdir.orig")).unwrap();
+
+    let files = pgdump_toc_rewrite::list_dump_files(&dest_dump_dir).unwrap();
+    let kind = |filename: &str| files.iter()
+        .find(|(path, _)| path.file_name().unwrap() == filename)
+        .map(|(_, kind)| *kind);
+    assert_eq!(kind("toc.dat"), Some(DumpFileKind::Referenced));
+    assert_eq!(kind("5991.dat.gz"), Some(DumpFileKind::Referenced));
+    assert_eq!(kind("toc.dat.orig"), Some(DumpFileKind::Backup));
+    assert_eq!(kind("5983.dat.orig.gz"), Some(DumpFileKind::Backup));
+    assert_eq!(kind("5981.dat.gz.42-0123abcd.tmp"), Some(DumpFileKind::Temp));
+    assert_eq!(kind("9999.dat.gz"), Some(DumpFileKind::Unknown));
+    assert_eq!(kind("subdir.orig"), None);
+    let orphans = pgdump_toc_rewrite::list_orphans(&toc_dat).unwrap();
+    assert_eq!(orphans.len(), files.iter().filter(|(_, kind)| DumpFileKind::Referenced != *kind).count());
+    assert!(orphans.contains(&dest_dump_dir.join("9999.dat.gz")));
+
+    // nothing is deleted while another process modifies the dump
+    let lock_file = dest_dump_dir.join(pgdump_toc_rewrite::LOCK_FILENAME);
+    fs::write(&lock_file, "4242").unwrap();
+    assert!(pgdump_toc_rewrite::clean_backups(&toc_dat).is_err());
+    assert!(dest_dump_dir.join("toc.dat.orig").exists());
+    assert!(dest_dump_dir.join("5981.dat.gz.42-0123abcd.tmp").exists());
+    fs::remove_file(&lock_file).unwrap();
+
+    // referenced and unknown files are kept
+    let deleted = pgdump_toc_rewrite::clean_backups(&toc_dat).unwrap();
+    assert!(deleted.contains(&dest_dump_dir.join("toc.dat.orig")));
+    assert!(deleted.contains(&dest_dump_dir.join("5981.dat.gz.42-0123abcd.tmp")));
+    assert_eq!(pgdump_toc_rewrite::list_orphans(&toc_dat).unwrap(), [dest_dump_dir.join("9999.dat.gz")]);
+    assert!(dest_dump_dir.join("subdir.orig").is_dir());
+    assert!(pgdump_toc_rewrite::check_dump_dir(&toc_dat).unwrap().missing_files.is_empty());
+}
+
+#[test]
+fn rewrite_sysdatabases_owner_test() {
+    let dest_dump_dir = prepare_dump("rewrite_sysdatabases_owner_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let sysdatabases = dest_dump_dir.join("5981.dat.gz");
+    let sysdatabases_st = read_gz(&sysdatabases);
+    let extra_row = "0\t0\ttest1_db_owner\tbbf_unicode_cp1_ci_as\ttest1\t2023-12-22 17:41:58+00\t{}\n";
+    write_gz(&sysdatabases, &sysdatabases_st.replacen("\\.\n", &format!("{}\\.\n", extra_row), 1));
+
+    pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap();
+
+    let rewritten = read_gz(&sysdatabases);
+    assert!(rewritten.contains("0\t0\twilton\tbbf_unicode_cp1_ci_as\tfoobar\t"));
+    assert!(rewritten.contains("0\t0\tfoobar_db_owner\tbbf_unicode_cp1_ci_as\tfoobar\t"));
+    assert!(!rewritten.contains("test1"));
+}
+
+#[test]
+fn rewrite_sql_dialect_test() {
+    let dest_dump_dir = prepare_dump("rewrite_sql_dialect_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    modify_toc_json(&toc_dat, |toc_json| {
+        for en in toc_json["entries"].as_array_mut().unwrap() {
+            if en["tag"] == "view1" && en["description"] == "VIEW" {
+                en["create_stmt"] = serde_json::Value::String(
+                    "CREATE VIEW test1_dbo.view1 AS SELECT 5 #'3'::integer AS f FROM test1_dbo.tab1;\n".to_string());
+            }
+        }
+    });
+
+    let err = pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap_err();
+    assert_eq!(err.kind(), "entry");
+    assert!(err.to_string().contains("tag: view1"));
+
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.sql_dialect = pgdump_toc_rewrite::SqlDialect::PostgreSql;
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+    let toc_json = read_toc_json(&toc_dat);
+    let view = toc_json["entries"].as_array().unwrap().iter()
+        .find(|en| en["tag"] == "view1" && en["description"] == "VIEW").unwrap();
+    assert_eq!(view["create_stmt"], "CREATE VIEW foobar_dbo.view1 AS SELECT 5 #'3'::integer AS f FROM foobar_dbo.tab1;\n");
+}
+
+#[test]
+fn rewrite_lock_and_temp_files_test() {
+    let dest_dump_dir = prepare_dump("rewrite_lock_and_temp_files_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let toc_orig = fs::read(&toc_dat).unwrap();
+    let lock_file = dest_dump_dir.join(pgdump_toc_rewrite::LOCK_FILENAME);
+    let dir_files = || {
+        let mut files: Vec<String> = fs::read_dir(&dest_dump_dir).unwrap()
+            .map(|en| en.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        files
+    };
+
+    // another run is in progress
+    fs::write(&lock_file, "4242").unwrap();
+    let err = pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap_err();
+    assert!(err.to_string().contains("Dump directory is being modified by another process, pid: 4242"));
+    assert!(pgdump_toc_rewrite::renumber_toc(&toc_dat).is_err());
+    assert!(pgdump_toc_rewrite::undo_rewrite(&dest_dump_dir).is_err());
+    assert_eq!(toc_orig, fs::read(&toc_dat).unwrap());
+    assert!(lock_file.exists());
+    // writing into the output directory only reads the source directory
+    let output_dir = dest_dump_dir.with_file_name("output");
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.output_dir = Some(&output_dir);
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+    assert_eq!(toc_orig, fs::read(&toc_dat).unwrap());
+    assert!(!output_dir.join(pgdump_toc_rewrite::LOCK_FILENAME).exists());
+    assert_eq!("4242", fs::read_to_string(&lock_file).unwrap());
+    fs::remove_file(&lock_file).unwrap();
+
+    // failed rewrite removes its temporary files and the lock,
+    // catalogs rewritten before the failure are restored from backups
+    let namespace_ext = dest_dump_dir.join("5983.dat.gz");
+    let namespace_ext_orig = fs::read(&namespace_ext).unwrap();
+    fs::write(&namespace_ext, &namespace_ext_orig[..namespace_ext_orig.len() / 2]).unwrap();
+    let files_before = dir_files();
+    let authid_user_ext_orig = fs::read(dest_dump_dir.join("5984.dat.gz")).unwrap();
+    assert!(pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").is_err());
+    assert!(!lock_file.exists());
+    assert_eq!(files_before, dir_files());
+    assert_eq!(authid_user_ext_orig, fs::read(dest_dump_dir.join("5984.dat.gz")).unwrap());
+    fs::write(&namespace_ext, &namespace_ext_orig).unwrap();
+
+    // temporary files left by a crashed run are removed
+    let stale = dest_dump_dir.join("5981.dat.gz.4242-0123abcd.tmp");
+    fs::write(&stale, "stale").unwrap();
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &Default::default()).unwrap();
+    assert!(summary.warnings.is_empty());
+    assert!(!stale.exists());
+    assert!(!lock_file.exists());
+    assert!(!dir_files().iter().any(|filename| filename.ends_with(".tmp")));
+}
+
+#[cfg(unix)]
+#[test]
+fn rewrite_preserve_metadata_test() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let prepare = |name: &str| {
+        let dump_dir = prepare_dump(name);
+        for filename in ["toc.dat", "5981.dat.gz"] {
+            let path = dump_dir.join(filename);
+            File::options().write(true).open(&path).unwrap().set_modified(mtime).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        dump_dir
+    };
+    let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    let modified = |path: &Path| fs::metadata(path).unwrap().modified().unwrap();
+
+    let dump_dir = prepare("rewrite_preserve_metadata_test");
+    pgdump_toc_rewrite::rewrite_toc(&dump_dir, "foobar").unwrap();
+    for filename in ["toc.dat", "5981.dat.gz"] {
+        assert_eq!(0o600, mode(&dump_dir.join(filename)));
+        assert_ne!(mtime, modified(&dump_dir.join(filename)));
+    }
+
+    let dump_dir = prepare("rewrite_preserve_metadata_test_mtime");
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.preserve_mtime = true;
+    pgdump_toc_rewrite::rewrite_toc_with_options(&dump_dir, "foobar", &options).unwrap();
+    for filename in ["toc.dat", "5981.dat.gz"] {
+        assert_eq!(0o600, mode(&dump_dir.join(filename)));
+        assert_eq!(mtime, modified(&dump_dir.join(filename)));
+    }
+    let toc_json = read_toc_json(&dump_dir.join("toc.dat"));
+    assert!(toc_json["entries"].as_array().unwrap().iter().any(|en| en["tag"] == "foobar_dbo"));
+}
+
+#[test]
+fn rewrite_durable_test() {
+    // flushes to disk cannot be observed here, every write path is checked
+    // to succeed with flushes enabled and to write the same files
+    let dir_files = |dir: &Path| {
+        let mut files: Vec<String> = fs::read_dir(dir).unwrap()
+            .map(|en| en.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        files
+    };
+    for mode in ["in_place", "output_dir", "write_new_files"] {
+        let mut dirs = Vec::new();
+        for durable in [false, true] {
+            let dump_dir = prepare_dump(&format!("rewrite_durable_test_{}_{}", mode, durable));
+            let output_dir = dump_dir.with_file_name("output");
+            let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+            options.durable = durable;
+            match mode {
+                "output_dir" => options.output_dir = Some(&output_dir),
+                "write_new_files" => options.write_new_files = true,
+                _ => {}
+            }
+            pgdump_toc_rewrite::rewrite_toc_with_options(&dump_dir, "foobar", &options).unwrap();
+            dirs.push(if "output_dir" == mode { output_dir } else { dump_dir });
+        }
+        let (plain_dir, durable_dir) = (&dirs[0], &dirs[1]);
+        assert_eq!(dir_files(plain_dir), dir_files(durable_dir), "mode: {}", mode);
+        for filename in dir_files(plain_dir) {
+            let path = plain_dir.join(&filename);
+            if filename.ends_with(".gz") {
+                assert_eq!(read_gz(&path), read_gz(&durable_dir.join(&filename)), "mode: {}, file: {}", mode, filename);
+            } else {
+                assert_eq!(fs::read(&path).unwrap(), fs::read(durable_dir.join(&filename)).unwrap(), "mode: {}, file: {}", mode, filename);
+            }
+        }
+    }
+}
+
+#[test]
+fn rewrite_relative_path_test() {
+    let dest_dump_dir = prepare_dump("rewrite_relative_path_test");
+    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let relative_dir = dest_dump_dir.strip_prefix(project_dir).unwrap();
+    assert!(relative_dir.is_relative());
+    pgdump_toc_rewrite::rewrite_toc(relative_dir.join("toc.dat"), "foobar").unwrap();
+    assert!(dest_dump_dir.join("toc.dat.orig").exists());
+    assert!(read_gz(&dest_dump_dir.join("5983.dat.gz")).contains("foobar_dbo"));
+}
+
+#[cfg(unix)]
+#[test]
+fn rewrite_symlink_test() {
+    use std::os::unix::fs::symlink;
+
+    // symlinked dump directory
+    let dest_dump_dir = prepare_dump("rewrite_symlink_test");
+    let work_dir = dest_dump_dir.parent().unwrap();
+    let link_dir = work_dir.join("link");
+    symlink(&dest_dump_dir, &link_dir).unwrap();
+    pgdump_toc_rewrite::rewrite_toc(link_dir.join("toc.dat"), "foobar").unwrap();
+    assert!(fs::symlink_metadata(&link_dir).unwrap().file_type().is_symlink());
+    assert!(dest_dump_dir.join("toc.dat.orig").exists());
+    assert!(read_gz(&dest_dump_dir.join("5983.dat.gz")).contains("foobar_dbo"));
+    pgdump_toc_rewrite::undo_rewrite(&link_dir).unwrap();
+
+    // symlinked TOC file is resolved only when requested
+    let toc_dir = work_dir.join("toc_only");
+    fs::create_dir(&toc_dir).unwrap();
+    symlink(dest_dump_dir.join("toc.dat"), toc_dir.join("toc.dat")).unwrap();
+    let err = pgdump_toc_rewrite::rewrite_toc(toc_dir.join("toc.dat"), "foobar").unwrap_err();
+    assert!(matches!(err, pgdump_toc_rewrite::TocError::DataFile { .. } | pgdump_toc_rewrite::TocError::Io(_)), "{}", err);
+    assert!(fs::symlink_metadata(toc_dir.join("toc.dat")).unwrap().file_type().is_symlink());
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.follow_symlinks = true;
+    pgdump_toc_rewrite::rewrite_toc_with_options(toc_dir.join("toc.dat"), "foobar", &options).unwrap();
+    assert!(fs::symlink_metadata(toc_dir.join("toc.dat")).unwrap().file_type().is_symlink());
+    assert!(!toc_dir.join("toc.dat.orig").exists());
+    assert!(dest_dump_dir.join("toc.dat.orig").exists());
+    let toc_json = read_toc_json(&toc_dir.join("toc.dat"));
+    assert!(toc_json["entries"].as_array().unwrap().iter().any(|en| en["tag"] == "foobar_dbo"));
+}
+
+#[test]
+fn rewrite_long_names_test() {
+    let dest_dump_dir = prepare_dump("rewrite_long_names_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+    let toc_orig = fs::read(&toc_dat).unwrap();
+    // 'test1_db_owner' -> 64 bytes
+    let dbname = "a".repeat(55);
+    let err = pgdump_toc_rewrite::rewrite_toc(&toc_dat, &dbname).unwrap_err();
+    assert_eq!(err.kind(), "name_too_long");
+    assert_eq!(err.to_string(), format!(
+        "Name is too long, original name: test1_db_owner, new name: {}_db_owner, length: 64, limit: 63", dbname));
+    assert_eq!(fs::read(&toc_dat).unwrap(), toc_orig);
+    let err = pgdump_toc_rewrite::derive_rename_maps(&toc_dat, &dbname).unwrap_err();
+    assert_eq!(err.kind(), "name_too_long");
+    let maps = pgdump_toc_rewrite::derive_rename_maps(&toc_dat, &"a".repeat(54)).unwrap();
+    assert!(maps.schemas.values().chain(maps.owners.values()).all(|name| name.len() <= 63));
+
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.allow_long_names = true;
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, &dbname, &options).unwrap();
+    assert!(summary.warnings.iter().any(|msg| msg.starts_with("Name is too long, original name: test1_db_owner,")));
+
+    let err = pgdump_toc_rewrite::rewrite_single_schema(&toc_dat, &format!("{}_dbo", dbname), &"b".repeat(64)).unwrap_err();
+    assert_eq!(err.kind(), "name_too_long");
+}
+
+#[test]
+fn rewrite_tablespaces_test() {
+    let dest_dump_dir = prepare_dump("rewrite_tablespaces_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    modify_toc_json(&toc_dat, |toc_json| {
+        for en in toc_json["entries"].as_array_mut().unwrap() {
+            if en["dump_id"] == 372 || en["dump_id"] == 5703 {
+                en["tablespace"] = serde_json::Value::String("ts1".to_string());
+            }
+            if en["dump_id"] == 5703 {
+                let create_stmt = en["create_stmt"].as_str().unwrap().replace(" (id);", " (id) TABLESPACE ts1;");
+                en["create_stmt"] = serde_json::Value::String(create_stmt);
+            }
+        }
+    });
+
+    let tablespaces = HashMap::from([("ts1".to_string(), "ts_new".to_string())]);
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.tablespaces = Some(&tablespaces);
+    pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+
+    let toc_json = read_toc_json(&toc_dat);
+    let entries = toc_json["entries"].as_array().unwrap();
+    let tab1 = entries.iter().find(|en| en["dump_id"] == 372).unwrap();
+    assert_eq!(tab1["tablespace"], "ts_new");
+    let index1 = entries.iter().find(|en| en["dump_id"] == 5703).unwrap();
+    assert_eq!(index1["tablespace"], "ts_new");
+    assert!(index1["create_stmt"].as_str().unwrap().ends_with(" ON foobar_dbo.tab1 USING btree (id) TABLESPACE ts_new;\n"));
+    // other entries are left as is
+    assert!(entries.iter().filter(|en| en["tablespace"] == "ts_new").count() == 2);
+    assert!(!toc_json.to_string().contains("ts1"));
+}
+
+#[test]
+fn error_variants_test() {
+    use std::error::Error;
+    use pgdump_toc_rewrite::TocError;
+    let dest_dump_dir = prepare_dump("error_variants_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    let err = pgdump_toc_rewrite::read_toc_to_json(dest_dump_dir.join("fail.dat")).unwrap_err();
+    assert!(matches!(err, TocError::Io(_)));
+    assert!(err.source().is_some());
+
+    fs::write(dest_dump_dir.join("fail.dat"), "PGDUMP").unwrap();
+    let err = pgdump_toc_rewrite::read_toc_to_json(dest_dump_dir.join("fail.dat")).unwrap_err();
+    assert!(matches!(err, TocError::MagicMismatch));
+    assert_eq!(err.to_string(), "Magic check failure");
+
+    let err = pgdump_toc_rewrite::rewrite_toc(&toc_dat, "Foo").unwrap_err();
+    assert!(matches!(err, TocError::InvalidDbName(ref name) if name == "Foo"));
+    assert_eq!(err.to_string(), "Invalid db name specified: [Foo]");
+
+    let err = pgdump_toc_rewrite::write_toc_from_json(dest_dump_dir.join("fail2.dat"), "{").unwrap_err();
+    assert!(matches!(err, TocError::Json(_)));
+
+    let err = pgdump_toc_rewrite::rewrite_schema_in_sql(&HashMap::new(), "SELECT 'foo").unwrap_err();
+    assert!(matches!(err, TocError::Tokenizer { ref context, .. } if context == "SELECT 'foo"));
+
+    let err = pgdump_toc_rewrite::rewrite_schema_in_sql_unqualified(
+        &HashMap::from([("test1_dbo\"".to_string(), "foobar_dbo".to_string())]), "CREATE SCHEMA \"test1_dbo\"\"\"").unwrap_err();
+    assert_eq!(err.kind(), "sql_rewrite");
+    assert!(err.to_string().starts_with("Replace error, sql: "));
+
+    let err = TocError::CatalogMissing("babelfish_sysdatabases".to_string());
+    assert_eq!(err.kind(), "catalog_missing");
+    assert_eq!(err.to_string(), "Catalog table not found: babelfish_sysdatabases");
+    assert!(err.source().is_none());
+
+    let err = TocError::Corrupt { offset: 42, context: "Invalid integer sign byte: 2, header field: toc_count".to_string() };
+    assert_eq!(err.kind(), "corrupt");
+    assert_eq!(err.to_string(), "Invalid integer sign byte: 2, header field: toc_count, offset: 42");
+
+    let err = TocError::SqlTooLong { length: 42, limit: 16 };
+    assert_eq!(err.kind(), "sql_too_long");
+    assert_eq!(err.to_string(), "SQL statement is too long, length: 42, limit: 16");
+}
+
+#[test]
+fn rewrite_tokenizer_error_test() {
+    let dest_dump_dir = prepare_dump("rewrite_tokenizer_error_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    let mut dump_id = 0;
+    modify_toc_json(&toc_dat, |toc_json| {
+        for en in toc_json["entries"].as_array_mut().unwrap() {
+            if en["tag"] == "view1" && en["description"] == "VIEW" {
+                dump_id = en["dump_id"].as_i64().unwrap();
+                let create_stmt = en["create_stmt"].as_str().unwrap().replacen("SELECT", "SELECT 'unterminated", 1);
+                en["create_stmt"] = serde_json::Value::String(create_stmt);
+            }
+        }
+    });
+
+    let err = pgdump_toc_rewrite::rewrite_toc(&toc_dat, "foobar").unwrap_err();
+    assert!(err.to_string().starts_with(&format!(
+        "TOC entry error, dump_id: {}, description: VIEW, tag: view1, message: Tokenizer error: Unterminated string literal", dump_id)), "{}", err);
+    match err {
+        pgdump_toc_rewrite::TocError::Entry { description, tag, source, .. } => {
+            assert_eq!((description.as_str(), tag.as_str()), ("VIEW", "view1"));
+            assert!(matches!(*source, pgdump_toc_rewrite::TocError::Tokenizer { .. }));
+        },
+        _ => panic!("unexpected error: {}", err)
+    }
+}
+
+#[cfg(feature = "manifest")]
+#[test]
+fn rewrite_catalog_checksums_test() {
+    use sha2::Digest;
+    let dest_dump_dir = prepare_dump("rewrite_catalog_checksums_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.catalog_checksums = true;
+    let summary = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap();
+
+    let sha256 = |path: &Path| format!("{:x}", sha2::Sha256::digest(read_gz(path).as_bytes()));
+    let expected = [
+        ("babelfish_authid_user_ext", "5984.dat.gz"),
+        ("babelfish_extended_properties", "5986.dat.gz"),
+        ("babelfish_function_ext", "5982.dat.gz"),
+        ("babelfish_namespace_ext", "5983.dat.gz"),
+        ("babelfish_sysdatabases", "5981.dat.gz"),
+    ];
+    assert_eq!(expected.len(), summary.catalog_checksums.len());
+    for (cs, (catalog, filename)) in summary.catalog_checksums.iter().zip(expected) {
+        assert_eq!(catalog, cs.catalog);
+        assert_eq!(filename, cs.filename);
+        let orig_filename = filename.replace(".dat.gz", ".dat.orig.gz");
+        assert_eq!(sha256(&dest_dump_dir.join(orig_filename)), cs.sha256_before);
+        assert_eq!(sha256(&dest_dump_dir.join(filename)), cs.sha256_after);
+        assert_ne!(cs.sha256_before, cs.sha256_after);
+    }
+}
+
+#[cfg(not(feature = "manifest"))]
+#[test]
+fn rewrite_catalog_checksums_disabled_test() {
+    let dest_dump_dir = prepare_dump("rewrite_catalog_checksums_disabled_test");
+    let toc_dat = dest_dump_dir.join("toc.dat");
+
+    let mut options = pgdump_toc_rewrite::RewriteOptions::default();
+    options.catalog_checksums = true;
+    let err = pgdump_toc_rewrite::rewrite_toc_with_options(&toc_dat, "foobar", &options).unwrap_err();
+    assert!(err.to_string().contains("'manifest' feature"));
+    assert!(!dest_dump_dir.join("toc.dat.orig").exists());
+}